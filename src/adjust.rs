@@ -0,0 +1,177 @@
+use super::errors::ParseAdjustmentError;
+use image::{DynamicImage, Rgba};
+use serde::Serialize;
+use std::str::FromStr;
+
+/// The HSL component an adjustment changes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Property {
+    /// Rotates the hue by a number of degrees.
+    Hue,
+    /// Scales saturation by a percentage.
+    Saturation,
+    /// Scales lightness by a percentage.
+    Lightness,
+}
+
+/// The colors an adjustment applies to. Each named range is centered on its hue and fades out
+/// gradually, so neighboring colors blend smoothly.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorRange {
+    All,
+    Reds,
+    Yellows,
+    Greens,
+    Cyans,
+    Blues,
+    Magentas,
+}
+
+impl ColorRange {
+    /// Returns how strongly a pixel with the given hue (in degrees) is affected, from 0 to 1.
+    fn weight(self, hue: f32) -> f32 {
+        let center = match self {
+            ColorRange::All => return 1.0,
+            ColorRange::Reds => 0.0,
+            ColorRange::Yellows => 60.0,
+            ColorRange::Greens => 120.0,
+            ColorRange::Cyans => 180.0,
+            ColorRange::Blues => 240.0,
+            ColorRange::Magentas => 300.0,
+        };
+        let distance = (hue - center).rem_euclid(360.0);
+        let distance = distance.min(360.0 - distance);
+        // Full strength within 15° of the center, fading to nothing at 45°.
+        ((45.0 - distance) / 30.0).clamp(0.0, 1.0)
+    }
+}
+
+/// A selective color adjustment, written as `<property>:<range>:<amount>`,
+/// e.g. `hue:reds:-10` or `sat:blues:+20`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Adjustment {
+    pub property: Property,
+    pub range: ColorRange,
+    /// Degrees for hue (-180 to 180), percent for saturation and lightness (-100 to 100).
+    pub amount: f32,
+}
+
+impl FromStr for Adjustment {
+    type Err = ParseAdjustmentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseAdjustmentError(s.to_string());
+        let mut parts = s.split(':').map(str::trim);
+        let (Some(property), Some(range), Some(amount), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(err());
+        };
+
+        let property = match property.to_lowercase().as_str() {
+            "hue" => Property::Hue,
+            "sat" | "saturation" => Property::Saturation,
+            "light" | "lightness" => Property::Lightness,
+            _ => return Err(err()),
+        };
+        let range = match range.to_lowercase().as_str() {
+            "all" | "master" => ColorRange::All,
+            "reds" => ColorRange::Reds,
+            "yellows" => ColorRange::Yellows,
+            "greens" => ColorRange::Greens,
+            "cyans" => ColorRange::Cyans,
+            "blues" => ColorRange::Blues,
+            "magentas" => ColorRange::Magentas,
+            _ => return Err(err()),
+        };
+        let amount: f32 = amount.parse().map_err(|_| err())?;
+        let limit = if property == Property::Hue { 180.0 } else { 100.0 };
+        if !amount.is_finite() || amount.abs() > limit {
+            return Err(err());
+        }
+        Ok(Adjustment { property, range, amount })
+    }
+}
+
+/// Applies adjustments to every pixel, in order. Alpha is left untouched.
+pub fn apply_adjustments(img: &mut DynamicImage, adjustments: &[Adjustment]) {
+    if adjustments.is_empty() {
+        return;
+    }
+    let has_alpha = img.color().has_alpha();
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        *pixel = adjust_pixel(*pixel, adjustments);
+    }
+    *img = if has_alpha {
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
+    };
+}
+
+fn adjust_pixel(pixel: Rgba<u8>, adjustments: &[Adjustment]) -> Rgba<u8> {
+    let [r, g, b, a] = pixel.0;
+    let (mut h, mut s, mut l) = rgb_to_hsl(r, g, b);
+    for adjustment in adjustments {
+        let weight = adjustment.range.weight(h);
+        if weight == 0.0 {
+            continue;
+        }
+        let amount = adjustment.amount * weight;
+        match adjustment.property {
+            Property::Hue => h = (h + amount).rem_euclid(360.0),
+            Property::Saturation => s = scale_toward(s, amount / 100.0),
+            Property::Lightness => l = scale_toward(l, amount / 100.0),
+        }
+    }
+    let (r, g, b) = hsl_to_rgb(h, s, l);
+    Rgba([r, g, b, a])
+}
+
+/// Moves a 0–1 value toward 1 for positive amounts and toward 0 for negative ones.
+fn scale_toward(value: f32, amount: f32) -> f32 {
+    if amount >= 0.0 {
+        value + (1.0 - value) * amount
+    } else {
+        value * (1.0 + amount)
+    }
+}
+
+/// Converts 8-bit RGB to hue (degrees), saturation and lightness (0–1).
+fn rgb_to_hsl(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, l);
+    }
+    let s = delta / (1.0 - (2.0 * l - 1.0).abs());
+    let h = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    (h, s, l)
+}
+
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (u8, u8, u8) {
+    let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+    let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = l - c / 2.0;
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    let to_u8 = |v: f32| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+    (to_u8(r), to_u8(g), to_u8(b))
+}
@@ -0,0 +1,228 @@
+use super::cli::AppIconsArgs;
+use super::decode::open_image;
+use super::i18n::Msg;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+/// A mobile platform to generate icons and splash screens for.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Platform {
+    Ios,
+    Android,
+}
+
+/// The icon slots of an iOS asset catalog: idiom, size in points and scale.
+const IOS_ICONS: [(&str, f32, u32); 18] = [
+    ("iphone", 20.0, 2),
+    ("iphone", 20.0, 3),
+    ("iphone", 29.0, 2),
+    ("iphone", 29.0, 3),
+    ("iphone", 40.0, 2),
+    ("iphone", 40.0, 3),
+    ("iphone", 60.0, 2),
+    ("iphone", 60.0, 3),
+    ("ipad", 20.0, 1),
+    ("ipad", 20.0, 2),
+    ("ipad", 29.0, 1),
+    ("ipad", 29.0, 2),
+    ("ipad", 40.0, 1),
+    ("ipad", 40.0, 2),
+    ("ipad", 76.0, 1),
+    ("ipad", 76.0, 2),
+    ("ipad", 83.5, 2),
+    ("ios-marketing", 1024.0, 1),
+];
+
+/// Side of the square iOS splash image in points; at 3x it covers the largest iPad.
+const IOS_SPLASH_POINTS: u32 = 910;
+
+/// Android density buckets and their scale relative to mdpi.
+const ANDROID_DENSITIES: [(&str, f32); 5] =
+    [("mdpi", 1.0), ("hdpi", 1.5), ("xhdpi", 2.0), ("xxhdpi", 3.0), ("xxxhdpi", 4.0)];
+
+/// Legacy launcher icon size, in dp.
+const LAUNCHER_DP: f32 = 48.0;
+/// Adaptive icon layer size, in dp, and the centered safe zone no launcher mask cuts into.
+const ADAPTIVE_DP: f32 = 108.0;
+const SAFE_ZONE_DP: f32 = 66.0;
+/// Portrait splash size at mdpi, in dp.
+const SPLASH_DP: (f32, f32) = (320.0, 480.0);
+const PLAY_STORE_ICON: u32 = 512;
+
+/// Writes the icon and splash sets for each platform under the output directory.
+pub fn run(args: &AppIconsArgs) -> Result<()> {
+    let icon = open_image(&args.input).with_context(|| format!("Failed to open {}", args.input.display()))?;
+    if icon.width() != icon.height() {
+        bail!("The icon must be square, but {} is {}x{}", args.input.display(), icon.width(), icon.height());
+    }
+    if icon.width() < 1024 {
+        eprintln!("{}", Msg::SmallIcon(icon.width()));
+    }
+    let icon = icon.to_rgba8();
+    let background = args.background.0;
+
+    let mut written = 0;
+    for platform in &args.platforms {
+        written += match platform {
+            Platform::Ios => write_ios(&icon, background, &args.output_dir.join("ios"))?,
+            Platform::Android => write_android(&icon, background, &args.output_dir.join("android"))?,
+        };
+    }
+    println!("{}", Msg::WroteIcons { count: written, dir: &args.output_dir });
+    Ok(())
+}
+
+/// Writes `AppIcon.appiconset` and `Splash.imageset`, each with its `Contents.json`. App Store
+/// icons may not be transparent, so the icon is flattened onto the background.
+fn write_ios(icon: &RgbaImage, background: Rgba<u8>, dir: &Path) -> Result<usize> {
+    let icon = flatten(icon, background);
+    let icon_dir = dir.join("AppIcon.appiconset");
+    fs::create_dir_all(&icon_dir)?;
+    let mut images = Vec::new();
+    let mut written = 0;
+    for (idiom, points, scale) in IOS_ICONS {
+        let filename = match scale {
+            1 => format!("Icon-{}.png", points),
+            _ => format!("Icon-{}@{}x.png", points, scale),
+        };
+        let path = icon_dir.join(&filename);
+        // iPhone and iPad share some sizes, and so the file.
+        if !path.exists() {
+            let side = (points * scale as f32).round() as u32;
+            save(&resize(&icon, side), &path)?;
+            written += 1;
+        }
+        images.push(json!({
+            "size": format!("{0}x{0}", points),
+            "idiom": idiom,
+            "filename": filename,
+            "scale": format!("{}x", scale),
+        }));
+    }
+    write_contents(&icon_dir, images)?;
+
+    let splash_dir = dir.join("Splash.imageset");
+    fs::create_dir_all(&splash_dir)?;
+    let mut images = Vec::new();
+    for scale in 1..=3 {
+        let filename = match scale {
+            1 => "Splash.png".to_string(),
+            _ => format!("Splash@{}x.png", scale),
+        };
+        let side = IOS_SPLASH_POINTS * scale;
+        save(&splash(&icon, background, side, side), &splash_dir.join(&filename))?;
+        written += 1;
+        images.push(json!({ "idiom": "universal", "filename": filename, "scale": format!("{}x", scale) }));
+    }
+    write_contents(&splash_dir, images)?;
+    Ok(written)
+}
+
+/// Writes an Android `res` tree: legacy square and round launcher icons, adaptive icon layers
+/// with their XML, portrait and landscape splash screens for each density, and the Play Store
+/// icon next to `res`.
+fn write_android(icon: &RgbaImage, background: Rgba<u8>, dir: &Path) -> Result<usize> {
+    let res = dir.join("res");
+    let mut written = 0;
+    for (density, scale) in ANDROID_DENSITIES {
+        let mipmap = res.join(format!("mipmap-{}", density));
+        fs::create_dir_all(&mipmap)?;
+        let launcher = resize(icon, (LAUNCHER_DP * scale).round() as u32);
+        save(&launcher, &mipmap.join("ic_launcher.png"))?;
+        save(&circle_mask(&launcher), &mipmap.join("ic_launcher_round.png"))?;
+        let layer = (ADAPTIVE_DP * scale).round() as u32;
+        let mut foreground = RgbaImage::new(layer, layer);
+        let art = resize(icon, (SAFE_ZONE_DP * scale).round() as u32);
+        overlay_centered(&mut foreground, &art);
+        save(&foreground, &mipmap.join("ic_launcher_foreground.png"))?;
+        written += 3;
+
+        let (width, height) = ((SPLASH_DP.0 * scale).round() as u32, (SPLASH_DP.1 * scale).round() as u32);
+        for (orientation, (w, h)) in [("port", (width, height)), ("land", (height, width))] {
+            let drawable = res.join(format!("drawable-{}-{}", orientation, density));
+            fs::create_dir_all(&drawable)?;
+            save(&splash(icon, background, w, h), &drawable.join("splash.png"))?;
+            written += 1;
+        }
+    }
+
+    let anydpi = res.join("mipmap-anydpi-v26");
+    fs::create_dir_all(&anydpi)?;
+    let adaptive = r#"<?xml version="1.0" encoding="utf-8"?>
+<adaptive-icon xmlns:android="http://schemas.android.com/apk/res/android">
+    <background android:drawable="@color/ic_launcher_background"/>
+    <foreground android:drawable="@mipmap/ic_launcher_foreground"/>
+</adaptive-icon>
+"#;
+    fs::write(anydpi.join("ic_launcher.xml"), adaptive)?;
+    fs::write(anydpi.join("ic_launcher_round.xml"), adaptive)?;
+    let values = res.join("values");
+    fs::create_dir_all(&values)?;
+    let [r, g, b, _] = background.0;
+    fs::write(
+        values.join("ic_launcher_background.xml"),
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<resources>\n    <color name=\"ic_launcher_background\">#{:02X}{:02X}{:02X}</color>\n</resources>\n",
+            r, g, b
+        ),
+    )?;
+
+    save(&flatten(&resize(icon, PLAY_STORE_ICON), background), &dir.join("playstore-icon.png"))?;
+    Ok(written + 1)
+}
+
+fn write_contents(dir: &Path, images: Vec<serde_json::Value>) -> Result<()> {
+    let contents = json!({ "images": images, "info": { "version": 1, "author": "imagekit" } });
+    fs::write(dir.join("Contents.json"), serde_json::to_string_pretty(&contents)?)?;
+    Ok(())
+}
+
+fn resize(icon: &RgbaImage, side: u32) -> RgbaImage {
+    if icon.width() == side {
+        icon.clone()
+    } else {
+        imageops::resize(icon, side, side, FilterType::Lanczos3)
+    }
+}
+
+fn save(img: &RgbaImage, path: &Path) -> Result<()> {
+    img.save(path).with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// Composites the icon onto an opaque background.
+fn flatten(icon: &RgbaImage, background: Rgba<u8>) -> RgbaImage {
+    let mut canvas = RgbaImage::from_pixel(icon.width(), icon.height(), Rgba([background[0], background[1], background[2], 255]));
+    imageops::overlay(&mut canvas, icon, 0, 0);
+    canvas
+}
+
+fn overlay_centered(canvas: &mut RgbaImage, art: &RgbaImage) {
+    let x = (canvas.width() as i64 - art.width() as i64) / 2;
+    let y = (canvas.height() as i64 - art.height() as i64) / 2;
+    imageops::overlay(canvas, art, x, y);
+}
+
+/// A splash screen: the icon at a quarter of the shorter side, centered on the background.
+fn splash(icon: &RgbaImage, background: Rgba<u8>, width: u32, height: u32) -> RgbaImage {
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba([background[0], background[1], background[2], 255]));
+    overlay_centered(&mut canvas, &resize(icon, (width.min(height) / 4).max(1)));
+    canvas
+}
+
+/// Cuts the icon to a circle, anti-aliasing the edge.
+fn circle_mask(icon: &RgbaImage) -> RgbaImage {
+    let radius = icon.width() as f32 / 2.0;
+    let mut round = icon.clone();
+    for (x, y, pixel) in round.enumerate_pixels_mut() {
+        let distance = ((x as f32 + 0.5 - radius).powi(2) + (y as f32 + 0.5 - radius).powi(2)).sqrt();
+        let coverage = (radius - distance + 0.5).clamp(0.0, 1.0);
+        pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+    }
+    round
+}
+
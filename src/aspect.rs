@@ -0,0 +1,94 @@
+use super::errors::ParseAspectFilterError;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// How far from 1:1 an image may be and still count as square, unless a filter says otherwise.
+pub const DEFAULT_SQUARE_TOLERANCE: f32 = 0.02;
+
+/// The orientation class of an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aspect {
+    Landscape,
+    Portrait,
+    Square,
+}
+
+impl fmt::Display for Aspect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Aspect::Landscape => "landscape",
+            Aspect::Portrait => "portrait",
+            Aspect::Square => "square",
+        })
+    }
+}
+
+impl Aspect {
+    /// Classifies a `width` x `height` image. It is square when the longer side is at most
+    /// `tolerance` (a fraction) longer than the shorter one.
+    pub fn classify(width: u32, height: u32, tolerance: f32) -> Self {
+        let (long, short) = (width.max(height) as f32, width.min(height) as f32);
+        if long <= short * (1.0 + tolerance) {
+            Aspect::Square
+        } else if width > height {
+            Aspect::Landscape
+        } else {
+            Aspect::Portrait
+        }
+    }
+
+    /// Classifies an image file from its header, or `None` if its size can't be read.
+    pub fn of_file(path: &Path, tolerance: f32) -> Option<Self> {
+        let (width, height) = image::image_dimensions(path).ok()?;
+        Some(Aspect::classify(width, height, tolerance))
+    }
+}
+
+/// An `--only-aspect` filter such as `landscape` or `square,0.05`; only `square` takes a
+/// tolerance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AspectFilter {
+    pub aspect: Aspect,
+    /// How far from 1:1 counts as square, as a fraction. One tolerance applies to the whole
+    /// run; see [`Cli::square_tolerance`](crate::cli::Cli::square_tolerance).
+    pub tolerance: f32,
+}
+
+impl FromStr for AspectFilter {
+    type Err = ParseAspectFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseAspectFilterError(s.to_string());
+        let (name, tolerance) = match s.split_once(',') {
+            Some((name, tolerance)) => (name, Some(tolerance.trim())),
+            None => (s, None),
+        };
+        let aspect = match name.trim().to_lowercase().as_str() {
+            "landscape" => Aspect::Landscape,
+            "portrait" => Aspect::Portrait,
+            "square" => Aspect::Square,
+            _ => return Err(err()),
+        };
+        let tolerance = match tolerance {
+            None => DEFAULT_SQUARE_TOLERANCE,
+            Some(_) if aspect != Aspect::Square => return Err(err()),
+            Some(t) => match t.strip_suffix('%') {
+                Some(percent) => percent.trim().parse::<f32>().map(|p| p / 100.0),
+                None => t.parse::<f32>(),
+            }
+            .ok()
+            .filter(|t| (0.0..1.0).contains(t))
+            .ok_or_else(err)?,
+        };
+        Ok(AspectFilter { aspect, tolerance })
+    }
+}
+
+/// Returns whether the image has one of the aspects in `filters`, telling squares apart with
+/// `tolerance`. Images whose size can't be read never match.
+pub fn passes_aspect_filter(path: &Path, filters: &[AspectFilter], tolerance: f32) -> bool {
+    Aspect::of_file(path, tolerance).is_some_and(|aspect| filters.iter().any(|f| f.aspect == aspect))
+}
@@ -1,5 +1,94 @@
+use super::errors::ParseFontSourceError;
+use anyhow::{Context, Result};
 use rust_embed::RustEmbed;
+use rusttype::Font;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
 
 #[derive(RustEmbed)]
 #[folder = "assets/"]
-pub struct Asset;
\ No newline at end of file
+pub struct Asset;
+
+/// Embedded watermark fonts in fallback order: Latin, CJK, then Thai.
+pub const FONT_FILES: [&str; 3] = ["Roboto-Regular.ttf", "SourceHanSansSC-Regular.otf", "NotoSansThai-Regular.ttf"];
+
+/// Names for the embedded fonts in a `--fonts` chain, matching [`FONT_FILES`].
+pub const EMBEDDED_NAMES: [&str; 3] = ["roboto", "source-han-sans", "noto-sans-thai"];
+
+/// One font of a watermark fallback chain: an embedded font, written `embedded:<name>` with a
+/// name from [`EMBEDDED_NAMES`], or a TrueType or OpenType file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FontSource {
+    /// The file name of an embedded font, one of [`FONT_FILES`].
+    Embedded(&'static str),
+    File(PathBuf),
+}
+
+impl FromStr for FontSource {
+    type Err = ParseFontSourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.strip_prefix("embedded:") {
+            Some(name) => EMBEDDED_NAMES
+                .iter()
+                .position(|known| known.eq_ignore_ascii_case(name))
+                .map(|i| FontSource::Embedded(FONT_FILES[i]))
+                .ok_or_else(|| ParseFontSourceError(s.to_string())),
+            None if s.is_empty() => Err(ParseFontSourceError(s.to_string())),
+            None => Ok(FontSource::File(PathBuf::from(s))),
+        }
+    }
+}
+
+impl fmt::Display for FontSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FontSource::Embedded(file) => {
+                let i = FONT_FILES.iter().position(|known| known == file).unwrap_or(0);
+                write!(f, "embedded:{}", EMBEDDED_NAMES[i])
+            }
+            FontSource::File(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+impl FontSource {
+    pub fn load(&self) -> Result<Font<'static>> {
+        match self {
+            FontSource::Embedded(name) => {
+                let data = Asset::get(name).with_context(|| format!("Could not find font '{}'", name))?;
+                Font::try_from_vec(data.data.into_owned()).with_context(|| format!("Error constructing font '{}'", name))
+            }
+            FontSource::File(path) => {
+                let data = fs::read(path).with_context(|| format!("Failed to read font {}", path.display()))?;
+                Font::try_from_vec(data).with_context(|| format!("{} is not a TrueType or OpenType font", path.display()))
+            }
+        }
+    }
+}
+
+/// The fallback chain used unless `--fonts` gives one: the embedded fonts, in order.
+pub fn default_chain() -> Vec<FontSource> {
+    FONT_FILES.iter().map(|&file| FontSource::Embedded(file)).collect()
+}
+
+/// Loads a fallback chain for watermark text. Each character is drawn with the first font
+/// that has a glyph for it, and the first font's metrics set the line height.
+pub fn load_chain(chain: &[FontSource]) -> Result<Vec<Font<'static>>> {
+    chain.iter().map(FontSource::load).collect()
+}
+
+/// Loads the embedded watermark fonts in fallback order.
+pub fn load_fonts() -> Result<Vec<Font<'static>>> {
+    load_chain(&default_chain())
+}
+
+/// Loads the watermark fonts in fallback order: `files`, as given with `--font`, ahead of the
+/// embedded ones, so glyphs they lack still come from those.
+pub fn load_fonts_with(files: &[PathBuf]) -> Result<Vec<Font<'static>>> {
+    let chain: Vec<FontSource> = files.iter().cloned().map(FontSource::File).chain(default_chain()).collect();
+    load_chain(&chain)
+}
@@ -0,0 +1,63 @@
+use image::{DynamicImage, GenericImageView, ImageFormat};
+
+/// Longest side of the grid of pixels inspected; larger images are sampled with a stride.
+const SAMPLE_SIDE: u32 = 512;
+/// At most this many distinct colors makes an image a palette graphic: icons, charts, UI.
+const PALETTE_COLORS: usize = 256;
+/// Neighbors whose channels all differ by at most this much count as the same flat area.
+const FLAT_TOLERANCE: u8 = 2;
+/// A luma step at least this big between neighbors is a hard edge, as around text.
+const EDGE_STEP: i32 = 48;
+/// Share of neighbors in flat areas from which an image looks like a screenshot.
+const MIN_FLAT_SHARE: f32 = 0.5;
+/// Share of hard edges among the remaining neighbors from which its detail looks like text.
+const MIN_EDGE_SHARE: f32 = 0.25;
+
+/// Picks the format for an `--output-format auto` output from the image's content. PNG keeps
+/// screenshots, text, flat graphics and anything with transparency sharp; JPEG suits photos,
+/// which lossy compression shrinks far more.
+///
+/// Screenshots and text have large areas of one color broken by hard edges, whereas photos
+/// are noisy and change gradually even in smooth skies. Neighboring pixels are sampled for
+/// both traits, along with the number of distinct colors.
+pub fn choose(img: &DynamicImage) -> ImageFormat {
+    if img.color().has_alpha() && img.pixels().any(|(_, _, p)| p[3] < u8::MAX) {
+        return ImageFormat::Png;
+    }
+    if is_graphic(&img.to_rgb8()) {
+        ImageFormat::Png
+    } else {
+        ImageFormat::Jpeg
+    }
+}
+
+fn is_graphic(rgb: &image::RgbImage) -> bool {
+    let (width, height) = rgb.dimensions();
+    if width < 2 {
+        return true;
+    }
+    let stride = (width.max(height) / SAMPLE_SIDE).max(1);
+    let luma = |p: &image::Rgb<u8>| (299 * p[0] as i32 + 587 * p[1] as i32 + 114 * p[2] as i32) / 1000;
+
+    let mut colors = std::collections::HashSet::new();
+    let (mut pairs, mut flat, mut edges) = (0usize, 0usize, 0usize);
+    for y in (0..height).step_by(stride as usize) {
+        for x in (0..width - 1).step_by(stride as usize) {
+            let (a, b) = (rgb.get_pixel(x, y), rgb.get_pixel(x + 1, y));
+            if colors.len() <= PALETTE_COLORS {
+                colors.insert(a.0);
+            }
+            pairs += 1;
+            if a.0.iter().zip(b.0).all(|(&p, q)| p.abs_diff(q) <= FLAT_TOLERANCE) {
+                flat += 1;
+            } else if (luma(a) - luma(b)).abs() >= EDGE_STEP {
+                edges += 1;
+            }
+        }
+    }
+    if colors.len() <= PALETTE_COLORS {
+        return true;
+    }
+    let detail = pairs - flat;
+    flat as f32 / pairs as f32 >= MIN_FLAT_SHARE && (detail == 0 || edges as f32 / detail as f32 >= MIN_EDGE_SHARE)
+}
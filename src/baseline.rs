@@ -0,0 +1,81 @@
+use super::cli::Cli;
+use super::context::RunContext;
+use super::processor::output_candidates;
+use super::rules::Pipeline;
+use super::sync::{hash_file, relative_key, settings_fingerprint};
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The parts of a previous `--report` that `--baseline` compares against.
+#[derive(Debug, Deserialize)]
+struct BaselineReport {
+    /// Missing from reports written before fingerprints were recorded.
+    settings: Option<String>,
+    images: Vec<BaselineImage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BaselineImage {
+    key: Option<String>,
+    status: String,
+    source_blake3: Option<String>,
+}
+
+/// Inputs split by comparison with a baseline report.
+pub struct BaselineSplit {
+    /// Inputs that are new or changed, or all of them if the settings changed.
+    pub to_process: Vec<PathBuf>,
+    /// Inputs whose content and settings match the baseline, with their existing outputs.
+    pub unchanged: Vec<(PathBuf, PathBuf)>,
+    /// Whether the baseline was made with the same settings.
+    pub same_settings: bool,
+}
+
+/// Compares inputs with the report at `path`. An input is unchanged when the baseline holds
+/// an output for it under the same path relative to the input directory, made from the same
+/// content with the same settings, and that output is still in the output directory. Paths
+/// are compared relative to the input and output directories, so a report made on another
+/// machine works as long as the output directory was carried over.
+pub fn split(cli: &Cli, context: &RunContext, inputs: Vec<PathBuf>, path: &Path) -> Result<BaselineSplit> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read baseline {}", path.display()))?;
+    let baseline: BaselineReport =
+        serde_json::from_str(&text).with_context(|| format!("Not an imagekit report: {}", path.display()))?;
+    let same_settings = baseline.settings.as_deref() == Some(settings_fingerprint(cli, context).as_str());
+    if !same_settings {
+        return Ok(BaselineSplit { to_process: inputs, unchanged: Vec::new(), same_settings });
+    }
+
+    let hashes: HashMap<String, String> = baseline
+        .images
+        .into_iter()
+        .filter(|image| matches!(image.status.as_str(), "processed" | "unchanged"))
+        .filter_map(|image| Some((image.key?, image.source_blake3?)))
+        .collect();
+    let decisions: Vec<(PathBuf, Option<PathBuf>)> = inputs
+        .into_par_iter()
+        .map(|input| {
+            let Some(hash) = hashes.get(&relative_key(&input, &cli.input_dir)) else {
+                return Ok((input, None));
+            };
+            if *hash != hash_file(&input)? {
+                return Ok((input, None));
+            }
+            let pipeline = Pipeline::for_path(cli, &input)?;
+            let existing = output_candidates(&input, cli, context, &pipeline)?.into_iter().find(|p| p.exists());
+            Ok((input, existing))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut split = BaselineSplit { to_process: Vec::new(), unchanged: Vec::new(), same_settings };
+    for (input, existing) in decisions {
+        match existing {
+            Some(output) => split.unchanged.push((input, output)),
+            None => split.to_process.push(input),
+        }
+    }
+    Ok(split)
+}
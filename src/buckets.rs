@@ -0,0 +1,86 @@
+use super::errors::ParseSizeBucketsError;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// `--bucket-by-size` tiers such as `small:<100KB,medium:<500KB,large`. An output goes into the
+/// subfolder of the first bucket its encoded size is below; the last bucket has no limit and
+/// takes everything else.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SizeBuckets(Vec<Bucket>);
+
+#[derive(Debug, Clone, PartialEq)]
+struct Bucket {
+    name: String,
+    /// Exclusive upper limit in bytes; `None` for the last bucket.
+    below: Option<u64>,
+}
+
+impl FromStr for SizeBuckets {
+    type Err = ParseSizeBucketsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSizeBucketsError(s.to_string());
+        let mut buckets = Vec::new();
+        for part in s.split(',') {
+            let (name, below) = match part.split_once(':') {
+                Some((name, limit)) => (name, Some(limit.trim().strip_prefix('<').and_then(parse_bytes).ok_or_else(err)?)),
+                None => (part, None),
+            };
+            let name = name.trim();
+            if name.is_empty() || matches!(name, "." | "..") || name.contains(['/', '\\']) {
+                return Err(err());
+            }
+            buckets.push(Bucket { name: name.to_string(), below });
+        }
+        let (last, limited) = buckets.split_last().ok_or_else(err)?;
+        let limits: Vec<u64> = limited.iter().filter_map(|b| b.below).collect();
+        if last.below.is_some() || limits.len() != limited.len() || limits.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(err());
+        }
+        Ok(SizeBuckets(buckets))
+    }
+}
+
+/// Parses a size such as `100KB`, `1.5MB` or `512KiB`. KB, MB and GB are powers of 1000;
+/// KiB, MiB and GiB powers of 1024; a bare number or `B` is bytes.
+pub(crate) fn parse_bytes(s: &str) -> Option<u64> {
+    let upper = s.trim().to_ascii_uppercase();
+    let split = upper.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(upper.len());
+    let (number, unit) = upper.split_at(split);
+    let multiplier: u64 = match unit.trim() {
+        "" | "B" => 1,
+        "KB" => 1000,
+        "MB" => 1000 * 1000,
+        "GB" => 1000 * 1000 * 1000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        _ => return None,
+    };
+    let value: f64 = number.parse().ok()?;
+    (value > 0.0).then(|| (value * multiplier as f64).round() as u64)
+}
+
+impl SizeBuckets {
+    /// Returns the name of the bucket an output of `size` bytes belongs in.
+    pub fn bucket_for(&self, size: u64) -> &str {
+        let bucket = self.0.iter().find(|b| b.below.is_none_or(|below| size < below));
+        // The last bucket has no limit, so one always matches.
+        bucket.map_or("", |b| b.name.as_str())
+    }
+
+    /// Moves the planned `output` under `output_dir` into the subfolder for `size`.
+    pub fn place(&self, output_dir: &Path, output: &Path, size: u64) -> PathBuf {
+        bucketed(output_dir, output, self.bucket_for(size))
+    }
+
+    /// Every place the planned `output` may have been written to, one per bucket.
+    pub fn candidates<'a>(&'a self, output_dir: &'a Path, output: &'a Path) -> impl Iterator<Item = PathBuf> + 'a {
+        self.0.iter().map(move |b| bucketed(output_dir, output, &b.name))
+    }
+}
+
+fn bucketed(output_dir: &Path, output: &Path, bucket: &str) -> PathBuf {
+    let relative = output.strip_prefix(output_dir).unwrap_or(output);
+    output_dir.join(bucket).join(relative)
+}
@@ -0,0 +1,134 @@
+use super::decode::open_image;
+use super::sequence::capture_seconds;
+use super::sharpness::{laplacian_variance, SHARPNESS_SIDE};
+use image::imageops::FilterType;
+use image::GrayImage;
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Bits in a difference hash: 8 rows of 8 left-to-right comparisons.
+const HASH_BITS: u32 = 64;
+
+/// Shots that `--pick-best-of-burst` took for one.
+#[derive(Debug, Clone)]
+pub struct Burst {
+    /// The sharpest shot, which is processed.
+    pub kept: PathBuf,
+    /// The others, which are left out.
+    pub discarded: Vec<PathBuf>,
+}
+
+/// What is known about one input for grouping.
+struct Shot {
+    /// Capture time in seconds, from EXIF.
+    time: Option<f64>,
+    /// Difference hash, or `None` if the image could not be decoded.
+    hash: Option<u64>,
+    /// Variance of the Laplacian: higher is sharper.
+    sharpness: f64,
+}
+
+/// Groups shots taken at most `window` seconds apart (by EXIF capture time, chaining a
+/// burst's shots) or looking alike, with at least `similarity` of their perceptual hashes
+/// matching, and keeps only the sharpest of each group. Returns the inputs to process, in
+/// their original order, and the bursts found. Images that cannot be decoded are kept, so
+/// the error is reported when they're processed.
+pub fn pick_best(inputs: Vec<PathBuf>, window: f64, similarity: f32) -> (Vec<PathBuf>, Vec<Burst>) {
+    let shots: Vec<Shot> = inputs.par_iter().map(|path| measure(path)).collect();
+    let mut groups = UnionFind::new(inputs.len());
+
+    let mut timed: Vec<(f64, usize)> = shots.iter().enumerate().filter_map(|(i, s)| Some((s.time?, i))).collect();
+    timed.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    for pair in timed.windows(2) {
+        if pair[1].0 - pair[0].0 <= window {
+            groups.union(pair[0].1, pair[1].1);
+        }
+    }
+    let max_distance = ((1.0 - similarity) * HASH_BITS as f32).round() as u32;
+    let hashed: Vec<(u64, usize)> = shots.iter().enumerate().filter_map(|(i, s)| Some((s.hash?, i))).collect();
+    for (n, &(a, i)) in hashed.iter().enumerate() {
+        for &(b, j) in &hashed[n + 1..] {
+            if (a ^ b).count_ones() <= max_distance {
+                groups.union(i, j);
+            }
+        }
+    }
+
+    let mut members: Vec<Vec<usize>> = vec![Vec::new(); inputs.len()];
+    for i in 0..inputs.len() {
+        members[groups.find(i)].push(i);
+    }
+    let mut discarded = vec![false; inputs.len()];
+    let mut bursts = Vec::new();
+    for group in members.into_iter().filter(|group| group.len() > 1) {
+        // Undecodable shots neither win nor lose.
+        let decoded: Vec<usize> = group.into_iter().filter(|&i| shots[i].hash.is_some()).collect();
+        if decoded.len() < 2 {
+            continue;
+        }
+        // Ties go to the first path, so reruns keep the same shot.
+        let Some(&best) = decoded.iter().max_by(|&&a, &&b| {
+            shots[a].sharpness.total_cmp(&shots[b].sharpness).then_with(|| inputs[b].cmp(&inputs[a]))
+        }) else {
+            continue;
+        };
+        let others: Vec<usize> = decoded.into_iter().filter(|&i| i != best).collect();
+        for &i in &others {
+            discarded[i] = true;
+        }
+        bursts.push(Burst { kept: inputs[best].clone(), discarded: others.iter().map(|&i| inputs[i].clone()).collect() });
+    }
+    bursts.sort_by(|a, b| a.kept.cmp(&b.kept));
+    let kept = inputs.into_iter().zip(discarded).filter(|(_, discarded)| !discarded).map(|(path, _)| path).collect();
+    (kept, bursts)
+}
+
+fn measure(path: &Path) -> Shot {
+    let time = capture_seconds(path);
+    let Ok(img) = open_image(path) else { return Shot { time, hash: None, sharpness: 0.0 } };
+    let luma = img.thumbnail(SHARPNESS_SIDE, SHARPNESS_SIDE).to_luma8();
+    Shot { time, hash: Some(difference_hash(&luma)), sharpness: laplacian_variance(&luma) }
+}
+
+/// Hashes the image's structure: each bit tells whether a pixel of a 9x8 thumbnail is
+/// brighter than its right neighbor. Similar images differ in few bits.
+fn difference_hash(luma: &GrayImage) -> u64 {
+    let small = image::imageops::resize(luma, 9, 8, FilterType::Triangle);
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash = hash << 1 | (small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0]) as u64;
+        }
+    }
+    hash
+}
+
+/// Disjoint sets of input indices.
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(len: usize) -> Self {
+        UnionFind { parent: (0..len).collect() }
+    }
+
+    fn find(&mut self, i: usize) -> usize {
+        let mut root = i;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut i = i;
+        while self.parent[i] != root {
+            i = std::mem::replace(&mut self.parent[i], root);
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[a.max(b)] = a.min(b);
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use super::sidecar::Operation;
+use anyhow::Result;
+use std::path::Path;
+
+/// Signs a JPEG output with a C2PA (Content Credentials) manifest recording its source and the
+/// operations applied to it, embedded in APP11 segments. `cert` is a PEM certificate chain,
+/// signing certificate first, and `key` the matching PKCS#8 P-256 private key (ES256).
+/// Requires the `c2pa` cargo feature.
+pub fn sign_file(output: &Path, source: &Path, operations: &[Operation], cert: &Path, key: &Path) -> Result<()> {
+    signing::sign_file(output, source, operations, cert, key)
+}
+
+#[cfg(feature = "c2pa")]
+mod signing {
+    use super::Operation;
+    use anyhow::{bail, Context, Result};
+    use ciborium::Value;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::DecodePrivateKey;
+    use sha2::{Digest, Sha256};
+    use std::fs;
+    use std::path::Path;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const GENERATOR: &str = concat!("imagekit/", env!("CARGO_PKG_VERSION"));
+    /// JUMBF content types are the four-character code followed by this ISO suffix.
+    const UUID_SUFFIX: [u8; 12] = [0x00, 0x11, 0x00, 0x10, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+    const APP11: u8 = 0xEB;
+    /// APP11 payload left after the length, JPEG XT header and repeated box header.
+    const MAX_CHUNK: usize = 65533 - 8 - 8;
+    /// COSE algorithm identifier for ECDSA with SHA-256.
+    const ES256: i64 = -7;
+    const X5CHAIN: i64 = 33;
+    const COSE_SIGN1_TAG: u64 = 18;
+
+    pub fn sign_file(output: &Path, source: &Path, operations: &[Operation], cert: &Path, key: &Path) -> Result<()> {
+        if !matches!(extension(output).as_deref(), Some("jpg" | "jpeg")) {
+            bail!("C2PA signing is only supported for JPEG outputs");
+        }
+        let key_pem = fs::read_to_string(key).with_context(|| format!("Failed to read {}", key.display()))?;
+        let key = SigningKey::from_pkcs8_pem(&key_pem).context("Expected a PKCS#8 P-256 private key")?;
+        let chain = read_certificates(cert)?;
+
+        let jpeg = fs::read(output)?;
+        if !jpeg.starts_with(&[0xFF, 0xD8]) {
+            bail!("{} is not a JPEG file", output.display());
+        }
+        let offset = insertion_offset(&jpeg)?;
+        // The manifest is excluded from its own hash, so this is the hash of the final file too.
+        let asset_hash = Sha256::digest(&jpeg).to_vec();
+
+        let manifest = Manifest {
+            label: format!("urn:uuid:{}", new_uuid(&format!("manifest:{}", output.display()))),
+            instance_id: format!("xmp:iid:{}", new_uuid(&format!("output:{}", output.display()))),
+            ingredient_id: format!("xmp:iid:{}", new_uuid(&format!("source:{}", source.display()))),
+            source_title: source.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default(),
+            source_format: mime_type(source),
+            actions: actions(operations, extension(source) != extension(output)),
+        };
+
+        // The exclusion length is part of the manifest, so repeat until its encoding stops growing.
+        let mut length = 0;
+        let segments = loop {
+            let store = manifest.build(&asset_hash, offset, length, &key, &chain)?;
+            let segments = app11_segments(&store);
+            if segments.len() == length {
+                break segments;
+            }
+            length = segments.len();
+        };
+
+        let mut signed = Vec::with_capacity(jpeg.len() + segments.len());
+        signed.extend_from_slice(&jpeg[..offset]);
+        signed.extend_from_slice(&segments);
+        signed.extend_from_slice(&jpeg[offset..]);
+        fs::write(output, signed).with_context(|| format!("Failed to write {}", output.display()))
+    }
+
+    struct Manifest {
+        label: String,
+        instance_id: String,
+        ingredient_id: String,
+        source_title: String,
+        source_format: &'static str,
+        actions: Vec<(&'static str, Option<&'static str>)>,
+    }
+
+    impl Manifest {
+        /// Builds the signed JUMBF manifest store.
+        fn build(&self, asset_hash: &[u8], start: usize, length: usize, key: &SigningKey, chain: &[Vec<u8>]) -> Result<Vec<u8>> {
+            let ingredient = assertion(
+                "c2pa.ingredient",
+                map(vec![
+                    ("dc:title", Value::Text(self.source_title.clone())),
+                    ("dc:format", Value::Text(self.source_format.into())),
+                    ("instanceID", Value::Text(self.ingredient_id.clone())),
+                    ("relationship", Value::Text("parentOf".into())),
+                ]),
+            )?;
+            let ingredient_ref = hashed_uri("c2pa.ingredient", &ingredient);
+
+            let mut actions = vec![map(vec![
+                ("action", Value::Text("c2pa.opened".into())),
+                ("softwareAgent", Value::Text(GENERATOR.into())),
+                ("parameters", map(vec![("ingredient", ingredient_ref)])),
+            ])];
+            for (action, description) in &self.actions {
+                let mut entry = vec![
+                    ("action", Value::Text((*action).into())),
+                    ("softwareAgent", Value::Text(GENERATOR.into())),
+                ];
+                if let Some(description) = description {
+                    entry.push(("parameters", map(vec![("description", Value::Text((*description).into()))])));
+                }
+                actions.push(map(entry));
+            }
+            let actions = assertion("c2pa.actions", map(vec![("actions", Value::Array(actions))]))?;
+
+            let exclusion = map(vec![
+                ("start", Value::Integer(start.into())),
+                ("length", Value::Integer(length.into())),
+            ]);
+            let hash_data = assertion(
+                "c2pa.hash.data",
+                map(vec![
+                    ("exclusions", Value::Array(vec![exclusion])),
+                    ("name", Value::Text("jumbf manifest".into())),
+                    ("alg", Value::Text("sha256".into())),
+                    ("hash", Value::Bytes(asset_hash.to_vec())),
+                    ("pad", Value::Bytes(Vec::new())),
+                ]),
+            )?;
+
+            let claim = cbor(&map(vec![
+                ("claim_generator", Value::Text(GENERATOR.into())),
+                (
+                    "claim_generator_info",
+                    Value::Array(vec![map(vec![
+                        ("name", Value::Text("imagekit".into())),
+                        ("version", Value::Text(env!("CARGO_PKG_VERSION").into())),
+                    ])]),
+                ),
+                ("signature", Value::Text("self#jumbf=c2pa.signature".into())),
+                (
+                    "assertions",
+                    Value::Array(vec![
+                        hashed_uri("c2pa.ingredient", &ingredient),
+                        hashed_uri("c2pa.actions", &actions),
+                        hashed_uri("c2pa.hash.data", &hash_data),
+                    ]),
+                ),
+                ("dc:format", Value::Text("image/jpeg".into())),
+                ("instanceID", Value::Text(self.instance_id.clone())),
+                ("alg", Value::Text("sha256".into())),
+            ]))?;
+            let signature = cose_sign1(&claim, key, chain)?;
+
+            let assertion_store = superbox(b"c2as", "c2pa.assertions", &[ingredient, actions, hash_data]);
+            let claim_box = superbox(b"c2cl", "c2pa.claim", &[jumbf_box(b"cbor", &claim)]);
+            let signature_box = superbox(b"c2cs", "c2pa.signature", &[jumbf_box(b"cbor", &signature)]);
+            let manifest = superbox(b"c2ma", &self.label, &[assertion_store, claim_box, signature_box]);
+            Ok(superbox(b"c2pa", "c2pa", &[manifest]))
+        }
+    }
+
+    /// Maps pipeline steps to C2PA actions, with a description where the action name is generic.
+    fn actions(operations: &[Operation], transcoded: bool) -> Vec<(&'static str, Option<&'static str>)> {
+        let mut actions = Vec::new();
+        for operation in operations {
+            match operation {
+                Operation::Copy | Operation::EmbeddedPreview { .. } | Operation::Page { .. } => {}
+                Operation::Orient { .. } => actions.push(("c2pa.orientation", Some("Applied the EXIF orientation"))),
+                Operation::Rasterize { .. } => actions.push(("c2pa.converted", Some("Rendered a PDF page"))),
+                Operation::LensCorrect { .. } => actions.push(("c2pa.edited", Some("Corrected lens distortion"))),
+                Operation::Perspective { .. } => actions.push(("c2pa.edited", Some("Corrected perspective"))),
+                Operation::Trim { .. } => actions.push(("c2pa.cropped", Some("Trimmed transparent borders"))),
+                Operation::Upscale { .. } => actions.push(("c2pa.resized", Some("Super-resolution upscaling"))),
+                Operation::Resize { .. } => actions.push(("c2pa.resized", None)),
+                Operation::SeamCarve { .. } => actions.push(("c2pa.resized", Some("Content-aware resize by seam carving"))),
+                Operation::PixelScale { .. } => actions.push(("c2pa.resized", Some("Integer nearest-neighbor scaling"))),
+                Operation::NineSlice { .. } => actions.push(("c2pa.resized", Some("Nine-slice scaling"))),
+                Operation::Fill { .. } => {
+                    actions.push(("c2pa.cropped", None));
+                    actions.push(("c2pa.resized", None));
+                }
+                Operation::Adjust(_) => actions.push(("c2pa.color_adjustments", Some("Selective HSL adjustment"))),
+                Operation::Grayscale => actions.push(("c2pa.color_adjustments", Some("Converted to grayscale"))),
+                Operation::Proof => actions.push(("c2pa.edited", Some("Applied proof styling"))),
+                Operation::Overlay { .. } => actions.push(("c2pa.placed", Some("Composited an overlay image"))),
+                Operation::ImageWatermark { .. } => actions.push(("c2pa.edited", Some("Added a visible image watermark"))),
+                Operation::Watermark { .. } => actions.push(("c2pa.edited", Some("Added a visible text watermark"))),
+                Operation::Palette { .. } => actions.push(("c2pa.color_adjustments", Some("Reduced to a fixed palette"))),
+                Operation::SetDpi { .. } => actions.push(("c2pa.edited.metadata", Some("Set print resolution"))),
+                Operation::AutoTag { .. } => actions.push(("c2pa.edited.metadata", Some("Added classifier keywords"))),
+            }
+        }
+        if transcoded {
+            actions.push(("c2pa.transcoded", None));
+        }
+        actions
+    }
+
+    /// Signs the claim as a detached-payload COSE_Sign1 with the certificate chain in the
+    /// protected header.
+    fn cose_sign1(claim: &[u8], key: &SigningKey, chain: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let x5chain = match chain {
+            [single] => Value::Bytes(single.clone()),
+            _ => Value::Array(chain.iter().cloned().map(Value::Bytes).collect()),
+        };
+        let protected = cbor(&Value::Map(vec![
+            (Value::Integer(1.into()), Value::Integer(ES256.into())),
+            (Value::Integer(X5CHAIN.into()), x5chain),
+        ]))?;
+        let to_be_signed = cbor(&Value::Array(vec![
+            Value::Text("Signature1".into()),
+            Value::Bytes(protected.clone()),
+            Value::Bytes(Vec::new()),
+            Value::Bytes(claim.to_vec()),
+        ]))?;
+        let signature: Signature = key.sign(&to_be_signed);
+
+        cbor(&Value::Tag(
+            COSE_SIGN1_TAG,
+            Box::new(Value::Array(vec![
+                Value::Bytes(protected),
+                Value::Map(Vec::new()),
+                Value::Null,
+                Value::Bytes(signature.to_bytes().to_vec()),
+            ])),
+        ))
+    }
+
+    /// Wraps a CBOR assertion in its JUMBF superbox.
+    fn assertion(label: &str, value: Value) -> Result<Vec<u8>> {
+        Ok(superbox(b"cbor", label, &[jumbf_box(b"cbor", &cbor(&value)?)]))
+    }
+
+    /// A reference from the claim to an assertion, hashed over the superbox's contents.
+    fn hashed_uri(label: &str, assertion: &[u8]) -> Value {
+        map(vec![
+            ("url", Value::Text(format!("self#jumbf=c2pa.assertions/{}", label))),
+            ("hash", Value::Bytes(Sha256::digest(&assertion[8..]).to_vec())),
+        ])
+    }
+
+    fn jumbf_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(8 + payload.len());
+        out.extend_from_slice(&((8 + payload.len()) as u32).to_be_bytes());
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// A `jumb` superbox: a description box with the content type and label, then the contents.
+    fn superbox(content_type: &[u8; 4], label: &str, contents: &[Vec<u8>]) -> Vec<u8> {
+        let mut description = content_type.to_vec();
+        description.extend_from_slice(&UUID_SUFFIX);
+        // Toggles: requestable, label present.
+        description.push(0x03);
+        description.extend_from_slice(label.as_bytes());
+        description.push(0);
+
+        let mut payload = jumbf_box(b"jumd", &description);
+        for content in contents {
+            payload.extend_from_slice(content);
+        }
+        jumbf_box(b"jumb", &payload)
+    }
+
+    /// Splits a JUMBF box into JPEG XT APP11 segments, repeating the box header in each.
+    fn app11_segments(store: &[u8]) -> Vec<u8> {
+        let (header, body) = store.split_at(8);
+        let mut out = Vec::new();
+        for (i, chunk) in body.chunks(MAX_CHUNK).enumerate() {
+            let length = 2 + 2 + 2 + 4 + header.len() + chunk.len();
+            out.extend_from_slice(&[0xFF, APP11]);
+            out.extend_from_slice(&(length as u16).to_be_bytes());
+            // Common identifier "JP", box instance 1, then the packet sequence number.
+            out.extend_from_slice(b"JP");
+            out.extend_from_slice(&1u16.to_be_bytes());
+            out.extend_from_slice(&(i as u32 + 1).to_be_bytes());
+            out.extend_from_slice(header);
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    /// Returns the byte offset after SOI and any leading APP0/APP1 (JFIF, EXIF, XMP) segments.
+    fn insertion_offset(jpeg: &[u8]) -> Result<usize> {
+        let mut offset = 2;
+        while let [0xFF, 0xE0 | 0xE1, hi, lo, ..] = jpeg[offset..] {
+            offset += 2 + u16::from_be_bytes([hi, lo]) as usize;
+            if offset > jpeg.len() {
+                bail!("Truncated JPEG segment");
+            }
+        }
+        Ok(offset)
+    }
+
+    fn read_certificates(path: &Path) -> Result<Vec<Vec<u8>>> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        const END: &str = "-----END CERTIFICATE-----";
+        let mut chain = Vec::new();
+        let mut rest = text.as_str();
+        while let Some(start) = rest.find("-----BEGIN CERTIFICATE-----") {
+            let end = rest[start..].find(END).context("Unterminated PEM certificate")? + start + END.len();
+            let (label, der) = pem_rfc7468::decode_vec(&rest.as_bytes()[start..end])
+                .map_err(|e| anyhow::anyhow!("Invalid PEM certificate: {}", e))?;
+            if label == "CERTIFICATE" {
+                chain.push(der);
+            }
+            rest = &rest[end..];
+        }
+        if chain.is_empty() {
+            bail!("No certificates found in {}", path.display());
+        }
+        Ok(chain)
+    }
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(entries.into_iter().map(|(k, v)| (Value::Text(k.into()), v)).collect())
+    }
+
+    fn cbor(value: &Value) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        ciborium::into_writer(value, &mut out)?;
+        Ok(out)
+    }
+
+    /// A random-looking version 4 UUID, unique per call.
+    fn new_uuid(seed: &str) -> String {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+        let mut bytes = *blake3::hash(format!("{}:{}", seed, nanos).as_bytes()).as_bytes();
+        bytes[6] = (bytes[6] & 0x0F) | 0x40;
+        bytes[8] = (bytes[8] & 0x3F) | 0x80;
+        let hex: String = bytes[..16].iter().map(|b| format!("{:02x}", b)).collect();
+        format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+    }
+
+    fn extension(path: &Path) -> Option<String> {
+        path.extension().and_then(|s| s.to_str()).map(str::to_lowercase)
+    }
+
+    fn mime_type(path: &Path) -> &'static str {
+        match extension(path).as_deref() {
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("png") => "image/png",
+            Some("gif") => "image/gif",
+            Some("bmp") => "image/bmp",
+            Some("webp") => "image/webp",
+            Some("tif" | "tiff") => "image/tiff",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+#[cfg(not(feature = "c2pa"))]
+mod signing {
+    use super::Operation;
+    use std::path::Path;
+
+    pub fn sign_file(_: &Path, _: &Path, _: &[Operation], _: &Path, _: &Path) -> anyhow::Result<()> {
+        anyhow::bail!("imagekit was built without the `c2pa` feature")
+    }
+}
@@ -0,0 +1,71 @@
+use super::cli::CbzArgs;
+use super::i18n::Msg;
+use super::processor::{encode_image, EncodeOptions};
+use super::zip::{read_entries, Method, ZipEntry, ZipWriter};
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::ImageFormat;
+use rayon::prelude::*;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufWriter, Cursor};
+use std::path::Path;
+
+/// Rewrites the images inside a comic archive or EPUB, keeping every entry's name and position
+/// so page order and the book's references to its images are unchanged. Other entries,
+/// including the EPUB `mimetype` that must stay first and uncompressed, are copied as they are.
+pub fn run(args: &CbzArgs) -> Result<()> {
+    let bytes = fs::read(&args.input).with_context(|| format!("Failed to read {}", args.input.display()))?;
+    let entries = read_entries(&bytes).with_context(|| format!("Failed to read {}", args.input.display()))?;
+
+    let rewritten: Vec<(ZipEntry, bool)> = entries
+        .into_par_iter()
+        .map(|entry| match optimize(&entry, args) {
+            Ok(Some(data)) => (ZipEntry { data, method: Method::Stored, ..entry }, true),
+            Ok(None) => (entry, false),
+            Err(e) => {
+                eprintln!("{}", Msg::KeptUnchanged { name: &entry.name, error: &format!("{:#}", e) });
+                (entry, false)
+            }
+        })
+        .collect();
+
+    // Written beside the output and renamed into place, so rewriting a book onto itself can't
+    // leave it half-written.
+    let mut staging = OsString::from(args.output.as_os_str());
+    staging.push(".partial");
+    let staging = Path::new(&staging);
+    let file = fs::File::create(staging).with_context(|| format!("Failed to create {}", staging.display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    for (entry, _) in &rewritten {
+        zip.add(&entry.name, &entry.data, entry.method)?;
+    }
+    zip.finish()?;
+    fs::rename(staging, &args.output)?;
+
+    let images = rewritten.iter().filter(|(_, changed)| *changed).count();
+    let after = fs::metadata(&args.output)?.len();
+    println!(
+        "{}",
+        Msg::RewroteEntries { images, entries: rewritten.len(), before: bytes.len() as u64, after }
+    );
+    Ok(())
+}
+
+/// Re-encodes an image entry in its own format, shrunk to `--max-dimension`. Returns `None`
+/// for entries that aren't JPEG, PNG or WebP images, and for images that would only grow.
+fn optimize(entry: &ZipEntry, args: &CbzArgs) -> Result<Option<Vec<u8>>> {
+    let path = Path::new(&entry.name);
+    let format = match ImageFormat::from_path(path) {
+        Ok(format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::WebP)) => format,
+        _ => return Ok(None),
+    };
+    let img = image::load_from_memory_with_format(&entry.data, format)?;
+    let img = match args.max_dimension {
+        Some(max) if img.width().max(img.height()) > max => img.resize(max, max, FilterType::Lanczos3),
+        _ => img,
+    };
+    let mut encoded = Vec::new();
+    encode_image(&img, Cursor::new(&mut encoded), format, args.quality, EncodeOptions::default(), path)?;
+    Ok((encoded.len() < entry.data.len()).then_some(encoded))
+}
@@ -0,0 +1,184 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::path::Path;
+
+/// A CDN whose cache can be invalidated after a run. Each provider is compiled in
+/// through its own cargo feature (`cdn-cloudflare`, `cdn-fastly`, `cdn-cloudfront`).
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum CdnProvider {
+    Cloudflare,
+    Fastly,
+    Cloudfront,
+}
+
+/// Maps an output file to its public URL under `base_url`.
+pub fn public_url(base_url: &str, output_dir: &Path, output_path: &Path) -> Option<String> {
+    let relative = output_path.strip_prefix(output_dir).ok()?;
+    let segments: Vec<String> = relative
+        .components()
+        .map(|c| percent_encode(&c.as_os_str().to_string_lossy()))
+        .collect();
+    Some(format!("{}/{}", base_url.trim_end_matches('/'), segments.join("/")))
+}
+
+pub(crate) fn percent_encode(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Invalidates the given URLs. Credentials are read from the environment:
+/// - Cloudflare: `CLOUDFLARE_API_TOKEN`, `CLOUDFLARE_ZONE_ID`
+/// - Fastly: `FASTLY_API_TOKEN`
+/// - CloudFront: `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, optional `AWS_SESSION_TOKEN`,
+///   and `CLOUDFRONT_DISTRIBUTION_ID`
+pub fn purge(provider: CdnProvider, urls: &[String]) -> Result<()> {
+    if urls.is_empty() {
+        return Ok(());
+    }
+    match provider {
+        CdnProvider::Cloudflare => cloudflare::purge(urls),
+        CdnProvider::Fastly => fastly::purge(urls),
+        CdnProvider::Cloudfront => cloudfront::purge(urls),
+    }
+}
+
+#[cfg(any(feature = "cdn-cloudflare", feature = "cdn-fastly", feature = "cdn-cloudfront"))]
+fn env_var(name: &str) -> Result<String> {
+    use anyhow::Context;
+    std::env::var(name).with_context(|| format!("Environment variable {} must be set to purge the CDN", name))
+}
+
+#[cfg(feature = "cdn-cloudflare")]
+mod cloudflare {
+    use super::env_var;
+    use anyhow::{Context, Result};
+
+    // The purge_cache endpoint accepts at most 30 URLs per request.
+    const BATCH_SIZE: usize = 30;
+
+    pub fn purge(urls: &[String]) -> Result<()> {
+        let token = env_var("CLOUDFLARE_API_TOKEN")?;
+        let zone = env_var("CLOUDFLARE_ZONE_ID")?;
+        let endpoint = format!("https://api.cloudflare.com/client/v4/zones/{}/purge_cache", zone);
+        for batch in urls.chunks(BATCH_SIZE) {
+            let files: Vec<String> = batch.iter().map(|u| format!("\"{}\"", u.replace('\\', "\\\\").replace('"', "\\\""))).collect();
+            ureq::post(&endpoint)
+                .header("Authorization", &format!("Bearer {}", token))
+                .header("Content-Type", "application/json")
+                .send(format!("{{\"files\":[{}]}}", files.join(",")))
+                .context("Cloudflare purge request failed")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "cdn-cloudflare"))]
+mod cloudflare {
+    pub fn purge(_: &[String]) -> anyhow::Result<()> {
+        anyhow::bail!("imagekit was built without the `cdn-cloudflare` feature")
+    }
+}
+
+#[cfg(feature = "cdn-fastly")]
+mod fastly {
+    use super::env_var;
+    use anyhow::{Context, Result};
+
+    pub fn purge(urls: &[String]) -> Result<()> {
+        let token = env_var("FASTLY_API_TOKEN")?;
+        for url in urls {
+            let target = url.split_once("://").map_or(url.as_str(), |(_, rest)| rest);
+            ureq::post(&format!("https://api.fastly.com/purge/{}", target))
+                .header("Fastly-Key", &token)
+                .send_empty()
+                .with_context(|| format!("Fastly purge request failed for {}", url))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "cdn-fastly"))]
+mod fastly {
+    pub fn purge(_: &[String]) -> anyhow::Result<()> {
+        anyhow::bail!("imagekit was built without the `cdn-fastly` feature")
+    }
+}
+
+#[cfg(feature = "cdn-cloudfront")]
+mod cloudfront {
+    use super::env_var;
+    use anyhow::{Context, Result};
+    use aws_credential_types::Credentials;
+    use aws_sigv4::http_request::{sign, SignableBody, SignableRequest, SigningSettings};
+    use aws_sigv4::sign::v4;
+    use std::env;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const HOST: &str = "cloudfront.amazonaws.com";
+    const REGION: &str = "us-east-1";
+
+    /// Creates one invalidation for all paths, signed with AWS Signature Version 4.
+    pub fn purge(urls: &[String]) -> Result<()> {
+        let credentials = Credentials::new(
+            env_var("AWS_ACCESS_KEY_ID")?,
+            env_var("AWS_SECRET_ACCESS_KEY")?,
+            env::var("AWS_SESSION_TOKEN").ok(),
+            None,
+            "environment",
+        );
+        let distribution = env_var("CLOUDFRONT_DISTRIBUTION_ID")?;
+
+        let now = SystemTime::now();
+        let secs = now.duration_since(UNIX_EPOCH)?.as_secs();
+        let paths: String = urls
+            .iter()
+            .map(|u| {
+                let path = u.split_once("://").and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]));
+                format!("<Path>{}</Path>", path.unwrap_or(u).replace('&', "&amp;").replace('<', "&lt;"))
+            })
+            .collect();
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+             <InvalidationBatch xmlns=\"http://cloudfront.amazonaws.com/doc/2020-05-31/\">\
+             <Paths><Quantity>{}</Quantity><Items>{}</Items></Paths>\
+             <CallerReference>imagekit-{}</CallerReference></InvalidationBatch>",
+            urls.len(),
+            paths,
+            secs
+        );
+
+        let url = format!("https://{}/2020-05-31/distribution/{}/invalidation", HOST, distribution);
+        let content_type = ("content-type", "application/xml");
+        let identity = credentials.into();
+        let params = v4::SigningParams::builder()
+            .identity(&identity)
+            .region(REGION)
+            .name("cloudfront")
+            .time(now)
+            .settings(SigningSettings::default())
+            .build()?
+            .into();
+        let signable =
+            SignableRequest::new("POST", &url, [content_type].into_iter(), SignableBody::Bytes(body.as_bytes()))?;
+        let (instructions, _) = sign(signable, &params).context("Failed to sign the CloudFront request")?.into_parts();
+
+        let mut request = ureq::post(&url).header(content_type.0, content_type.1);
+        for (name, value) in instructions.headers() {
+            request = request.header(name, value);
+        }
+        request.send(body).context("CloudFront invalidation request failed")?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "cdn-cloudfront"))]
+mod cloudfront {
+    pub fn purge(_: &[String]) -> anyhow::Result<()> {
+        anyhow::bail!("imagekit was built without the `cdn-cloudfront` feature")
+    }
+}
@@ -0,0 +1,20 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Splits the inputs, in path order, into runs of at most `size` and returns each one's
+/// subfolder: `0001` for the first run, `0002` for the next, and so on. Names are widened past
+/// four digits only when there are more chunks than that.
+///
+/// Every input is assigned, not just those being processed, so filters don't move an image to
+/// another chunk.
+pub fn assign(size: u32, inputs: &[PathBuf]) -> BTreeMap<PathBuf, String> {
+    let mut ordered: Vec<&PathBuf> = inputs.iter().collect();
+    ordered.sort();
+    let chunks = ordered.len().div_ceil(size as usize);
+    let width = chunks.to_string().len().max(4);
+    ordered
+        .into_iter()
+        .enumerate()
+        .map(|(index, path)| (path.clone(), format!("{:0width$}", index / size as usize + 1, width = width)))
+        .collect()
+}
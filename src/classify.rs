@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Class names by labels file, so each is read once per run.
+static LABELS: OnceLock<Mutex<HashMap<PathBuf, Arc<Vec<String>>>>> = OnceLock::new();
+
+/// Runs the ONNX image classifier at `model` on `img`, returning a probability per class. The
+/// model takes one image as a `1x3xHxW` (or `1xHxWx3`) tensor normalized with the ImageNet
+/// mean and deviation, as the common small classifiers do, and returns a score per class,
+/// either probabilities or logits.
+pub fn probabilities(img: &DynamicImage, model: &Path) -> Result<Vec<f32>> {
+    onnx::probabilities(img, model)
+}
+
+/// Reads class names, one per line in the order of a model's outputs.
+pub fn labels(path: &Path) -> Result<Arc<Vec<String>>> {
+    let mut cache = LABELS.get_or_init(Default::default).lock().unwrap();
+    if let Some(labels) = cache.get(path) {
+        return Ok(Arc::clone(labels));
+    }
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read labels {}", path.display()))?;
+    let labels = Arc::new(text.lines().map(|line| line.trim().to_string()).collect());
+    cache.insert(path.to_path_buf(), Arc::clone(&labels));
+    Ok(labels)
+}
+
+#[cfg(feature = "ml")]
+mod onnx {
+    use anyhow::{bail, Context, Result};
+    use image::imageops::FilterType;
+    use image::DynamicImage;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex, OnceLock};
+    use tract_onnx::prelude::*;
+    use tract_onnx::tract_hir::infer::Factoid;
+
+    /// ImageNet channel statistics, which the common small classifiers are trained with.
+    const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+    const STD: [f32; 3] = [0.229, 0.224, 0.225];
+    /// Side of the square input used when a model leaves its input size open.
+    const DEFAULT_INPUT_SIZE: usize = 224;
+
+    /// Loaded classifiers by model path, so each is read and optimized once per run.
+    static CLASSIFIERS: OnceLock<Mutex<HashMap<PathBuf, Arc<Classifier>>>> = OnceLock::new();
+
+    struct Classifier {
+        plan: TypedRunnableModel<TypedModel>,
+        /// Input height and width.
+        size: (usize, usize),
+        /// Whether the input is `1xHxWx3` rather than `1x3xHxW`.
+        channels_last: bool,
+    }
+
+    fn load(path: &Path) -> Result<Arc<Classifier>> {
+        let mut classifiers = CLASSIFIERS.get_or_init(Default::default).lock().unwrap();
+        if let Some(classifier) = classifiers.get(path) {
+            return Ok(Arc::clone(classifier));
+        }
+        let classifier = Arc::new(
+            Classifier::load(path).with_context(|| format!("Failed to load classifier {}", path.display()))?,
+        );
+        classifiers.insert(path.to_path_buf(), Arc::clone(&classifier));
+        Ok(classifier)
+    }
+
+    pub fn probabilities(img: &DynamicImage, model: &Path) -> Result<Vec<f32>> {
+        load(model)?.classify(img)
+    }
+
+    impl Classifier {
+        /// Unlike upscaling, every image is scaled to one input size, so the model is
+        /// optimized once for it.
+        fn load(path: &Path) -> Result<Self> {
+            let model = tract_onnx::onnx().model_for_path(path)?;
+            let fact = model.input_fact(0)?;
+            let dims: Vec<Option<usize>> = if fact.shape.is_open() {
+                vec![Some(1), Some(3), None, None]
+            } else {
+                fact.shape.dims().map(|d| d.concretize().and_then(|d| d.as_i64()).map(|d| d as usize)).collect()
+            };
+            if dims.len() != 4 {
+                bail!("expected a 4-dimensional image input, found {:?}", fact.shape);
+            }
+            let channels_last = dims[3] == Some(3) && dims[1] != Some(3);
+            let (h, w) = if channels_last { (dims[1], dims[2]) } else { (dims[2], dims[3]) };
+            let size = (h.unwrap_or(DEFAULT_INPUT_SIZE), w.unwrap_or(DEFAULT_INPUT_SIZE));
+            let shape = if channels_last { tvec!(1, size.0, size.1, 3) } else { tvec!(1, 3, size.0, size.1) };
+            let plan = model
+                .with_input_fact(0, InferenceFact::dt_shape(f32::datum_type(), shape))?
+                .with_output_fact(0, InferenceFact::default())?
+                .into_optimized()?
+                .into_runnable()?;
+            Ok(Classifier { plan, size, channels_last })
+        }
+
+        fn classify(&self, img: &DynamicImage) -> Result<Vec<f32>> {
+            let (height, width) = self.size;
+            let rgb = img.resize_exact(width as u32, height as u32, FilterType::Triangle).to_rgb8();
+            let value =
+                |x: usize, y: usize, c: usize| (rgb.get_pixel(x as u32, y as u32)[c] as f32 / 255.0 - MEAN[c]) / STD[c];
+            let input: Tensor = if self.channels_last {
+                tract_ndarray::Array4::from_shape_fn((1, height, width, 3), |(_, y, x, c)| value(x, y, c)).into()
+            } else {
+                tract_ndarray::Array4::from_shape_fn((1, 3, height, width), |(_, c, y, x)| value(x, y, c)).into()
+            };
+            let outputs = self.plan.run(tvec!(input.into()))?;
+            let scores = outputs[0].to_array_view::<f32>()?;
+            Ok(normalize(scores.iter().copied().collect()))
+        }
+    }
+
+    /// Keeps outputs that already are probabilities (in 0..1, summing to 1) and turns logits
+    /// into probabilities with a softmax.
+    fn normalize(outputs: Vec<f32>) -> Vec<f32> {
+        let sum: f32 = outputs.iter().sum();
+        if outputs.iter().all(|p| (0.0..=1.0).contains(p)) && (sum - 1.0).abs() < 1e-3 {
+            return outputs;
+        }
+        let max = outputs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let exps: Vec<f32> = outputs.iter().map(|x| (x - max).exp()).collect();
+        let total: f32 = exps.iter().sum();
+        exps.into_iter().map(|e| e / total).collect()
+    }
+}
+
+#[cfg(not(feature = "ml"))]
+mod onnx {
+    use image::DynamicImage;
+    use std::path::Path;
+
+    pub fn probabilities(_: &DynamicImage, _: &Path) -> anyhow::Result<Vec<f32>> {
+        anyhow::bail!("imagekit was built without the `ml` feature")
+    }
+}
@@ -1,12 +1,381 @@
-use super::errors::{ParseColorError, ParseWatermarkPositionError};
-use clap::Parser;
+use super::adjust::Adjustment;
+use super::appicons::Platform;
+use super::assets::FontSource;
+use super::aspect::{Aspect, AspectFilter, DEFAULT_SQUARE_TOLERANCE};
+use super::buckets::{parse_bytes, SizeBuckets};
+use super::cdn::CdnProvider;
+use super::console::ColorChoice;
+use super::encrypt::Recipient;
+use super::errors::{
+    ParseByteSizeError, ParseColorError, ParsePercentageError, ParsePhysicalSizeError, ParsePixelSizeError, ParseWatermarkLayerError,
+    ParseWatermarkPositionError,
+};
+use super::i18n::Lang;
+use super::imposition::{Paper, SheetFormat};
+use super::lens::LensCorrect;
+use super::meta::TagAssignment;
+use super::nineslice::NineSlice;
+use super::order::Order;
+use super::pages::PageSelection;
+use super::palette::Palette;
+use super::perspective::Perspective;
+use super::processor::WatermarkSpec;
+use super::report::GroupKey;
+use super::rules::Rule;
+use super::sample::SampleSize;
+use super::sequence::SequenceTemplate;
+use super::upscale::Upscale;
+use super::zones::Zone;
+use clap::{Args, Parser, Subcommand};
 use image::Rgba;
 use std::path::PathBuf;
 use std::str::FromStr;
 use clap::ValueEnum;
+use image::codecs::png::FilterType;
 use image::ImageFormat;
 
+// The full command line: a batch run over a directory (the default), or a subcommand.
 #[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub struct App {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub batch: Option<Cli>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Process images referenced by a static site's content and emit a variant manifest
+    Ssg(SsgArgs),
+    /// Edit EXIF metadata without decoding or re-encoding pixel data
+    Meta(MetaArgs),
+    /// Lay images out N-up on print-ready pages with crop marks
+    Imposition(ImpositionArgs),
+    /// Compare two images, printing a similarity score and optionally a highlighted difference image
+    Diff(DiffArgs),
+    /// Check codecs, fonts, output directory permissions and the thread pool
+    Doctor(DoctorArgs),
+    /// Render a watermark onto a checkerboard or sample image to try out its styling
+    PreviewWatermark(PreviewWatermarkArgs),
+    /// Generate the iOS and Android app icon and splash screen sets from one square icon
+    #[command(name = "appicons")]
+    AppIcons(AppIconsArgs),
+    /// Recompress and shrink the images inside a CBZ comic or EPUB book
+    Cbz(CbzArgs),
+    /// Stitch overlapping shots, taken left to right and named in that order, into a panorama
+    Stitch(StitchArgs),
+    /// Fuse bracketed exposures into one image each, then process the results like a batch run
+    HdrMerge(Box<HdrMergeArgs>),
+    /// Merge shots focused at different depths, named in any order, into one sharp composite
+    FocusStack(FocusStackArgs),
+    /// Turn a folder of interval shots into numbered frames in capture order, or a video
+    Timelapse(TimelapseArgs),
+    /// Run as an AWS Lambda function: process the S3 objects each event names and upload the outputs
+    Lambda(Box<LambdaArgs>),
+    /// Process jobs popped from a Redis queue and publish each one's result
+    Worker(WorkerArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct SsgArgs {
+    #[arg(long, default_value = "content", help = "Directory of Markdown/HTML pages to scan for image references")]
+    pub content_dir: PathBuf,
+
+    #[arg(long, default_value = "static", help = "Directory served at the site root; absolute references resolve here")]
+    pub static_dir: PathBuf,
+
+    #[arg(short, long)]
+    pub output_dir: PathBuf,
+
+    #[arg(long, value_delimiter = ',', default_value = "480,800,1200")]
+    pub widths: Vec<u32>,
+
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "webp,jpg")]
+    pub formats: Vec<OutputFormat>,
+
+    #[arg(short, long, default_value_t = 85, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+
+    #[arg(long, help = "Path of the generated manifest (.json or .toml)")]
+    pub manifest: PathBuf,
+
+    #[arg(long, help = "URL prefix for generated variants [default: derived from the output dir's location under the static dir]")]
+    pub url_prefix: Option<String>,
+
+    #[arg(long, help = "Also write a <picture> HTML snippet per image into this directory")]
+    pub snippets_dir: Option<PathBuf>,
+
+    #[arg(long, default_value = "100vw", help = "Value of the `sizes` attribute in generated snippets")]
+    pub sizes: String,
+}
+
+#[derive(Args, Debug)]
+pub struct MetaArgs {
+    #[arg(short, long)]
+    pub input_dir: PathBuf,
+
+    #[arg(short, long)]
+    pub output_dir: PathBuf,
+
+    #[arg(long, help = "Remove all EXIF GPS tags")]
+    pub strip_gps: bool,
+
+    #[arg(long = "set", value_name = "TAG=VALUE", help = "Set an EXIF text tag, e.g. 'Copyright=ACME' (repeatable)")]
+    pub set: Vec<TagAssignment>,
+}
+
+#[derive(Args, Debug)]
+pub struct ImpositionArgs {
+    #[arg(short, long)]
+    pub input_dir: PathBuf,
+
+    #[arg(short, long)]
+    pub output_dir: PathBuf,
+
+    #[arg(long, value_enum, default_value_t = Paper::A4)]
+    pub paper: Paper,
+
+    #[arg(long, help = "Use the paper in landscape orientation")]
+    pub landscape: bool,
+
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..=64), help = "Number of images per page")]
+    pub per_page: u32,
+
+    #[arg(long, default_value_t = 10.0, value_name = "MM", help = "Blank border around the page edge")]
+    pub margin: f32,
+
+    #[arg(long, default_value_t = 8.0, value_name = "MM", help = "Space between images, which also holds the crop marks")]
+    pub gutter: f32,
+
+    #[arg(long, help = "Leave out crop marks")]
+    pub no_crop_marks: bool,
+
+    #[arg(long, default_value_t = 300, value_parser = clap::value_parser!(u32).range(1..=1200), help = "Resolution the pages are rendered at")]
+    pub dpi: u32,
+
+    #[arg(long, value_enum, default_value_t = SheetFormat::Pdf, help = "Write one PDF with all pages, or one PNG per page")]
+    pub format: SheetFormat,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    /// The reference image
+    pub a: PathBuf,
+
+    /// The image to compare against the reference
+    pub b: PathBuf,
+
+    #[arg(short, long, help = "Write the highlighted difference image here")]
+    pub output: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 0, help = "Channel difference a pixel must exceed to count as changed")]
+    pub threshold: u8,
+
+    #[arg(long, value_name = "SSIM", help = "Exit with an error if the SSIM score is below this value")]
+    pub fail_under: Option<f64>,
+}
+
+#[derive(Args, Debug)]
+pub struct DoctorArgs {
+    #[arg(short, long, help = "Also check that this output directory can be written")]
+    pub output_dir: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct PreviewWatermarkArgs {
+    #[arg(long)]
+    pub text: String,
+
+    #[arg(long, default_value = "1200x800", help = "Size of the checkerboard, or of the sample image it is resized to")]
+    pub size: PixelSize,
+
+    #[arg(long, help = "Draw on this image instead of a checkerboard")]
+    pub sample: Option<PathBuf>,
+
+    #[arg(short, long, default_value = "watermark-preview.png")]
+    pub output: PathBuf,
+
+    #[arg(long, default_value_t = WatermarkPosition::Se, help="[possible values: nw, north, ne, west, center, east, sw, south, se]")]
+    pub position: WatermarkPosition,
+
+    #[arg(long, default_value_t = 24)]
+    pub font_size: u32,
+
+    #[arg(long = "font", value_name = "PATH", help = "Font to try before the embedded fonts, as with the batch run's --font (repeatable)")]
+    pub fonts: Vec<PathBuf>,
+
+    #[arg(long = "fonts", value_delimiter = ',', conflicts_with = "fonts", value_name = "CHAIN", help = "Complete font fallback chain, as with the batch run's --fonts")]
+    pub font_chain: Vec<FontSource>,
+
+    #[arg(long, default_value_t = HexColor(Rgba([255, 255, 255, 128])))]
+    pub color: HexColor,
+
+    #[arg(long, value_name = "PERCENT", help = "Font size as a fraction of the image width, e.g. 5% (overrides --font-size)")]
+    pub relative_size: Option<Percentage>,
+
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_name = "PIXELS")]
+    pub letter_spacing: f32,
+
+    #[arg(long, default_value_t = 1.0, value_name = "MULTIPLIER")]
+    pub line_height: f32,
+
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_name = "DEGREES")]
+    pub arc: f32,
+
+    #[arg(long, value_enum, default_value_t = TextAlign::Left)]
+    pub align: TextAlign,
+
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_name = "DEGREES")]
+    pub angle: f32,
+}
+
+#[derive(Args, Debug)]
+pub struct AppIconsArgs {
+    #[arg(short, long, help = "Square icon artwork, ideally 1024x1024 or larger")]
+    pub input: PathBuf,
+
+    #[arg(short, long, default_value = "appicons")]
+    pub output_dir: PathBuf,
+
+    #[arg(long = "platform", value_enum, value_delimiter = ',', default_value = "ios,android")]
+    pub platforms: Vec<Platform>,
+
+    #[arg(long, default_value_t = HexColor(Rgba([255, 255, 255, 255])), help = "Splash screen and adaptive icon background; also fills transparency in iOS icons")]
+    pub background: HexColor,
+}
+
+#[derive(Args, Debug)]
+pub struct CbzArgs {
+    #[arg(short, long, help = "CBZ or EPUB file to optimize")]
+    pub input: PathBuf,
+
+    #[arg(short, long, help = "Where to write the optimized archive; may be the input itself")]
+    pub output: PathBuf,
+
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..), help = "Shrink pages whose longest edge exceeds this")]
+    pub max_dimension: Option<u32>,
+
+    #[arg(short, long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+}
+
+#[derive(Args, Debug)]
+pub struct StitchArgs {
+    #[arg(short, long, help = "Directory of the shots, stitched in file name order")]
+    pub input_dir: PathBuf,
+
+    #[arg(short, long, help = "Where to write the panorama; its extension picks the format")]
+    pub output: PathBuf,
+
+    #[arg(short, long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+}
+
+#[derive(Args, Debug)]
+pub struct FocusStackArgs {
+    #[arg(short, long, help = "Directory of the shots; the first in file name order sets the framing")]
+    pub input_dir: PathBuf,
+
+    #[arg(short, long, help = "Where to write the composite; its extension picks the format")]
+    pub output: PathBuf,
+
+    #[arg(short, long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+
+    #[arg(long, help = "Don't scale and shift the shots onto the first, for shots from a tripod with a lens that doesn't breathe")]
+    pub no_align: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TimelapseArgs {
+    #[arg(short, long, help = "Directory of the frames, ordered by EXIF capture time")]
+    pub input_dir: PathBuf,
+
+    #[arg(short, long, help = "Directory to write frame_00001.jpg, frame_00002.jpg, ... to")]
+    pub output_dir: PathBuf,
+
+    #[arg(long, value_enum, default_value = "jpg", help = "Format of the frames")]
+    pub frame_format: OutputFormat,
+
+    #[arg(short, long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), help = "Scale the frames to this width, e.g. 1920 for HD video")]
+    pub width: Option<u32>,
+
+    #[arg(long, help = "Even out the brightness of the frames, which flickers as the camera's exposure steps")]
+    pub deflicker: bool,
+
+    #[arg(long, default_value_t = 15, value_name = "FRAMES", requires = "deflicker", value_parser = clap::value_parser!(u32).range(2..), help = "Frames the --deflicker brightness is averaged over; longer follows slow changes such as sunset less closely")]
+    pub deflicker_window: u32,
+
+    #[arg(long, help = "Stamp each frame with its EXIF capture time")]
+    pub stamp: bool,
+
+    #[arg(long, default_value_t = WatermarkPosition::Sw, requires = "stamp", help="[possible values: nw, north, ne, west, center, east, sw, south, se]")]
+    pub stamp_position: WatermarkPosition,
+
+    #[arg(long, default_value = "3%", requires = "stamp", value_name = "PERCENT", help = "Font size of the --stamp as a fraction of the frame width")]
+    pub stamp_size: Percentage,
+
+    #[arg(long, default_value_t = HexColor(Rgba([255, 255, 255, 255])), requires = "stamp")]
+    pub stamp_color: HexColor,
+
+    #[arg(long, value_name = "PATH", help = "Also encode the frames into this video, e.g. timelapse.mp4, with ffmpeg (needs the `ffmpeg` feature and ffmpeg on the PATH)")]
+    pub video: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 24, requires = "video", value_parser = clap::value_parser!(u32).range(1..))]
+    pub fps: u32,
+}
+
+#[derive(Args, Debug)]
+pub struct HdrMergeArgs {
+    #[arg(long, default_value_t = 2.0, value_name = "SECONDS", help = "Longest gap between two shots of one bracket, by their EXIF capture time")]
+    pub bracket_window: f64,
+
+    #[arg(long, value_name = "SHOTS", value_parser = clap::value_parser!(u32).range(2..), help = "Shots per bracket, for cameras whose brackets can't be told apart by exposure bias or time")]
+    pub bracket_size: Option<u32>,
+
+    #[command(flatten)]
+    pub batch: Cli,
+}
+
+#[derive(Args, Debug)]
+pub struct LambdaArgs {
+    #[arg(long, value_name = "BUCKET", help = "Bucket to upload outputs to [default: the bucket of each source object]")]
+    pub output_bucket: Option<String>,
+
+    #[arg(long, default_value = "processed/", value_name = "PREFIX", help = "Key prefix for uploaded outputs; objects under it in the source bucket are skipped, so outputs written back don't trigger more work")]
+    pub output_prefix: String,
+
+    #[command(flatten)]
+    pub batch: Cli,
+}
+
+#[derive(Args, Debug)]
+pub struct WorkerArgs {
+    #[arg(long, value_name = "URL", help = "Redis to take jobs from, as redis://[[user]:password@]host[:port][/db]")]
+    pub queue: String,
+
+    #[arg(long, default_value = "imagekit:jobs", value_name = "KEY", help = "List jobs are popped from")]
+    pub jobs_key: String,
+
+    #[arg(long, default_value = "imagekit:results", value_name = "KEY", help = "List each job's result is pushed to")]
+    pub results_key: String,
+
+    #[arg(long, value_name = "ID", help = "Names the list <jobs-key>:processing:<ID> holding the job in progress, requeued when a worker with this id restarts [default: the host name]")]
+    pub worker_id: Option<String>,
+
+    #[arg(long, value_name = "FILE", help = "TOML file of options for every job, like a --config file; a job's own options take precedence")]
+    pub defaults: Option<PathBuf>,
+
+    #[arg(long, value_name = "N", help = "Exit after this many jobs [default: run until stopped]")]
+    pub max_jobs: Option<u64>,
+}
+
+#[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
     #[arg(short, long)]
@@ -15,29 +384,412 @@ pub struct Cli {
     #[arg(short, long)]
     pub output_dir: PathBuf,
 
+    #[arg(long, value_name = "FILE", help = "Read options from a TOML file, e.g. 'quality = 80'; options on the command line take precedence")]
+    pub config: Option<PathBuf>,
+
     #[arg(long)]
     pub width: Option<u32>,
 
     #[arg(long)]
     pub height: Option<u32>,
 
+    #[arg(long, value_name = "PIXELS", conflicts_with_all = ["width", "height", "physical_size", "width_landscape", "height_landscape", "width_portrait", "height_portrait"], value_parser = clap::value_parser!(u32).range(1..), help = "Shrink images whose longest edge exceeds this, keeping the aspect ratio; smaller images keep their size")]
+    pub max_dimension: Option<u32>,
+
+    #[arg(long, value_name = "PIXELS,...", value_delimiter = ',', conflicts_with_all = ["width", "height", "max_dimension", "physical_size", "width_landscape", "height_landscape", "width_portrait", "height_portrait", "sync", "baseline", "bucket_by_size"], value_parser = clap::value_parser!(u32).range(1..), help = "Write each image at every one of these widths, e.g. '480,800,1200', named like photo-800w.jpg; the source is decoded once")]
+    pub widths: Vec<u32>,
+
+    #[arg(long, help = "Keep pixels as stored instead of turning them upright by the EXIF Orientation tag; outputs carry no orientation tag unless --keep-metadata copies it")]
+    pub no_auto_orient: bool,
+
+    #[arg(long, value_enum, value_name = "MODE", help = "Undo lens distortion and color fringing with the lensfun profile of the lens named in each image's EXIF data, at its focal length; images of unknown lenses are left as they are")]
+    pub lens_correct: Option<LensCorrect>,
+
+    #[arg(long, value_name = "PATH", requires = "lens_correct", help = "Lensfun database to read profiles from, a directory of XML files or one file; defaults to where lensfun installs it")]
+    pub lens_db: Option<PathBuf>,
+
+    #[arg(long, value_name = "CORNERS", help = "Straighten a quadrilateral, such as a whiteboard or receipt shot at an angle, into a rectangle: its four corners as 'x1,y1 x2,y2 x3,y3 x4,y4' in pixels of the upright image, in any order, or 'auto' to find a document in each image")]
+    pub perspective: Option<Perspective>,
+
+    #[arg(long, help = "Crop away fully transparent borders before resizing")]
+    pub trim_transparent: bool,
+
+    #[arg(long, value_name = "L,T,R,B", conflicts_with_all = ["physical_size", "max_dimension"], help = "Resize UI assets by stretching only what lies between these insets, keeping the corners intact; a missing --width or --height keeps that side")]
+    pub nine_slice: Option<NineSlice>,
+
+    #[arg(long, conflicts_with_all = ["physical_size", "max_dimension", "nine_slice"], help = "Scale pixel art only by whole factors with nearest-neighbor sampling, padding with transparency to reach --width/--height")]
+    pub pixel_perfect: bool,
+
+    #[arg(long, value_enum, default_value_t = ResizeMode::Stretch, conflicts_with_all = ["physical_size", "pixel_perfect", "nine_slice"], help = "How --width and --height together change the aspect ratio")]
+    pub resize_mode: ResizeMode,
+
+    #[arg(long, value_name = "ai:FACTORx", requires = "upscale_model", conflicts_with_all = ["pixel_perfect", "nine_slice"], help = "Enlarge images with a super-resolution model when the target is larger than the source, e.g. 'ai:4x', then resize the result (needs the `upscale-ai` feature)")]
+    pub upscale: Option<Upscale>,
+
+    #[arg(long, value_name = "PATH", requires = "upscale", help = "ONNX model for --upscale, taking and returning RGB as 1x3xHxW tensors of values in 0..1")]
+    pub upscale_model: Option<PathBuf>,
+
+    #[arg(long, value_name = "PIXELS", conflicts_with = "physical_size", help = "Width for landscape and square images, overriding --width/--height for them")]
+    pub width_landscape: Option<u32>,
+
+    #[arg(long, value_name = "PIXELS", conflicts_with = "physical_size", help = "Height for landscape and square images, overriding --width/--height for them")]
+    pub height_landscape: Option<u32>,
+
+    #[arg(long, value_name = "PIXELS", conflicts_with = "physical_size", help = "Width for portrait images, overriding --width/--height for them")]
+    pub width_portrait: Option<u32>,
+
+    #[arg(long, value_name = "PIXELS", conflicts_with = "physical_size", help = "Height for portrait images, overriding --width/--height for them")]
+    pub height_portrait: Option<u32>,
+
     #[arg(long)]
     pub watermark_text: Option<String>,
 
     #[arg(long, default_value_t = WatermarkPosition::Se, help="[possible values: nw, north, ne, west, center, east, sw, south, se]")]
     pub watermark_position: WatermarkPosition,
 
+    #[arg(long, value_name = "PATH", help = "Stamp this image, e.g. a PNG logo with transparency, at --watermark-position")]
+    pub watermark_image: Option<PathBuf>,
+
+    #[arg(long, default_value = "20%", requires = "watermark_image", value_name = "PERCENT", help = "Width of the --watermark-image as a fraction of each image's width")]
+    pub watermark_scale: Percentage,
+
+    #[arg(long, value_name = "X,Y,WxH", help = "Keep watermarks off this area of the source image, e.g. a product label, moving them to the nearest clear position (repeatable; per-image zones can be listed in <image>.avoid)")]
+    pub watermark_avoid: Vec<Zone>,
+
+    #[arg(long, value_name = "DIR", help = "Composite the image with the same relative path and name in this directory on top, e.g. a frame (falls back to _default.png there)")]
+    pub overlay_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = WatermarkPosition::Center, requires = "overlay_dir", help = "Where overlays are placed [possible values: nw, north, ne, west, center, east, sw, south, se]")]
+    pub overlay_position: WatermarkPosition,
+
+    #[arg(long, default_value = "100%", requires = "overlay_dir", value_name = "PERCENT", help = "Fit overlays within this fraction of the image's width and height, keeping their aspect ratio")]
+    pub overlay_scale: Percentage,
+
     #[arg(long, default_value_t = 24)]
     pub font_size: u32,
 
+    #[arg(long = "font", value_name = "PATH", help = "TrueType or OpenType font for watermark text, tried before the embedded fonts (repeatable, first match wins for each character)")]
+    pub fonts: Vec<PathBuf>,
+
+    #[arg(long = "fonts", value_delimiter = ',', conflicts_with = "fonts", value_name = "CHAIN", help = "Complete font fallback chain for watermark text, in order, replacing the embedded one: font files and embedded:roboto, embedded:source-han-sans or embedded:noto-sans-thai, e.g. 'NotoSansKR.otf,embedded:roboto'")]
+    pub font_chain: Vec<FontSource>,
+
     #[arg(long, default_value_t = HexColor(Rgba([255, 255, 255, 128])))]
     pub watermark_color: HexColor,
 
+    #[arg(long, value_name = "PERCENT", help = "Font size as a fraction of each image's width, e.g. 5% (overrides --font-size)")]
+    pub watermark_relative_size: Option<Percentage>,
+
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_name = "PIXELS", help = "Extra space between watermark characters, in pixels at the requested font size")]
+    pub watermark_letter_spacing: f32,
+
+    #[arg(long, default_value_t = 1.0, value_name = "MULTIPLIER", help = "Distance between lines of multi-line watermark text, relative to the font's line height")]
+    pub watermark_line_height: f32,
+
+    #[arg(long, value_enum, default_value_t = TextAlign::Left, help = "How the lines of multi-line watermark text line up; lines break at newlines and at a typed '\\n' (type '\\\\n' for a backslash-n)")]
+    pub watermark_align: TextAlign,
+
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_name = "DEGREES", help = "Bend the watermark text along an arc spanning this many degrees (negative curves downward)")]
+    pub watermark_arc: f32,
+
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true, value_name = "DEGREES", help = "Turn the watermark text by this many degrees counterclockwise, e.g. 45 for a diagonal banner with --watermark-position center (negative turns clockwise)")]
+    pub watermark_angle: f32,
+
+    #[arg(long, value_name = "PIXELS", help = "Shift each watermark by a random amount, up to this many pixels each way")]
+    pub watermark_random_offset: Option<u32>,
+
+    #[arg(long, requires = "watermark_random_offset", help = "Pick a different random shift for every image instead of one per run")]
+    pub watermark_random_per_image: bool,
+
+    #[arg(long, default_value_t = 0, help = "Seed for the random watermark shifts and proof borders; the same seed gives the same result")]
+    pub watermark_seed: u64,
+
+    #[arg(long = "watermark", value_name = "TEXT[@POSITION][;STYLE=VALUE]...", help = "Add a watermark with its own position and styles, e.g. 'CONFIDENTIAL@center;size=96;color=#ff000080' (repeatable; styles it doesn't set come from the --watermark-* options)")]
+    pub watermarks: Vec<WatermarkLayer>,
+
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=65535), help = "Print resolution stored in the output (JFIF density, PNG pHYs or EXIF)")]
+    pub dpi: Option<u32>,
+
+    #[arg(long, value_name = "SIZE", conflicts_with_all = ["width", "height"], help = "Crop and resize to a print size such as 4x6in, 10x15cm or 100x150mm at --dpi (default 300)")]
+    pub physical_size: Option<PhysicalSize>,
+
     #[arg(short, long, default_value_t = 85, value_parser = clap::value_parser!(u8).range(1..=100))]
     pub quality: u8,
 
     #[arg(long, value_enum, help = "Specify the output image format")]
     pub output_format: Option<OutputFormat>,
+
+    #[arg(long, value_enum, help = "Fill in settings for a common job; options given explicitly take precedence")]
+    pub preset: Option<Preset>,
+
+    #[arg(long, value_name = "SIZE", help = "Lower the quality in steps and re-encode until all outputs together fit this size, e.g. '18MB'")]
+    pub total_size: Option<ByteSize>,
+
+    #[arg(long, value_name = "PATH", help = "Also pack the outputs into this ZIP archive")]
+    pub zip: Option<PathBuf>,
+
+    #[arg(long = "rule", value_name = "RULE", help = "Conditional override, e.g. 'width > 2000 => width=1600' (repeatable, first match wins)")]
+    pub rules: Vec<Rule>,
+
+    #[arg(long, help = "Convert images to grayscale (saved as single-channel JPEG/PNG)")]
+    pub grayscale: bool,
+
+    #[arg(long, help = "Style outputs as client proofs: desaturated, with a diagonal PROOF band and a patterned border unique to each image")]
+    pub proof_style: bool,
+
+    #[arg(long, value_name = "COLORS", help = "Reduce outputs to these RRGGBB colors, e.g. '000000,ffffff,ff0000' for an e-ink panel")]
+    pub palette: Option<Palette>,
+
+    #[arg(long, value_enum, default_value_t = Dither::None, requires = "palette", help = "How --palette reduces colors")]
+    pub dither: Dither,
+
+    #[arg(long = "adjust", value_name = "ADJUSTMENT", help = "Selective HSL adjustment, e.g. 'hue:reds:-10' or 'sat:blues:+20' (repeatable, applied in order)")]
+    pub adjustments: Vec<Adjustment>,
+
+    #[arg(long, help = "Copy EXIF data (capture date, camera, copyright, ...), the ICC profile and XMP from each source into its JPEG, PNG or WebP output")]
+    pub keep_metadata: bool,
+
+    #[arg(long, conflicts_with_all = ["keep_metadata", "auto_tag", "c2pa_cert"], help = "Make sure no EXIF data, XMP, ICC profile, comment or text chunk ends up in any output, e.g. to publish photos others sent in; WebP outputs lose their --dpi, which they keep in EXIF")]
+    pub strip_metadata: bool,
+
+    #[arg(long = "require-keyword", value_name = "KEYWORD", help = "Only process images tagged with this IPTC/XMP keyword (repeatable)")]
+    pub require_keywords: Vec<String>,
+
+    #[arg(long = "exclude-keyword", value_name = "KEYWORD", help = "Skip images tagged with this IPTC/XMP keyword (repeatable)")]
+    pub exclude_keywords: Vec<String>,
+
+    #[arg(long, value_name = "STARS", value_parser = clap::value_parser!(u8).range(0..=5), help = "Only process images rated at least this many stars (0-5) in their XMP metadata, e.g. Lightroom picks; unrated images count as 0 and rejected ones are always skipped")]
+    pub min_rating: Option<u8>,
+
+    #[arg(long, help = "Only process images flagged in their XMP metadata: with a color label (xmp:Label) or marked good (xmpDM:good)")]
+    pub only_flagged: bool,
+
+    #[arg(long, value_name = "ASPECT[,TOLERANCE]", help = "Only process landscape, portrait or square images (repeatable); a tolerance on 'square' sets how close to 1:1 counts as square, e.g. 'square,5%' (default 2%)")]
+    pub only_aspect: Vec<AspectFilter>,
+
+    #[arg(long, value_name = "SCORE", help = "Skip images whose focus score (variance of the Laplacian; sharp photos usually score in the hundreds, blurry ones in the tens) is below this")]
+    pub min_sharpness: Option<f64>,
+
+    #[arg(long, value_name = "DIR", requires = "min_sharpness", help = "Copy the sources --min-sharpness skips into this directory, keeping their relative paths, for review")]
+    pub quarantine_dir: Option<PathBuf>,
+
+    #[arg(long, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "2", help = "Group burst shots, taken at most this many seconds apart (default 2) by their EXIF time or looking nearly the same, and process only the sharpest of each group")]
+    pub pick_best_of_burst: Option<f64>,
+
+    #[arg(long, default_value = "90%", requires = "pick_best_of_burst", value_name = "PERCENT", help = "How alike two images' perceptual hashes must be for --pick-best-of-burst to group them regardless of time")]
+    pub burst_similarity: Percentage,
+
+    #[arg(long, value_name = "COUNT|PERCENT", conflicts_with_all = ["sync", "prune", "prune_dry_run"], help = "Process only a random sample of the images, e.g. '100' or '5%', to try settings before a full run")]
+    pub sample: Option<SampleSize>,
+
+    #[arg(long, default_value_t = 0, requires = "sample", help = "Seed for --sample; the same seed picks the same images")]
+    pub sample_seed: u64,
+
+    #[arg(long, value_name = "ORDER", help = "Process, log and report images in a fixed order: name, size, mtime or random(<seed>)")]
+    pub order: Option<Order>,
+
+    #[arg(long, value_enum, requires = "purge_base_url", help = "Invalidate changed outputs on this CDN after the run")]
+    pub purge_cdn: Option<CdnProvider>,
+
+    #[arg(long, value_name = "URL", help = "Public URL that the output directory is served from")]
+    pub purge_base_url: Option<String>,
+
+    #[arg(long = "referenced-by", value_name = "GLOB", help = "Only process images referenced from Markdown/HTML files matching this glob (repeatable)")]
+    pub referenced_by: Vec<String>,
+
+    #[arg(long, requires = "referenced_by", help = "Update references in the scanned pages when an output's extension changes")]
+    pub rewrite_links: bool,
+
+    #[arg(long, help = "Delete image files in the output directory that no input maps to")]
+    pub prune: bool,
+
+    #[arg(long, conflicts_with = "prune", help = "List the files --prune would delete without deleting them")]
+    pub prune_dry_run: bool,
+
+    #[arg(long, help = "Print each image's outputs and the steps leading to them, without decoding or writing anything")]
+    pub dry_run: bool,
+
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, help = "Color console output: auto (terminals only, honors NO_COLOR), always or never")]
+    pub color: ColorChoice,
+
+    #[arg(long, value_enum, help = "Language of console messages [default: from LC_ALL/LC_MESSAGES/LANG]")]
+    pub lang: Option<Lang>,
+
+    #[arg(long, help = "Print neither the progress bar nor a line per image; failures, warnings and the summary still appear")]
+    pub quiet: bool,
+
+    #[arg(long, help = "Move files that would be overwritten or deleted to the OS trash instead")]
+    pub use_trash: bool,
+
+    #[arg(long, help = "List the files that will be overwritten or deleted and ask before continuing")]
+    pub confirm: bool,
+
+    #[arg(long, requires = "confirm", help = "Answer yes to the --confirm prompt, e.g. in scripts")]
+    pub yes: bool,
+
+    #[arg(long, value_enum, default_value_t = IfExists::Overwrite, value_name = "POLICY", conflicts_with = "sync", help = "What to do when an output already exists: overwrite it, skip the image, write a numbered name beside it, or abort the run")]
+    pub if_exists: IfExists,
+
+    #[arg(long, help = "Keep the output directory in sync: skip unchanged inputs, rename outputs of moved inputs and delete outputs of removed ones")]
+    pub sync: bool,
+
+    #[arg(long, value_name = "PEM", requires = "c2pa_key", help = "Sign JPEG outputs with a C2PA manifest using this certificate chain (needs the `c2pa` feature)")]
+    pub c2pa_cert: Option<PathBuf>,
+
+    #[arg(long, value_name = "PEM", requires = "c2pa_cert", help = "PKCS#8 P-256 private key for --c2pa-cert")]
+    pub c2pa_key: Option<PathBuf>,
+
+    #[arg(long, help = "Write provenance (source hash, operations, encoder settings, timings) to <output>.json next to each output")]
+    pub sidecar_json: bool,
+
+    #[arg(long, value_name = "PATH", conflicts_with = "sidecar_json", help = "Collect the provenance records into one zstd-compressed JSON Lines file instead, e.g. outputs.meta.zst")]
+    pub sidecar_bundle: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", help = "Write a JSON report of the run: per-image status and summary counts")]
+    pub report: Option<PathBuf>,
+
+    #[arg(long, value_name = "REPORT", conflicts_with = "sync", help = "Skip images whose content and settings match this earlier --report and whose output still exists")]
+    pub baseline: Option<PathBuf>,
+
+    #[arg(long, conflicts_with_all = ["sync", "baseline"], help = "Skip images whose outputs all exist and are newer than the source; changed settings are not noticed")]
+    pub skip_up_to_date: bool,
+
+    #[arg(long = "group-by", value_name = "KEY", help = "Break the summary down by camera, lens or date: exif.Make, exif.Model, exif.LensModel, exif.Date, ... (repeatable)")]
+    pub group_by: Vec<GroupKey>,
+
+    #[arg(long, requires = "report", help = "Include each image's GPS coordinates in the --report")]
+    pub emit_geo: bool,
+
+    #[arg(long, value_name = "MODEL", requires = "auto_tag_labels", help = "Label images with this ONNX image classifier, writing the labels as XMP keywords in JPEG and PNG outputs and into --report (needs the `ml` feature)")]
+    pub auto_tag: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", requires = "auto_tag", help = "Class names for --auto-tag, one per line in the order of the model's outputs")]
+    pub auto_tag_labels: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 5, requires = "auto_tag", value_parser = clap::value_parser!(u32).range(1..), help = "Most labels --auto-tag gives an image")]
+    pub auto_tag_max: u32,
+
+    #[arg(long, default_value = "20%", requires = "auto_tag", value_name = "PERCENT", help = "Least confidence for an --auto-tag label")]
+    pub auto_tag_min_score: Percentage,
+
+    #[arg(long, value_enum, requires_all = ["safety_model", "safety_labels"], help = "Screen images with --safety-model, holding back (block) or marking (flag) those whose unsafe classes score at least --safety-threshold; decisions go into --report (needs the `ml` feature)")]
+    pub safety_filter: Option<SafetyMode>,
+
+    #[arg(long, value_name = "MODEL", requires = "safety_filter", help = "ONNX image classifier for --safety-filter")]
+    pub safety_model: Option<PathBuf>,
+
+    #[arg(long, value_name = "PATH", requires = "safety_filter", help = "Class names for --safety-model, one per line in the order of the model's outputs")]
+    pub safety_labels: Option<PathBuf>,
+
+    #[arg(long, value_name = "LABEL,...", value_delimiter = ',', default_value = "nsfw,porn,hentai,sexy", requires = "safety_filter", help = "Classes of --safety-labels that count as unsafe; their probabilities are added up")]
+    pub safety_classes: Vec<String>,
+
+    #[arg(long, default_value = "50%", requires = "safety_filter", value_name = "PERCENT", help = "Unsafe score from which --safety-filter acts")]
+    pub safety_threshold: Percentage,
+
+    #[arg(long, value_enum, default_value_t = PngFilter::Adaptive, help = "Row filter for PNG output; adaptive usually compresses photos best")]
+    pub png_filter: PngFilter,
+
+    #[arg(long, help = "Write Adam7-interlaced PNGs, which display progressively while loading")]
+    pub png_interlace: bool,
+
+    #[arg(long, value_enum, default_value_t = TiffCompression::Lzw, help = "Compression for TIFF output; deflate is usually smaller, LZW more widely supported")]
+    pub tiff_compression: TiffCompression,
+
+    #[arg(long, help = "Read inputs through memory maps and write each output in one preallocated piece, which is faster for large files on fast disks; inputs must not change during the run")]
+    pub fast_io: bool,
+
+    #[arg(long, value_name = "age:RECIPIENT", help = "Encrypt each output to this age recipient, writing <output>.age (repeatable; needs the `encrypt` feature)")]
+    pub encrypt: Vec<Recipient>,
+
+    #[arg(long, help = "Start from the embedded EXIF preview when it covers the target size, and read TIFF-based RAW files (DNG, CR2, NEF, ARW, ...) through it")]
+    pub fast_thumbs: bool,
+
+    #[arg(long, value_name = "DPI", value_parser = clap::value_parser!(u32).range(1..=2400), help = "Also take PDF inputs, rendering each page at this resolution and processing it like an image; pages of multi-page PDFs are written as <name>_p<page> (needs the `pdf` feature and the Pdfium library)")]
+    pub pdf_dpi: Option<u32>,
+
+    #[arg(long, value_name = "PAGES", help = "Only process these pages of multi-page TIFFs and PDFs, counting from 1, e.g. '1-5,9' or '3-'; each page is written as <name>_p<page>")]
+    pub pages: Option<PageSelection>,
+
+    #[arg(long, value_enum, default_value_t = RawJpegPolicy::PreferJpeg, help = "With --fast-thumbs, what to do with a RAW file that has a JPEG of the same name next to it: process only one of them, or both (the RAW's output gets a '-raw' suffix)")]
+    pub raw_jpeg_policy: RawJpegPolicy,
+
+    #[arg(long, value_name = "TEMPLATE", help = "Rename outputs in EXIF capture order, e.g. 'IMG_{seq:04}' ({stem} is the original name)")]
+    pub sequence_rename: Option<SequenceTemplate>,
+
+    #[arg(long, value_name = "BUCKETS", help = "Sort outputs into subfolders by encoded size, e.g. 'small:<100KB,medium:<500KB,large'")]
+    pub bucket_by_size: Option<SizeBuckets>,
+
+    #[arg(long, value_name = "FILES", value_parser = clap::value_parser!(u32).range(1..), help = "Spread outputs over numbered subfolders (0001, 0002, ...) of at most this many images each, filled in name order")]
+    pub chunk: Option<u32>,
+}
+
+impl Cli {
+    /// Returns the watermark layers the options describe, in drawing order: `--watermark-text`,
+    /// then each `--watermark`, styled by the `--watermark-*` options where it sets no style of
+    /// its own.
+    pub fn watermark_specs(&self) -> Vec<WatermarkSpec> {
+        let styled = |text: &str, position: WatermarkPosition| WatermarkSpec {
+            text: text.to_string(),
+            position,
+            font_size: self.font_size,
+            relative_size: self.watermark_relative_size,
+            color: self.watermark_color,
+            letter_spacing: self.watermark_letter_spacing,
+            line_height: self.watermark_line_height,
+            align: self.watermark_align,
+            arc: self.watermark_arc,
+            angle: self.watermark_angle,
+            ..WatermarkSpec::default()
+        };
+        let primary = self.watermark_text.as_ref().map(|text| styled(text, self.watermark_position));
+        let repeated = self.watermarks.iter().map(|layer| {
+            let mut spec = styled(&layer.text, layer.position.unwrap_or(self.watermark_position));
+            for (key, value) in &layer.style {
+                // Parsing the option checked these already.
+                let _ = spec.set_style(key, value);
+            }
+            spec
+        });
+        primary.into_iter().chain(repeated).collect()
+    }
+
+    /// Fills in the settings of `--preset` that weren't given explicitly.
+    pub fn apply_preset(&mut self) {
+        match self.preset {
+            Some(Preset::Email) => {
+                let sized = self.width.is_some()
+                    || self.height.is_some()
+                    || self.physical_size.is_some()
+                    || self.max_dimension.is_some()
+                    || self.width_landscape.is_some()
+                    || self.height_landscape.is_some()
+                    || self.width_portrait.is_some()
+                    || self.height_portrait.is_some();
+                if !sized {
+                    self.max_dimension = Some(EMAIL_MAX_DIMENSION);
+                }
+                self.output_format.get_or_insert(OutputFormat::Jpg);
+                self.total_size.get_or_insert(ByteSize(EMAIL_TOTAL_SIZE));
+                if self.zip.is_none() {
+                    self.zip = Some(self.output_dir.join("attachments.zip"));
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Returns how close to 1:1 counts as square, for both `--only-aspect` and the report: the
+    /// tolerance given with the first `square` filter, or the default.
+    pub fn square_tolerance(&self) -> f32 {
+        let square = self.only_aspect.iter().find(|f| f.aspect == Aspect::Square);
+        square.map_or(DEFAULT_SQUARE_TOLERANCE, |f| f.tolerance)
+    }
+
+    /// Returns the print resolution to embed, if any. A physical size implies 300 DPI.
+    pub fn effective_dpi(&self) -> Option<u32> {
+        self.dpi.or(self.physical_size.map(|_| 300))
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -73,11 +825,144 @@ impl std::fmt::Display for HexColor {
     }
 }
 
+/// A fraction in `(0, 1]`, written either as a percentage (`5%`) or a plain fraction (`0.05`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Percentage(pub f32);
+
+impl FromStr for Percentage {
+    type Err = ParsePercentageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePercentageError(s.to_string());
+        let value = match s.trim().strip_suffix('%') {
+            Some(percent) => percent.trim().parse::<f32>().map_err(|_| err())? / 100.0,
+            None => s.trim().parse::<f32>().map_err(|_| err())?,
+        };
+        if value > 0.0 && value <= 1.0 {
+            Ok(Percentage(value))
+        } else {
+            Err(err())
+        }
+    }
+}
+
+impl std::fmt::Display for Percentage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}%", self.0 * 100.0)
+    }
+}
+
+/// A print size such as `4x6in`, `10x15cm` or `100x150mm`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicalSize {
+    /// Width in inches.
+    pub width: f32,
+    /// Height in inches.
+    pub height: f32,
+}
+
+impl PhysicalSize {
+    /// Returns the pixel dimensions at the given resolution, swapping the sides if needed so
+    /// the print has the same orientation as the image.
+    pub fn pixels(&self, dpi: u32, landscape: bool) -> (u32, u32) {
+        let to_px = |inches: f32| ((inches * dpi as f32).round() as u32).max(1);
+        let (w, h) = (to_px(self.width), to_px(self.height));
+        if landscape == (w >= h) { (w, h) } else { (h, w) }
+    }
+}
+
+impl FromStr for PhysicalSize {
+    type Err = ParsePhysicalSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePhysicalSizeError(s.to_string());
+        let s = s.trim().to_lowercase();
+        let (dims, per_inch) = [("in", 1.0), ("cm", 2.54), ("mm", 25.4)]
+            .iter()
+            .find_map(|(unit, per_inch)| s.strip_suffix(unit).map(|d| (d, *per_inch)))
+            .ok_or_else(err)?;
+        let (w, h) = dims.split_once('x').ok_or_else(err)?;
+        let w: f32 = w.trim().parse().map_err(|_| err())?;
+        let h: f32 = h.trim().parse().map_err(|_| err())?;
+        if !(w > 0.0 && h > 0.0 && w.is_finite() && h.is_finite()) {
+            return Err(err());
+        }
+        Ok(PhysicalSize { width: w / per_inch, height: h / per_inch })
+    }
+}
+
+/// Image dimensions in pixels, written `1200x800`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PixelSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for PixelSize {
+    type Err = ParsePixelSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePixelSizeError(s.to_string());
+        let s = s.trim().to_lowercase();
+        let (w, h) = s.split_once('x').ok_or_else(err)?;
+        let width: u32 = w.trim().parse().map_err(|_| err())?;
+        let height: u32 = h.trim().parse().map_err(|_| err())?;
+        if width == 0 || height == 0 {
+            return Err(err());
+        }
+        Ok(PixelSize { width, height })
+    }
+}
+
+/// A `--watermark` value: text with an optional `@position` suffix, then optional `;key=value`
+/// styles such as `;size=96;color=#ff000080` (see [`WatermarkSpec::set_style`]). A suffix that
+/// is not a position or a style is kept as part of the text, so `info@acme.com` stays intact.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WatermarkLayer {
+    pub text: String,
+    pub position: Option<WatermarkPosition>,
+    /// Styles set for this layer alone, in the order given; the rest come from the options.
+    pub style: Vec<(String, String)>,
+}
+
+impl FromStr for WatermarkLayer {
+    type Err = ParseWatermarkLayerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        let mut style = Vec::new();
+        // Styles are taken from the end, so a `;` earlier on stays part of the text.
+        while let Some((head, last)) = rest.rsplit_once(';') {
+            let is_style = |(key, _): &(&str, &str)| WatermarkSpec::STYLE_KEYS.contains(&key.replace('_', "-").as_str());
+            let Some((key, value)) = last.split_once('=').filter(is_style) else {
+                break;
+            };
+            WatermarkSpec::default()
+                .set_style(key, value)
+                .map_err(|e| ParseWatermarkLayerError(s.to_string(), e.to_string()))?;
+            style.insert(0, (key.to_string(), value.to_string()));
+            rest = head;
+        }
+        if let Some((text, suffix)) = rest.rsplit_once('@') {
+            if let Ok(position) = suffix.parse() {
+                return Ok(WatermarkLayer { text: text.to_string(), position: Some(position), style });
+            }
+        }
+        Ok(WatermarkLayer { text: rest.to_string(), position: None, style })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum WatermarkPosition {
     Nw, North, Ne, West, Center, East, Sw, South, Se,
 }
 
+impl WatermarkPosition {
+    pub const ALL: [WatermarkPosition; 9] = [
+        Self::Nw, Self::North, Self::Ne, Self::West, Self::Center, Self::East, Self::Sw, Self::South, Self::Se,
+    ];
+}
+
 impl FromStr for WatermarkPosition {
     type Err = ParseWatermarkPositionError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -96,23 +981,170 @@ impl std::fmt::Display for WatermarkPosition {
     }
 }
 
-#[derive(Clone, Debug, ValueEnum)]
+/// How the lines of multi-line watermark text line up with each other.
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum TextAlign {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+impl std::fmt::Display for TextAlign {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", format!("{:?}", self).to_lowercase())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, ValueEnum)]
 pub enum OutputFormat {
     Jpg,
     Png,
     Webp,
     Gif,
     Bmp,
+    Qoi,
+    /// 16-bit RGBA with a tiny header.
+    Farbfeld,
+    /// Bare 8-bit RGBA pixels, described by a `.layout.json` file next to them.
+    Raw,
+    /// Lossless, compressed with `--tiff-compression`.
+    Tiff,
+    /// PNG for screenshots, text, flat graphics and transparency, JPEG for photos, per image.
+    Auto,
 }
 
-impl From<OutputFormat> for ImageFormat {
-    fn from(format: OutputFormat) -> Self {
-        match format {
-            OutputFormat::Jpg => ImageFormat::Jpeg,
-            OutputFormat::Png => ImageFormat::Png,
-            OutputFormat::Webp => ImageFormat::WebP,
-            OutputFormat::Gif => ImageFormat::Gif,
-            OutputFormat::Bmp => ImageFormat::Bmp,
+impl OutputFormat {
+    /// Returns the image format to encode with, or `None` for raw pixel dumps. `auto` is
+    /// decided once the image is decoded; until then it is JPEG, the choice for photos.
+    pub fn image_format(&self) -> Option<ImageFormat> {
+        match self {
+            OutputFormat::Jpg => Some(ImageFormat::Jpeg),
+            OutputFormat::Png => Some(ImageFormat::Png),
+            OutputFormat::Webp => Some(ImageFormat::WebP),
+            OutputFormat::Gif => Some(ImageFormat::Gif),
+            OutputFormat::Bmp => Some(ImageFormat::Bmp),
+            OutputFormat::Qoi => Some(ImageFormat::Qoi),
+            OutputFormat::Farbfeld => Some(ImageFormat::Farbfeld),
+            OutputFormat::Tiff => Some(ImageFormat::Tiff),
+            OutputFormat::Raw => None,
+            OutputFormat::Auto => Some(ImageFormat::Jpeg),
         }
     }
 }
+
+/// A bundle of settings selected with `--preset`.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Preset {
+    /// Shrink for email: JPEG, longest edge 1600px, 18MB in total, zipped.
+    Email,
+}
+
+/// Longest edge of `--preset email` outputs.
+const EMAIL_MAX_DIMENSION: u32 = 1600;
+/// Total size of `--preset email` outputs. Base64 makes attachments a third larger, so this
+/// stays under the common 25MB limit.
+const EMAIL_TOTAL_SIZE: u64 = 18_000_000;
+
+/// A byte count such as `18MB`, `500KB` or `2GiB`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = ParseByteSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_bytes(s).map(ByteSize).ok_or_else(|| ParseByteSizeError(s.to_string()))
+    }
+}
+
+/// How a resize to both `--width` and `--height` changes the aspect ratio.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ResizeMode {
+    /// Scale each axis independently.
+    Stretch,
+    /// Scale to cover the target, then remove the least noticeable seams of pixels.
+    SeamCarve,
+}
+
+/// What `--if-exists` does when an output is already there.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum IfExists {
+    /// Replace it.
+    Overwrite,
+    /// Leave it, and the image, alone.
+    Skip,
+    /// Keep it and write the new output beside it as `photo-1.jpg`, `photo-2.jpg`, ...
+    Rename,
+    /// Abort the run before anything is written.
+    Error,
+}
+
+/// What `--safety-filter` does with images the model finds unsafe.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum SafetyMode {
+    /// Write no output for them.
+    Block,
+    /// Process them as usual and mark them in the log and report.
+    Flag,
+}
+
+/// How `--palette` reduces colors.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Dither {
+    /// Use the nearest palette color.
+    None,
+    /// Diffuse the error to neighboring pixels.
+    FloydSteinberg,
+}
+
+impl Dither {
+    pub fn name(self) -> &'static str {
+        match self {
+            Dither::None => "none",
+            Dither::FloydSteinberg => "floyd-steinberg",
+        }
+    }
+}
+
+/// PNG row filter. `adaptive` picks the best filter for each row.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    Adaptive,
+}
+
+impl From<PngFilter> for FilterType {
+    fn from(filter: PngFilter) -> Self {
+        match filter {
+            PngFilter::None => FilterType::NoFilter,
+            PngFilter::Sub => FilterType::Sub,
+            PngFilter::Up => FilterType::Up,
+            PngFilter::Avg => FilterType::Avg,
+            PngFilter::Paeth => FilterType::Paeth,
+            PngFilter::Adaptive => FilterType::Adaptive,
+        }
+    }
+}
+
+/// Lossless compression for TIFF outputs.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    Deflate,
+}
+
+/// Which of a RAW+JPEG pair `--raw-jpeg-policy` processes.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum RawJpegPolicy {
+    /// The RAW file, read through its embedded preview.
+    PreferRaw,
+    /// The camera's JPEG, which is full size, whereas a RAW's preview may be smaller.
+    PreferJpeg,
+    Both,
+}
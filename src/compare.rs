@@ -0,0 +1,144 @@
+use super::cli::DiffArgs;
+use super::decode::open_image;
+use super::i18n::Msg;
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, GenericImageView, GrayImage, Rgba, RgbaImage};
+
+/// SSIM stabilizing constants for 8-bit data: (0.01 * 255)^2 and (0.03 * 255)^2.
+const C1: f64 = 6.5025;
+const C2: f64 = 58.5225;
+/// SSIM is computed over square windows of this size, overlapping by half.
+const WINDOW: u32 = 8;
+
+/// How far apart two images are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiffStats {
+    /// Structural similarity of the luma channels, 1.0 for identical images.
+    pub ssim: f64,
+    /// Pixels whose largest channel difference exceeds the threshold.
+    pub differing_pixels: u64,
+    pub total_pixels: u64,
+    /// Largest difference in any channel of any pixel.
+    pub max_delta: u8,
+}
+
+impl DiffStats {
+    /// Fraction of pixels that differ, from 0 to 1.
+    pub fn differing_ratio(&self) -> f64 {
+        if self.total_pixels == 0 { 0.0 } else { self.differing_pixels as f64 / self.total_pixels as f64 }
+    }
+}
+
+/// Compares two images and prints the score, optionally writing a highlighted difference image.
+pub fn run(args: &DiffArgs) -> Result<()> {
+    let a = open_image(&args.a).with_context(|| format!("Failed to open {}", args.a.display()))?;
+    let b = open_image(&args.b).with_context(|| format!("Failed to open {}", args.b.display()))?;
+    let (visual, stats) = diff(&a, &b, args.threshold)?;
+
+    println!("SSIM: {:.6}", stats.ssim);
+    println!(
+        "{}",
+        Msg::DifferingPixels {
+            differing: stats.differing_pixels,
+            total: stats.total_pixels,
+            percent: stats.differing_ratio() * 100.0,
+            max_delta: stats.max_delta,
+        }
+    );
+    if let Some(output) = &args.output {
+        visual.save(output).with_context(|| format!("Failed to save {}", output.display()))?;
+        println!("{}", Msg::SavedDiff(output));
+    }
+    if let Some(min) = args.fail_under {
+        if stats.ssim < min {
+            bail!("SSIM {:.6} is below the required {}", stats.ssim, min);
+        }
+    }
+    Ok(())
+}
+
+/// Returns a visualization of the differences between two same-sized images, and their
+/// statistics. The first image is shown faded to gray, with every pixel that differs by more
+/// than `threshold` in some channel painted red, brighter for larger differences.
+pub fn diff(a: &DynamicImage, b: &DynamicImage, threshold: u8) -> Result<(RgbaImage, DiffStats)> {
+    if a.dimensions() != b.dimensions() {
+        bail!("Images differ in size: {:?} vs {:?}", a.dimensions(), b.dimensions());
+    }
+    let (pa, pb) = (a.to_rgba8(), b.to_rgba8());
+    let mut visual = RgbaImage::new(a.width(), a.height());
+    let mut differing_pixels = 0;
+    let mut max_delta = 0;
+
+    for ((x, y, p), q) in pa.enumerate_pixels().zip(pb.pixels()) {
+        let delta = p.0.iter().zip(q.0.iter()).map(|(c, d)| c.abs_diff(*d)).max().unwrap_or(0);
+        max_delta = max_delta.max(delta);
+        let pixel = if delta > threshold {
+            differing_pixels += 1;
+            Rgba([128 + delta / 2, 0, 0, 255])
+        } else {
+            let luma = (0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) as u8;
+            let faded = 255 - (255 - luma) / 4;
+            Rgba([faded, faded, faded, 255])
+        };
+        visual.put_pixel(x, y, pixel);
+    }
+
+    let stats = DiffStats {
+        ssim: ssim(&a.to_luma8(), &b.to_luma8()),
+        differing_pixels,
+        total_pixels: a.width() as u64 * a.height() as u64,
+        max_delta,
+    };
+    Ok((visual, stats))
+}
+
+/// Computes the mean structural similarity of two same-sized grayscale images.
+pub fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    let (width, height) = a.dimensions();
+    if width == 0 || height == 0 {
+        return 1.0;
+    }
+    let (win_w, win_h) = (WINDOW.min(width), WINDOW.min(height));
+    let step = (WINDOW / 2).max(1);
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    let mut y = 0;
+    loop {
+        let mut x = 0;
+        loop {
+            total += window_ssim(a, b, x, y, win_w, win_h);
+            windows += 1;
+            if x + win_w >= width {
+                break;
+            }
+            x = (x + step).min(width - win_w);
+        }
+        if y + win_h >= height {
+            break;
+        }
+        y = (y + step).min(height - win_h);
+    }
+    total / windows as f64
+}
+
+fn window_ssim(a: &GrayImage, b: &GrayImage, x0: u32, y0: u32, w: u32, h: u32) -> f64 {
+    let n = (w * h) as f64;
+    let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for y in y0..y0 + h {
+        for x in x0..x0 + w {
+            let (va, vb) = (a.get_pixel(x, y)[0] as f64, b.get_pixel(x, y)[0] as f64);
+            sum_a += va;
+            sum_b += vb;
+            sum_aa += va * va;
+            sum_bb += vb * vb;
+            sum_ab += va * vb;
+        }
+    }
+    let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+    let var_a = sum_aa / n - mean_a * mean_a;
+    let var_b = sum_bb / n - mean_b * mean_b;
+    let covariance = sum_ab / n - mean_a * mean_b;
+    ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+        / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+}
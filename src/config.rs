@@ -0,0 +1,156 @@
+use super::cli::Cli;
+use super::processor::WatermarkSpec;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::parser::ValueSource;
+use clap::CommandFactory;
+use std::ffi::OsString;
+use std::fs;
+use std::path::PathBuf;
+
+/// Expands `--config <file>` in a batch command line into the options the TOML file sets, and
+/// returns them along with the watermark layers of its `[[watermark_layer]]` tables.
+///
+/// Keys are the long option names, with `_` or `-` (`watermark_text = "© ACME"`). Flags take
+/// `true` or `false`, and repeatable options an array. Options given on the command line take
+/// precedence; for repeatable ones, the command line replaces the file's list rather than
+/// adding to it. Without `--config` the arguments are returned unchanged.
+pub fn expand_args(args: Vec<OsString>) -> Result<(Vec<OsString>, Vec<WatermarkSpec>)> {
+    let Some(path) = config_path(&args) else {
+        return Ok((args, Vec::new()));
+    };
+    let text = fs::read_to_string(&path).with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let mut table: toml::Table =
+        text.parse().with_context(|| format!("Failed to parse config file {}", path.display()))?;
+    let source = path.display().to_string();
+    let layers = match table.remove("watermark_layer") {
+        Some(layers) => watermark_layers(&layers, &source)?,
+        None => Vec::new(),
+    };
+
+    // Only which options were given matters here; the full parse reports any errors.
+    let given = Cli::command().ignore_errors(true).get_matches_from(&args);
+    let from_file =
+        table_args(&table, |id| given.value_source(id) == Some(ValueSource::CommandLine), &source)?;
+
+    let mut args = args.into_iter();
+    Ok((args.next().into_iter().chain(from_file).chain(args).collect(), layers))
+}
+
+/// Reads the `[[watermark_layer]]` tables of a config file, each a layer drawn after those of the
+/// options:
+///
+/// ```toml
+/// [[watermark_layer]]
+/// kind = "image"          # or "text"; implied by which of `image` and `text` is given
+/// image = "logo.png"
+/// position = "nw"
+/// scale = "15%"
+///
+/// [[watermark_layer]]
+/// text = "CONFIDENTIAL"
+/// tile = true
+/// angle = 30
+/// color = "#ff000040"
+/// ```
+///
+/// A layer's style keys are those of [`WatermarkSpec::set_style`]; the ones it leaves out take
+/// the built-in defaults, not the `--watermark-*` options.
+fn watermark_layers(value: &toml::Value, source: &str) -> Result<Vec<WatermarkSpec>> {
+    let toml::Value::Array(tables) = value else {
+        bail!("'watermark_layer' in {} must be written as [[watermark_layer]] tables", source);
+    };
+    tables
+        .iter()
+        .enumerate()
+        .map(|(index, table)| {
+            let layer = format!("watermark layer {} in {}", index + 1, source);
+            let table = table.as_table().ok_or_else(|| anyhow!("The {} is not a table", layer))?;
+            watermark_layer(table).with_context(|| format!("Invalid {}", layer))
+        })
+        .collect()
+}
+
+fn watermark_layer(table: &toml::Table) -> Result<WatermarkSpec> {
+    let mut spec = WatermarkSpec::default();
+    let mut kind = None;
+    for (key, value) in table {
+        let text = match value {
+            // An offset may be written as an array, `offset = [10, -5]`.
+            toml::Value::Array(items) => items.iter().map(scalar).collect::<Result<Vec<_>>>()?.join(","),
+            value => scalar(value)?,
+        };
+        match key.replace('_', "-").as_str() {
+            "kind" => kind = Some(text),
+            "text" => spec.text = text,
+            "image" => spec.image = Some(PathBuf::from(text)),
+            "position" => spec.position = text.parse()?,
+            "tile" => spec.tile = text.parse().map_err(|_| anyhow!("'tile' must be true or false"))?,
+            _ => spec.set_style(key, &text)?,
+        }
+    }
+    match (kind.as_deref(), spec.image.is_some(), spec.text.is_empty()) {
+        (None | Some("text"), false, false) | (None | Some("image"), true, true) => Ok(spec),
+        (Some("text" | "image") | None, _, _) => bail!("A layer needs either 'text' or 'image', matching its kind"),
+        (Some(kind), _, _) => bail!("Unknown kind '{}'; expected text or image", kind),
+    }
+}
+
+/// Turns a table of batch options, keyed by long option name, into command-line arguments,
+/// leaving out those `given` reports as set already. `source` names the table in errors.
+pub fn table_args(table: &toml::Table, given: impl Fn(&str) -> bool, source: &str) -> Result<Vec<OsString>> {
+    let command = Cli::command();
+    let mut args: Vec<OsString> = Vec::new();
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && long != "config")
+            .ok_or_else(|| anyhow!("Unknown option '{}' in {}", key, source))?;
+        if given(arg.get_id().as_str()) {
+            continue;
+        }
+        let values = match value {
+            toml::Value::Boolean(true) => {
+                args.push(format!("--{}", long).into());
+                continue;
+            }
+            toml::Value::Boolean(false) => continue,
+            toml::Value::Array(items) => items.iter().collect(),
+            value => vec![value],
+        };
+        for value in values {
+            let value = scalar(value).with_context(|| format!("Invalid value for '{}' in {}", key, source))?;
+            // `--name=value` keeps values such as `-30` from being read as options.
+            args.push(format!("--{}={}", long, value).into());
+        }
+    }
+    Ok(args)
+}
+
+/// Finds the value of `--config` without parsing the rest, which may still lack required options.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1).map(|a| a.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(|path| PathBuf::from(path.as_ref()));
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+fn scalar(value: &toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(n) => n.to_string(),
+        toml::Value::Float(x) => x.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        toml::Value::Datetime(d) => d.to_string(),
+        toml::Value::Array(_) | toml::Value::Table(_) => bail!("expected a string, number or boolean"),
+    })
+}
@@ -0,0 +1,108 @@
+use super::cli::{Cli, IfExists};
+use super::console::warn;
+use super::context::RunContext;
+use super::i18n::Msg;
+use super::processor::output_candidates;
+use super::prune::find_orphans;
+use super::rules::Pipeline;
+use anyhow::{bail, Result};
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+
+/// How many paths of each kind are listed before the rest are summarized.
+const LISTED: usize = 20;
+
+/// Existing files a run is about to destroy.
+#[derive(Debug, Default, PartialEq)]
+pub struct Destruction {
+    /// Input files whose output path is the input itself.
+    pub originals: Vec<PathBuf>,
+    /// Earlier outputs that will be replaced.
+    pub overwritten: Vec<PathBuf>,
+    /// Orphaned outputs `--prune` will delete.
+    pub pruned: Vec<PathBuf>,
+}
+
+impl Destruction {
+    pub fn is_empty(&self) -> bool {
+        self.originals.is_empty() && self.overwritten.is_empty() && self.pruned.is_empty()
+    }
+}
+
+/// Works out which existing files processing `inputs` would overwrite or delete. Orphans are
+/// found relative to `all_inputs`, the inputs before any filtering.
+pub fn destruction(cli: &Cli, context: &RunContext, inputs: &[PathBuf], all_inputs: &[PathBuf]) -> Result<Destruction> {
+    let mut found = Destruction::default();
+    // Under any other `--if-exists` policy, existing outputs are left in place.
+    let inputs = if cli.if_exists == IfExists::Overwrite { inputs } else { &[] };
+    for input in inputs {
+        let pipeline = Pipeline::for_path(cli, input)?;
+        if pipeline.skip {
+            continue;
+        }
+        // An output is replaced wherever it was, even if it changes bucket or format.
+        for output in output_candidates(input, cli, context, &pipeline)?.into_iter().filter(|p| p.exists()) {
+            if fs::canonicalize(&output).ok() == fs::canonicalize(input).ok() {
+                found.originals.push(input.clone());
+            } else {
+                found.overwritten.push(output);
+            }
+        }
+    }
+    if cli.prune {
+        found.pruned = find_orphans(cli, context, all_inputs)?;
+    }
+    found.originals.sort();
+    found.overwritten.sort();
+    Ok(found)
+}
+
+/// Lists what the run will destroy and, with `--confirm`, asks before going ahead. Without
+/// `--confirm`, only overwriting originals is reported, as a warning.
+pub fn confirm(cli: &Cli, context: &RunContext, inputs: &[PathBuf], all_inputs: &[PathBuf]) -> Result<()> {
+    if !cli.confirm {
+        let originals = destruction(cli, context, inputs, all_inputs)?.originals.len();
+        if originals > 0 {
+            warn(Msg::OverwriteWarning(originals));
+        }
+        return Ok(());
+    }
+
+    let found = destruction(cli, context, inputs, all_inputs)?;
+    if found.is_empty() {
+        return Ok(());
+    }
+    print_list(Msg::OriginalsHeading, &found.originals);
+    print_list(Msg::OverwrittenHeading, &found.overwritten);
+    print_list(Msg::PrunedHeading, &found.pruned);
+    if cli.yes {
+        return Ok(());
+    }
+
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        bail!("Refusing to continue without confirmation; pass --yes to proceed non-interactively");
+    }
+    print!("{}", Msg::ContinuePrompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    stdin.lock().read_line(&mut answer)?;
+    if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes" | "是") {
+        bail!("Aborted; no files were changed");
+    }
+    Ok(())
+}
+
+fn print_list(heading: Msg, paths: &[PathBuf]) {
+    if paths.is_empty() {
+        return;
+    }
+    println!("{}", heading);
+    for path in paths.iter().take(LISTED) {
+        println!("  {}", path.display());
+    }
+    if paths.len() > LISTED {
+        println!("{}", Msg::AndMore(paths.len() - LISTED));
+    }
+}
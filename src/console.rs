@@ -0,0 +1,256 @@
+use super::i18n::{format_bytes, Msg};
+use super::processor::Processed;
+use super::report::Outcome;
+use super::safety::SafetyCheck;
+use clap::ValueEnum;
+use indicatif::{ProgressBar, ProgressState, ProgressStyle};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// When to color console output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color when writing to a terminal, unless `NO_COLOR` is set or `TERM=dumb`.
+    Auto,
+    Always,
+    Never,
+}
+
+static STDOUT_COLOR: AtomicBool = AtomicBool::new(false);
+static STDERR_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Decides, once per run, whether stdout and stderr get colored.
+pub fn init(choice: ColorChoice) {
+    let enabled = |terminal: bool| match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            terminal
+                && env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && env::var("TERM").map_or(true, |t| t != "dumb")
+        }
+    };
+    STDOUT_COLOR.store(enabled(io::stdout().is_terminal()), Ordering::Relaxed);
+    STDERR_COLOR.store(enabled(io::stderr().is_terminal()), Ordering::Relaxed);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Red,
+    Green,
+    Yellow,
+    Dim,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Wraps `text` in ANSI color codes if colors are enabled for the stream.
+pub fn paint(text: &str, style: Style, stream: Stream) -> String {
+    let enabled = match stream {
+        Stream::Stdout => STDOUT_COLOR.load(Ordering::Relaxed),
+        Stream::Stderr => STDERR_COLOR.load(Ordering::Relaxed),
+    };
+    if !enabled {
+        return text.to_string();
+    }
+    let code = match style {
+        Style::Red => "31",
+        Style::Green => "32",
+        Style::Yellow => "33",
+        Style::Dim => "2",
+    };
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+/// Prints a warning to stderr in yellow.
+pub fn warn(msg: Msg) {
+    eprintln!("{}", paint(&msg.to_string(), Style::Yellow, Stream::Stderr));
+}
+
+/// Prints the before/after size summary, green when the outputs are smaller.
+pub fn print_size_summary(images: usize, input_bytes: u64, output_bytes: u64) {
+    let text = Msg::SizeSummary { images, input_bytes, output_bytes }.to_string();
+    let style = if output_bytes <= input_bytes { Style::Green } else { Style::Yellow };
+    println!("{}", paint(&text, style, Stream::Stdout));
+}
+
+/// A progress bar on stderr for a run's images: how many are done, how fast, and the time
+/// left. It is hidden when stderr isn't a terminal, so logs get the per-image lines alone.
+pub struct Progress(ProgressBar);
+
+impl Progress {
+    pub fn new(total: usize, quiet: bool) -> Self {
+        if quiet {
+            return Progress(ProgressBar::hidden());
+        }
+        let style = ProgressStyle::with_template("{bar:40} {pos}/{len}  {rate}  ETA {eta}")
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .with_key("rate", |state: &ProgressState, w: &mut dyn Write| {
+                let _ = write!(w, "{:.1} img/s", state.per_sec());
+            });
+        let bar = ProgressBar::new(total as u64).with_style(style);
+        // Redrawn between images too, so the ETA keeps moving while a slow one is encoded.
+        bar.enable_steady_tick(Duration::from_millis(250));
+        Progress(bar)
+    }
+
+    /// Counts one image as done, running `print` with the bar cleared so its lines appear above.
+    pub fn done(&self, print: impl FnOnce()) {
+        self.0.suspend(print);
+        self.0.inc(1);
+    }
+
+    pub fn finish(&self) {
+        self.0.finish_and_clear();
+    }
+}
+
+/// Prints one aligned line per processed file: status, source, sizes and output. With
+/// `--quiet` only failures are printed.
+pub struct FileTable {
+    input_dir: PathBuf,
+    output_dir: PathBuf,
+    status_width: usize,
+    source_width: usize,
+    quiet: bool,
+    queue: Mutex<LineQueue>,
+}
+
+/// Lines finished out of order, waiting for the ones before them.
+#[derive(Debug, Default)]
+struct LineQueue {
+    next: usize,
+    waiting: BTreeMap<usize, (PathBuf, Outcome)>,
+}
+
+impl FileTable {
+    pub fn new(input_dir: &Path, output_dir: &Path, sources: &[PathBuf], quiet: bool) -> Self {
+        let statuses = [
+            Msg::StatusDone,
+            Msg::StatusSkipped,
+            Msg::StatusFailed,
+            Msg::StatusFlagged,
+            Msg::StatusBlocked,
+            Msg::StatusBlurry,
+        ];
+        FileTable {
+            input_dir: input_dir.to_path_buf(),
+            output_dir: output_dir.to_path_buf(),
+            status_width: statuses.iter().map(|s| display_width(&s.to_string())).max().unwrap_or(0),
+            source_width: sources.iter().map(|s| display_width(&relative(s, input_dir))).max().unwrap_or(0),
+            quiet,
+            queue: Mutex::default(),
+        }
+    }
+
+    /// Prints the line for the `index`th source once the lines before it have been printed, so
+    /// parallel work is logged in a fixed order.
+    pub fn print_in_order(&self, index: usize, source: &Path, outcome: &Outcome) {
+        let mut guard = self.queue.lock().unwrap_or_else(|e| e.into_inner());
+        let queue = &mut *guard;
+        queue.waiting.insert(index, (source.to_path_buf(), outcome.clone()));
+        while let Some((source, outcome)) = queue.waiting.remove(&queue.next) {
+            self.print(&source, &outcome);
+            queue.next += 1;
+        }
+    }
+
+    pub fn print(&self, source: &Path, outcome: &Outcome) {
+        if self.quiet && !matches!(outcome, Outcome::Failed(_)) {
+            return;
+        }
+        let name = pad(&relative(source, &self.input_dir), self.source_width);
+        match outcome {
+            Outcome::Processed(Processed { outputs, safety, .. }) => {
+                let size = |p: &Path| fs::metadata(p).map_or(0, |m| m.len());
+                let (before, after) = (size(source), outputs.iter().map(|p| size(p)).sum::<u64>());
+                let change = if before == 0 { 0.0 } else { (after as f64 / before as f64 - 1.0) * 100.0 };
+                let change_style = if after <= before { Style::Green } else { Style::Yellow };
+                let names: Vec<String> = outputs.iter().map(|p| relative(p, &self.output_dir)).collect();
+                let names = names.join(", ");
+                println!(
+                    "{} {}  {:>10} -> {:>10} {}  {}",
+                    match safety {
+                        Some(SafetyCheck { suspect: true, .. }) => {
+                            paint(&pad(&Msg::StatusFlagged.to_string(), self.status_width), Style::Yellow, Stream::Stdout)
+                        }
+                        _ => paint(&pad(&Msg::StatusDone.to_string(), self.status_width), Style::Green, Stream::Stdout),
+                    },
+                    name,
+                    format_bytes(before),
+                    format_bytes(after),
+                    paint(&format!("{:>+7.1}%", change), change_style, Stream::Stdout),
+                    paint(&names, Style::Dim, Stream::Stdout),
+                );
+            }
+            Outcome::Skipped => println!(
+                "{} {}",
+                paint(&pad(&Msg::StatusSkipped.to_string(), self.status_width), Style::Dim, Stream::Stdout),
+                name.trim_end()
+            ),
+            // Images left alone by `--baseline` or `--skip-up-to-date` are counted in one line
+            // rather than listed.
+            Outcome::Unchanged(_) => {}
+            // Discarded burst shots are listed with the shot kept, before processing starts.
+            Outcome::Discarded(_) => {}
+            Outcome::Blocked(check) => println!(
+                "{} {}  {:.0}%",
+                paint(&pad(&Msg::StatusBlocked.to_string(), self.status_width), Style::Yellow, Stream::Stdout),
+                name.trim_end(),
+                check.score * 100.0
+            ),
+            Outcome::Blurry(score) => println!(
+                "{} {}  {:.1}",
+                paint(&pad(&Msg::StatusBlurry.to_string(), self.status_width), Style::Yellow, Stream::Stdout),
+                name.trim_end(),
+                score
+            ),
+            Outcome::Failed(error) => eprintln!(
+                "{} {}  {}",
+                paint(&pad(&Msg::StatusFailed.to_string(), self.status_width), Style::Red, Stream::Stderr),
+                name,
+                paint(error, Style::Red, Stream::Stderr)
+            ),
+        }
+    }
+}
+
+fn relative(path: &Path, root: &Path) -> String {
+    path.strip_prefix(root).unwrap_or(path).display().to_string()
+}
+
+fn pad(text: &str, width: usize) -> String {
+    format!("{}{}", text, " ".repeat(width.saturating_sub(display_width(text))))
+}
+
+/// Terminal columns taken by `text`, counting East Asian wide characters as two.
+pub fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match c as u32 {
+            0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x20000..=0x3FFFD => 2,
+            _ => 1,
+        })
+        .sum()
+}
@@ -0,0 +1,31 @@
+use super::cli::Cli;
+use super::processor::WatermarkSpec;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+/// What a batch run works out before processing, passed alongside the [`Cli`] it was given.
+/// `Cli` holds only the options as the user set them, so it can be fingerprinted as is.
+#[derive(Debug, Clone, Default)]
+pub struct RunContext {
+    /// Additional watermark layers, drawn after the ones the options describe: the
+    /// `[[watermark_layer]]` tables of a config file, or layers set through the library API.
+    pub watermark_layers: Vec<WatermarkSpec>,
+    /// New file stems assigned by `--sequence-rename`, keyed by input path.
+    pub sequence_names: BTreeMap<PathBuf, String>,
+    /// Subfolders assigned by `--chunk`, keyed by input path.
+    pub chunk_dirs: BTreeMap<PathBuf, String>,
+    /// RAW inputs processed alongside a JPEG twin with `--raw-jpeg-policy both`.
+    pub raw_twins: BTreeSet<PathBuf>,
+    /// Private directory outputs are written to in the clear before `--encrypt` seals them.
+    pub staging_dir: Option<PathBuf>,
+    /// The quality `--total-size` lowered encoding to, which no output goes above.
+    pub quality: Option<u8>,
+}
+
+impl RunContext {
+    /// Returns every watermark layer to apply, in drawing order: those of the options, then the
+    /// programmatic layers.
+    pub fn watermark_specs(&self, cli: &Cli) -> Vec<WatermarkSpec> {
+        cli.watermark_specs().into_iter().chain(self.watermark_layers.iter().cloned()).collect()
+    }
+}
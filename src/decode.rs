@@ -0,0 +1,111 @@
+use super::report::read_exif;
+use anyhow::{Context, Result};
+use exif::{In, Tag};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageFormat, RgbImage};
+use moxcms::{ColorProfile, Layout, TransformOptions};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+/// The TIFF tag holding an embedded ICC profile.
+const TIFF_ICC_PROFILE_TAG: u16 = 34675;
+
+/// Opens an image, converting CMYK JPEGs and TIFFs to RGB. The embedded ICC profile is used
+/// for the conversion when present; otherwise a naive ink-subtraction formula is applied.
+/// Every other image is decoded by the `image` crate as usual.
+pub fn open_image(path: &Path) -> Result<DynamicImage> {
+    match ImageFormat::from_path(path).ok() {
+        Some(format @ (ImageFormat::Jpeg | ImageFormat::Tiff)) => decode_image(&fs::read(path)?, Some(format)),
+        _ => Ok(image::open(path)?),
+    }
+}
+
+/// Decodes an image held in memory like [`open_image`], in `format` if known or else the one
+/// its contents indicate.
+pub fn decode_image(data: &[u8], format: Option<ImageFormat>) -> Result<DynamicImage> {
+    let converted = match format {
+        Some(ImageFormat::Jpeg) => decode_cmyk_jpeg(data)?,
+        Some(ImageFormat::Tiff) => decode_cmyk_tiff(data)?,
+        _ => None,
+    };
+    match (converted, format) {
+        (Some(img), _) => Ok(img),
+        (None, Some(format)) => Ok(image::load_from_memory_with_format(data, format)?),
+        (None, None) => Ok(image::load_from_memory(data)?),
+    }
+}
+
+/// Decodes a JPEG only if it is CMYK (including Adobe YCCK). Ink values come back with
+/// 0 meaning no ink, as ICC profiles expect.
+fn decode_cmyk_jpeg(data: &[u8]) -> Result<Option<DynamicImage>> {
+    let mut decoder = jpeg_decoder::Decoder::new(Cursor::new(data));
+    decoder.read_info().context("Failed to read JPEG header")?;
+    let Some(info) = decoder.info() else { return Ok(None) };
+    if info.pixel_format != jpeg_decoder::PixelFormat::CMYK32 {
+        return Ok(None);
+    }
+    let pixels = decoder.decode().context("Failed to decode CMYK JPEG")?;
+    let icc = decoder.icc_profile();
+    cmyk_to_rgb(info.width as u32, info.height as u32, &pixels, icc.as_deref()).map(Some)
+}
+
+/// Decodes a TIFF only if it is 8-bit CMYK.
+fn decode_cmyk_tiff(data: &[u8]) -> Result<Option<DynamicImage>> {
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    let mut decoder = Decoder::new(Cursor::new(data)).context("Failed to read TIFF header")?;
+    if decoder.colortype()? != tiff::ColorType::CMYK(8) {
+        return Ok(None);
+    }
+    let (width, height) = decoder.dimensions()?;
+    let icc = tiff_icc_profile(&mut decoder);
+    let DecodingResult::U8(pixels) = decoder.read_image().context("Failed to decode CMYK TIFF")? else {
+        return Ok(None);
+    };
+    cmyk_to_rgb(width, height, &pixels, icc.as_deref()).map(Some)
+}
+
+/// Reads the ICC profile embedded in a TIFF.
+pub(crate) fn tiff_icc_profile<R: std::io::Read + std::io::Seek>(decoder: &mut tiff::decoder::Decoder<R>) -> Option<Vec<u8>> {
+    decoder.get_tag_u8_vec(tiff::tags::Tag::Unknown(TIFF_ICC_PROFILE_TAG)).ok()
+}
+
+/// Converts packed CMYK samples to an sRGB image.
+pub fn cmyk_to_rgb(width: u32, height: u32, cmyk: &[u8], icc: Option<&[u8]>) -> Result<DynamicImage> {
+    let mut rgb = vec![0u8; (width * height * 3) as usize];
+    let profile = icc.and_then(|data| ColorProfile::new_from_slice(data).ok());
+    let transformed = profile.is_some_and(|source| {
+        source
+            .create_transform_8bit(Layout::Rgba, &ColorProfile::new_srgb(), Layout::Rgb, TransformOptions::default())
+            .and_then(|transform| transform.transform(cmyk, &mut rgb))
+            .is_ok()
+    });
+    if !transformed {
+        for (out, ink) in rgb.chunks_exact_mut(3).zip(cmyk.chunks_exact(4)) {
+            let white = 255 - ink[3] as u32;
+            for channel in 0..3 {
+                out[channel] = ((255 - ink[channel] as u32) * white / 255) as u8;
+            }
+        }
+    }
+    let img = RgbImage::from_raw(width, height, rgb).context("CMYK image has an unexpected size")?;
+    Ok(DynamicImage::ImageRgb8(img))
+}
+
+/// Reads how an image must be turned to display upright from its EXIF Orientation tag, as
+/// cameras store portraits sideways. Images without one are already upright.
+pub fn exif_orientation(path: &Path) -> Orientation {
+    read_exif(path)
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY)?.value.get_uint(0))
+        .and_then(|value| Orientation::from_exif(value.min(u8::MAX as u32) as u8))
+        .unwrap_or(Orientation::NoTransforms)
+}
+
+/// Returns whether applying `orientation` swaps an image's width and height.
+pub fn swaps_dimensions(orientation: Orientation) -> bool {
+    matches!(
+        orientation,
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH
+    )
+}
@@ -0,0 +1,115 @@
+use super::meta::thumbnail;
+use anyhow::{Context, Result};
+use exif::experimental::Writer;
+use exif::{Field, In, Rational, Reader, Tag, Value};
+use img_parts::jpeg::{markers, Jpeg, JpegSegment};
+use img_parts::png::{Png, PngChunk};
+use img_parts::webp::WebP;
+use img_parts::{Bytes, ImageEXIF};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+const JFIF_IDENTIFIER: &[u8] = b"JFIF\0";
+const PNG_PHYS: [u8; 4] = *b"pHYs";
+const PNG_IHDR: [u8; 4] = *b"IHDR";
+
+/// Records the print resolution of a saved image in its format's native field: the JFIF
+/// density for JPEG, a pHYs chunk for PNG and EXIF resolution tags for WebP. Other formats
+/// are left unchanged.
+pub fn set_dpi(path: &Path, dpi: u32) -> Result<()> {
+    let data = Bytes::from(fs::read(path)?);
+    let bytes = match path.extension().and_then(|s| s.to_str()).map(str::to_lowercase).as_deref() {
+        Some("jpg" | "jpeg") => {
+            let mut jpeg = Jpeg::from_bytes(data)?;
+            set_jfif_density(&mut jpeg, dpi);
+            jpeg.encoder().bytes()
+        }
+        Some("png") => {
+            let mut png = Png::from_bytes(data)?;
+            set_phys(&mut png, dpi);
+            png.encoder().bytes()
+        }
+        Some("webp") => {
+            let mut webp = WebP::from_bytes(data)?;
+            let exif = resolution_exif(webp.exif().as_deref(), dpi)?;
+            webp.set_exif(Some(Bytes::from(exif)));
+            webp.encoder().bytes()
+        }
+        _ => return Ok(()),
+    };
+    fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Updates the JFIF APP0 segment, adding one if the file has none.
+fn set_jfif_density(jpeg: &mut Jpeg, dpi: u32) {
+    let density = (dpi.min(u16::MAX as u32) as u16).to_be_bytes();
+    let segments = jpeg.segments_mut();
+    let existing = segments
+        .iter()
+        .position(|s| s.marker() == markers::APP0 && s.contents().starts_with(JFIF_IDENTIFIER));
+
+    // Identifier, version, units (1 = dots per inch), X and Y density, then the thumbnail.
+    let mut contents = JFIF_IDENTIFIER.to_vec();
+    match existing.map(|i| segments[i].contents()) {
+        Some(old) if old.len() >= 12 => contents.extend_from_slice(&old[5..7]),
+        _ => contents.extend_from_slice(&[1, 1]),
+    }
+    contents.push(1);
+    contents.extend_from_slice(&density);
+    contents.extend_from_slice(&density);
+    match existing.map(|i| segments[i].contents()) {
+        Some(old) if old.len() >= 12 => contents.extend_from_slice(&old[12..]),
+        _ => contents.extend_from_slice(&[0, 0]),
+    }
+
+    let segment = JpegSegment::new_with_contents(markers::APP0, Bytes::from(contents));
+    match existing {
+        Some(i) => segments[i] = segment,
+        None => segments.insert(0, segment),
+    }
+}
+
+/// Replaces any pHYs chunk with one in pixels per meter, placed right after IHDR.
+fn set_phys(png: &mut Png, dpi: u32) {
+    let per_meter = ((dpi as f64 / 0.0254).round() as u32).to_be_bytes();
+    let mut contents = per_meter.to_vec();
+    contents.extend_from_slice(&per_meter);
+    contents.push(1);
+
+    png.remove_chunks_by_type(PNG_PHYS);
+    let chunks = png.chunks_mut();
+    let at = chunks.iter().position(|c| c.kind() == PNG_IHDR).map_or(0, |i| i + 1);
+    chunks.insert(at, PngChunk::new(PNG_PHYS, Bytes::from(contents)));
+}
+
+/// Builds an EXIF block with the resolution tags set, keeping every other field of `existing`
+/// and its thumbnail. An unreadable existing block is replaced.
+fn resolution_exif(existing: Option<&[u8]>, dpi: u32) -> Result<Vec<u8>> {
+    let exif = existing.and_then(|raw| Reader::new().read_raw(raw.to_vec()).ok());
+    let resolution = Value::Rational(vec![Rational { num: dpi, denom: 1 }]);
+    let fields = [
+        Field { tag: Tag::XResolution, ifd_num: In::PRIMARY, value: resolution.clone() },
+        Field { tag: Tag::YResolution, ifd_num: In::PRIMARY, value: resolution },
+        // 2 = inches
+        Field { tag: Tag::ResolutionUnit, ifd_num: In::PRIMARY, value: Value::Short(vec![2]) },
+    ];
+    let mut writer = Writer::new();
+    if let Some(exif) = &exif {
+        for field in exif.fields() {
+            if field.ifd_num == In::PRIMARY && fields.iter().any(|f| f.tag == field.tag) {
+                continue;
+            }
+            writer.push_field(field);
+        }
+        if let Some(thumbnail) = thumbnail(exif) {
+            writer.set_jpeg(thumbnail, In::THUMBNAIL);
+        }
+    }
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut buf = Cursor::new(Vec::new());
+    writer.write(&mut buf, exif.as_ref().is_some_and(|e| e.little_endian()))?;
+    Ok(buf.into_inner())
+}
@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Deletes a file, or moves it to the OS trash (recycle bin) when `use_trash` is set.
+pub fn remove_file(path: &Path, use_trash: bool) -> Result<()> {
+    if use_trash {
+        trash::delete(path).with_context(|| format!("Failed to move {} to the trash", path.display()))
+    } else {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {}", path.display()))
+    }
+}
+
+/// With `use_trash`, moves an existing file to the trash before it is overwritten.
+pub fn make_room(path: &Path, use_trash: bool) -> Result<()> {
+    if use_trash && path.exists() {
+        remove_file(path, true)?;
+    }
+    Ok(())
+}
+
+/// Returns the temporary file an output is written to before it replaces `output`, e.g.
+/// `.photo.partial.jpg` for `photo.jpg`. It sits in the same directory, so the final rename is
+/// atomic, and keeps the extension so later steps still recognize the format.
+pub fn temp_path(output: &Path) -> PathBuf {
+    let stem = output.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    match output.extension() {
+        Some(ext) => output.with_file_name(format!(".{}.partial.{}", stem, ext.to_string_lossy())),
+        None => output.with_file_name(format!(".{}.partial", stem)),
+    }
+}
+
+/// Returns whether a file is one of [`temp_path`]'s, such as one left by an interrupted run.
+pub fn is_temp_file(path: &Path) -> bool {
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let plain = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    name.starts_with('.') && (name.ends_with(".partial") || plain.ends_with(".partial"))
+}
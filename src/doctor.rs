@@ -0,0 +1,121 @@
+use super::assets::load_fonts;
+use super::cli::DoctorArgs;
+use super::console::{self, paint, ColorChoice, Stream, Style};
+use anyhow::{bail, Result};
+use image::{DynamicImage, ImageFormat, RgbImage};
+use rayon::prelude::*;
+use std::env;
+use std::fs;
+use std::io::Cursor;
+
+/// Formats the batch run can write, round-tripped through the encoder and decoder.
+const CODECS: [ImageFormat; 10] = [
+    ImageFormat::Jpeg,
+    ImageFormat::Png,
+    ImageFormat::WebP,
+    ImageFormat::Gif,
+    ImageFormat::Bmp,
+    ImageFormat::Tiff,
+    ImageFormat::Pnm,
+    ImageFormat::Tga,
+    ImageFormat::Ico,
+    ImageFormat::Qoi,
+];
+
+/// Optional cargo features and whether this build has them.
+const FEATURES: [(&str, bool); 11] = [
+    ("c2pa", cfg!(feature = "c2pa")),
+    ("encrypt", cfg!(feature = "encrypt")),
+    ("upscale-ai", cfg!(feature = "upscale-ai")),
+    ("ml", cfg!(feature = "ml")),
+    ("ffmpeg", cfg!(feature = "ffmpeg")),
+    ("pdf", cfg!(feature = "pdf")),
+    ("lambda", cfg!(feature = "lambda")),
+    ("worker", cfg!(feature = "worker")),
+    ("cdn-cloudflare", cfg!(feature = "cdn-cloudflare")),
+    ("cdn-fastly", cfg!(feature = "cdn-fastly")),
+    ("cdn-cloudfront", cfg!(feature = "cdn-cloudfront")),
+];
+
+/// Checks that codecs, fonts, the output directory and the thread pool work, printing a line
+/// per check with a hint for each failure. Fails if any check fails.
+pub fn run(args: &DoctorArgs) -> Result<()> {
+    console::init(ColorChoice::Auto);
+    println!("imagekit {} ({} {})", env!("CARGO_PKG_VERSION"), env::consts::OS, env::consts::ARCH);
+    let enabled: Vec<&str> = FEATURES.iter().filter(|(_, on)| *on).map(|(name, _)| *name).collect();
+    println!("Features: {}", if enabled.is_empty() { "none".to_string() } else { enabled.join(", ") });
+
+    let mut failed = 0;
+    let mut check = |name: &str, result: Result<String>, hint: &str| match result {
+        Ok(detail) => println!("{}  {}: {}", paint("ok  ", Style::Green, Stream::Stdout), name, detail),
+        Err(e) => {
+            failed += 1;
+            println!("{}  {}: {:#}", paint("FAIL", Style::Red, Stream::Stdout), name, e);
+            println!("      {}", hint);
+        }
+    };
+
+    for format in CODECS {
+        check(
+            &format!("codec {}", format.extensions_str()[0]),
+            round_trip(format).map(|()| "encode and decode".to_string()),
+            "This build's image crate lacks the codec; rebuild with the default features of `image`.",
+        );
+    }
+    check(
+        "fonts",
+        load_fonts().map(|fonts| format!("{} embedded fonts loaded", fonts.len())),
+        "The fonts are embedded from assets/ at build time; rebuild from a complete checkout.",
+    );
+    if let Some(dir) = &args.output_dir {
+        check(
+            "output directory",
+            check_writable(dir).map(|()| format!("{} is writable", dir.display())),
+            "Create the directory or choose one you have write permission for.",
+        );
+    }
+    check(
+        "thread pool",
+        check_threads(),
+        "Set RAYON_NUM_THREADS to a positive number, or unset it to use every CPU.",
+    );
+
+    if failed > 0 {
+        bail!("{} checks failed", failed);
+    }
+    println!("All checks passed.");
+    Ok(())
+}
+
+fn round_trip(format: ImageFormat) -> Result<()> {
+    let img = DynamicImage::ImageRgb8(RgbImage::from_fn(8, 8, |x, y| image::Rgb([x as u8 * 32, y as u8 * 32, 128])));
+    // Icons are written as RGBA, as the batch run does; the ICO reader rejects RGB ones.
+    let img = if format == ImageFormat::Ico { DynamicImage::ImageRgba8(img.to_rgba8()) } else { img };
+    let mut encoded = Vec::new();
+    img.write_to(&mut Cursor::new(&mut encoded), format)?;
+    let decoded = image::load_from_memory_with_format(&encoded, format)?;
+    if decoded.width() != 8 || decoded.height() != 8 {
+        bail!("decoded {}x{} instead of 8x8", decoded.width(), decoded.height());
+    }
+    Ok(())
+}
+
+fn check_writable(dir: &std::path::Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".imagekit-doctor");
+    fs::write(&probe, b"probe")?;
+    fs::remove_file(&probe)?;
+    Ok(())
+}
+
+fn check_threads() -> Result<String> {
+    let threads = rayon::current_num_threads();
+    let sum: u64 = (1..=1000u64).into_par_iter().sum();
+    if sum != 500_500 {
+        bail!("parallel sum returned {}", sum);
+    }
+    Ok(match env::var("RAYON_NUM_THREADS") {
+        Ok(value) => format!("{} worker threads (RAYON_NUM_THREADS={})", threads, value),
+        Err(_) => format!("{} worker threads", threads),
+    })
+}
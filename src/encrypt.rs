@@ -0,0 +1,131 @@
+use super::errors::ParseRecipientError;
+use anyhow::{Context, Result};
+use bech32::{FromBase32, Variant};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An `age` X25519 recipient that outputs are encrypted to, written `age:age1...`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recipient {
+    encoded: String,
+}
+
+impl FromStr for Recipient {
+    type Err = ParseRecipientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseRecipientError(s.to_string());
+        let encoded = s.strip_prefix("age:").ok_or_else(err)?.trim().to_lowercase();
+        let (hrp, data, variant) = bech32::decode(&encoded).map_err(|_| err())?;
+        let key = Vec::<u8>::from_base32(&data).map_err(|_| err())?;
+        if hrp != "age" || variant != Variant::Bech32 || key.len() != 32 {
+            return Err(err());
+        }
+        Ok(Recipient { encoded })
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "age:{}", self.encoded)
+    }
+}
+
+/// Returns where the encrypted output for `output` goes, e.g. `photo.jpg.age`.
+pub fn sealed_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+    name.push(".age");
+    output.with_file_name(name)
+}
+
+/// A directory only the current user can enter, where outputs are written in the clear before
+/// they are sealed. It lies outside the output directory, so an interrupted run never leaves
+/// plaintext beside the sealed files. It is removed when dropped.
+pub struct StagingDir(PathBuf);
+
+impl StagingDir {
+    pub fn create() -> Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        for attempt in 0u32.. {
+            let seed = format!("{}:{}:{}", std::process::id(), nanos, attempt);
+            let name = format!("imagekit-encrypt-{}", &blake3::hash(seed.as_bytes()).to_hex()[..16]);
+            let dir = std::env::temp_dir().join(name);
+            let mut builder = fs::DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            match builder.create(&dir) {
+                Ok(()) => return Ok(StagingDir(dir)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e).with_context(|| format!("Failed to create {}", dir.display())),
+            }
+        }
+        unreachable!()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Returns the file in `dir` an output is written to in the clear before it is sealed into
+/// `sealed`. The name is derived from `sealed`, so parallel outputs never share one, and keeps
+/// the image extension so later steps still recognize the format.
+pub fn staging_path(dir: &Path, sealed: &Path) -> PathBuf {
+    let image = sealed.with_extension("");
+    let hash = blake3::hash(image.as_os_str().as_encoded_bytes()).to_hex();
+    match image.extension() {
+        Some(ext) => dir.join(format!("{}.{}", &hash[..32], ext.to_string_lossy())),
+        None => dir.join(&hash[..32]),
+    }
+}
+
+/// Encrypts `input` to `output` in the age v1 format, readable by every recipient with
+/// `age -d -i <identity>`.
+pub fn encrypt_file(input: &Path, output: &Path, recipients: &[Recipient]) -> Result<()> {
+    cipher::encrypt_file(input, output, recipients)
+}
+
+#[cfg(feature = "encrypt")]
+mod cipher {
+    use super::Recipient;
+    use age::x25519;
+    use anyhow::{anyhow, Context, Result};
+    use std::fs::File;
+    use std::io::{self, BufWriter};
+    use std::path::Path;
+
+    pub fn encrypt_file(input: &Path, output: &Path, recipients: &[Recipient]) -> Result<()> {
+        let recipients: Vec<x25519::Recipient> = recipients
+            .iter()
+            .map(|r| r.encoded.parse().map_err(|e| anyhow!("Invalid recipient {}: {}", r, e)))
+            .collect::<Result<_>>()?;
+        let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+
+        let mut plaintext = File::open(input).with_context(|| format!("Failed to read {}", input.display()))?;
+        let file = File::create(output).with_context(|| format!("Failed to write {}", output.display()))?;
+        let mut writer = encryptor.wrap_output(BufWriter::new(file))?;
+        io::copy(&mut plaintext, &mut writer)?;
+        writer.finish()?.into_inner()?.sync_all()?;
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "encrypt"))]
+mod cipher {
+    use super::Recipient;
+    use std::path::Path;
+
+    pub fn encrypt_file(_: &Path, _: &Path, _: &[Recipient]) -> anyhow::Result<()> {
+        anyhow::bail!("imagekit was built without the `encrypt` feature")
+    }
+}
@@ -4,6 +4,94 @@ use thiserror::Error;
 #[error("Invalid watermark position: '{0}'. Valid options are: nw, north, ne, west, center, east, sw, south, se")]
 pub struct ParseWatermarkPositionError(pub String);
 
+#[derive(Debug, Error)]
+#[error("Invalid watermark '{0}': {1}")]
+pub struct ParseWatermarkLayerError(pub String, pub String);
+
 #[derive(Debug, Error)]
 #[error("Invalid hex color code: '{0}'. Must be in RRGGBB or RRGGBBAA format.")]
-pub struct ParseColorError(pub String);
\ No newline at end of file
+pub struct ParseColorError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid rule: '{0}'. Expected '<width|height|format> <op> <value> => <action>[,<action>...]'")]
+pub struct ParseRuleError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid percentage: '{0}'. Use a value such as '5%' or '0.05', greater than 0 and at most 100%.")]
+pub struct ParsePercentageError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid physical size: '{0}'. Use '<width>x<height><unit>' with unit in, cm or mm, e.g. '4x6in'.")]
+pub struct ParsePhysicalSizeError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid adjustment: '{0}'. Expected '<hue|sat|light>:<all|reds|yellows|greens|cyans|blues|magentas>:<amount>', e.g. 'sat:blues:+20'")]
+pub struct ParseAdjustmentError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid tag assignment: '{0}'. Expected '<tag>=<value>' with one of: Artist, Copyright, ImageDescription, Make, Model, Software, DateTime, DateTimeOriginal")]
+pub struct ParseTagAssignmentError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid group key: '{0}'. Expected one of: exif.Make, exif.Model, exif.LensMake, exif.LensModel, exif.DateTimeOriginal, exif.Date")]
+pub struct ParseGroupKeyError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid pixel size: '{0}'. Use '<width>x<height>', e.g. '1200x800'.")]
+pub struct ParsePixelSizeError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid sequence template: '{0}'. It must contain '{{seq}}' or a padded '{{seq:04}}', e.g. 'IMG_{{seq:04}}'.")]
+pub struct ParseSequenceTemplateError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid encryption recipient: '{0}'. Expected 'age:<recipient>' with an X25519 recipient such as 'age:age1...'")]
+pub struct ParseRecipientError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid size buckets: '{0}'. Expected '<name>:<<size>,...,<name>' with limits in increasing order and a last bucket without a limit, e.g. 'small:<100KB,medium:<500KB,large'.")]
+pub struct ParseSizeBucketsError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid aspect filter: '{0}'. Expected 'landscape', 'portrait' or 'square', the last optionally followed by a tolerance such as ',0.05' or ',5%'.")]
+pub struct ParseAspectFilterError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid nine-slice insets: '{0}'. Expected '<left>,<top>,<right>,<bottom>' in pixels, e.g. '12,12,12,12'.")]
+pub struct ParseNineSliceError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid palette: '{0}'. Expected comma-separated RRGGBB colors, e.g. '000000,ffffff,ff0000'.")]
+pub struct ParsePaletteError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid sample size: '{0}'. Expected a number of images such as '100' or a percentage such as '5%'.")]
+pub struct ParseSampleSizeError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid order: '{0}'. Expected 'name', 'size', 'mtime' or 'random(<seed>)'.")]
+pub struct ParseOrderError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid size: '{0}'. Expected a number of bytes with an optional unit, e.g. '18MB', '500KB' or '2GiB'.")]
+pub struct ParseByteSizeError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid zone: '{0}'. Expected '<x>,<y>,<width>x<height>' in pixels, e.g. '40,300,200x80'.")]
+pub struct ParseZoneError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid upscale: '{0}'. Expected 'ai:<factor>x' with a factor from 2 to 8, e.g. 'ai:2x'.")]
+pub struct ParseUpscaleError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid perspective: '{0}'. Expected 'auto' or four corners of a convex quadrilateral as 'x1,y1 x2,y2 x3,y3 x4,y4' in pixels, e.g. '120,80 1850,40 1900,1400 90,1380'.")]
+pub struct ParsePerspectiveError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid font: '{0}'. Expected a font file path or one of embedded:roboto, embedded:source-han-sans, embedded:noto-sans-thai.")]
+pub struct ParseFontSourceError(pub String);
+
+#[derive(Debug, Error)]
+#[error("Invalid page selection: '{0}'. Expected page numbers and ranges counting from 1, separated by commas, e.g. '1-5,9' or '3-'.")]
+pub struct ParsePageSelectionError(pub String);
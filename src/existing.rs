@@ -0,0 +1,64 @@
+use super::cli::Cli;
+use super::context::RunContext;
+use super::processor::output_candidates;
+use super::rules::Pipeline;
+use anyhow::{bail, Result};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// How many existing outputs `--if-exists error` names before summarizing the rest.
+const LISTED: usize = 5;
+
+/// Inputs split by whether any of their outputs already exists.
+pub struct ExistingSplit {
+    /// Inputs none of whose outputs exist yet.
+    pub to_process: Vec<PathBuf>,
+    /// Inputs with an output already in place, with the first such output.
+    pub existing: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Splits inputs for `--if-exists skip` and `--if-exists error`. An output counts wherever a
+/// run may write it: under either extension with `--output-format auto`, at any width and in
+/// any bucket.
+pub fn split(cli: &Cli, context: &RunContext, inputs: Vec<PathBuf>) -> Result<ExistingSplit> {
+    let decisions: Vec<(PathBuf, Option<PathBuf>)> = inputs
+        .into_par_iter()
+        .map(|input| {
+            let output = existing_output(cli, context, &input)?;
+            Ok((input, output))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut split = ExistingSplit { to_process: Vec::new(), existing: Vec::new() };
+    for (input, output) in decisions {
+        match output {
+            Some(output) => split.existing.push((input, output)),
+            None => split.to_process.push(input),
+        }
+    }
+    Ok(split)
+}
+
+impl ExistingSplit {
+    /// Fails, naming the existing outputs, if there are any, for `--if-exists error`.
+    pub fn ensure_none(&self) -> Result<()> {
+        if self.existing.is_empty() {
+            return Ok(());
+        }
+        let mut outputs: Vec<&PathBuf> = self.existing.iter().map(|(_, output)| output).collect();
+        outputs.sort();
+        let mut listed: Vec<String> = outputs.iter().take(LISTED).map(|p| p.display().to_string()).collect();
+        if outputs.len() > LISTED {
+            listed.push(format!("and {} more", outputs.len() - LISTED));
+        }
+        bail!("{} outputs already exist (--if-exists error): {}", outputs.len(), listed.join(", "))
+    }
+}
+
+fn existing_output(cli: &Cli, context: &RunContext, input: &Path) -> Result<Option<PathBuf>> {
+    let pipeline = Pipeline::for_path(cli, input)?;
+    if pipeline.skip {
+        return Ok(None);
+    }
+    Ok(output_candidates(input, cli, context, &pipeline)?.into_iter().find(|p| p.exists()))
+}
@@ -0,0 +1,58 @@
+use super::decode::decode_image;
+use super::processor::{encode_image, EncodeOptions};
+use anyhow::{Context, Result};
+use image::{DynamicImage, ImageFormat};
+use memmap2::Mmap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Write};
+use std::path::Path;
+
+/// Decodes an image like [`open_image`](super::decode::open_image), straight from a memory map
+/// of the file rather than through buffered reads.
+pub fn open_image(path: &Path) -> Result<DynamicImage> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    // SAFETY: the map is only read while decoding. Like any map of a file, its contents change
+    // if another process writes to the file meanwhile, which `--fast-io` documents as unsupported.
+    let map = unsafe { Mmap::map(&file) }.with_context(|| format!("Failed to map {}", path.display()))?;
+    decode_image(&map, ImageFormat::from_path(path).ok())
+}
+
+/// Encodes an image like `save_image_with_format`,
+/// but into memory first, so the file can be allocated at its final size and written at once.
+pub fn save_image(img: &DynamicImage, path: &Path, format: ImageFormat, quality: u8, options: EncodeOptions) -> Result<()> {
+    let mut data = Cursor::new(Vec::new());
+    encode_image(img, &mut data, format, quality, options, path)?;
+    write(path, data.get_ref())
+}
+
+/// Writes `data` to a new file whose blocks are reserved up front, which avoids fragmenting
+/// large outputs written in parallel.
+pub fn write(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(path)?;
+    preallocate(&file, data.len() as u64).with_context(|| format!("Failed to allocate {}", path.display()))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    if len == 0 {
+        return Ok(());
+    }
+    // `posix_fallocate` returns the error code instead of setting errno.
+    match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, len as libc::off_t) } {
+        0 => Ok(()),
+        // Filesystems that can't reserve space just get a plain write.
+        libc::EOPNOTSUPP | libc::EINVAL => Ok(()),
+        code => Err(io::Error::from_raw_os_error(code)),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn preallocate(file: &File, len: u64) -> io::Result<()> {
+    file.set_len(len)
+}
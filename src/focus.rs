@@ -0,0 +1,234 @@
+use super::cli::FocusStackArgs;
+use super::decode::open_image;
+use super::i18n::Msg;
+use super::is_supported_image;
+use super::processor::{save_image_with_format, EncodeOptions};
+use super::pyramid::{collapse, laplacian_pyramid, Plane};
+use anyhow::{bail, Context, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, ImageFormat, Rgb, RgbImage};
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+/// Width the coarsest alignment search runs at; finer levels double it up to full size.
+const COARSE_WIDTH: u32 = 128;
+/// How much a shot's magnification may differ from the first's. Refocusing a macro lens
+/// changes it by a few percent ("focus breathing"), and so does moving a focusing rail.
+const MAX_BREATHING: f32 = 0.05;
+/// Magnifications tried at the coarsest level; each finer level halves the step.
+const SCALE_STEP: f32 = 0.005;
+/// How far a shot may be shifted, as a share of the width.
+const MAX_SHIFT: f32 = 0.03;
+
+/// Merges a series of shots focused at different depths into one sharp composite and saves it.
+pub fn run(args: &FocusStackArgs) -> Result<()> {
+    let mut paths: Vec<PathBuf> = walkdir::WalkDir::new(&args.input_dir)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| is_supported_image(e.path()))
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    paths.sort();
+    if paths.len() < 2 {
+        bail!("Focus stacking needs at least two images in {}", args.input_dir.display());
+    }
+    let format = ImageFormat::from_path(&args.output)
+        .with_context(|| format!("Unknown image format for {}", args.output.display()))?;
+
+    let images: Vec<DynamicImage> = paths
+        .iter()
+        .map(|path| open_image(path).with_context(|| format!("Failed to open {}", path.display())))
+        .collect::<Result<_>>()?;
+    let stacked = DynamicImage::ImageRgb8(stack(&images, !args.no_align)?);
+    save_image_with_format(&stacked, &args.output, format, args.quality, EncodeOptions::default())
+        .with_context(|| format!("Failed to save {}", args.output.display()))?;
+    println!(
+        "{}",
+        Msg::Stacked { images: images.len(), path: &args.output, width: stacked.width(), height: stacked.height() }
+    );
+    Ok(())
+}
+
+/// Merges shots of one scene focused at different depths, keeping each part from the shot
+/// where it's sharpest. The shots are split into Laplacian pyramids; at every level each
+/// pixel takes the detail of the shot with the most local contrast there, and the coarsest
+/// level, which holds no detail, is averaged. Choosing per level rather than per pixel keeps
+/// the transitions between shots free of seams. With `align`, every shot is first scaled and
+/// shifted onto the first one, undoing the change in magnification that refocusing brings,
+/// and the result is cropped to the area all shots cover. The shots must be the same size.
+pub fn stack(images: &[DynamicImage], align: bool) -> Result<RgbImage> {
+    let Some(first) = images.first() else { bail!("No images to stack") };
+    let (width, height) = (first.width() as usize, first.height() as usize);
+    if let Some(other) = images.iter().find(|img| (img.width() as usize, img.height() as usize) != (width, height)) {
+        bail!("The shots differ in size: {}x{} and {}x{}", width, height, other.width(), other.height());
+    }
+    let mut shots: Vec<RgbImage> = images.iter().map(DynamicImage::to_rgb8).collect();
+    if align {
+        let reference = imageops::grayscale(&shots[0]);
+        let transforms: Vec<Transform> = shots[1..]
+            .par_iter()
+            .map(|shot| find_transform(&reference, &imageops::grayscale(shot)).unwrap_or_default())
+            .collect();
+        let (x0, y0, x1, y1) = transforms.iter().fold((0.0f32, 0.0f32, width as f32, height as f32), |crop, t| {
+            let (ax, ay, bx, by) = t.covered(width as f32, height as f32);
+            (crop.0.max(ax), crop.1.max(ay), crop.2.min(bx), crop.3.min(by))
+        });
+        let (x0, y0) = (x0.ceil() as u32, y0.ceil() as u32);
+        let (cw, ch) = ((x1.floor() as u32).saturating_sub(x0).max(1), (y1.floor() as u32).saturating_sub(y0).max(1));
+        let warped: Vec<RgbImage> = shots[1..].par_iter().zip(&transforms).map(|(shot, t)| t.warp(shot)).collect();
+        shots.truncate(1);
+        shots.extend(warped);
+        for shot in &mut shots {
+            *shot = imageops::crop_imm(shot, x0, y0, cw, ch).to_image();
+        }
+    }
+
+    let (width, height) = (shots[0].width() as usize, shots[0].height() as usize);
+    let levels = ((width.min(height) as f32).log2() as usize).saturating_sub(3).max(1);
+    let mut stacked: Vec<Plane> = Vec::new();
+    let mut best: Vec<Vec<f32>> = Vec::new();
+    for shot in &shots {
+        let pyramid = laplacian_pyramid(&Plane::from_rgb(shot), levels);
+        if stacked.is_empty() {
+            best = pyramid.iter().map(activity).collect();
+            stacked = pyramid;
+            continue;
+        }
+        let last = pyramid.len() - 1;
+        for (level, detail) in pyramid.into_iter().enumerate() {
+            let (target, c) = (&mut stacked[level], detail.channels);
+            if level == last {
+                target.data.iter_mut().zip(&detail.data).for_each(|(sum, v)| *sum += v);
+                continue;
+            }
+            for (i, energy) in activity(&detail).into_iter().enumerate() {
+                if energy > best[level][i] {
+                    best[level][i] = energy;
+                    target.data[i * c..][..c].copy_from_slice(&detail.data[i * c..][..c]);
+                }
+            }
+        }
+    }
+    if let Some(base) = stacked.last_mut() {
+        base.data.iter_mut().for_each(|v| *v /= shots.len() as f32);
+    }
+    Ok(collapse(stacked).context("Empty pyramid")?.to_rgb())
+}
+
+/// Local contrast of one pyramid level: the detail's energy, smoothed so neighboring pixels
+/// tend to come from the same shot.
+fn activity(detail: &Plane) -> Vec<f32> {
+    let data = detail.data.chunks(detail.channels).map(|p| p.iter().map(|v| v * v).sum()).collect();
+    Plane { width: detail.width, height: detail.height, channels: 1, data }.blur().data
+}
+
+/// Where a pixel of the first shot is found in another: scaled by `scale` about the center,
+/// then shifted by `dx`, `dy`.
+#[derive(Debug, Clone, Copy)]
+struct Transform {
+    scale: f32,
+    dx: f32,
+    dy: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform { scale: 1.0, dx: 0.0, dy: 0.0 }
+    }
+}
+
+impl Transform {
+    fn source(&self, x: f32, y: f32, width: f32, height: f32) -> (f32, f32) {
+        let (cx, cy) = ((width - 1.0) / 2.0, (height - 1.0) / 2.0);
+        ((x - cx) * self.scale + cx + self.dx, (y - cy) * self.scale + cy + self.dy)
+    }
+
+    /// The rectangle of the first shot's frame, as left, top, right and bottom, that this
+    /// shot covers.
+    fn covered(&self, width: f32, height: f32) -> (f32, f32, f32, f32) {
+        let (cx, cy) = ((width - 1.0) / 2.0, (height - 1.0) / 2.0);
+        let back = |s: f32, c: f32, d: f32| (s - c - d) / self.scale + c;
+        let (left, right) = (back(0.0, cx, self.dx), back(width - 1.0, cx, self.dx) + 1.0);
+        let (top, bottom) = (back(0.0, cy, self.dy), back(height - 1.0, cy, self.dy) + 1.0);
+        (left.max(0.0), top.max(0.0), right.min(width), bottom.min(height))
+    }
+
+    /// Resamples `shot` onto the first shot's frame, bilinearly, repeating edge pixels.
+    fn warp(&self, shot: &RgbImage) -> RgbImage {
+        let (width, height) = (shot.width() as f32, shot.height() as f32);
+        RgbImage::from_fn(shot.width(), shot.height(), |x, y| {
+            let (sx, sy) = self.source(x as f32, y as f32, width, height);
+            let (sx, sy) = (sx.clamp(0.0, width - 1.0), sy.clamp(0.0, height - 1.0));
+            let (x0, y0) = (sx.floor() as u32, sy.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(shot.width() - 1), (y0 + 1).min(shot.height() - 1));
+            let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+            let at = |x: u32, y: u32, c: usize| shot.get_pixel(x, y)[c] as f32;
+            Rgb([0, 1, 2].map(|c| {
+                let top = at(x0, y0, c) * (1.0 - fx) + at(x1, y0, c) * fx;
+                let bottom = at(x0, y1, c) * (1.0 - fx) + at(x1, y1, c) * fx;
+                (top * (1.0 - fy) + bottom * fy).round() as u8
+            }))
+        })
+    }
+}
+
+/// Finds the scale and shift that best map `reference` onto `shot`, searched coarse to
+/// fine. Returns `None` if the images are too small to compare.
+fn find_transform(reference: &GrayImage, shot: &GrayImage) -> Option<Transform> {
+    let mut levels = vec![(reference.clone(), shot.clone())];
+    while levels[levels.len() - 1].0.width() > COARSE_WIDTH {
+        let (a, b) = &levels[levels.len() - 1];
+        let half = |img: &GrayImage| imageops::resize(img, (img.width() / 2).max(1), (img.height() / 2).max(1), FilterType::Triangle);
+        let next = (half(a), half(b));
+        levels.push(next);
+    }
+
+    let (a, b) = levels.pop()?;
+    let shift = (a.width() as f32 * MAX_SHIFT).ceil() as i32;
+    let steps = (MAX_BREATHING / SCALE_STEP).round() as i32;
+    let candidates = (-steps..=steps).flat_map(|s| {
+        (-shift..=shift).flat_map(move |dx| {
+            (-shift..=shift).map(move |dy| Transform { scale: 1.0 + s as f32 * SCALE_STEP, dx: dx as f32, dy: dy as f32 })
+        })
+    });
+    let mut best = best_match(&a, &b, candidates)?;
+    let mut step = SCALE_STEP;
+    for (a, b) in levels.iter().rev() {
+        step /= 2.0;
+        let (x, y) = (best.dx * 2.0, best.dy * 2.0);
+        let scale = best.scale;
+        let around = [-step, 0.0, step].into_iter().flat_map(move |ds| {
+            [-1.0, 0.0, 1.0].into_iter().flat_map(move |dx| {
+                [-1.0, 0.0, 1.0].into_iter().map(move |dy| Transform { scale: scale + ds, dx: x + dx, dy: y + dy })
+            })
+        });
+        best = best_match(a, b, around)?;
+    }
+    Some(best)
+}
+
+/// Returns the transform under which the images differ least, ties going to the first.
+fn best_match(a: &GrayImage, b: &GrayImage, candidates: impl Iterator<Item = Transform>) -> Option<Transform> {
+    candidates
+        .filter_map(|t| Some((difference(a, b, t)?, t)))
+        .min_by(|x, y| x.0.total_cmp(&y.0))
+        .map(|(_, t)| t)
+}
+
+/// Mean absolute difference between `a` and `b` sampled through `t`, over the pixels of `a`
+/// that land inside `b`. `None` if fewer than half do.
+fn difference(a: &GrayImage, b: &GrayImage, t: Transform) -> Option<f64> {
+    let (width, height) = (a.width() as f32, a.height() as f32);
+    let (mut sum, mut count) = (0.0, 0usize);
+    for (x, y, pixel) in a.enumerate_pixels() {
+        let (sx, sy) = t.source(x as f32, y as f32, width, height);
+        let (sx, sy) = (sx.round(), sy.round());
+        if sx < 0.0 || sy < 0.0 || sx >= b.width() as f32 || sy >= b.height() as f32 {
+            continue;
+        }
+        sum += (pixel[0] as f64 - b.get_pixel(sx as u32, sy as u32)[0] as f64).abs();
+        count += 1;
+    }
+    (count * 2 >= (a.width() * a.height()) as usize && count > 0).then(|| sum / count as f64)
+}
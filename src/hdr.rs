@@ -0,0 +1,199 @@
+use super::cli::{Cli, HdrMergeArgs, OutputFormat};
+use super::decode::open_image;
+use super::i18n::Msg;
+use super::pyramid::{collapse, gaussian_pyramid, laplacian_pyramid, Plane};
+use super::report::read_exif;
+use super::sequence::capture_seconds;
+use super::{is_supported_image, nested_output_dir};
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use exif::{In, Tag};
+use image::{DynamicImage, RgbImage};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where merged brackets wait, inside the output directory, until the batch run picks them up.
+const STAGING_DIR: &str = ".imagekit-hdr";
+/// Spread of the well-exposedness weight around mid-gray, as in Mertens et al.
+const EXPOSEDNESS_SIGMA: f32 = 0.2;
+/// Keeps weights positive where every shot scores zero, such as flat clipped areas.
+const MIN_WEIGHT: f32 = 1e-12;
+
+/// Groups the input directory's bracketed exposures, fuses each bracket into one image and
+/// runs the batch pipeline with the other options over the results.
+pub fn run(args: &HdrMergeArgs) -> Result<()> {
+    let cli = &args.batch;
+    let nested_output = nested_output_dir(&cli.input_dir, &cli.output_dir);
+    let inputs: Vec<PathBuf> = walkdir::WalkDir::new(&cli.input_dir)
+        .into_iter()
+        .filter_entry(|e| Some(e.path()) != nested_output.as_deref())
+        .filter_map(|e| e.ok())
+        .filter(|e| is_supported_image(e.path()))
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    let brackets = brackets(&inputs, args.bracket_window, args.bracket_size);
+    let bracketed: usize = brackets.iter().map(Vec::len).sum();
+    println!("{}", Msg::BracketsFound { brackets: brackets.len(), left_out: inputs.len() - bracketed });
+    if brackets.is_empty() {
+        return Ok(());
+    }
+
+    let staging = cli.output_dir.join(STAGING_DIR);
+    let merged: Vec<bool> = brackets
+        .par_iter()
+        .map(|shots| {
+            let merged = merge_bracket(shots, &cli.input_dir, &staging);
+            if let Err(e) = &merged {
+                eprintln!("{}", Msg::MergeFailed { path: &shots[0], error: &format!("{:#}", e) });
+            }
+            merged.is_ok()
+        })
+        .collect();
+    println!("{}", Msg::Merged { merged: merged.iter().filter(|&&ok| ok).count(), total: brackets.len() });
+
+    // Outputs keep the shots' format, not that of the staged PNGs.
+    let format = cli.output_format.clone().or_else(|| {
+        let ext = brackets[0][0].extension()?.to_str()?.to_lowercase();
+        let name = match ext.as_str() {
+            "jpeg" => "jpg",
+            "tif" => "tiff",
+            other => other,
+        };
+        OutputFormat::from_str(name, true).ok()
+    });
+    let batch = Cli { input_dir: staging.clone(), output_format: format, ..cli.clone() };
+    let result = super::run(batch);
+    fs::remove_dir_all(&staging).with_context(|| format!("Failed to remove {}", staging.display()))?;
+    result
+}
+
+/// Fuses one bracket and stages the result as `<first shot>-hdr.png`, under the first shot's
+/// path relative to the input directory.
+fn merge_bracket(shots: &[PathBuf], input_dir: &Path, staging: &Path) -> Result<()> {
+    let images: Vec<DynamicImage> = shots
+        .iter()
+        .map(|path| open_image(path).with_context(|| format!("Failed to open {}", path.display())))
+        .collect::<Result<_>>()?;
+    let fused = fuse(&images)?;
+    let relative = shots[0].strip_prefix(input_dir)?;
+    let stem = relative.file_stem().unwrap_or_default().to_string_lossy();
+    let path = staging.join(relative).with_file_name(format!("{}-hdr.png", stem));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fused.save(&path).with_context(|| format!("Failed to save {}", path.display()))
+}
+
+/// Groups shots into exposure brackets. Within each directory, shots are taken in capture
+/// order, and a bracket ends at a gap of more than `window` seconds, or when an exposure
+/// repeats, which starts the next bracket. With `size`, it instead ends after that many shots.
+/// Shots without a capture time, and brackets of one, are left out.
+pub fn brackets(inputs: &[PathBuf], window: f64, size: Option<u32>) -> Vec<Vec<PathBuf>> {
+    let mut by_dir: BTreeMap<&Path, Vec<Shot>> = BTreeMap::new();
+    for path in inputs {
+        if let Some(time) = capture_seconds(path) {
+            let shot = Shot { time, exposure: exposure(path), path };
+            by_dir.entry(path.parent().unwrap_or(Path::new(""))).or_default().push(shot);
+        }
+    }
+    let mut brackets = Vec::new();
+    for mut shots in by_dir.into_values() {
+        shots.sort_by(|a, b| a.time.total_cmp(&b.time).then_with(|| a.path.cmp(b.path)));
+        let mut current: Vec<Shot> = Vec::new();
+        for shot in shots {
+            let ends = current.last().is_some_and(|last| {
+                shot.time - last.time > window
+                    || match size {
+                        Some(size) => current.len() >= size as usize,
+                        None => shot.exposure.is_some() && current.iter().any(|s| s.exposure == shot.exposure),
+                    }
+            });
+            if ends {
+                brackets.push(std::mem::take(&mut current));
+            }
+            current.push(shot);
+        }
+        brackets.push(current);
+    }
+    brackets
+        .into_iter()
+        .filter(|bracket| bracket.len() > 1)
+        .map(|bracket| bracket.into_iter().map(|shot| shot.path.clone()).collect())
+        .collect()
+}
+
+/// What bracketing needs to know about one input.
+struct Shot<'a> {
+    /// Capture time in seconds, from EXIF.
+    time: f64,
+    exposure: Option<String>,
+    path: &'a PathBuf,
+}
+
+/// Identifies a shot's exposure by its EXIF exposure bias, or its exposure time for manual
+/// brackets.
+fn exposure(path: &Path) -> Option<String> {
+    let exif = read_exif(path)?;
+    let field = exif
+        .get_field(Tag::ExposureBiasValue, In::PRIMARY)
+        .or_else(|| exif.get_field(Tag::ExposureTime, In::PRIMARY))?;
+    Some(field.display_value().to_string())
+}
+
+/// Fuses differently exposed shots of one scene with Mertens exposure fusion: each pixel of
+/// each shot is weighted by its contrast, saturation and closeness to mid-gray, and the shots
+/// are blended with those weights level by level of a Laplacian pyramid, so the blend leaves
+/// no seams. No tone mapping is needed, as the result is already in 0..1. The shots must be
+/// the same size, taken from a tripod, as they are not aligned.
+pub fn fuse(images: &[DynamicImage]) -> Result<RgbImage> {
+    let Some(first) = images.first() else { bail!("No images to fuse") };
+    let (width, height) = (first.width() as usize, first.height() as usize);
+    if let Some(other) = images.iter().find(|img| (img.width() as usize, img.height() as usize) != (width, height)) {
+        bail!("The shots differ in size: {}x{} and {}x{}", width, height, other.width(), other.height());
+    }
+    let shots: Vec<Plane> = images.iter().map(|img| Plane::from_rgb(&img.to_rgb8())).collect();
+    let mut weights: Vec<Plane> = shots.iter().map(weight).collect();
+    for i in 0..width * height {
+        let total: f32 = weights.iter().map(|w| w.data[i]).sum();
+        for w in &mut weights {
+            w.data[i] /= total;
+        }
+    }
+
+    let levels = ((width.min(height) as f32).log2() as usize).saturating_sub(3).max(1);
+    let mut fused: Option<Vec<Plane>> = None;
+    for (shot, weight) in shots.iter().zip(&weights) {
+        let detail = laplacian_pyramid(shot, levels);
+        let weight = gaussian_pyramid(weight, levels);
+        let blended = detail.iter().zip(&weight).map(|(d, w)| d.scaled_by(w));
+        fused = Some(match fused {
+            None => blended.collect(),
+            Some(sum) => sum.iter().zip(blended).map(|(s, b)| s.plus(&b)).collect(),
+        });
+    }
+    Ok(collapse(fused.unwrap_or_default()).context("Empty pyramid")?.to_rgb())
+}
+
+/// The Mertens weight of every pixel: contrast times saturation times well-exposedness.
+fn weight(shot: &Plane) -> Plane {
+    let (width, height) = (shot.width, shot.height);
+    let gray: Vec<f32> = shot.data.chunks(3).map(|p| 0.299 * p[0] + 0.587 * p[1] + 0.114 * p[2]).collect();
+    let at = |x: usize, y: usize| gray[y * width + x];
+    let mut data = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let (left, right) = (at(x.saturating_sub(1), y), at((x + 1).min(width - 1), y));
+            let (up, down) = (at(x, y.saturating_sub(1)), at(x, (y + 1).min(height - 1)));
+            let contrast = (4.0 * at(x, y) - left - right - up - down).abs();
+            let p = &shot.data[(y * width + x) * 3..][..3];
+            let mean = (p[0] + p[1] + p[2]) / 3.0;
+            let saturation = (p.iter().map(|c| (c - mean).powi(2)).sum::<f32>() / 3.0).sqrt();
+            let exposedness: f32 =
+                p.iter().map(|c| (-(c - 0.5).powi(2) / (2.0 * EXPOSEDNESS_SIGMA * EXPOSEDNESS_SIGMA)).exp()).product();
+            data.push(contrast * saturation * exposedness + MIN_WEIGHT);
+        }
+    }
+    Plane { width, height, channels: 1, data }
+}
@@ -0,0 +1,455 @@
+use clap::ValueEnum;
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// Language of console messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Lang {
+    En,
+    Zh,
+}
+
+static CURRENT: AtomicU8 = AtomicU8::new(0);
+
+impl Lang {
+    /// Picks the language from the standard locale variables, falling back to English.
+    pub fn from_env() -> Lang {
+        for var in ["LC_ALL", "LC_MESSAGES", "LANG"] {
+            match env::var(var) {
+                Ok(value) if !value.is_empty() => {
+                    return if value.to_lowercase().starts_with("zh") { Lang::Zh } else { Lang::En };
+                }
+                _ => {}
+            }
+        }
+        Lang::En
+    }
+}
+
+/// Sets the language used when messages are displayed.
+pub fn set_lang(lang: Lang) {
+    CURRENT.store(lang as u8, Ordering::Relaxed);
+}
+
+pub fn lang() -> Lang {
+    match CURRENT.load(Ordering::Relaxed) {
+        1 => Lang::Zh,
+        _ => Lang::En,
+    }
+}
+
+/// Formats a byte count with binary units, e.g. `512 B` or `12.3 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// A console message of the batch run or a subcommand. Displaying it uses the language set with
+/// [`set_lang`].
+#[derive(Debug, Clone, Copy)]
+pub enum Msg<'a> {
+    KeywordFilterExcluded { excluded: usize, total: usize },
+    RatingFilterExcluded { excluded: usize, total: usize },
+    NotReferenced { skipped: usize, total: usize },
+    AspectFilterExcluded { excluded: usize, total: usize },
+    Sampled { kept: usize, total: usize },
+    BurstKept { kept: &'a str, discarded: &'a str },
+    BurstsFound { bursts: usize, discarded: usize },
+    RawJpegPairsSkipped { skipped: usize },
+    BaselineUnchanged { unchanged: usize, total: usize },
+    BaselineSettingsChanged,
+    UpToDate { skipped: usize, total: usize },
+    UpToDateSummary { processed: usize, skipped: usize },
+    ExistingSkipped { skipped: usize, total: usize },
+    AlreadyInSync,
+    NoImages,
+    FoundImages(usize),
+    StatusDone,
+    StatusSkipped,
+    StatusFailed,
+    StatusFlagged,
+    StatusBlocked,
+    StatusBlurry,
+    ProcessingComplete,
+    SizeSummary { images: usize, input_bytes: u64, output_bytes: u64 },
+    Geotagged { count: usize, processed: usize },
+    SafetyFiltered { suspect: usize, blocked: bool },
+    BlurrySkipped(usize),
+    DryRunRuleSkip(&'a str),
+    DryRunPlanned(usize),
+    BlurryQuarantined { count: usize, dir: &'a Path },
+    ImagesBy(&'a str),
+    WroteReport(&'a Path),
+    OverBudget { total: u64, budget: u64, quality: u8 },
+    BudgetUnreachable { total: u64, budget: u64 },
+    WroteZip { path: &'a Path, files: usize },
+    RewroteLinks(usize),
+    PurgedUrls(usize),
+    WouldRemove(&'a Path),
+    Removed(&'a Path),
+    SyncRenamed { from: &'a str, to: &'a str, source: &'a str },
+    SyncRemoved { output: &'a str, source: &'a str },
+    OverwriteWarning(usize),
+    OriginalsHeading,
+    OverwrittenHeading,
+    PrunedHeading,
+    AndMore(usize),
+    ContinuePrompt,
+    NoReferences(&'a Path),
+    FoundReferenced(usize),
+    FailedToProcess { path: &'a Path, error: &'a str },
+    ProcessedVariants { path: &'a Path, variants: usize },
+    WroteManifest { images: usize, path: &'a Path },
+    FoundToUpdate(usize),
+    SavedTo(&'a Path),
+    FailedToUpdate { path: &'a Path, error: &'a str },
+    UpdatedMetadata { updated: usize, total: usize },
+    Placed(&'a Path),
+    PlacedOnPages { images: usize, pages: usize, dir: &'a Path },
+    DifferingPixels { differing: u64, total: u64, percent: f64, max_delta: u8 },
+    SavedDiff(&'a Path),
+    SavedPreview(&'a Path),
+    SmallIcon(u32),
+    WroteIcons { count: usize, dir: &'a Path },
+    KeptUnchanged { name: &'a str, error: &'a str },
+    RewroteEntries { images: usize, entries: usize, before: u64, after: u64 },
+    Stitched { images: usize, path: &'a Path, width: u32, height: u32 },
+    Stacked { images: usize, path: &'a Path, width: u32, height: u32 },
+    BracketsFound { brackets: usize, left_out: usize },
+    MergeFailed { path: &'a Path, error: &'a str },
+    Merged { merged: usize, total: usize },
+    UntimedFrames(usize),
+    WroteFrames { frames: usize, dir: &'a Path },
+    EncodedVideo { path: &'a Path, fps: u32 },
+    InvocationFailed { id: &'a str, error: &'a str },
+    UnderOutputPrefix { bucket: &'a str, key: &'a str },
+    Requeued { jobs: usize, list: &'a str },
+    WaitingForJobs(&'a str),
+    JobFailed { id: &'a str, error: &'a str },
+}
+
+impl Msg<'_> {
+    /// Renders the message in the given language.
+    pub fn text(&self, lang: Lang) -> String {
+        use Msg::*;
+        match (lang, *self) {
+            (Lang::En, KeywordFilterExcluded { excluded, total }) => {
+                format!("Keyword filter excluded {} of {} images.", excluded, total)
+            }
+            (Lang::Zh, KeywordFilterExcluded { excluded, total }) => {
+                format!("关键词过滤排除了 {} 张图片中的 {} 张。", total, excluded)
+            }
+            (Lang::En, RatingFilterExcluded { excluded, total }) => {
+                format!("Rating filter excluded {} of {} images.", excluded, total)
+            }
+            (Lang::Zh, RatingFilterExcluded { excluded, total }) => {
+                format!("评级过滤排除了 {} 张图片中的 {} 张。", total, excluded)
+            }
+            (Lang::En, NotReferenced { skipped, total }) => {
+                format!("Skipping {} of {} images not referenced by any page.", skipped, total)
+            }
+            (Lang::Zh, NotReferenced { skipped, total }) => {
+                format!("跳过 {} 张图片中未被任何页面引用的 {} 张。", total, skipped)
+            }
+            (Lang::En, AspectFilterExcluded { excluded, total }) => {
+                format!("Aspect filter excluded {} of {} images.", excluded, total)
+            }
+            (Lang::Zh, AspectFilterExcluded { excluded, total }) => {
+                format!("宽高比过滤排除了 {} 张图片中的 {} 张。", total, excluded)
+            }
+            (Lang::En, RawJpegPairsSkipped { skipped }) => {
+                format!("Skipped {} duplicates from RAW+JPEG pairs.", skipped)
+            }
+            (Lang::Zh, RawJpegPairsSkipped { skipped }) => format!("跳过了 RAW+JPEG 成对文件中的 {} 个重复文件。", skipped),
+            (Lang::En, BurstKept { kept, discarded }) => format!("  kept {} over {}", kept, discarded),
+            (Lang::Zh, BurstKept { kept, discarded }) => format!("  保留 {}，舍弃 {}", kept, discarded),
+            (Lang::En, BurstsFound { bursts, discarded }) => {
+                format!("Found {} bursts; left out {} less sharp shots.", bursts, discarded)
+            }
+            (Lang::Zh, BurstsFound { bursts, discarded }) => {
+                format!("发现 {} 组连拍，舍弃了 {} 张不够清晰的照片。", bursts, discarded)
+            }
+            (Lang::En, Sampled { kept, total }) => format!("Sampled {} of {} images.", kept, total),
+            (Lang::Zh, Sampled { kept, total }) => format!("从 {} 张图片中抽样了 {} 张。", total, kept),
+            (Lang::En, BaselineUnchanged { unchanged, total }) => {
+                format!("Skipping {} of {} images unchanged since the baseline.", unchanged, total)
+            }
+            (Lang::Zh, BaselineUnchanged { unchanged, total }) => {
+                format!("跳过 {} 张图片中自基准以来未变化的 {} 张。", total, unchanged)
+            }
+            (Lang::En, BaselineSettingsChanged) => {
+                "The settings differ from the baseline's, so every image is processed.".to_string()
+            }
+            (Lang::Zh, BaselineSettingsChanged) => "设置与基准不同，将处理所有图片。".to_string(),
+            (Lang::En, UpToDate { skipped, total }) => {
+                format!("Skipping {} of {} images whose outputs are up to date.", skipped, total)
+            }
+            (Lang::Zh, UpToDate { skipped, total }) => {
+                format!("跳过 {} 张图片中输出已是最新的 {} 张。", total, skipped)
+            }
+            (Lang::En, UpToDateSummary { processed, skipped }) => {
+                format!("Processed {} images; skipped {} that were up to date.", processed, skipped)
+            }
+            (Lang::Zh, UpToDateSummary { processed, skipped }) => {
+                format!("处理了 {} 张图片，跳过了 {} 张已是最新的图片。", processed, skipped)
+            }
+            (Lang::En, ExistingSkipped { skipped, total }) => {
+                format!("Skipping {} of {} images whose outputs already exist.", skipped, total)
+            }
+            (Lang::Zh, ExistingSkipped { skipped, total }) => {
+                format!("跳过 {} 张图片中输出已存在的 {} 张。", total, skipped)
+            }
+            (Lang::En, AlreadyInSync) => "Output directory is already in sync.".to_string(),
+            (Lang::Zh, AlreadyInSync) => "输出目录已是最新。".to_string(),
+            (Lang::En, NoImages) => "No images found in the input directory.".to_string(),
+            (Lang::Zh, NoImages) => "输入目录中没有找到图片。".to_string(),
+            (Lang::En, FoundImages(n)) => format!("Found {} images to process.", n),
+            (Lang::Zh, FoundImages(n)) => format!("找到 {} 张待处理的图片。", n),
+            (Lang::En, StatusDone) => "done".to_string(),
+            (Lang::Zh, StatusDone) => "完成".to_string(),
+            (Lang::En, StatusSkipped) => "skipped".to_string(),
+            (Lang::Zh, StatusSkipped) => "跳过".to_string(),
+            (Lang::En, StatusFailed) => "failed".to_string(),
+            (Lang::Zh, StatusFailed) => "失败".to_string(),
+            (Lang::En, StatusFlagged) => "flagged".to_string(),
+            (Lang::Zh, StatusFlagged) => "已标记".to_string(),
+            (Lang::En, StatusBlocked) => "blocked".to_string(),
+            (Lang::Zh, StatusBlocked) => "已拦截".to_string(),
+            (Lang::En, StatusBlurry) => "blurry".to_string(),
+            (Lang::Zh, StatusBlurry) => "模糊".to_string(),
+            (Lang::En, ProcessingComplete) => "Image processing complete!".to_string(),
+            (Lang::Zh, ProcessingComplete) => "图片处理完成！".to_string(),
+            (lang, SizeSummary { images, input_bytes, output_bytes }) => {
+                let change = if input_bytes == 0 {
+                    0.0
+                } else {
+                    (output_bytes as f64 / input_bytes as f64 - 1.0) * 100.0
+                };
+                let (input, output) = (format_bytes(input_bytes), format_bytes(output_bytes));
+                match lang {
+                    Lang::En => format!("{} images: {} -> {} ({:+.1}%)", images, input, output, change),
+                    Lang::Zh => format!("{} 张图片：{} -> {}（{:+.1}%）", images, input, output, change),
+                }
+            }
+            (Lang::En, Geotagged { count, processed }) => {
+                format!("{} of {} processed images have a GPS position.", count, processed)
+            }
+            (Lang::Zh, Geotagged { count, processed }) => {
+                format!("{} 张已处理图片中有 {} 张带有 GPS 位置。", processed, count)
+            }
+            (Lang::En, SafetyFiltered { suspect, blocked: true }) => {
+                format!("Safety filter: {} images blocked.", suspect)
+            }
+            (Lang::En, SafetyFiltered { suspect, blocked: false }) => {
+                format!("Safety filter: {} images flagged.", suspect)
+            }
+            (Lang::Zh, SafetyFiltered { suspect, blocked: true }) => format!("安全过滤：已拦截 {} 张图片。", suspect),
+            (Lang::Zh, SafetyFiltered { suspect, blocked: false }) => format!("安全过滤：已标记 {} 张图片。", suspect),
+            (Lang::En, DryRunRuleSkip(source)) => format!("{} -> skipped by a rule", source),
+            (Lang::Zh, DryRunRuleSkip(source)) => format!("{} -> 被规则跳过", source),
+            (Lang::En, DryRunPlanned(count)) => format!("Dry run: {} images would be processed; nothing was written.", count),
+            (Lang::Zh, DryRunPlanned(count)) => format!("试运行：将处理 {} 张图片；未写入任何文件。", count),
+            (Lang::En, BlurrySkipped(count)) => format!("Sharpness filter: {} blurry images skipped.", count),
+            (Lang::Zh, BlurrySkipped(count)) => format!("清晰度过滤：跳过了 {} 张模糊图片。", count),
+            (Lang::En, BlurryQuarantined { count, dir }) => {
+                format!("Sharpness filter: {} blurry images copied to {}.", count, dir.display())
+            }
+            (Lang::Zh, BlurryQuarantined { count, dir }) => {
+                format!("清晰度过滤：已将 {} 张模糊图片复制到 {}。", count, dir.display())
+            }
+            (Lang::En, ImagesBy(key)) => format!("Images by {}:", key),
+            (Lang::Zh, ImagesBy(key)) => format!("按 {} 分组的图片：", key),
+            (Lang::En, WroteReport(path)) => format!("Wrote report to {}", path.display()),
+            (Lang::Zh, WroteReport(path)) => format!("报告已写入 {}", path.display()),
+            (Lang::En, OverBudget { total, budget, quality }) => format!(
+                "Outputs total {}, over the {} limit; re-encoding at quality {}.",
+                format_bytes(total),
+                format_bytes(budget),
+                quality
+            ),
+            (Lang::Zh, OverBudget { total, budget, quality }) => format!(
+                "输出共 {}，超过 {} 的上限；以质量 {} 重新编码。",
+                format_bytes(total),
+                format_bytes(budget),
+                quality
+            ),
+            (Lang::En, BudgetUnreachable { total, budget }) => format!(
+                "Outputs still total {} at the lowest quality, over the {} limit.",
+                format_bytes(total),
+                format_bytes(budget)
+            ),
+            (Lang::Zh, BudgetUnreachable { total, budget }) => {
+                format!("即使使用最低质量，输出仍共 {}，超过 {} 的上限。", format_bytes(total), format_bytes(budget))
+            }
+            (Lang::En, WroteZip { path, files }) => format!("Packed {} files into {}", files, path.display()),
+            (Lang::Zh, WroteZip { path, files }) => format!("已将 {} 个文件打包到 {}", files, path.display()),
+            (Lang::En, RewroteLinks(n)) => format!("Rewrote image links in {} pages.", n),
+            (Lang::Zh, RewroteLinks(n)) => format!("已更新 {} 个页面中的图片链接。", n),
+            (Lang::En, PurgedUrls(n)) => format!("Purged {} URLs from the CDN.", n),
+            (Lang::Zh, PurgedUrls(n)) => format!("已从 CDN 刷新 {} 个 URL。", n),
+            (Lang::En, WouldRemove(path)) => format!("Would remove {}", path.display()),
+            (Lang::Zh, WouldRemove(path)) => format!("将删除 {}", path.display()),
+            (Lang::En, Removed(path)) => format!("Removed {}", path.display()),
+            (Lang::Zh, Removed(path)) => format!("已删除 {}", path.display()),
+            (Lang::En, SyncRenamed { from, to, source }) => {
+                format!("Renamed {} -> {} (source moved from {})", from, to, source)
+            }
+            (Lang::Zh, SyncRenamed { from, to, source }) => {
+                format!("已重命名 {} -> {}（源文件从 {} 移动）", from, to, source)
+            }
+            (Lang::En, SyncRemoved { output, source }) => format!("Removed {} (source {} was deleted)", output, source),
+            (Lang::Zh, SyncRemoved { output, source }) => format!("已删除 {}（源文件 {} 已被删除）", output, source),
+            (Lang::En, OverwriteWarning(n)) => format!(
+                "Warning: {} input files will be overwritten in place. Use --confirm to review them first.",
+                n
+            ),
+            (Lang::Zh, OverwriteWarning(n)) => {
+                format!("警告：{} 个输入文件将被原地覆盖。可使用 --confirm 先确认。", n)
+            }
+            (Lang::En, OriginalsHeading) => "Input files that will be overwritten in place:".to_string(),
+            (Lang::Zh, OriginalsHeading) => "将被原地覆盖的输入文件：".to_string(),
+            (Lang::En, OverwrittenHeading) => "Existing outputs that will be replaced:".to_string(),
+            (Lang::Zh, OverwrittenHeading) => "将被替换的已有输出：".to_string(),
+            (Lang::En, PrunedHeading) => "Orphaned outputs that --prune will delete:".to_string(),
+            (Lang::Zh, PrunedHeading) => "--prune 将删除的孤立输出：".to_string(),
+            (Lang::En, AndMore(n)) => format!("  ... and {} more", n),
+            (Lang::Zh, AndMore(n)) => format!("  ……另有 {} 个", n),
+            (Lang::En, ContinuePrompt) => "Continue? [y/N] ".to_string(),
+            (Lang::Zh, ContinuePrompt) => "是否继续？[y/N] ".to_string(),
+            (Lang::En, NoReferences(dir)) => format!("No image references found in {}.", dir.display()),
+            (Lang::Zh, NoReferences(dir)) => format!("{} 中没有找到图片引用。", dir.display()),
+            (Lang::En, FoundReferenced(n)) => format!("Found {} referenced images.", n),
+            (Lang::Zh, FoundReferenced(n)) => format!("找到 {} 张被引用的图片。", n),
+            (Lang::En, FailedToProcess { path, error }) => format!("Failed to process {}: {}", path.display(), error),
+            (Lang::Zh, FailedToProcess { path, error }) => format!("处理 {} 失败：{}", path.display(), error),
+            (Lang::En, ProcessedVariants { path, variants }) => {
+                format!("Processed {} ({} variants)", path.display(), variants)
+            }
+            (Lang::Zh, ProcessedVariants { path, variants }) => format!("已处理 {}（{} 个变体）", path.display(), variants),
+            (Lang::En, WroteManifest { images, path }) => {
+                format!("Wrote manifest for {} images to {}", images, path.display())
+            }
+            (Lang::Zh, WroteManifest { images, path }) => format!("已将 {} 张图片的清单写入 {}", images, path.display()),
+            (Lang::En, FoundToUpdate(n)) => format!("Found {} images to update.", n),
+            (Lang::Zh, FoundToUpdate(n)) => format!("找到 {} 张待更新的图片。", n),
+            (Lang::En, SavedTo(path)) => format!("Saved to {}", path.display()),
+            (Lang::Zh, SavedTo(path)) => format!("已保存到 {}", path.display()),
+            (Lang::En, FailedToUpdate { path, error }) => format!("Failed to update {}: {}", path.display(), error),
+            (Lang::Zh, FailedToUpdate { path, error }) => format!("更新 {} 失败：{}", path.display(), error),
+            (Lang::En, UpdatedMetadata { updated, total }) => {
+                format!("Updated metadata of {} of {} images.", updated, total)
+            }
+            (Lang::Zh, UpdatedMetadata { updated, total }) => {
+                format!("已更新 {} 张图片中 {} 张的元数据。", total, updated)
+            }
+            (Lang::En, Placed(path)) => format!("Placed {}", path.display()),
+            (Lang::Zh, Placed(path)) => format!("已排版 {}", path.display()),
+            (Lang::En, PlacedOnPages { images, pages, dir }) => {
+                format!("Placed {} images on {} pages in {}", images, pages, dir.display())
+            }
+            (Lang::Zh, PlacedOnPages { images, pages, dir }) => {
+                format!("已将 {} 张图片排到 {} 页上，保存在 {}", images, pages, dir.display())
+            }
+            (Lang::En, DifferingPixels { differing, total, percent, max_delta }) => format!(
+                "Differing pixels: {} of {} ({:.4}%), max channel delta {}",
+                differing, total, percent, max_delta
+            ),
+            (Lang::Zh, DifferingPixels { differing, total, percent, max_delta }) => format!(
+                "不同的像素：{} 个像素中有 {} 个（{:.4}%），最大通道差值 {}",
+                total, differing, percent, max_delta
+            ),
+            (Lang::En, SavedDiff(path)) => format!("Saved difference image to {}", path.display()),
+            (Lang::Zh, SavedDiff(path)) => format!("差异图已保存到 {}", path.display()),
+            (Lang::En, SavedPreview(path)) => format!("Saved watermark preview to {}", path.display()),
+            (Lang::Zh, SavedPreview(path)) => format!("水印预览已保存到 {}", path.display()),
+            (Lang::En, SmallIcon(side)) => format!(
+                "The icon is {0}x{0}; the largest sizes are upscaled, so 1024x1024 or more is recommended.",
+                side
+            ),
+            (Lang::Zh, SmallIcon(side)) => {
+                format!("图标为 {0}x{0}；最大的几种尺寸需要放大，建议使用 1024x1024 或更大的图标。", side)
+            }
+            (Lang::En, WroteIcons { count, dir }) => {
+                format!("Wrote {} icons and splash screens to {}", count, dir.display())
+            }
+            (Lang::Zh, WroteIcons { count, dir }) => format!("已将 {} 个图标和启动画面写入 {}", count, dir.display()),
+            (Lang::En, KeptUnchanged { name, error }) => format!("Keeping {} unchanged: {}", name, error),
+            (Lang::Zh, KeptUnchanged { name, error }) => format!("保留 {} 不变：{}", name, error),
+            (Lang::En, RewroteEntries { images, entries, before, after }) => format!(
+                "Rewrote {} of {} entries: {} -> {}",
+                images,
+                entries,
+                format_bytes(before),
+                format_bytes(after)
+            ),
+            (Lang::Zh, RewroteEntries { images, entries, before, after }) => format!(
+                "重写了 {} 个条目中的 {} 个：{} -> {}",
+                entries,
+                images,
+                format_bytes(before),
+                format_bytes(after)
+            ),
+            (Lang::En, Stitched { images, path, width, height }) => {
+                format!("Stitched {} images into {} ({}x{})", images, path.display(), width, height)
+            }
+            (Lang::Zh, Stitched { images, path, width, height }) => {
+                format!("已将 {} 张图片拼接为 {}（{}x{}）", images, path.display(), width, height)
+            }
+            (Lang::En, Stacked { images, path, width, height }) => {
+                format!("Stacked {} images into {} ({}x{})", images, path.display(), width, height)
+            }
+            (Lang::Zh, Stacked { images, path, width, height }) => {
+                format!("已将 {} 张图片景深合成为 {}（{}x{}）", images, path.display(), width, height)
+            }
+            (Lang::En, BracketsFound { brackets, left_out }) => {
+                format!("Found {} brackets; {} shots outside any bracket are left out.", brackets, left_out)
+            }
+            (Lang::Zh, BracketsFound { brackets, left_out }) => {
+                format!("发现 {} 组包围曝光；舍弃了 {} 张不属于任何一组的照片。", brackets, left_out)
+            }
+            (Lang::En, MergeFailed { path, error }) => {
+                format!("Failed to merge the bracket of {}: {}", path.display(), error)
+            }
+            (Lang::Zh, MergeFailed { path, error }) => format!("合并 {} 所在的包围曝光失败：{}", path.display(), error),
+            (Lang::En, Merged { merged, total }) => format!("Merged {} of {} brackets.", merged, total),
+            (Lang::Zh, Merged { merged, total }) => format!("合并了 {} 组包围曝光中的 {} 组。", total, merged),
+            (Lang::En, UntimedFrames(n)) => {
+                format!("{} frames have no EXIF capture time and follow the rest in file name order.", n)
+            }
+            (Lang::Zh, UntimedFrames(n)) => format!("{} 帧没有 EXIF 拍摄时间，按文件名顺序排在其余帧之后。", n),
+            (Lang::En, WroteFrames { frames, dir }) => format!("Wrote {} frames to {}", frames, dir.display()),
+            (Lang::Zh, WroteFrames { frames, dir }) => format!("已将 {} 帧写入 {}", frames, dir.display()),
+            (Lang::En, EncodedVideo { path, fps }) => format!("Encoded {} at {} fps", path.display(), fps),
+            (Lang::Zh, EncodedVideo { path, fps }) => format!("已以 {} fps 编码 {}", fps, path.display()),
+            (Lang::En, InvocationFailed { id, error }) => format!("Invocation {} failed: {}", id, error),
+            (Lang::Zh, InvocationFailed { id, error }) => format!("调用 {} 失败：{}", id, error),
+            (Lang::En, UnderOutputPrefix { bucket, key }) => {
+                format!("Skipping s3://{}/{}, which is under the output prefix", bucket, key)
+            }
+            (Lang::Zh, UnderOutputPrefix { bucket, key }) => format!("跳过位于输出前缀下的 s3://{}/{}", bucket, key),
+            (Lang::En, Requeued { jobs, list }) => format!("Requeued {} unfinished jobs from {}", jobs, list),
+            (Lang::Zh, Requeued { jobs, list }) => format!("已将 {} 中 {} 个未完成的任务放回队列", list, jobs),
+            (Lang::En, WaitingForJobs(key)) => format!("Waiting for jobs on {}", key),
+            (Lang::Zh, WaitingForJobs(key)) => format!("正在等待 {} 上的任务", key),
+            (Lang::En, JobFailed { id, error }) => format!("Job {} failed: {}", id, error),
+            (Lang::Zh, JobFailed { id, error }) => format!("任务 {} 失败：{}", id, error),
+        }
+    }
+}
+
+impl fmt::Display for Msg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.text(lang()))
+    }
+}
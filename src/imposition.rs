@@ -0,0 +1,237 @@
+use super::cli::ImpositionArgs;
+use super::decode::open_image;
+use super::i18n::Msg;
+use super::is_supported_image;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::{self, FilterType};
+use image::{GenericImageView, Rgb, RgbImage};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const MM_PER_INCH: f32 = 25.4;
+const POINTS_PER_INCH: f32 = 72.0;
+
+/// Standard paper sizes.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Paper {
+    A3,
+    A4,
+    A5,
+    Letter,
+    Legal,
+}
+
+impl Paper {
+    /// Returns the portrait width and height in millimeters.
+    pub fn size_mm(self) -> (f32, f32) {
+        match self {
+            Paper::A3 => (297.0, 420.0),
+            Paper::A4 => (210.0, 297.0),
+            Paper::A5 => (148.0, 210.0),
+            Paper::Letter => (215.9, 279.4),
+            Paper::Legal => (215.9, 355.6),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SheetFormat {
+    Pdf,
+    Png,
+}
+
+/// Lays the input images out N-up and writes the pages.
+pub fn run(args: &ImpositionArgs) -> Result<()> {
+    let mut image_paths: Vec<PathBuf> = walkdir::WalkDir::new(&args.input_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| is_supported_image(e.path()))
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    image_paths.sort();
+
+    if image_paths.is_empty() {
+        println!("{}", Msg::NoImages);
+        return Ok(());
+    }
+    fs::create_dir_all(&args.output_dir)?;
+
+    let pages: Vec<RgbImage> = image_paths
+        .chunks(args.per_page as usize)
+        .map(|chunk| render_page(chunk, args))
+        .collect::<Result<_>>()?;
+
+    match args.format {
+        SheetFormat::Png => {
+            for (i, page) in pages.iter().enumerate() {
+                let path = args.output_dir.join(format!("page-{:03}.png", i + 1));
+                page.save(&path).with_context(|| format!("Failed to save {}", path.display()))?;
+            }
+        }
+        SheetFormat::Pdf => {
+            let path = args.output_dir.join("imposition.pdf");
+            write_pdf(&path, &pages, args.dpi)?;
+        }
+    }
+    println!("{}", Msg::PlacedOnPages { images: image_paths.len(), pages: pages.len(), dir: &args.output_dir });
+    Ok(())
+}
+
+/// Returns the page size in pixels at the requested resolution.
+fn page_pixels(args: &ImpositionArgs) -> (u32, u32) {
+    let (w, h) = args.paper.size_mm();
+    let (w, h) = if args.landscape { (h, w) } else { (w, h) };
+    (mm_to_px(w, args.dpi), mm_to_px(h, args.dpi))
+}
+
+fn mm_to_px(mm: f32, dpi: u32) -> u32 {
+    (mm / MM_PER_INCH * dpi as f32).round() as u32
+}
+
+/// Picks a grid for `n` cells, with more rows than columns on portrait pages.
+pub fn grid(n: u32, portrait: bool) -> (u32, u32) {
+    let long = (n as f32).sqrt().ceil() as u32;
+    let short = n.div_ceil(long);
+    if portrait { (short, long) } else { (long, short) }
+}
+
+fn render_page(paths: &[PathBuf], args: &ImpositionArgs) -> Result<RgbImage> {
+    let (page_w, page_h) = page_pixels(args);
+    let margin = mm_to_px(args.margin, args.dpi);
+    let gutter = mm_to_px(args.gutter, args.dpi);
+    let (cols, rows) = grid(args.per_page, page_h >= page_w);
+
+    let usable_w = page_w.saturating_sub(2 * margin + (cols - 1) * gutter);
+    let usable_h = page_h.saturating_sub(2 * margin + (rows - 1) * gutter);
+    let (cell_w, cell_h) = (usable_w / cols, usable_h / rows);
+    if cell_w == 0 || cell_h == 0 {
+        bail!("Margins and gutters leave no room for {} images per page", args.per_page);
+    }
+
+    let mut page = RgbImage::from_pixel(page_w, page_h, Rgb([255, 255, 255]));
+    let mut placed = Vec::new();
+    for (i, path) in paths.iter().enumerate() {
+        let mut img = open_image(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let (w, h) = img.dimensions();
+        // Turn images to match the cell's orientation so they fill more of it.
+        if (w > h) != (cell_w > cell_h) && w != h {
+            img = img.rotate90();
+        }
+        let fitted = img.resize(cell_w, cell_h, FilterType::Lanczos3).to_rgb8();
+
+        let (col, row) = (i as u32 % cols, i as u32 / cols);
+        let cell_x = margin + col * (cell_w + gutter);
+        let cell_y = margin + row * (cell_h + gutter);
+        let x = cell_x + (cell_w - fitted.width()) / 2;
+        let y = cell_y + (cell_h - fitted.height()) / 2;
+        placed.push((fitted, x, y));
+        println!("{}", Msg::Placed(path));
+    }
+
+    // Marks go down first so that, with a narrow gutter, images cover their neighbors' marks.
+    if !args.no_crop_marks {
+        for (fitted, x, y) in &placed {
+            draw_crop_marks(&mut page, (*x, *y, fitted.width(), fitted.height()), args.dpi);
+        }
+    }
+    for (fitted, x, y) in &placed {
+        imageops::replace(&mut page, fitted, *x as i64, *y as i64);
+    }
+    Ok(page)
+}
+
+/// Draws short trim lines just outside each corner of the rectangle.
+fn draw_crop_marks(page: &mut RgbImage, (x, y, w, h): (u32, u32, u32, u32), dpi: u32) {
+    let offset = mm_to_px(1.0, dpi) as i64;
+    let length = mm_to_px(3.0, dpi) as i64;
+    let thickness = (dpi / 150).max(1) as i64;
+    let (left, top, right, bottom) = (x as i64, y as i64, (x + w) as i64, (y + h) as i64);
+
+    for (corner_x, corner_y, dx, dy) in [(left, top, -1, -1), (right, top, 1, -1), (left, bottom, -1, 1), (right, bottom, 1, 1)] {
+        // Horizontal mark in line with the top/bottom edge, vertical mark in line with the side.
+        let horizontal_x = if dx < 0 { corner_x - offset - length } else { corner_x + offset };
+        fill_rect(page, horizontal_x, corner_y - thickness / 2, length, thickness);
+        let vertical_y = if dy < 0 { corner_y - offset - length } else { corner_y + offset };
+        fill_rect(page, corner_x - thickness / 2, vertical_y, thickness, length);
+    }
+}
+
+fn fill_rect(page: &mut RgbImage, x: i64, y: i64, w: i64, h: i64) {
+    for py in y.max(0)..(y + h).min(page.height() as i64) {
+        for px in x.max(0)..(x + w).min(page.width() as i64) {
+            page.put_pixel(px as u32, py as u32, Rgb([0, 0, 0]));
+        }
+    }
+}
+
+/// Writes the pages into a PDF, each as a full-page JPEG image sized for the paper.
+fn write_pdf(path: &Path, pages: &[RgbImage], dpi: u32) -> Result<()> {
+    let mut out = Vec::new();
+    let mut offsets = Vec::new();
+    out.extend_from_slice(b"%PDF-1.4\n%\xE2\xE3\xCF\xD3\n");
+
+    // Objects: 1 catalog, 2 page tree, then a page, its content and its image per page.
+    let page_ids: Vec<usize> = (0..pages.len()).map(|i| 3 + i * 3).collect();
+    let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    push_object(&mut out, &mut offsets, b"<< /Type /Catalog /Pages 2 0 R >>");
+    push_object(
+        &mut out,
+        &mut offsets,
+        format!("<< /Type /Pages /Kids [{}] /Count {} >>", kids.join(" "), pages.len()).as_bytes(),
+    );
+
+    for (page, &id) in pages.iter().zip(&page_ids) {
+        let to_points = |px: u32| px as f32 / dpi as f32 * POINTS_PER_INCH;
+        let (w, h) = (to_points(page.width()), to_points(page.height()));
+        push_object(
+            &mut out,
+            &mut offsets,
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {:.2} {:.2}] /Resources << /XObject << /Im0 {} 0 R >> >> /Contents {} 0 R >>",
+                w, h, id + 2, id + 1
+            )
+            .as_bytes(),
+        );
+
+        let content = format!("q {:.2} 0 0 {:.2} 0 0 cm /Im0 Do Q", w, h);
+        push_stream(&mut out, &mut offsets, "", content.as_bytes());
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 92).encode_image(page)?;
+        let dict = format!(
+            "/Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB /BitsPerComponent 8 /Filter /DCTDecode ",
+            page.width(),
+            page.height()
+        );
+        push_stream(&mut out, &mut offsets, &dict, &jpeg);
+    }
+
+    let xref_start = out.len();
+    out.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1).as_bytes());
+    for offset in &offsets {
+        out.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    out.extend_from_slice(
+        format!("trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n", offsets.len() + 1, xref_start).as_bytes(),
+    );
+
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    writer.write_all(&out).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn push_object(out: &mut Vec<u8>, offsets: &mut Vec<usize>, body: &[u8]) {
+    offsets.push(out.len());
+    out.extend_from_slice(format!("{} 0 obj\n", offsets.len()).as_bytes());
+    out.extend_from_slice(body);
+    out.extend_from_slice(b"\nendobj\n");
+}
+
+fn push_stream(out: &mut Vec<u8>, offsets: &mut Vec<usize>, dict: &str, data: &[u8]) {
+    let mut body = format!("<< {}/Length {} >>\nstream\n", dict, data.len()).into_bytes();
+    body.extend_from_slice(data);
+    body.extend_from_slice(b"\nendstream");
+    push_object(out, offsets, &body);
+}
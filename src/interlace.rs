@@ -0,0 +1,126 @@
+use super::cli::PngFilter;
+use anyhow::Result;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::codecs::png::CompressionType;
+use image::DynamicImage;
+use std::io::Write;
+
+/// The seven Adam7 passes as (first column, first row, column step, row step).
+const ADAM7: [(u32, u32, u32, u32); 7] =
+    [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
+
+/// Writes an Adam7-interlaced PNG, which the `image` crate's encoder cannot produce.
+/// Each pass is filtered as its own sub-image, as the PNG specification requires.
+pub fn write_interlaced_png<W: Write>(
+    writer: W,
+    img: &DynamicImage,
+    filter: PngFilter,
+    compression: CompressionType,
+) -> Result<()> {
+    let (color_type, bit_depth, samples) = png_layout(img);
+    let channels = match color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::Rgb => 3,
+        _ => 4,
+    };
+    let bpp = channels * if bit_depth == png::BitDepth::Sixteen { 2 } else { 1 };
+    let (width, height) = (img.width(), img.height());
+    let stride = width as usize * bpp;
+
+    let level = match compression {
+        CompressionType::Fast => Compression::fast(),
+        CompressionType::Best => Compression::best(),
+        _ => Compression::default(),
+    };
+    let mut zlib = ZlibEncoder::new(Vec::new(), level);
+    for (x0, y0, dx, dy) in ADAM7 {
+        let pass_width = width.saturating_sub(x0).div_ceil(dx) as usize;
+        if pass_width == 0 {
+            continue;
+        }
+        let mut previous = vec![0u8; pass_width * bpp];
+        for y in (y0..height).step_by(dy as usize) {
+            let row: Vec<u8> = (x0..width)
+                .step_by(dx as usize)
+                .flat_map(|x| {
+                    let start = y as usize * stride + x as usize * bpp;
+                    samples[start..start + bpp].iter().copied()
+                })
+                .collect();
+            zlib.write_all(&filter_row(&row, &previous, bpp, filter))?;
+            previous = row;
+        }
+    }
+
+    let mut info = png::Info::with_size(width, height);
+    info.color_type = color_type;
+    info.bit_depth = bit_depth;
+    info.interlaced = true;
+    let mut png_writer = png::Encoder::with_info(writer, info)?.write_header()?;
+    png_writer.write_chunk(png::chunk::IDAT, &zlib.finish()?)?;
+    png_writer.finish()?;
+    Ok(())
+}
+
+/// Returns the PNG color type and bit depth for an image, with its samples in PNG byte order.
+fn png_layout(img: &DynamicImage) -> (png::ColorType, png::BitDepth, Vec<u8>) {
+    use png::{BitDepth, ColorType};
+
+    let big_endian = |samples: &[u16]| samples.iter().flat_map(|s| s.to_be_bytes()).collect();
+    match img {
+        DynamicImage::ImageLuma8(i) => (ColorType::Grayscale, BitDepth::Eight, i.as_raw().clone()),
+        DynamicImage::ImageLumaA8(i) => (ColorType::GrayscaleAlpha, BitDepth::Eight, i.as_raw().clone()),
+        DynamicImage::ImageRgb8(i) => (ColorType::Rgb, BitDepth::Eight, i.as_raw().clone()),
+        DynamicImage::ImageLuma16(i) => (ColorType::Grayscale, BitDepth::Sixteen, big_endian(i.as_raw())),
+        DynamicImage::ImageLumaA16(i) => (ColorType::GrayscaleAlpha, BitDepth::Sixteen, big_endian(i.as_raw())),
+        DynamicImage::ImageRgb16(i) => (ColorType::Rgb, BitDepth::Sixteen, big_endian(i.as_raw())),
+        DynamicImage::ImageRgba16(i) => (ColorType::Rgba, BitDepth::Sixteen, big_endian(i.as_raw())),
+        _ => (ColorType::Rgba, BitDepth::Eight, img.to_rgba8().into_raw()),
+    }
+}
+
+/// Filters one row, prefixed with its filter type byte. `adaptive` keeps the filter whose
+/// output has the smallest sum of absolute values, the heuristic the PNG specification suggests.
+fn filter_row(row: &[u8], previous: &[u8], bpp: usize, filter: PngFilter) -> Vec<u8> {
+    let candidates: &[PngFilter] = match filter {
+        PngFilter::Adaptive => &[PngFilter::None, PngFilter::Sub, PngFilter::Up, PngFilter::Avg, PngFilter::Paeth],
+        _ => std::slice::from_ref(&filter),
+    };
+    candidates
+        .iter()
+        .map(|&kind| apply_filter(row, previous, bpp, kind))
+        .min_by_key(|filtered| filtered[1..].iter().map(|&b| (b as i8).unsigned_abs() as u64).sum::<u64>())
+        .unwrap_or_default()
+}
+
+fn apply_filter(row: &[u8], previous: &[u8], bpp: usize, kind: PngFilter) -> Vec<u8> {
+    let (code, predict): (u8, fn(u8, u8, u8) -> u8) = match kind {
+        PngFilter::None | PngFilter::Adaptive => (0, |_, _, _| 0),
+        PngFilter::Sub => (1, |left, _, _| left),
+        PngFilter::Up => (2, |_, up, _| up),
+        PngFilter::Avg => (3, |left, up, _| ((left as u16 + up as u16) / 2) as u8),
+        PngFilter::Paeth => (4, paeth),
+    };
+    let mut out = Vec::with_capacity(row.len() + 1);
+    out.push(code);
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+        out.push(row[i].wrapping_sub(predict(left, previous[i], up_left)));
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
@@ -0,0 +1,242 @@
+use super::cli::LambdaArgs;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+
+/// An object an S3 event notification names.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Object {
+    pub bucket: String,
+    pub key: String,
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "Records")]
+    records: Vec<Record>,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    s3: S3Entity,
+}
+
+#[derive(Deserialize)]
+struct S3Entity {
+    bucket: Named,
+    object: Keyed,
+}
+
+#[derive(Deserialize)]
+struct Named {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Keyed {
+    key: String,
+}
+
+/// Reads the objects an S3 event notification names. S3 sends keys form-encoded, with `+`
+/// for spaces; they are returned decoded.
+pub fn s3_objects(event: &str) -> Result<Vec<S3Object>> {
+    let event: Event = serde_json::from_str(event).context("Event is not an S3 event notification")?;
+    event
+        .records
+        .into_iter()
+        .map(|record| Ok(S3Object { bucket: record.s3.bucket.name, key: decode_key(&record.s3.object.key)? }))
+        .collect()
+}
+
+fn decode_key(key: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(key.len());
+    let mut rest = key.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' if tail.len() >= 2 => {
+                let hex = std::str::from_utf8(&tail[..2]).ok().and_then(|h| u8::from_str_radix(h, 16).ok());
+                let Some(byte) = hex else { bail!("Malformed S3 key '{}'", key) };
+                bytes.push(byte);
+                rest = &tail[2..];
+                continue;
+            }
+            _ => bytes.push(b),
+        }
+        rest = tail;
+    }
+    String::from_utf8(bytes).with_context(|| format!("S3 key '{}' is not UTF-8", key))
+}
+
+/// Where the object with `key` is staged under `dir`, keeping its folders. Keys that don't
+/// name a file below `dir`, such as ones with `..` or a trailing `/`, are refused.
+pub fn staged_path(dir: &Path, key: &str) -> Result<PathBuf> {
+    let relative = Path::new(key);
+    if key.is_empty() || key.ends_with('/') || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        bail!("Refusing S3 key '{}', which doesn't name a file below the staging directory", key);
+    }
+    Ok(dir.join(relative))
+}
+
+/// The key an output staged at `relative`, under the output staging directory, is written to.
+pub fn output_key(prefix: &str, relative: &Path) -> String {
+    let segments: Vec<String> = relative.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    format!("{}{}", prefix, segments.join("/"))
+}
+
+/// Serves Lambda invocations until the function is shut down. Each S3 object an event names is
+/// downloaded, processed by the batch pipeline `args` configures and its outputs uploaded.
+pub fn run(args: &LambdaArgs) -> Result<()> {
+    runtime::run(args)
+}
+
+#[cfg(feature = "lambda")]
+mod runtime {
+    use super::{output_key, s3_objects, staged_path, S3Object};
+    use crate::cli::{Cli, LambdaArgs};
+    use crate::context::RunContext;
+    use crate::i18n::Msg;
+    use anyhow::{bail, Context, Result};
+    use aws_config::BehaviorVersion;
+    use aws_sdk_s3::primitives::ByteStream;
+    use aws_sdk_s3::Client;
+    use lambda_runtime::{service_fn, LambdaEvent};
+    use serde_json::{json, Value};
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    /// Where each object is downloaded to, under the input directory.
+    const INPUT_STAGING_DIR: &str = ".imagekit-lambda-in";
+    /// Where each object's outputs are written before upload, under the output directory.
+    const OUTPUT_STAGING_DIR: &str = ".imagekit-lambda-out";
+    /// Largest object downloaded, well above any image Lambda's memory could decode.
+    const MAX_OBJECT_SIZE: i64 = 1 << 30;
+
+    pub fn run(args: &LambdaArgs) -> Result<()> {
+        if env::var_os("AWS_LAMBDA_RUNTIME_API").is_none() {
+            bail!("AWS_LAMBDA_RUNTIME_API is not set; `imagekit lambda` is meant to run as a Lambda function's bootstrap");
+        }
+        let runtime = tokio::runtime::Builder::new_multi_thread().enable_all().build()?;
+        runtime.block_on(async {
+            let s3 = Client::new(&aws_config::load_defaults(BehaviorVersion::latest()).await);
+            // An error is posted to the runtime API, so Lambda retries the event or sends it to
+            // the dead-letter queue.
+            let handler = service_fn(|event: LambdaEvent<Value>| {
+                let s3 = &s3;
+                async move {
+                    let request_id = event.context.request_id.clone();
+                    let result = handle(args, s3, &event.payload.to_string()).await;
+                    if let Err(e) = &result {
+                        eprintln!("{}", Msg::InvocationFailed { id: &request_id, error: &format!("{:#}", e) });
+                    }
+                    result
+                }
+            });
+            lambda_runtime::run(handler).await.map_err(|e| anyhow::anyhow!(e))
+        })
+    }
+
+    /// Processes every object the event names and returns the uploaded outputs. Fails if any
+    /// object, or any image processed from it, fails.
+    async fn handle(args: &LambdaArgs, s3: &Client, event: &str) -> Result<Value> {
+        let mut outputs = Vec::new();
+        for object in s3_objects(event)? {
+            let bucket = args.output_bucket.as_deref().unwrap_or(&object.bucket);
+            // Outputs written back into the source bucket trigger events of their own.
+            if bucket == object.bucket && object.key.starts_with(&args.output_prefix) {
+                println!("{}", Msg::UnderOutputPrefix { bucket: &object.bucket, key: &object.key });
+                continue;
+            }
+            for key in process_object(args, s3, &object, bucket).await? {
+                outputs.push(json!({ "bucket": bucket, "key": key }));
+            }
+        }
+        Ok(json!({ "outputs": outputs }))
+    }
+
+    /// Downloads one object into the input staging directory, runs the batch pipeline on it and
+    /// uploads what it wrote. Returns the keys written.
+    async fn process_object(args: &LambdaArgs, s3: &Client, object: &S3Object, bucket: &str) -> Result<Vec<String>> {
+        let cli = &args.batch;
+        let input_stage = cli.input_dir.join(INPUT_STAGING_DIR);
+        let output_stage = cli.output_dir.join(OUTPUT_STAGING_DIR);
+        clear(&input_stage)?;
+        clear(&output_stage)?;
+
+        let result = async {
+            let source = staged_path(&input_stage, &object.key)?;
+            if let Some(parent) = source.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&source, download(s3, object).await?)
+                .with_context(|| format!("Failed to stage {}", source.display()))?;
+
+            let batch = Cli { input_dir: input_stage.clone(), output_dir: output_stage.clone(), ..cli.clone() };
+            let outcomes = tokio::task::block_in_place(|| crate::run_with(batch, RunContext::default()))?;
+            crate::ensure_no_failures(&outcomes)
+                .with_context(|| format!("Failed to process s3://{}/{}", object.bucket, object.key))?;
+
+            let mut keys = Vec::new();
+            for entry in walkdir::WalkDir::new(&output_stage).into_iter().filter_map(|e| e.ok()).filter(|e| e.file_type().is_file()) {
+                let relative = entry.path().strip_prefix(&output_stage)?;
+                let key = output_key(&args.output_prefix, relative);
+                s3.put_object()
+                    .bucket(bucket)
+                    .key(&key)
+                    .content_type(content_type(&key))
+                    .body(ByteStream::from(fs::read(entry.path())?))
+                    .send()
+                    .await
+                    .with_context(|| format!("Failed to upload s3://{}/{}", bucket, key))?;
+                keys.push(key);
+            }
+            Ok(keys)
+        }
+        .await;
+        clear(&input_stage)?;
+        clear(&output_stage)?;
+        result
+    }
+
+    async fn download(s3: &Client, object: &S3Object) -> Result<Vec<u8>> {
+        let context = || format!("Failed to download s3://{}/{}", object.bucket, object.key);
+        let response = s3.get_object().bucket(&object.bucket).key(&object.key).send().await.with_context(context)?;
+        if response.content_length().is_some_and(|len| len > MAX_OBJECT_SIZE) {
+            bail!("s3://{}/{} is larger than {} bytes", object.bucket, object.key, MAX_OBJECT_SIZE);
+        }
+        Ok(response.body.collect().await.with_context(context)?.to_vec())
+    }
+
+    fn clear(dir: &Path) -> Result<()> {
+        if dir.exists() {
+            fs::remove_dir_all(dir).with_context(|| format!("Failed to remove {}", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    /// The Content-Type to store an output under, by its extension.
+    fn content_type(key: &str) -> &'static str {
+        let ext = Path::new(key).extension().and_then(|e| e.to_str()).unwrap_or_default().to_ascii_lowercase();
+        match ext.as_str() {
+            "jpg" | "jpeg" => "image/jpeg",
+            "png" => "image/png",
+            "webp" => "image/webp",
+            "gif" => "image/gif",
+            "bmp" => "image/bmp",
+            "tif" | "tiff" => "image/tiff",
+            "json" => "application/json",
+            _ => "application/octet-stream",
+        }
+    }
+}
+
+#[cfg(not(feature = "lambda"))]
+mod runtime {
+    use crate::cli::LambdaArgs;
+    use anyhow::{bail, Result};
+
+    pub fn run(_: &LambdaArgs) -> Result<()> {
+        bail!("imagekit was built without the `lambda` feature")
+    }
+}
@@ -0,0 +1,417 @@
+use super::report::read_exif;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use exif::{In, Tag, Value};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Where lensfun installs its database, searched in order when `--lens-db` isn't given.
+const DB_DIRS: [&str; 3] = ["/usr/share/lensfun/version_1", "/usr/local/share/lensfun/version_1", "/opt/homebrew/share/lensfun/version_1"];
+/// Border points checked on each side when fitting the corrected image to the frame.
+const EDGE_SAMPLES: usize = 64;
+
+/// Parsed databases by path, so each is read once per run.
+static DATABASES: OnceLock<Mutex<HashMap<PathBuf, Arc<LensDatabase>>>> = OnceLock::new();
+
+/// Which lens errors `--lens-correct` undoes.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LensCorrect {
+    /// Distortion and lateral chromatic aberration, as far as the profile covers them.
+    Auto,
+    /// Only distortion.
+    Distortion,
+    /// Only lateral chromatic aberration (color fringes towards the corners).
+    Tca,
+}
+
+/// Lens profiles read from lensfun's XML files.
+#[derive(Debug, Default)]
+pub struct LensDatabase {
+    lenses: Vec<LensProfile>,
+}
+
+#[derive(Debug, Default)]
+struct LensProfile {
+    /// The lens's name and any translations of it, normalized by `normalize`.
+    names: Vec<String>,
+    /// Crop factor of the camera the lens was calibrated on.
+    crop_factor: Option<f32>,
+    distortion: Vec<(f32, Distortion)>,
+    tca: Vec<(f32, Tca)>,
+}
+
+/// Radial distortion, in lensfun's models, taking an undistorted radius to a distorted one.
+/// Radii are measured from the center in units of half the image's shorter side.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Distortion {
+    /// `r * (1 - k1 + k1 * r^2)`
+    Poly3 { k1: f32 },
+    /// `r * (1 + k1 * r^2 + k2 * r^4)`
+    Poly5 { k1: f32, k2: f32 },
+    /// `r * (a * r^3 + b * r^2 + c * r + 1 - a - b - c)`, as in PanoTools and Hugin.
+    PtLens { a: f32, b: f32, c: f32 },
+}
+
+/// Lateral chromatic aberration: the red and blue radii relative to green, each
+/// `r * (b * r^2 + c * r + v)`. Lensfun's `linear` model has only `v`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+struct Tca {
+    red: [f32; 3],
+    blue: [f32; 3],
+}
+
+impl Distortion {
+    fn apply(&self, r: f32) -> f32 {
+        match *self {
+            Distortion::Poly3 { k1 } => r * (1.0 - k1 + k1 * r * r),
+            Distortion::Poly5 { k1, k2 } => r * (1.0 + k1 * r * r + k2 * r.powi(4)),
+            Distortion::PtLens { a, b, c } => r * (a * r.powi(3) + b * r * r + c * r + 1.0 - a - b - c),
+        }
+    }
+
+    /// The distortion between two calibrated focal lengths, or `None` if they use different
+    /// models.
+    fn lerp(&self, other: &Distortion, t: f32) -> Option<Distortion> {
+        let mix = |x: f32, y: f32| x + (y - x) * t;
+        Some(match (*self, *other) {
+            (Distortion::Poly3 { k1 }, Distortion::Poly3 { k1: o1 }) => Distortion::Poly3 { k1: mix(k1, o1) },
+            (Distortion::Poly5 { k1, k2 }, Distortion::Poly5 { k1: o1, k2: o2 }) => {
+                Distortion::Poly5 { k1: mix(k1, o1), k2: mix(k2, o2) }
+            }
+            (Distortion::PtLens { a, b, c }, Distortion::PtLens { a: oa, b: ob, c: oc }) => {
+                Distortion::PtLens { a: mix(a, oa), b: mix(b, ob), c: mix(c, oc) }
+            }
+            _ => return None,
+        })
+    }
+}
+
+impl Tca {
+    /// Scale of channel `c`'s radius relative to green at radius `r`.
+    fn scale(&self, c: usize, r: f32) -> f32 {
+        let [v, cc, b] = match c {
+            0 => self.red,
+            2 => self.blue,
+            _ => return 1.0,
+        };
+        b * r * r + cc * r + v
+    }
+
+    fn lerp(&self, other: &Tca, t: f32) -> Tca {
+        let mix = |x: [f32; 3], y: [f32; 3]| [0, 1, 2].map(|i| x[i] + (y[i] - x[i]) * t);
+        Tca { red: mix(self.red, other.red), blue: mix(self.blue, other.blue) }
+    }
+}
+
+/// Reads the lensfun database at `path`, a directory of XML files or one file, or from where
+/// lensfun installs it when `path` is `None`.
+pub fn load(path: Option<&Path>) -> Result<Arc<LensDatabase>> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => DB_DIRS
+            .iter()
+            .map(PathBuf::from)
+            .find(|dir| dir.is_dir())
+            .context("No lensfun database found; install lensfun or point --lens-db at its XML files")?,
+    };
+    let mut cache = DATABASES.get_or_init(Default::default).lock().unwrap();
+    if let Some(db) = cache.get(&path) {
+        return Ok(Arc::clone(db));
+    }
+    let files: Vec<PathBuf> = if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(&path)
+            .with_context(|| format!("Failed to read lens database {}", path.display()))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("xml")))
+            .collect();
+        files.sort();
+        files
+    } else {
+        vec![path.clone()]
+    };
+    let mut db = LensDatabase::default();
+    for file in &files {
+        let xml = fs::read_to_string(file).with_context(|| format!("Failed to read lens database {}", file.display()))?;
+        db.lenses.extend(parse(&xml).lenses);
+    }
+    if db.lenses.is_empty() {
+        bail!("No lens profiles in {}", path.display());
+    }
+    let db = Arc::new(db);
+    cache.insert(path, Arc::clone(&db));
+    Ok(db)
+}
+
+/// Parses lensfun's XML, keeping each lens's names, crop factor and the distortion and TCA
+/// calibrations. Anything else, such as vignetting or cameras, is skipped.
+fn parse(xml: &str) -> LensDatabase {
+    let lenses = elements(xml, "lens")
+        .map(|lens| {
+            let distortion = tags(lens, "distortion")
+                .filter_map(|attrs| {
+                    let get = |name: &str| attribute(attrs, name).and_then(|v| v.parse::<f32>().ok());
+                    let model = match attribute(attrs, "model")? {
+                        "poly3" => Distortion::Poly3 { k1: get("k1")? },
+                        "poly5" => Distortion::Poly5 { k1: get("k1")?, k2: get("k2").unwrap_or(0.0) },
+                        "ptlens" => Distortion::PtLens {
+                            a: get("a").unwrap_or(0.0),
+                            b: get("b").unwrap_or(0.0),
+                            c: get("c").unwrap_or(0.0),
+                        },
+                        _ => return None,
+                    };
+                    Some((get("focal")?, model))
+                })
+                .collect();
+            let tca = tags(lens, "tca")
+                .filter_map(|attrs| {
+                    let get = |name: &str, default: f32| {
+                        attribute(attrs, name).map_or(Some(default), |v| v.parse::<f32>().ok())
+                    };
+                    let tca = match attribute(attrs, "model")? {
+                        "linear" => Tca { red: [get("kr", 1.0)?, 0.0, 0.0], blue: [get("kb", 1.0)?, 0.0, 0.0] },
+                        "poly3" => Tca {
+                            red: [get("vr", 1.0)?, get("cr", 0.0)?, get("br", 0.0)?],
+                            blue: [get("vb", 1.0)?, get("cb", 0.0)?, get("bb", 0.0)?],
+                        },
+                        _ => return None,
+                    };
+                    Some((attribute(attrs, "focal")?.parse().ok()?, tca))
+                })
+                .collect();
+            LensProfile {
+                names: elements(lens, "model").map(|name| normalize(&unescape(name))).collect(),
+                crop_factor: elements(lens, "cropfactor").next().and_then(|v| v.trim().parse().ok()),
+                distortion,
+                tca,
+            }
+        })
+        .filter(|lens| !lens.names.is_empty())
+        .collect();
+    LensDatabase { lenses }
+}
+
+impl LensDatabase {
+    /// The profile for the lens EXIF calls `name`: one with the same name, or else the one
+    /// with the longest name whose words all appear in it.
+    fn find(&self, name: &str) -> Option<&LensProfile> {
+        let name = normalize(name);
+        let words: Vec<&str> = name.split(' ').collect();
+        self.lenses.iter().find(|lens| lens.names.contains(&name)).or_else(|| {
+            self.lenses
+                .iter()
+                .flat_map(|lens| lens.names.iter().map(move |n| (n, lens)))
+                .filter(|(n, _)| n.split(' ').all(|word| words.contains(&word)))
+                .max_by_key(|(n, _)| n.len())
+                .map(|(_, lens)| lens)
+        })
+    }
+}
+
+impl LensProfile {
+    /// The distortion at `focal`, interpolated between the nearest calibrations.
+    fn distortion_at(&self, focal: f32) -> Option<Distortion> {
+        let (below, above) = bracket(&self.distortion, focal)?;
+        match (below, above) {
+            ((f0, d0), (f1, d1)) if f1 > f0 => d0.lerp(&d1, (focal - f0) / (f1 - f0)).or(Some(d0)),
+            ((_, d0), _) => Some(d0),
+        }
+    }
+
+    fn tca_at(&self, focal: f32) -> Option<Tca> {
+        let (below, above) = bracket(&self.tca, focal)?;
+        match (below, above) {
+            ((f0, t0), (f1, t1)) if f1 > f0 => Some(t0.lerp(&t1, (focal - f0) / (f1 - f0))),
+            ((_, t0), _) => Some(t0),
+        }
+    }
+}
+
+/// The calibrations at or just below and above `focal`, clamped to the calibrated range.
+fn bracket<T: Copy>(calibrations: &[(f32, T)], focal: f32) -> Option<((f32, T), (f32, T))> {
+    let below = calibrations.iter().filter(|(f, _)| *f <= focal).max_by(|a, b| a.0.total_cmp(&b.0));
+    let above = calibrations.iter().filter(|(f, _)| *f >= focal).min_by(|a, b| a.0.total_cmp(&b.0));
+    match (below, above) {
+        (Some(&b), Some(&a)) => Some((b, a)),
+        (Some(&x), None) | (None, Some(&x)) => Some((x, x)),
+        (None, None) => None,
+    }
+}
+
+/// Undoes the distortion and color fringing of the lens `path`'s EXIF data names, at its
+/// focal length, and scales the result to fill the frame. Returns the corrected image and the
+/// lens's name, or `None` if the EXIF data doesn't name a lens in `db` or the profile has
+/// nothing to correct.
+pub fn correct(img: &DynamicImage, path: &Path, mode: LensCorrect, db: &LensDatabase) -> Option<(DynamicImage, String)> {
+    let exif = read_exif(path)?;
+    let lens_name = match exif.get_field(Tag::LensModel, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(parts)) => String::from_utf8_lossy(parts.first()?).trim().to_string(),
+        _ => return None,
+    };
+    let focal = match exif.get_field(Tag::FocalLength, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Rational(values)) => values.first()?.to_f64() as f32,
+        _ => return None,
+    };
+    let lens = db.find(&lens_name)?;
+    let distortion = lens.distortion_at(focal).filter(|_| mode != LensCorrect::Tca);
+    let tca = lens.tca_at(focal).filter(|_| mode != LensCorrect::Distortion);
+    if distortion.is_none() && tca.is_none() {
+        return None;
+    }
+    // A camera with a smaller sensor than the calibration's sees less of the lens's image.
+    let crop = exif.get_field(Tag::FocalLengthIn35mmFilm, In::PRIMARY).and_then(|f| f.value.get_uint(0));
+    let scale = match (crop, lens.crop_factor) {
+        (Some(equivalent), Some(calibrated)) if equivalent > 0 && focal > 0.0 => calibrated / (equivalent as f32 / focal),
+        _ => 1.0,
+    };
+    let warp = Warp { distortion, tca, scale, zoom: 1.0 };
+    Some((warp.fitted(img.width(), img.height()).apply(img), lens_name))
+}
+
+/// Maps corrected pixels back to where the lens put them.
+struct Warp {
+    distortion: Option<Distortion>,
+    tca: Option<Tca>,
+    /// Converts radii from this image's units to the calibration's.
+    scale: f32,
+    /// How much of the source the corrected image spans; above 1 it takes in more.
+    zoom: f32,
+}
+
+impl Warp {
+    /// The source position of channel `c` for the corrected pixel at `(x, y)`, in pixels.
+    fn source(&self, x: f32, y: f32, c: usize, width: f32, height: f32) -> (f32, f32) {
+        let half = width.min(height) / 2.0;
+        let (nx, ny) = ((x + 0.5 - width / 2.0) / half * self.zoom, (y + 0.5 - height / 2.0) / half * self.zoom);
+        let r = nx.hypot(ny) * self.scale;
+        let mut ratio = 1.0;
+        if r > 0.0 {
+            let distorted = self.distortion.map_or(r, |d| d.apply(r));
+            ratio = distorted / r * self.tca.map_or(1.0, |t| t.scale(c, distorted));
+        }
+        (nx * ratio * half + width / 2.0 - 0.5, ny * ratio * half + height / 2.0 - 0.5)
+    }
+
+    /// The largest zoom at which every border pixel of the corrected image still comes from
+    /// inside the source, so the corners aren't left empty.
+    fn fitted(mut self, width: u32, height: u32) -> Warp {
+        let (w, h) = (width as f32, height as f32);
+        let border: Vec<(f32, f32)> = (0..=EDGE_SAMPLES)
+            .flat_map(|i| {
+                let t = i as f32 / EDGE_SAMPLES as f32;
+                [(t * (w - 1.0), 0.0), (t * (w - 1.0), h - 1.0), (0.0, t * (h - 1.0)), (w - 1.0, t * (h - 1.0))]
+            })
+            .collect();
+        let fits = |warp: &Warp| {
+            border.iter().all(|&(x, y)| {
+                (0..3).all(|c| {
+                    let (sx, sy) = warp.source(x, y, c, w, h);
+                    sx >= -0.5 && sy >= -0.5 && sx <= w - 0.5 && sy <= h - 0.5
+                })
+            })
+        };
+        let (mut low, mut high) = (0.25f32, 2.0f32);
+        for _ in 0..24 {
+            self.zoom = (low + high) / 2.0;
+            if fits(&self) {
+                low = self.zoom;
+            } else {
+                high = self.zoom;
+            }
+        }
+        self.zoom = low;
+        self
+    }
+
+    /// Resamples `img` bilinearly, channel by channel.
+    fn apply(&self, img: &DynamicImage) -> DynamicImage {
+        let rgba = img.to_rgba8();
+        let (width, height) = (rgba.width(), rgba.height());
+        let (w, h) = (width as f32, height as f32);
+        let sample = |sx: f32, sy: f32, c: usize| {
+            let (sx, sy) = (sx.clamp(0.0, w - 1.0), sy.clamp(0.0, h - 1.0));
+            let (x0, y0) = (sx.floor() as u32, sy.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+            let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+            let at = |x: u32, y: u32| rgba.get_pixel(x, y)[c] as f32;
+            let top = at(x0, y0) * (1.0 - fx) + at(x1, y0) * fx;
+            let bottom = at(x0, y1) * (1.0 - fx) + at(x1, y1) * fx;
+            (top * (1.0 - fy) + bottom * fy).round() as u8
+        };
+        let corrected = RgbaImage::from_fn(width, height, |x, y| {
+            let (x, y) = (x as f32, y as f32);
+            let green = self.source(x, y, 1, w, h);
+            let [r, b] = [0, 2].map(|c| {
+                let (sx, sy) = self.source(x, y, c, w, h);
+                sample(sx, sy, c)
+            });
+            Rgba([r, sample(green.0, green.1, 1), b, sample(green.0, green.1, 3)])
+        });
+        let corrected = DynamicImage::ImageRgba8(corrected);
+        if img.color().has_alpha() { corrected } else { DynamicImage::ImageRgb8(corrected.to_rgb8()) }
+    }
+}
+
+/// Lowercases and collapses whitespace, so names match however EXIF spaces them.
+fn normalize(name: &str) -> String {
+    name.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// The contents of every `<name>...</name>` element in `xml`, with or without attributes.
+fn elements<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    let (open, close) = (format!("<{}", name), format!("</{}>", name));
+    let mut rest = xml;
+    std::iter::from_fn(move || loop {
+        let start = rest.find(&open)?;
+        let after = &rest[start + open.len()..];
+        // `<lens` must not match `<lensdatabase`.
+        if !after.starts_with(['>', ' ', '\t', '\r', '\n']) {
+            rest = after;
+            continue;
+        }
+        let body_start = after.find('>')? + 1;
+        let end = after.find(&close)?;
+        let body = after.get(body_start..end)?;
+        rest = &after[end + close.len()..];
+        return Some(body);
+    })
+}
+
+/// The attributes of every `<name .../>` tag in `xml`.
+fn tags<'a>(xml: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    let open = format!("<{} ", name);
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        let start = rest.find(&open)?;
+        let after = &rest[start + open.len()..];
+        let end = after.find('>')?;
+        rest = &after[end..];
+        Some(after[..end].trim_end_matches('/'))
+    })
+}
+
+/// The value of `name="..."` in a tag's attributes.
+fn attribute<'a>(attrs: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = attrs;
+    loop {
+        let at = rest.find(name)?;
+        let preceded = rest[..at].chars().next_back().is_none_or(char::is_whitespace);
+        let after = rest[at + name.len()..].trim_start();
+        if let (true, Some(value)) = (preceded, after.strip_prefix('=')) {
+            let value = value.trim_start();
+            let quote = value.chars().next()?;
+            if quote == '"' || quote == '\'' {
+                let value = &value[1..];
+                return value.find(quote).map(|end| &value[..end]);
+            }
+        }
+        rest = &rest[at + name.len()..];
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+}
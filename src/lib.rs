@@ -1,76 +1,582 @@
+pub mod adjust;
+pub mod appicons;
+pub mod buckets;
+pub mod aspect;
 pub mod assets;
+pub mod autoformat;
+pub mod baseline;
+pub mod burst;
+pub mod c2pa;
+pub mod cbz;
+pub mod cdn;
+pub mod chunk;
+pub mod classify;
 pub mod cli;
+pub mod compare;
+pub mod config;
+pub mod context;
+pub mod confirm;
+pub mod console;
+pub mod decode;
+pub mod density;
+pub mod discard;
+pub mod doctor;
+pub mod encrypt;
 pub mod errors;
+pub mod existing;
+pub mod fastio;
+pub mod focus;
+pub mod hdr;
+pub mod i18n;
+pub mod imposition;
+pub mod interlace;
+pub mod lambda;
+pub mod lens;
+pub mod meta;
+pub mod metadata;
+pub mod nineslice;
+pub mod order;
+pub mod overlay;
+pub mod pages;
+pub mod palette;
+pub mod perspective;
+pub mod plan;
+pub mod pixelart;
+pub mod preview;
 pub mod processor;
+pub mod prune;
+pub mod pyramid;
+pub mod random;
+pub mod rawdump;
+pub mod references;
+pub mod report;
+pub mod rules;
+pub mod safety;
+pub mod sample;
+pub mod seamcarve;
+pub mod sequence;
+pub mod sharpness;
+pub mod sidecar;
+pub mod ssg;
+pub mod stitch;
+pub mod sync;
+pub mod tagging;
+pub mod testing;
+pub mod thumbs;
+pub mod timelapse;
+pub mod trim;
+pub mod upscale;
+pub mod uptodate;
+pub mod worker;
+pub mod zip;
+pub mod zones;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use rayon::prelude::*;
 use rusttype::Font;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use assets::Asset;
-use cli::Cli;
-use processor::process_image;
+use cli::{Cli, Command, IfExists, SafetyMode};
+use console::{FileTable, Progress};
+use context::RunContext;
+use i18n::{Lang, Msg};
+use processor::{process_image, Processed};
+use report::{Outcome, Report};
+use sidecar::SidecarBundle;
+
+/// Returns whether a path is a file with an extension imagekit can process.
+pub fn is_supported_image(path: &Path) -> bool {
+    path.is_file()
+        && path.extension().and_then(|s| s.to_str()).is_some_and(|s| {
+            matches!(
+                s.to_lowercase().as_str(),
+                "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tif" | "tiff" | "qoi"
+                    | "ppm" | "pgm" | "pbm" | "pam" | "tga" | "dds" | "ico"
+            )
+        })
+}
+
+/// Returns where the walk of `input_dir` finds `output_dir`, if it lies strictly inside it.
+pub(crate) fn nested_output_dir(input_dir: &Path, output_dir: &Path) -> Option<PathBuf> {
+    let input = fs::canonicalize(input_dir).ok()?;
+    let output = fs::canonicalize(output_dir).ok()?;
+    let relative = output.strip_prefix(&input).ok()?;
+    (!relative.as_os_str().is_empty()).then(|| input_dir.join(relative))
+}
+
+/// Runs a subcommand.
+pub fn run_command(command: Command) -> Result<()> {
+    // Subcommands built around a batch run take its `--lang`; the rest follow the locale.
+    let lang = match &command {
+        Command::HdrMerge(args) => args.batch.lang,
+        Command::Lambda(args) => args.batch.lang,
+        _ => None,
+    };
+    i18n::set_lang(lang.unwrap_or_else(Lang::from_env));
+    match command {
+        Command::Ssg(args) => ssg::run(&args),
+        Command::Meta(args) => meta::run(&args),
+        Command::Imposition(args) => imposition::run(&args),
+        Command::Diff(args) => compare::run(&args),
+        Command::Doctor(args) => doctor::run(&args),
+        Command::PreviewWatermark(args) => preview::run(&args),
+        Command::AppIcons(args) => appicons::run(&args),
+        Command::Cbz(args) => cbz::run(&args),
+        Command::Stitch(args) => stitch::run(&args),
+        Command::HdrMerge(args) => hdr::run(&args),
+        Command::FocusStack(args) => focus::run(&args),
+        Command::Timelapse(args) => timelapse::run(&args),
+        Command::Lambda(args) => lambda::run(&args),
+        Command::Worker(args) => worker::run(&args),
+    }
+}
 
 // The `run` function is now part of the library's public API.
 pub fn run(cli: Cli) -> Result<()> {
+    run_with(cli, RunContext::default()).map(|_| ())
+}
+
+/// Runs a batch like [`run`], also drawing the watermark layers of `context`, and returns each
+/// input's outcome. The rest of the context is worked out from the inputs. A failed image is
+/// logged and reported as [`Outcome::Failed`] rather than failing the run.
+pub fn run_with(mut cli: Cli, mut context: RunContext) -> Result<Vec<(PathBuf, Outcome)>> {
+    cli.apply_preset();
+    i18n::set_lang(cli.lang.unwrap_or_else(Lang::from_env));
+    console::init(cli.color);
+
     // Check and create the output directory if it doesn't exist.
-    if !cli.output_dir.exists() {
+    if !cli.output_dir.exists() && !cli.dry_run {
         fs::create_dir_all(&cli.output_dir)?;
     }
 
-    let primary_font_data = Asset::get("Roboto-Regular.ttf")
-        .context("Could not find font 'Roboto-Regular.ttf'")?;
-    let primary_font_vec: Vec<u8> = primary_font_data.data.into_owned();
-    let primary_font = Font::try_from_vec(primary_font_vec)
-        .context("Error constructing primary font")?;
-
-    let cjk_font_data = Asset::get("SourceHanSansSC-Regular.otf")
-        .context("Could not find CJK font 'SourceHanSansSC-Regular.otf'")?;
-    let cjk_font_vec: Vec<u8> = cjk_font_data.data.into_owned();
-    let cjk_font = Font::try_from_vec(cjk_font_vec)
-        .context("Error constructing CJK font")?;
-
-    let thai_font_data = Asset::get("NotoSansThai-Regular.ttf")
-        .context("Could not find Thai font 'NotoSansThai-Regular.ttf'")?;
-    let thai_font_vec: Vec<u8> = thai_font_data.data.into_owned();
-    let thai_font = Font::try_from_vec(thai_font_vec)
-        .context("Error constructing Thai font")?;
-
-    let fonts = Arc::new(vec![primary_font, cjk_font, thai_font]);
+    let fonts = Arc::new(if cli.font_chain.is_empty() {
+        assets::load_fonts_with(&cli.fonts)?
+    } else {
+        assets::load_chain(&cli.font_chain)?
+    });
+    if cli.pdf_dpi.is_some() && !cfg!(feature = "pdf") {
+        bail!("imagekit was built without the `pdf` feature, which --pdf-dpi needs");
+    }
+    // A missing lens database fails the run up front rather than every image.
+    if cli.lens_correct.is_some() {
+        lens::load(cli.lens_db.as_deref())?;
+    }
 
-    // Collect all image paths from the input directory.
+    // Collect all image paths from the input directory. Outputs must never be taken for inputs:
+    // an output directory inside the input directory is skipped, and so are temporary files an
+    // interrupted in-place run left behind.
+    let nested_output = nested_output_dir(&cli.input_dir, &cli.output_dir);
     let image_paths: Vec<PathBuf> = walkdir::WalkDir::new(&cli.input_dir)
         .into_iter()
+        .filter_entry(|e| Some(e.path()) != nested_output.as_deref() && !discard::is_temp_file(e.path()))
         .filter_map(|e| e.ok())
         .filter(|e| {
-            e.path().is_file() &&
-                e.path().extension().and_then(|s| s.to_str()).map_or(false, |s| {
-                    matches!(s.to_lowercase().as_str(), "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp")
-                })
+            is_supported_image(e.path())
+                || (cli.fast_thumbs && thumbs::is_raw(e.path()))
+                || (cli.pdf_dpi.is_some() && pages::is_pdf(e.path()))
         })
         .map(|e| e.path().to_path_buf())
         .collect();
 
-    if image_paths.is_empty() {
-        println!("No images found in the input directory.");
-        return Ok(());
+    // Only one of a RAW+JPEG pair is an input, unless both are wanted.
+    let image_paths = if cli.fast_thumbs {
+        let total = image_paths.len();
+        let (kept, twins) = thumbs::resolve_raw_pairs(cli.raw_jpeg_policy, image_paths);
+        if kept.len() < total {
+            println!("{}", Msg::RawJpegPairsSkipped { skipped: total - kept.len() });
+        }
+        context.raw_twins = twins;
+        kept
+    } else {
+        image_paths
+    };
+
+    // Pruning considers every input, including those the filters below leave out.
+    let all_inputs = image_paths.clone();
+    // Numbering covers every input too, so a file keeps its name whichever filters are used.
+    if let Some(template) = &cli.sequence_rename {
+        context.sequence_names = sequence::assign(template, &all_inputs);
+    }
+    if let Some(size) = cli.chunk {
+        context.chunk_dirs = chunk::assign(size, &all_inputs);
+    }
+
+    let image_paths = if cli.require_keywords.is_empty() && cli.exclude_keywords.is_empty() {
+        image_paths
+    } else {
+        let total = image_paths.len();
+        let kept: Vec<PathBuf> = image_paths
+            .into_par_iter()
+            .filter(|p| metadata::passes_keyword_filter(p, &cli.require_keywords, &cli.exclude_keywords))
+            .collect();
+        println!("{}", Msg::KeywordFilterExcluded { excluded: total - kept.len(), total });
+        kept
+    };
+
+    let image_paths = if cli.min_rating.is_none() && !cli.only_flagged {
+        image_paths
+    } else {
+        let total = image_paths.len();
+        let kept: Vec<PathBuf> = image_paths
+            .into_par_iter()
+            .filter(|p| metadata::passes_rating_filter(p, cli.min_rating, cli.only_flagged))
+            .collect();
+        println!("{}", Msg::RatingFilterExcluded { excluded: total - kept.len(), total });
+        kept
+    };
+
+    let image_paths = if cli.only_aspect.is_empty() {
+        image_paths
+    } else {
+        let total = image_paths.len();
+        let tolerance = cli.square_tolerance();
+        let kept: Vec<PathBuf> = image_paths
+            .into_par_iter()
+            .filter(|p| aspect::passes_aspect_filter(p, &cli.only_aspect, tolerance))
+            .collect();
+        println!("{}", Msg::AspectFilterExcluded { excluded: total - kept.len(), total });
+        kept
+    };
+
+    let pages = references::expand_globs(&cli.referenced_by)?;
+    let image_paths = if cli.referenced_by.is_empty() {
+        image_paths
+    } else {
+        // Absolute references like `/img/a.png` are resolved against the input directory.
+        let referenced = references::referenced_files(&pages, &cli.input_dir)?;
+        let total = image_paths.len();
+        let kept: Vec<PathBuf> = image_paths
+            .into_iter()
+            .filter(|p| fs::canonicalize(p).is_ok_and(|c| referenced.contains(&c)))
+            .collect();
+        println!("{}", Msg::NotReferenced { skipped: total - kept.len(), total });
+        kept
+    };
+
+    let (image_paths, bursts) = match cli.pick_best_of_burst {
+        Some(window) => {
+            let (kept, bursts) = burst::pick_best(image_paths, window, cli.burst_similarity.0);
+            let discarded = bursts.iter().map(|b| b.discarded.len()).sum();
+            println!("{}", Msg::BurstsFound { bursts: bursts.len(), discarded });
+            let name = |path: &Path| path.strip_prefix(&cli.input_dir).unwrap_or(path).display().to_string();
+            for burst in &bursts {
+                let discarded: Vec<String> = burst.discarded.iter().map(|p| name(p)).collect();
+                println!("{}", Msg::BurstKept { kept: &name(&burst.kept), discarded: &discarded.join(", ") });
+            }
+            (kept, bursts)
+        }
+        None => (image_paths, Vec::new()),
+    };
+
+    // Sampling comes after the filters, so the sample is of what a full run would process.
+    let image_paths = match cli.sample {
+        Some(size) => {
+            let total = image_paths.len();
+            let kept = sample::sample(image_paths, size, cli.sample_seed, &cli.input_dir);
+            println!("{}", Msg::Sampled { kept: kept.len(), total });
+            kept
+        }
+        None => image_paths,
+    };
+
+    let (image_paths, unchanged) = match &cli.baseline {
+        Some(path) => {
+            let total = image_paths.len();
+            let split = baseline::split(&cli, &context, image_paths, path)?;
+            if split.same_settings {
+                println!("{}", Msg::BaselineUnchanged { unchanged: split.unchanged.len(), total });
+            } else {
+                println!("{}", Msg::BaselineSettingsChanged);
+            }
+            (split.to_process, split.unchanged)
+        }
+        None => (image_paths, Vec::new()),
+    };
+    // Conflicts with `--baseline`, so nothing is unchanged yet.
+    let (image_paths, unchanged) = if cli.skip_up_to_date {
+        let total = image_paths.len();
+        let split = uptodate::split(&cli, &context, image_paths)?;
+        println!("{}", Msg::UpToDate { skipped: split.up_to_date.len(), total });
+        (split.to_process, split.up_to_date)
+    } else {
+        (image_paths, unchanged)
+    };
+    let (image_paths, unchanged) = match cli.if_exists {
+        IfExists::Overwrite | IfExists::Rename => (image_paths, unchanged),
+        IfExists::Skip => {
+            let total = image_paths.len();
+            let split = existing::split(&cli, &context, image_paths)?;
+            println!("{}", Msg::ExistingSkipped { skipped: split.existing.len(), total });
+            let mut unchanged = unchanged;
+            unchanged.extend(split.existing);
+            (split.to_process, unchanged)
+        }
+        IfExists::Error => {
+            let split = existing::split(&cli, &context, image_paths)?;
+            split.ensure_none()?;
+            (split.to_process, unchanged)
+        }
+    };
+
+    if cli.dry_run {
+        plan::print(&cli, &context, &image_paths)?;
+        if cli.prune || cli.prune_dry_run {
+            prune::prune(&cli, &context, &all_inputs, true)?;
+        }
+        return Ok(Vec::new());
+    }
+    if !cli.prune_dry_run {
+        confirm::confirm(&cli, &context, &image_paths, &all_inputs)?;
+    }
+    if cli.prune || cli.prune_dry_run {
+        prune::prune(&cli, &context, &all_inputs, cli.prune_dry_run)?;
+    }
+
+    let sync_plan = if cli.sync { Some(sync::plan(&cli, &context, &image_paths)?) } else { None };
+    let image_paths = match &sync_plan {
+        Some(plan) => plan.to_process.clone(),
+        None => image_paths,
+    };
+
+    // With a baseline, the report and archive still cover the unchanged images.
+    if image_paths.is_empty() && unchanged.is_empty() {
+        match sync_plan {
+            Some(plan) => {
+                println!("{}", Msg::AlreadyInSync);
+                plan.finish(&cli, &[])?;
+            }
+            None => println!("{}", Msg::NoImages),
+        }
+        return Ok(Vec::new());
+    }
+
+    let mut image_paths = image_paths;
+    if let Some(order) = cli.order {
+        order::sort(&mut image_paths, order, &cli.input_dir);
+    }
+
+    println!("{}", Msg::FoundImages(image_paths.len()));
+    let table = FileTable::new(&cli.input_dir, &cli.output_dir, &image_paths, cli.quiet);
+    let mut bundle = cli.sidecar_bundle.as_ref().map(|_| SidecarBundle::default());
+    let staging = if cli.encrypt.is_empty() { None } else { Some(encrypt::StagingDir::create()?) };
+    context.staging_dir = staging.as_ref().map(|dir| dir.path().to_path_buf());
+    // Taken before `--total-size` lowers the quality, so reruns with the same options match.
+    let settings = sync::settings_fingerprint(&cli, &context);
+
+    let mut results = process_all(&cli, &context, &image_paths, &fonts, &table, bundle.as_ref());
+    // With a size budget, everything is re-encoded at a lower quality until the outputs fit.
+    if let Some(budget) = cli.total_size {
+        loop {
+            let total: u64 = results
+                .iter()
+                .flat_map(|(_, outcome)| match outcome {
+                    Outcome::Processed(processed) => processed.outputs.as_slice(),
+                    _ => &[],
+                })
+                .filter_map(|out| fs::metadata(out).ok().map(|m| m.len()))
+                .sum();
+            if total <= budget.0 {
+                break;
+            }
+            let quality = context.quality.unwrap_or(cli.quality);
+            if quality <= MIN_BUDGET_QUALITY {
+                console::warn(Msg::BudgetUnreachable { total, budget: budget.0 });
+                break;
+            }
+            let quality = quality.saturating_sub(BUDGET_QUALITY_STEP).max(MIN_BUDGET_QUALITY);
+            context.quality = Some(quality);
+            println!("{}", Msg::OverBudget { total, budget: budget.0, quality });
+            // `--if-exists rename` picks a free name each pass; with this pass's outputs gone, the
+            // next one takes the same names instead of writing numbered copies beside them.
+            if cli.if_exists == IfExists::Rename {
+                remove_outputs(&results)?;
+            }
+            bundle = cli.sidecar_bundle.as_ref().map(|_| SidecarBundle::default());
+            results = process_all(&cli, &context, &image_paths, &fonts, &table, bundle.as_ref());
+        }
+    }
+    results.extend(unchanged.iter().map(|(src, out)| (src.clone(), Outcome::Unchanged(out.clone()))));
+    results.extend(bursts.iter().flat_map(|b| b.discarded.iter().map(|src| (src.clone(), Outcome::Discarded(b.kept.clone())))));
+    // Each output with its source; with `--widths` a source appears once per width, widest last.
+    let written: Vec<(PathBuf, PathBuf)> = results
+        .iter()
+        .flat_map(|(src, outcome)| match outcome {
+            Outcome::Processed(processed) => {
+                processed.outputs.iter().map(|out| (src.clone(), out.clone())).collect()
+            }
+            _ => Vec::new(),
+        })
+        .collect();
+
+    println!("{}", Msg::ProcessingComplete);
+    let processed: Vec<&PathBuf> =
+        results.iter().filter(|(_, outcome)| matches!(outcome, Outcome::Processed(_))).map(|(src, _)| src).collect();
+    if cli.skip_up_to_date {
+        println!("{}", Msg::UpToDateSummary { processed: processed.len(), skipped: unchanged.len() });
+    }
+    if !processed.is_empty() {
+        let size = |p: &PathBuf| fs::metadata(p).map_or(0, |m| m.len());
+        console::print_size_summary(
+            processed.len(),
+            processed.iter().map(|src| size(src)).sum(),
+            written.iter().map(|(_, out)| size(out)).sum(),
+        );
+    }
+    if let Some(mode) = cli.safety_filter {
+        let suspect = results
+            .iter()
+            .filter(|(_, outcome)| match outcome {
+                Outcome::Blocked(_) => true,
+                Outcome::Processed(processed) => processed.safety.is_some_and(|check| check.suspect),
+                _ => false,
+            })
+            .count();
+        println!("{}", Msg::SafetyFiltered { suspect, blocked: mode == SafetyMode::Block });
+    }
+
+    if cli.min_sharpness.is_some() {
+        let count = results.iter().filter(|(_, outcome)| matches!(outcome, Outcome::Blurry(_))).count();
+        match &cli.quarantine_dir {
+            Some(dir) => println!("{}", Msg::BlurryQuarantined { count, dir }),
+            None => println!("{}", Msg::BlurrySkipped(count)),
+        }
+    }
+
+    if let (Some(bundle), Some(path)) = (bundle, &cli.sidecar_bundle) {
+        bundle.write(path)?;
     }
 
-    println!("Found {} images to process.", image_paths.len());
+    if let Some(path) = &cli.zip {
+        let outputs: Vec<&Path> = written.iter().chain(&unchanged).map(|(_, out)| out.as_path()).collect();
+        zip::pack(path, &cli.output_dir, &outputs)?;
+        println!("{}", Msg::WroteZip { path, files: outputs.len() });
+    }
+
+    if cli.report.is_some() || !cli.group_by.is_empty() {
+        let report = Report::new(&results, &cli, settings)?;
+        report.print_groups();
+        if let Some(count) = report.geotagged {
+            println!("{}", Msg::Geotagged { count, processed: report.processed });
+        }
+        if let Some(path) = &cli.report {
+            report.write(path)?;
+            println!("{}", Msg::WroteReport(path));
+        }
+    }
 
-    // Use Rayon to process images in parallel.
-    image_paths.par_iter().for_each(move |path| {
+    if let Some(plan) = sync_plan {
+        plan.finish(&cli, &written)?;
+    }
+
+    if cli.rewrite_links {
+        let outputs: HashMap<PathBuf, PathBuf> = written
+            .iter()
+            .chain(&unchanged)
+            .filter_map(|(src, out)| fs::canonicalize(src).ok().map(|c| (c, out.clone())))
+            .collect();
+        let changed = references::rewrite_links(&pages, &cli.input_dir, &outputs)?;
+        println!("{}", Msg::RewroteLinks(changed));
+    }
+
+    if let (Some(provider), Some(base_url)) = (cli.purge_cdn, &cli.purge_base_url) {
+        let urls: Vec<String> = written
+            .iter()
+            .filter_map(|(_, p)| cdn::public_url(base_url, &cli.output_dir, p))
+            .collect();
+        cdn::purge(provider, &urls).context("CDN purge failed")?;
+        println!("{}", Msg::PurgedUrls(urls.len()));
+    }
+    Ok(results)
+}
+
+/// Fails, naming the first failed image, if any of `results` failed. For callers such as
+/// `lambda` and `worker` that must not report a partly failed run as done.
+pub fn ensure_no_failures(results: &[(PathBuf, Outcome)]) -> Result<()> {
+    let failed: Vec<(&PathBuf, &String)> = results
+        .iter()
+        .filter_map(|(src, outcome)| match outcome {
+            Outcome::Failed(error) => Some((src, error)),
+            _ => None,
+        })
+        .collect();
+    match failed.first() {
+        Some((src, error)) => bail!("{} of {} images failed; {}: {}", failed.len(), results.len(), src.display(), error),
+        None => Ok(()),
+    }
+}
+
+/// `--total-size` lowers the quality by this much per attempt, and no further than the minimum.
+const BUDGET_QUALITY_STEP: u8 = 10;
+const MIN_BUDGET_QUALITY: u8 = 30;
+
+/// Deletes the outputs `results` lists, along with their sidecars and raw layouts.
+fn remove_outputs(results: &[(PathBuf, Outcome)]) -> Result<()> {
+    let outputs = results.iter().flat_map(|(_, outcome)| match outcome {
+        Outcome::Processed(processed) => processed.outputs.as_slice(),
+        _ => &[],
+    });
+    for output in outputs {
+        for file in [sidecar::sidecar_path(output), rawdump::layout_path(output), output.clone()] {
+            if file.exists() {
+                fs::remove_file(&file).with_context(|| format!("Failed to remove {}", file.display()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Processes the images in parallel, printing a line for each, and returns their outcomes in
+/// the order of `image_paths`.
+fn process_all(
+    cli: &Cli,
+    context: &RunContext,
+    image_paths: &[PathBuf],
+    fonts: &Arc<Vec<Font<'static>>>,
+    table: &FileTable,
+    bundle: Option<&SidecarBundle>,
+) -> Vec<(PathBuf, Outcome)> {
+    let process = |path: &PathBuf| {
         // Clone the Arc pointer, which is a lightweight operation.
-        let fonts_clone = Arc::clone(&fonts);
+        let fonts_clone = Arc::clone(fonts);
         // Rust automatically dereferences `&Arc<Vec<Font>>` to `&[Font]`.
-        if let Err(e) = process_image(path, &cli, &fonts_clone) {
-            eprintln!("Failed to process {}: {}", path.display(), e);
+        match process_image(path, cli, context, &fonts_clone, bundle) {
+            Ok(Some(Processed { safety: Some(check), outputs, .. })) if outputs.is_empty() => Outcome::Blocked(check),
+            Ok(Some(Processed { sharpness: Some(score), outputs, .. })) if outputs.is_empty() => Outcome::Blurry(score),
+            Ok(Some(processed)) => Outcome::Processed(processed),
+            Ok(None) => Outcome::Skipped,
+            Err(e) => Outcome::Failed(format!("{:#}", e)),
         }
-    });
+    };
 
-    println!("Image processing complete!");
-    Ok(())
+    let progress = Progress::new(image_paths.len(), cli.quiet);
+    let results = if cli.order.is_some() {
+        // Images are started in the chosen order and logged in it, so two runs' logs can be
+        // diffed. `par_bridge` hands them out one at a time instead of splitting the list.
+        let mut results: Vec<(usize, PathBuf, Outcome)> = image_paths
+            .iter()
+            .enumerate()
+            .par_bridge()
+            .map(|(index, path)| {
+                let outcome = process(path);
+                progress.done(|| table.print_in_order(index, path, &outcome));
+                (index, path.clone(), outcome)
+            })
+            .collect();
+        results.sort_unstable_by_key(|(index, ..)| *index);
+        results.into_iter().map(|(_, path, outcome)| (path, outcome)).collect()
+    } else {
+        image_paths
+            .par_iter()
+            .map(|path| {
+                let outcome = process(path);
+                progress.done(|| table.print(path, &outcome));
+                (path.clone(), outcome)
+            })
+            .collect()
+    };
+    progress.finish();
+    results
 }
@@ -1,10 +1,21 @@
-use anyhow::Result;
-use clap::Parser;
-use imagekit::cli::Cli;
+use anyhow::{bail, Result};
+use clap::{CommandFactory, Parser};
+use imagekit::cli::App;
+use imagekit::context::RunContext;
 
 fn main() -> Result<()> {
-    // 1. Parse command-line arguments.
-    let cli = Cli::parse();
+    // 1. Parse command-line arguments, filling in options and watermark layers from `--config`.
+    let (args, watermark_layers) = imagekit::config::expand_args(std::env::args_os().collect())?;
+    let app = App::parse_from(args);
     // 2. Call the core run logic from the library.
-    imagekit::run(cli)
+    match (app.command, app.batch) {
+        (Some(_), _) if !watermark_layers.is_empty() => {
+            bail!("Watermark layers in a config file apply only to batch runs")
+        }
+        (Some(command), _) => imagekit::run_command(command),
+        (None, Some(cli)) => {
+            imagekit::run_with(cli, RunContext { watermark_layers, ..RunContext::default() }).map(|_| ())
+        }
+        (None, None) => Ok(App::command().print_help()?),
+    }
 }
@@ -0,0 +1,156 @@
+use super::cli::MetaArgs;
+use super::errors::ParseTagAssignmentError;
+use super::i18n::Msg;
+use super::is_supported_image;
+use anyhow::{bail, Context as _, Result};
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Reader, Tag, Value};
+use img_parts::{Bytes, DynImage, ImageEXIF};
+use rayon::prelude::*;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// An EXIF text tag to set, written as `<tag>=<value>`, e.g. `Copyright=ACME`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TagAssignment {
+    pub tag: Tag,
+    pub value: String,
+}
+
+impl FromStr for TagAssignment {
+    type Err = ParseTagAssignmentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseTagAssignmentError(s.to_string());
+        let (name, value) = s.split_once('=').ok_or_else(err)?;
+        let tag = match name.trim().to_lowercase().as_str() {
+            "artist" => Tag::Artist,
+            "copyright" => Tag::Copyright,
+            "imagedescription" => Tag::ImageDescription,
+            "make" => Tag::Make,
+            "model" => Tag::Model,
+            "software" => Tag::Software,
+            "datetime" => Tag::DateTime,
+            "datetimeoriginal" => Tag::DateTimeOriginal,
+            _ => return Err(err()),
+        };
+        Ok(TagAssignment { tag, value: value.to_string() })
+    }
+}
+
+/// Rewrites the metadata of every image in the input directory into the output directory.
+/// Only the EXIF block is replaced; the compressed image data is copied byte for byte.
+pub fn run(args: &MetaArgs) -> Result<()> {
+    let image_paths: Vec<PathBuf> = walkdir::WalkDir::new(&args.input_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| is_supported_image(e.path()))
+        .map(|e| e.path().to_path_buf())
+        .collect();
+
+    if image_paths.is_empty() {
+        println!("{}", Msg::NoImages);
+        return Ok(());
+    }
+    println!("{}", Msg::FoundToUpdate(image_paths.len()));
+
+    let updated = image_paths
+        .par_iter()
+        .filter(|path| match rewrite_file(path, args) {
+            Ok(output) => {
+                println!("{}", Msg::SavedTo(&output));
+                true
+            }
+            Err(e) => {
+                eprintln!("{}", Msg::FailedToUpdate { path, error: &e.to_string() });
+                false
+            }
+        })
+        .count();
+
+    println!("{}", Msg::UpdatedMetadata { updated, total: image_paths.len() });
+    Ok(())
+}
+
+fn rewrite_file(path: &Path, args: &MetaArgs) -> Result<PathBuf> {
+    let output = args.output_dir.join(path.strip_prefix(&args.input_dir)?);
+    let data = fs::read(path)?;
+    let Some(mut image) = DynImage::from_bytes(Bytes::from(data))? else {
+        bail!("metadata editing is only supported for JPEG, PNG and WebP");
+    };
+
+    let exif = match image.exif() {
+        Some(raw) => Some(Reader::new().read_raw(raw.to_vec()).context("Failed to parse EXIF")?),
+        None => None,
+    };
+    if exif.is_some() || !args.set.is_empty() {
+        image.set_exif(edit_exif(exif.as_ref(), args)?.map(Bytes::from));
+    }
+
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::File::create(&output)?;
+    image.encoder().write_to(std::io::BufWriter::new(file))?;
+    Ok(output)
+}
+
+/// Builds a new EXIF block from the existing one with the requested edits applied.
+/// Returns `None` when no tags remain.
+fn edit_exif(exif: Option<&exif::Exif>, args: &MetaArgs) -> Result<Option<Vec<u8>>> {
+    let assigned: Vec<Field> = args
+        .set
+        .iter()
+        .map(|a| Field { tag: a.tag, ifd_num: In::PRIMARY, value: Value::Ascii(vec![a.value.clone().into_bytes()]) })
+        .collect();
+
+    let mut writer = Writer::new();
+    let mut kept = 0;
+    if let Some(exif) = exif {
+        for field in exif.fields() {
+            let replaced = field.ifd_num == In::PRIMARY && assigned.iter().any(|a| a.tag == field.tag);
+            if replaced || (args.strip_gps && field.tag.context() == Context::Gps) {
+                continue;
+            }
+            writer.push_field(field);
+            if !is_structural(field.tag) {
+                kept += 1;
+            }
+        }
+        if let Some(thumbnail) = thumbnail(exif) {
+            writer.set_jpeg(thumbnail, In::THUMBNAIL);
+        }
+    }
+    for field in &assigned {
+        writer.push_field(field);
+    }
+    if kept + assigned.len() == 0 {
+        return Ok(None);
+    }
+
+    let mut buf = Cursor::new(Vec::new());
+    let little_endian = exif.is_none_or(|e| e.little_endian());
+    writer.write(&mut buf, little_endian).context("Failed to write EXIF")?;
+    Ok(Some(buf.into_inner()))
+}
+
+/// Returns the embedded JPEG thumbnail, if any, so it survives the rewrite.
+pub(crate) fn thumbnail(exif: &exif::Exif) -> Option<&[u8]> {
+    let offset = exif.get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    let length = exif.get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?.value.get_uint(0)? as usize;
+    exif.buf().get(offset..offset.checked_add(length)?)
+}
+
+/// Tags the writer synthesizes itself; they don't count as content.
+pub(crate) fn is_structural(tag: Tag) -> bool {
+    matches!(
+        tag,
+        Tag::ExifIFDPointer
+            | Tag::GPSInfoIFDPointer
+            | Tag::InteropIFDPointer
+            | Tag::JPEGInterchangeFormat
+            | Tag::JPEGInterchangeFormatLength
+    )
+}
@@ -0,0 +1,451 @@
+use super::decode::tiff_icc_profile;
+use super::meta::is_structural;
+use super::report::read_exif;
+use anyhow::{Context, Result};
+use exif::experimental::Writer;
+use exif::{Field, In, Tag, Value};
+use image::{ImageDecoder, ImageFormat, ImageReader};
+use img_parts::jpeg::{markers, Jpeg, JpegSegment};
+use img_parts::png::{Png, PngChunk};
+use img_parts::riff::{RiffChunk, RiffContent};
+use img_parts::webp::WebP;
+use img_parts::{Bytes, DynImage, ImageEXIF, ImageICC};
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+/// Identifies an XMP packet in a JPEG APP1 segment.
+const JPEG_XMP_IDENTIFIER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// Keyword of the PNG iTXt chunk that holds XMP.
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+const PNG_ITXT: [u8; 4] = *b"iTXt";
+const PNG_IHDR: [u8; 4] = *b"IHDR";
+/// Ancillary PNG chunks that affect how the pixels look rather than describe them.
+const PNG_DISPLAY_CHUNKS: [[u8; 4]; 10] =
+    [*b"tRNS", *b"gAMA", *b"cHRM", *b"sRGB", *b"sBIT", *b"bKGD", *b"pHYs", *b"acTL", *b"fcTL", *b"fdAT"];
+const JFIF_IDENTIFIER: &[u8] = b"JFIF\0";
+const ADOBE_IDENTIFIER: &[u8] = b"Adobe";
+const WEBP_VP8X: [u8; 4] = *b"VP8X";
+/// WebP chunks holding pixels or animation frames.
+const WEBP_IMAGE_CHUNKS: [[u8; 4]; 6] = [WEBP_VP8X, *b"VP8 ", *b"VP8L", *b"ALPH", *b"ANIM", *b"ANMF"];
+
+/// Reads the keywords embedded in an image file, from both its XMP packet (`dc:subject`)
+/// and, for JPEGs, its IPTC block. Keywords are returned in file order without duplicates.
+pub fn read_keywords(data: &[u8]) -> Vec<String> {
+    let mut keywords = xmp_keywords(data);
+    for keyword in iptc_keywords(data) {
+        if !keywords.contains(&keyword) {
+            keywords.push(keyword);
+        }
+    }
+    keywords
+}
+
+/// Returns whether an image passes the `--require-keyword` / `--exclude-keyword` filters:
+/// every required keyword must be present and no excluded keyword may be. Matching is
+/// case-insensitive. Unreadable files pass, so the error is reported when they're processed.
+pub fn passes_keyword_filter(path: &Path, require: &[String], exclude: &[String]) -> bool {
+    if require.is_empty() && exclude.is_empty() {
+        return true;
+    }
+    let Ok(data) = fs::read(path) else { return true };
+    let keywords: Vec<String> = read_keywords(&data).iter().map(|k| k.to_lowercase()).collect();
+    let has = |k: &String| keywords.contains(&k.to_lowercase());
+    require.iter().all(has) && !exclude.iter().any(has)
+}
+
+/// Reads the star rating from an image's XMP packet (`xmp:Rating`): 1 to 5, 0 when unrated
+/// and -1 for rejected images.
+pub fn read_rating(data: &[u8]) -> Option<i32> {
+    xmp_property(find_xmp(data)?, "xmp:Rating")?.parse::<f32>().ok().map(|r| r.round() as i32)
+}
+
+/// Returns whether an image is flagged as a pick: it has a color label (`xmp:Label`), as set
+/// by Lightroom, Bridge or Capture One, or is marked good (`xmpDM:good`).
+pub fn is_flagged(data: &[u8]) -> bool {
+    let Some(xmp) = find_xmp(data) else { return false };
+    xmp_property(xmp, "xmp:Label").is_some_and(|label| !label.is_empty())
+        || xmp_property(xmp, "xmpDM:good").is_some_and(|good| good.eq_ignore_ascii_case("true"))
+}
+
+/// Returns whether an image passes the `--min-rating` / `--only-flagged` filters. Unrated
+/// images count as 0 stars. Unreadable files pass, so the error is reported when they're
+/// processed.
+pub fn passes_rating_filter(path: &Path, min_rating: Option<u8>, only_flagged: bool) -> bool {
+    if min_rating.is_none() && !only_flagged {
+        return true;
+    }
+    let Ok(data) = fs::read(path) else { return true };
+    min_rating.is_none_or(|min| read_rating(&data).unwrap_or(0) >= min as i32) && (!only_flagged || is_flagged(&data))
+}
+
+/// Embeds an XMP packet listing `keywords` as `dc:subject` in a saved JPEG or PNG, replacing
+/// any XMP it had. Other formats are left unchanged.
+pub fn write_keywords(path: &Path, keywords: &[String]) -> Result<()> {
+    embed_xmp(path, &xmp_packet(keywords))
+}
+
+/// Copies the EXIF data, ICC profile and XMP packet of `source` into a saved JPEG, PNG or
+/// WebP, for `--keep-metadata`; WebP outputs get no XMP. The EXIF data is rebuilt to describe
+/// the output: its pixel size is updated, its thumbnail, which shows the unedited source, is
+/// dropped, and with `upright` its orientation is reset, as the pixels were already turned.
+/// The ICC profile is only carried over if it still suits the output's colors, which a CMYK
+/// source's doesn't. Other formats are left unchanged.
+pub fn copy_metadata(source: &Path, output: &Path, upright: bool) -> Result<()> {
+    let data = Bytes::from(fs::read(output)?);
+    let Some(mut image) = DynImage::from_bytes(data)? else { return Ok(()) };
+    let decoder = ImageReader::open(output)?.with_guessed_format()?.into_decoder()?;
+    let (dimensions, color) = (decoder.dimensions(), decoder.color_type());
+    let gray = !color.has_color();
+
+    let source_data = Bytes::from(fs::read(source)?);
+    if let Some(exif) = read_exif(source) {
+        image.set_exif(output_exif(&exif, dimensions, upright)?.map(Bytes::from));
+    }
+    if let Some(profile) = icc_profile(source, &source_data).filter(|p| suits(p, gray)) {
+        image.set_icc_profile(Some(Bytes::from(profile)));
+    }
+    if let DynImage::WebP(webp) = &mut image {
+        update_vp8x_flags(webp, color.has_alpha());
+    }
+    let file = fs::File::create(output).with_context(|| format!("Failed to write {}", output.display()))?;
+    image.encoder().write_to(std::io::BufWriter::new(file))?;
+
+    match find_xmp(&source_data) {
+        Some(packet) => embed_xmp(output, packet),
+        None => Ok(()),
+    }
+}
+
+/// Embeds the ICC profile of `source` in a saved JPEG, PNG or WebP, so colors edited in a wide
+/// gamut such as Display P3 or Adobe RGB keep looking the same; the pixels are never converted,
+/// so they are still in the source's color space. The profile is only carried over if it
+/// suits the output's colors, which a CMYK source's doesn't. Other formats are left unchanged.
+pub fn copy_icc_profile(source: &Path, output: &Path) -> Result<()> {
+    let source_data = Bytes::from(fs::read(source)?);
+    let Some(profile) = icc_profile(source, &source_data) else { return Ok(()) };
+    let Some(mut image) = DynImage::from_bytes(Bytes::from(fs::read(output)?))? else { return Ok(()) };
+    let color = ImageReader::open(output)?.with_guessed_format()?.into_decoder()?.color_type();
+    if !suits(&profile, !color.has_color()) {
+        return Ok(());
+    }
+    image.set_icc_profile(Some(Bytes::from(profile)));
+    if let DynImage::WebP(webp) = &mut image {
+        update_vp8x_flags(webp, color.has_alpha());
+    }
+    let file = fs::File::create(output).with_context(|| format!("Failed to write {}", output.display()))?;
+    image.encoder().write_to(std::io::BufWriter::new(file))?;
+    Ok(())
+}
+
+/// Reads the ICC profile embedded in `source`, whose contents are `data`.
+fn icc_profile(source: &Path, data: &Bytes) -> Option<Vec<u8>> {
+    match ImageFormat::from_path(source).ok() {
+        Some(ImageFormat::Tiff) => {
+            tiff::decoder::Decoder::new(Cursor::new(&data[..])).ok().and_then(|mut d| tiff_icc_profile(&mut d))
+        }
+        _ => DynImage::from_bytes(data.clone()).ok().flatten().and_then(|s| s.icc_profile()).map(|p| p.to_vec()),
+    }
+}
+
+/// Returns whether an ICC profile describes RGB colors for a color output, or gray ones for a
+/// grayscale output.
+fn suits(profile: &[u8], gray: bool) -> bool {
+    // The profile's color space is at bytes 16 to 20 of its header.
+    match profile.get(16..20) {
+        Some(b"RGB ") => !gray,
+        Some(b"GRAY") => gray,
+        _ => false,
+    }
+}
+
+/// Sets the flags of a WebP's extended header to the chunks it holds. Adding chunks with
+/// img-parts leaves them stale, and its header for a lossless image would drop the alpha
+/// channel.
+fn update_vp8x_flags(webp: &mut WebP, alpha: bool) {
+    let flag = |id: [u8; 4], bit: u8| if webp.has_chunk(id) { bit } else { 0 };
+    let flags = flag(*b"ICCP", 0x20)
+        | if alpha { 0x10 } else { 0 }
+        | flag(*b"EXIF", 0x08)
+        | flag(*b"XMP ", 0x04)
+        | flag(*b"ANIM", 0x02);
+    if let Some(vp8x) = webp.chunks_mut().iter_mut().find(|c| c.id() == WEBP_VP8X) {
+        if let Some(mut header) = vp8x.content().data().map(|d| d.to_vec()).filter(|d| !d.is_empty()) {
+            header[0] = flags;
+            *vp8x = RiffChunk::new(WEBP_VP8X, RiffContent::Data(Bytes::from(header)));
+        }
+    }
+}
+
+/// Removes every EXIF block, XMP packet, ICC profile, comment and text chunk from a saved
+/// JPEG, PNG or WebP, for `--strip-metadata`. Only what's needed to show the pixels right is
+/// kept: the JFIF and Adobe segments of a JPEG, the critical chunks of a PNG plus those
+/// describing transparency, gamma, density and animation, and the image and animation chunks
+/// of a WebP. The other formats are written with no metadata to begin with.
+pub fn strip_metadata(path: &Path) -> Result<()> {
+    let data = Bytes::from(fs::read(path)?);
+    let bytes = match path.extension().and_then(|s| s.to_str()).map(str::to_lowercase).as_deref() {
+        Some("jpg" | "jpeg") => {
+            let mut jpeg = Jpeg::from_bytes(data)?;
+            jpeg.segments_mut().retain(|s| match s.marker() {
+                markers::APP0 => s.contents().starts_with(JFIF_IDENTIFIER),
+                // Tells decoders how the channels of CMYK and YCCK files are transformed.
+                markers::APP14 => s.contents().starts_with(ADOBE_IDENTIFIER),
+                markers::APP1..=markers::APP15 | markers::COM => false,
+                _ => true,
+            });
+            jpeg.encoder().bytes()
+        }
+        Some("png") => {
+            let mut png = Png::from_bytes(data)?;
+            png.chunks_mut().retain(|c| c.kind()[0].is_ascii_uppercase() || PNG_DISPLAY_CHUNKS.contains(&c.kind()));
+            png.encoder().bytes()
+        }
+        Some("webp") => {
+            let mut webp = WebP::from_bytes(data)?;
+            let chunks = webp.chunks_mut();
+            chunks.retain(|c| WEBP_IMAGE_CHUNKS.contains(&c.id()));
+            // Clear the flags announcing an ICC profile, EXIF and XMP.
+            if let Some(vp8x) = chunks.iter_mut().find(|c| c.id() == WEBP_VP8X) {
+                if let Some(mut header) = vp8x.content().data().map(|d| d.to_vec()).filter(|d| !d.is_empty()) {
+                    header[0] &= !(0x20 | 0x08 | 0x04);
+                    *vp8x = RiffChunk::new(WEBP_VP8X, RiffContent::Data(Bytes::from(header)));
+                }
+            }
+            webp.encoder().bytes()
+        }
+        _ => return Ok(()),
+    };
+    fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Rebuilds source EXIF data for an output of `width` by `height` pixels. Tags describing how
+/// a TIFF or RAW source stores its pixels are dropped along with unknown ones, as they'd be
+/// wrong for the output. Returns `None` when no tags remain.
+fn output_exif(exif: &exif::Exif, (width, height): (u32, u32), upright: bool) -> Result<Option<Vec<u8>>> {
+    let dimensions = [
+        Field { tag: Tag::PixelXDimension, ifd_num: In::PRIMARY, value: Value::Long(vec![width]) },
+        Field { tag: Tag::PixelYDimension, ifd_num: In::PRIMARY, value: Value::Long(vec![height]) },
+    ];
+    let upright_orientation = Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![1]) };
+    let mut writer = Writer::new();
+    let mut kept = 0;
+    for field in exif.fields() {
+        let storage = matches!(
+            field.tag,
+            Tag::ImageWidth
+                | Tag::ImageLength
+                | Tag::BitsPerSample
+                | Tag::Compression
+                | Tag::PhotometricInterpretation
+                | Tag::StripOffsets
+                | Tag::SamplesPerPixel
+                | Tag::RowsPerStrip
+                | Tag::StripByteCounts
+                | Tag::PlanarConfiguration
+                | Tag::TileOffsets
+                | Tag::TileByteCounts
+                | Tag::PixelXDimension
+                | Tag::PixelYDimension
+        );
+        if field.ifd_num != In::PRIMARY || storage || field.tag.description().is_none() {
+            continue;
+        }
+        if field.tag == Tag::Orientation && upright {
+            writer.push_field(&upright_orientation);
+        } else {
+            writer.push_field(field);
+        }
+        if !is_structural(field.tag) {
+            kept += 1;
+        }
+    }
+    if kept == 0 {
+        return Ok(None);
+    }
+    for field in &dimensions {
+        writer.push_field(field);
+    }
+    let mut buf = Cursor::new(Vec::new());
+    writer.write(&mut buf, exif.little_endian()).context("Failed to write EXIF")?;
+    Ok(Some(buf.into_inner()))
+}
+
+/// Embeds an XMP packet in a saved JPEG or PNG, replacing any XMP it had. Other formats are
+/// left unchanged.
+fn embed_xmp(path: &Path, packet: &str) -> Result<()> {
+    let data = Bytes::from(fs::read(path)?);
+    let bytes = match path.extension().and_then(|s| s.to_str()).map(str::to_lowercase).as_deref() {
+        Some("jpg" | "jpeg") => {
+            let mut jpeg = Jpeg::from_bytes(data)?;
+            let segments = jpeg.segments_mut();
+            segments.retain(|s| !(s.marker() == markers::APP1 && s.contents().starts_with(JPEG_XMP_IDENTIFIER)));
+            // After JFIF and EXIF, where readers look for it.
+            let at = segments.iter().take_while(|s| matches!(s.marker(), markers::APP0 | markers::APP1)).count();
+            let contents = [JPEG_XMP_IDENTIFIER, packet.as_bytes()].concat();
+            segments.insert(at, JpegSegment::new_with_contents(markers::APP1, Bytes::from(contents)));
+            jpeg.encoder().bytes()
+        }
+        Some("png") => {
+            let mut png = Png::from_bytes(data)?;
+            let chunks = png.chunks_mut();
+            chunks.retain(|c| !(c.kind() == PNG_ITXT && c.contents().starts_with(PNG_XMP_KEYWORD)));
+            // Keyword, then uncompressed, with empty language and translated keyword.
+            let contents = [PNG_XMP_KEYWORD, &[0, 0, 0, 0, 0], packet.as_bytes()].concat();
+            let at = chunks.iter().position(|c| c.kind() == PNG_IHDR).map_or(0, |i| i + 1);
+            chunks.insert(at, PngChunk::new(PNG_ITXT, Bytes::from(contents)));
+            png.encoder().bytes()
+        }
+        _ => return Ok(()),
+    };
+    fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn xmp_packet(keywords: &[String]) -> String {
+    let items: String = keywords.iter().map(|k| format!("<rdf:li>{}</rdf:li>", escape_xml(k))).collect();
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+            "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">",
+            "<dc:subject><rdf:Bag>{}</rdf:Bag></dc:subject>",
+            "</rdf:Description></rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>"
+        ),
+        items
+    )
+}
+
+/// Finds the XMP packet in a file. XMP is stored as plain UTF-8 in every format we read,
+/// so a byte search is enough and avoids format-specific container parsing.
+pub fn find_xmp(data: &[u8]) -> Option<&str> {
+    let start = find(data, b"<x:xmpmeta")?;
+    let end = find(&data[start..], b"</x:xmpmeta>")? + start + b"</x:xmpmeta>".len();
+    std::str::from_utf8(&data[start..end]).ok()
+}
+
+fn xmp_keywords(data: &[u8]) -> Vec<String> {
+    let Some(xmp) = find_xmp(data) else { return Vec::new() };
+    let Some(start) = xmp.find("<dc:subject") else { return Vec::new() };
+    let Some(len) = xmp[start..].find("</dc:subject>") else { return Vec::new() };
+    list_items(&xmp[start..start + len])
+}
+
+/// Reads a simple XMP property, written either as an attribute (`xmp:Rating="3"`) or as an
+/// element (`<xmp:Rating>3</xmp:Rating>`).
+fn xmp_property(xmp: &str, name: &str) -> Option<String> {
+    let attribute = format!("{}=", name);
+    if let Some(at) = xmp.find(&attribute) {
+        let rest = &xmp[at + attribute.len()..];
+        let quote = rest.chars().next().filter(|&c| c == '"' || c == '\'')?;
+        let value = &rest[1..];
+        return Some(unescape_xml(value[..value.find(quote)?].trim()));
+    }
+    let open = format!("<{}>", name);
+    let start = xmp.find(&open)? + open.len();
+    let len = xmp[start..].find(&format!("</{}>", name))?;
+    Some(unescape_xml(xmp[start..start + len].trim()))
+}
+
+/// Extracts the text of each `<rdf:li>` element in an XMP fragment.
+pub(crate) fn list_items(fragment: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut rest = fragment;
+    while let Some(open) = rest.find("<rdf:li") {
+        rest = &rest[open..];
+        let Some(gt) = rest.find('>') else { break };
+        let Some(close) = rest.find("</rdf:li>") else { break };
+        if gt < close {
+            let text = unescape_xml(rest[gt + 1..close].trim());
+            if !text.is_empty() {
+                items.push(text);
+            }
+        }
+        rest = &rest[close + "</rdf:li>".len()..];
+    }
+    items
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub(crate) fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Reads IPTC-IIM keywords (dataset 2:25) from a JPEG's Photoshop APP13 segment.
+fn iptc_keywords(data: &[u8]) -> Vec<String> {
+    let mut keywords = Vec::new();
+    for segment in jpeg_segments(data, 0xED) {
+        let Some(mut resources) = segment.strip_prefix(b"Photoshop 3.0\0") else { continue };
+        // Each image resource block: "8BIM", id (2), padded Pascal name, size (4), padded data.
+        while resources.len() >= 12 && &resources[0..4] == b"8BIM" {
+            let id = u16::from_be_bytes([resources[4], resources[5]]);
+            let name_len = resources[6] as usize;
+            let name_field = (1 + name_len + 1) & !1;
+            let size_at = 6 + name_field;
+            if resources.len() < size_at + 4 {
+                break;
+            }
+            let size = u32::from_be_bytes(resources[size_at..size_at + 4].try_into().unwrap()) as usize;
+            let body_at = size_at + 4;
+            if resources.len() < body_at + size {
+                break;
+            }
+            if id == 0x0404 {
+                keywords.extend(iptc_datasets(&resources[body_at..body_at + size], 2, 25));
+            }
+            // The last block may leave out its pad byte.
+            resources = resources.get((body_at + size + 1) & !1..).unwrap_or_default();
+        }
+    }
+    keywords
+}
+
+fn iptc_datasets(mut iim: &[u8], record: u8, dataset: u8) -> Vec<String> {
+    let mut values = Vec::new();
+    while iim.len() >= 5 && iim[0] == 0x1C {
+        let len = u16::from_be_bytes([iim[3], iim[4]]) as usize;
+        if iim.len() < 5 + len {
+            break;
+        }
+        if iim[1] == record && iim[2] == dataset {
+            values.push(String::from_utf8_lossy(&iim[5..5 + len]).trim().to_string());
+        }
+        iim = &iim[5 + len..];
+    }
+    values
+}
+
+/// Returns the payloads of every JPEG marker segment with the given marker byte.
+pub(crate) fn jpeg_segments(data: &[u8], marker: u8) -> Vec<&[u8]> {
+    let mut segments = Vec::new();
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return segments;
+    }
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let kind = data[pos + 1];
+        // Start of scan: entropy-coded data follows, no more metadata segments.
+        if kind == 0xDA {
+            break;
+        }
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        if len < 2 || pos + 2 + len > data.len() {
+            break;
+        }
+        if kind == marker {
+            segments.push(&data[pos + 4..pos + 2 + len]);
+        }
+        pos += 2 + len;
+    }
+    segments
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
@@ -0,0 +1,71 @@
+use super::errors::ParseNineSliceError;
+use anyhow::{bail, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImage, GenericImageView};
+use std::fmt;
+use std::str::FromStr;
+
+/// `--nine-slice` insets in pixels, measured from each edge of the source. The corners they
+/// cut off keep their size, the edges stretch along their length only, and the center
+/// stretches both ways.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NineSlice {
+    pub left: u32,
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+}
+
+impl FromStr for NineSlice {
+    type Err = ParseNineSliceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseNineSliceError(s.to_string());
+        let values = s.split(',').map(|v| v.trim().parse::<u32>().map_err(|_| err())).collect::<Result<Vec<_>, _>>()?;
+        let [left, top, right, bottom] = values[..] else { return Err(err()) };
+        Ok(NineSlice { left, top, right, bottom })
+    }
+}
+
+impl NineSlice {
+    /// Scales `img` to `width` x `height` by stretching everything between the insets.
+    pub fn resize(&self, img: &DynamicImage, width: u32, height: u32) -> Result<DynamicImage> {
+        let (source_width, source_height) = img.dimensions();
+        let (horizontal, vertical) = (self.left + self.right, self.top + self.bottom);
+        if horizontal > source_width || vertical > source_height {
+            bail!("Nine-slice insets {} don't fit in a {}x{} image", self, source_width, source_height);
+        }
+        if horizontal > width || vertical > height {
+            bail!("Nine-slice insets {} don't fit in the {}x{} target", self, width, height);
+        }
+        if (horizontal == source_width && width > horizontal) || (vertical == source_height && height > vertical) {
+            bail!("Nine-slice insets {} leave no middle to stretch in a {}x{} image", self, source_width, source_height);
+        }
+
+        // Start and length of the three columns and rows, in the source and in the output.
+        let spans = |low: u32, high: u32, total: u32| [(0, low), (low, total - low - high), (total - high, high)];
+        let (source_columns, source_rows) = (spans(self.left, self.right, source_width), spans(self.top, self.bottom, source_height));
+        let (columns, rows) = (spans(self.left, self.right, width), spans(self.top, self.bottom, height));
+
+        let mut out = DynamicImage::new(width, height, img.color());
+        for ((sy, sh), (y, h)) in source_rows.into_iter().zip(rows) {
+            for ((sx, sw), (x, w)) in source_columns.into_iter().zip(columns) {
+                if w == 0 || h == 0 || sw == 0 || sh == 0 {
+                    continue;
+                }
+                let mut cell = img.crop_imm(sx, sy, sw, sh);
+                if (sw, sh) != (w, h) {
+                    cell = cell.resize_exact(w, h, FilterType::Lanczos3);
+                }
+                out.copy_from(&cell, x, y)?;
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl fmt::Display for NineSlice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.left, self.top, self.right, self.bottom)
+    }
+}
@@ -0,0 +1,67 @@
+use super::errors::ParseOrderError;
+use super::random::Rng;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// The order in which `--order` processes and reports images.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    /// By path relative to the input directory.
+    Name,
+    /// Smallest file first.
+    Size,
+    /// Oldest modification time first.
+    Mtime,
+    /// Shuffled by a seed; the same seed gives the same order.
+    Random(u64),
+}
+
+impl FromStr for Order {
+    type Err = ParseOrderError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseOrderError(s.to_string());
+        let s = s.trim().to_lowercase();
+        match s.as_str() {
+            "name" => Ok(Order::Name),
+            "size" => Ok(Order::Size),
+            "mtime" => Ok(Order::Mtime),
+            _ => {
+                let seed = s.strip_prefix("random(").and_then(|rest| rest.strip_suffix(')')).ok_or_else(err)?;
+                Ok(Order::Random(seed.trim().parse().map_err(|_| err())?))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Order {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Order::Name => f.write_str("name"),
+            Order::Size => f.write_str("size"),
+            Order::Mtime => f.write_str("mtime"),
+            Order::Random(seed) => write!(f, "random({})", seed),
+        }
+    }
+}
+
+/// Sorts the inputs into the given order. Ties, and files whose size or time can't be read,
+/// fall back to name order, so the result never depends on the order the files were found in.
+pub fn sort(paths: &mut [PathBuf], order: Order, input_dir: &Path) {
+    let name = |path: &Path| path.strip_prefix(input_dir).unwrap_or(path).to_string_lossy().into_owned();
+    match order {
+        Order::Name => paths.sort_by_cached_key(|p| name(p)),
+        Order::Size => paths.sort_by_cached_key(|p| (fs::metadata(p).map_or(0, |m| m.len()), name(p))),
+        Order::Mtime => paths.sort_by_cached_key(|p| {
+            let modified = fs::metadata(p).and_then(|m| m.modified()).unwrap_or(SystemTime::UNIX_EPOCH);
+            (modified, name(p))
+        }),
+        Order::Random(seed) => paths.sort_by_cached_key(|p| {
+            let name = name(p);
+            (Rng::new(seed, &format!("order/{}", name)).next_u64(), name)
+        }),
+    }
+}
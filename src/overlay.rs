@@ -0,0 +1,60 @@
+use super::cli::{Percentage, WatermarkPosition};
+use super::is_supported_image;
+use super::processor::anchor;
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, RgbaImage};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Stem of the overlay used for images without one of their own.
+pub const DEFAULT_OVERLAY: &str = "_default";
+
+/// Finds the overlay for an image: the file in `dir` at the image's relative path with any
+/// image extension, e.g. `frames/team/alice.png` for `team/alice.jpg`, or else `_default.*` at
+/// the top of `dir`. When several extensions match, the first by name wins.
+pub fn find_overlay(dir: &Path, relative: &Path) -> Option<PathBuf> {
+    let stem = relative.file_stem()?;
+    let folder = dir.join(relative.parent().unwrap_or(Path::new("")));
+    with_stem(&folder, stem.to_str()?).or_else(|| with_stem(dir, DEFAULT_OVERLAY))
+}
+
+fn with_stem(folder: &Path, stem: &str) -> Option<PathBuf> {
+    let mut matches: Vec<PathBuf> = fs::read_dir(folder)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.file_stem().and_then(|s| s.to_str()) == Some(stem) && is_supported_image(p))
+        .collect();
+    matches.sort();
+    matches.into_iter().next()
+}
+
+/// Composites `overlay` onto the image at `position`, with no margin. The overlay is resized,
+/// keeping its aspect ratio, to fit within `scale` of the image's width and height, so a frame
+/// shaped like the image covers it exactly at 100%. Returns the overlay's drawn size.
+pub fn composite(img: &mut DynamicImage, overlay: &DynamicImage, position: WatermarkPosition, scale: Percentage) -> (u32, u32) {
+    let (width, height) = (img.width(), img.height());
+    let box_width = ((width as f32 * scale.0).round() as u32).max(1);
+    let box_height = ((height as f32 * scale.0).round() as u32).max(1);
+    let ratio = (box_width as f32 / overlay.width() as f32).min(box_height as f32 / overlay.height() as f32);
+    let size = (
+        ((overlay.width() as f32 * ratio).round() as u32).max(1),
+        ((overlay.height() as f32 * ratio).round() as u32).max(1),
+    );
+    let resized = imageops::resize(&overlay.to_rgba8(), size.0, size.1, FilterType::Lanczos3);
+    let (x, y) = anchor(position, (width, height), size, 0);
+    blend_onto(img, &resized, (x, y));
+    size
+}
+
+/// Alpha-blends `layer` onto the image with its top-left corner at `at`.
+pub(crate) fn blend_onto(img: &mut DynamicImage, layer: &RgbaImage, (x, y): (u32, u32)) {
+    // Blending needs an alpha-aware buffer; gray and RGB images are converted back afterwards.
+    let mut canvas = img.to_rgba8();
+    imageops::overlay(&mut canvas, layer, x as i64, y as i64);
+    *img = if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(canvas)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8())
+    };
+}
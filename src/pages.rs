@@ -0,0 +1,253 @@
+use super::decode::{cmyk_to_rgb, tiff_icc_profile};
+use super::errors::ParsePageSelectionError;
+use anyhow::{bail, Context, Result};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageBuffer};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use std::str::FromStr;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+/// The pages `--pages` picks from multi-page sources, as inclusive ranges counting from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageSelection(Vec<(u32, u32)>);
+
+impl PageSelection {
+    pub fn contains(&self, page: u32) -> bool {
+        self.0.iter().any(|&(first, last)| (first..=last).contains(&page))
+    }
+}
+
+impl FromStr for PageSelection {
+    type Err = ParsePageSelectionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePageSelectionError(s.to_string());
+        let page = |n: &str| n.trim().parse::<u32>().ok().filter(|&n| n >= 1).ok_or_else(err);
+        s.split(',')
+            .map(|part| {
+                let (first, last) = match part.split_once('-') {
+                    Some((first, "")) => (page(first)?, u32::MAX),
+                    Some((first, last)) => (page(first)?, page(last)?),
+                    None => (page(part)?, page(part)?),
+                };
+                if first > last { Err(err()) } else { Ok((first, last)) }
+            })
+            .collect::<Result<_, _>>()
+            .map(PageSelection)
+    }
+}
+
+impl fmt::Display for PageSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parts: Vec<String> = self
+            .0
+            .iter()
+            .map(|&(first, last)| match last {
+                _ if first == last => first.to_string(),
+                u32::MAX => format!("{}-", first),
+                _ => format!("{}-{}", first, last),
+            })
+            .collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+/// Whether page `page` of a source with `count` pages is processed: every page unless
+/// `--pages` picks some, and always the only page of a single-page source.
+pub fn wanted(selection: Option<&PageSelection>, page: u32, count: u32) -> bool {
+    count == 1 || selection.is_none_or(|selection| selection.contains(page))
+}
+
+/// Whether `path` is a PDF, judged by its extension.
+pub fn is_pdf(path: &Path) -> bool {
+    path.is_file() && path.extension().and_then(|s| s.to_str()).is_some_and(|s| s.eq_ignore_ascii_case("pdf"))
+}
+
+/// Whether `path` is a TIFF, judged by its extension.
+pub fn is_tiff(path: &Path) -> bool {
+    path.is_file()
+        && path.extension().and_then(|s| s.to_str()).is_some_and(|s| s.eq_ignore_ascii_case("tif") || s.eq_ignore_ascii_case("tiff"))
+}
+
+/// How many pages a multi-page source has, or `None` if it is a single image or can't be read.
+pub fn page_count(path: &Path) -> Option<u32> {
+    if is_pdf(path) {
+        pdf::page_count(path).ok()
+    } else if is_tiff(path) {
+        tiff_page_count(path).ok().filter(|&count| count > 1)
+    } else {
+        None
+    }
+}
+
+/// Whether `path` is a TIFF holding more than one page, each processed as its own image.
+pub fn is_multipage_tiff(path: &Path) -> bool {
+    is_tiff(path) && page_count(path).is_some()
+}
+
+/// Renders each page of the PDF at `path` that `selection` picks at `dpi`, one at a time so
+/// only one is held in memory, and hands it to `each` with its number, counting from 1, and
+/// the page count.
+pub fn rasterize(
+    path: &Path,
+    dpi: u32,
+    selection: Option<&PageSelection>,
+    each: impl FnMut(u32, u32, DynamicImage) -> Result<()>,
+) -> Result<()> {
+    pdf::rasterize(path, dpi, selection, each)
+}
+
+fn open_tiff(path: &Path) -> Result<Decoder<BufReader<File>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    Decoder::new(BufReader::new(file)).with_context(|| format!("Failed to read TIFF header of {}", path.display()))
+}
+
+fn tiff_page_count(path: &Path) -> Result<u32> {
+    let mut decoder = open_tiff(path)?;
+    let mut count = 1;
+    while decoder.more_images() {
+        decoder.next_image()?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Decodes each page of the TIFF at `path` that `selection` picks, one at a time, and hands it
+/// to `each` with its number, counting from 1, the page count and the orientation its own
+/// Orientation tag gives.
+pub fn tiff_pages(
+    path: &Path,
+    selection: Option<&PageSelection>,
+    mut each: impl FnMut(u32, u32, DynamicImage, Orientation) -> Result<()>,
+) -> Result<()> {
+    let count = tiff_page_count(path)?;
+    let mut decoder = open_tiff(path)?;
+    for number in 1..=count {
+        if !wanted(selection, number, count) {
+            continue;
+        }
+        decoder.seek_to_image(number as usize - 1)?;
+        let img = decode_tiff_page(&mut decoder).with_context(|| format!("Failed to decode page {} of {}", number, path.display()))?;
+        let orientation = decoder
+            .find_tag_unsigned::<u8>(Tag::Orientation)
+            .ok()
+            .flatten()
+            .and_then(Orientation::from_exif)
+            .unwrap_or(Orientation::NoTransforms);
+        each(number, count, img, orientation)?;
+    }
+    Ok(())
+}
+
+/// Decodes the page `decoder` is at. CMYK pages are converted to RGB as single-page TIFFs are.
+fn decode_tiff_page<R: Read + Seek>(decoder: &mut Decoder<R>) -> Result<DynamicImage> {
+    let (width, height) = decoder.dimensions()?;
+    let colortype = decoder.colortype()?;
+    if colortype == ColorType::CMYK(8) {
+        let icc = tiff_icc_profile(decoder);
+        let DecodingResult::U8(pixels) = decoder.read_image()? else { bail!("Unexpected CMYK sample format") };
+        return cmyk_to_rgb(width, height, &pixels, icc.as_deref());
+    }
+    let img = match (colortype, decoder.read_image()?) {
+        (ColorType::Gray(8), DecodingResult::U8(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageLuma8),
+        (ColorType::GrayA(8), DecodingResult::U8(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageLumaA8),
+        (ColorType::RGB(8), DecodingResult::U8(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageRgb8),
+        (ColorType::RGBA(8), DecodingResult::U8(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageRgba8),
+        (ColorType::Gray(16), DecodingResult::U16(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageLuma16),
+        (ColorType::GrayA(16), DecodingResult::U16(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageLumaA16),
+        (ColorType::RGB(16), DecodingResult::U16(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageRgb16),
+        (ColorType::RGBA(16), DecodingResult::U16(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageRgba16),
+        (ColorType::RGB(32), DecodingResult::F32(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageRgb32F),
+        (ColorType::RGBA(32), DecodingResult::F32(p)) => ImageBuffer::from_raw(width, height, p).map(DynamicImage::ImageRgba32F),
+        (colortype, _) => bail!("Unsupported TIFF color type {:?}", colortype),
+    };
+    img.context("TIFF page has an unexpected size")
+}
+
+#[cfg(feature = "pdf")]
+mod pdf {
+    use super::{wanted, PageSelection};
+    use anyhow::{anyhow, Context, Result};
+    use image::DynamicImage;
+    use pdfium_render::prelude::*;
+    use std::env;
+    use std::path::Path;
+    use std::sync::OnceLock;
+
+    /// PDF pages are measured in points, 72 to the inch.
+    const POINTS_PER_INCH: f32 = 72.0;
+
+    /// The Pdfium library, bound once per run: from next to the executable, or else from the
+    /// system's library path.
+    fn pdfium() -> Result<&'static Pdfium> {
+        static PDFIUM: OnceLock<Result<Pdfium, String>> = OnceLock::new();
+        PDFIUM
+            .get_or_init(|| {
+                let beside = env::current_exe().ok().and_then(|exe| {
+                    let dir = exe.parent()?.to_path_buf();
+                    Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(&dir)).ok()
+                });
+                match beside {
+                    Some(bindings) => Ok(bindings),
+                    None => Pdfium::bind_to_system_library(),
+                }
+                .map(Pdfium::new)
+                .map_err(|e| e.to_string())
+            })
+            .as_ref()
+            .map_err(|e| anyhow!("Failed to load the Pdfium library ({}); install it or put it next to imagekit", e))
+    }
+
+    fn open<'a>(pdfium: &'a Pdfium, path: &Path) -> Result<PdfDocument<'a>> {
+        pdfium.load_pdf_from_file(path, None).with_context(|| format!("Failed to open PDF {}", path.display()))
+    }
+
+    pub fn page_count(path: &Path) -> Result<u32> {
+        Ok(open(pdfium()?, path)?.pages().len() as u32)
+    }
+
+    pub fn rasterize(
+        path: &Path,
+        dpi: u32,
+        selection: Option<&PageSelection>,
+        mut each: impl FnMut(u32, u32, DynamicImage) -> Result<()>,
+    ) -> Result<()> {
+        let document = open(pdfium()?, path)?;
+        let pages = document.pages();
+        let count = pages.len() as u32;
+        // Form fields are drawn as filled in, on white, as a viewer shows them.
+        let config = PdfRenderConfig::new().scale_page_by_factor(dpi as f32 / POINTS_PER_INCH).render_form_data(true);
+        for (index, page) in pages.iter().enumerate() {
+            let number = index as u32 + 1;
+            if !wanted(selection, number, count) {
+                continue;
+            }
+            let bitmap = page
+                .render_with_config(&config)
+                .with_context(|| format!("Failed to render page {} of {}", number, path.display()))?;
+            each(number, count, DynamicImage::ImageRgb8(bitmap.as_image().to_rgb8()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "pdf"))]
+mod pdf {
+    use super::PageSelection;
+    use anyhow::{bail, Result};
+    use image::DynamicImage;
+    use std::path::Path;
+
+    pub fn page_count(_: &Path) -> Result<u32> {
+        bail!("imagekit was built without the `pdf` feature")
+    }
+
+    pub fn rasterize(_: &Path, _: u32, _: Option<&PageSelection>, _: impl FnMut(u32, u32, DynamicImage) -> Result<()>) -> Result<()> {
+        bail!("imagekit was built without the `pdf` feature")
+    }
+}
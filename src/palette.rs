@@ -0,0 +1,73 @@
+use super::cli::Dither;
+use super::errors::ParsePaletteError;
+use image::imageops::colorops::{dither, ColorMap};
+use image::{DynamicImage, Rgba};
+use std::fmt;
+use std::str::FromStr;
+
+/// A fixed `--palette` of opaque colors that outputs are reduced to, e.g. the inks of an
+/// e-ink panel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette(pub Vec<[u8; 3]>);
+
+impl FromStr for Palette {
+    type Err = ParsePaletteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePaletteError(s.to_string());
+        let colors = s
+            .split(',')
+            .map(|hex| {
+                let hex = hex.trim();
+                let hex = hex.strip_prefix('#').unwrap_or(hex);
+                if hex.len() != 6 || !hex.is_ascii() {
+                    return Err(err());
+                }
+                let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| err());
+                Ok([channel(0)?, channel(2)?, channel(4)?])
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Palette(colors))
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let colors: Vec<String> = self.0.iter().map(|[r, g, b]| format!("{:02x}{:02x}{:02x}", r, g, b)).collect();
+        f.write_str(&colors.join(","))
+    }
+}
+
+impl ColorMap for Palette {
+    type Color = Rgba<u8>;
+
+    fn index_of(&self, color: &Rgba<u8>) -> usize {
+        let distance = |c: &[u8; 3]| (0..3).map(|i| (c[i] as i32 - color[i] as i32).pow(2)).sum::<i32>();
+        (0..self.0.len()).min_by_key(|&i| distance(&self.0[i])).unwrap_or(0)
+    }
+
+    fn lookup(&self, index: usize) -> Option<Rgba<u8>> {
+        self.0.get(index).map(|&[r, g, b]| Rgba([r, g, b, 255]))
+    }
+
+    fn has_lookup(&self) -> bool {
+        true
+    }
+
+    /// Replaces the color with its nearest palette entry; alpha is left alone.
+    fn map_color(&self, color: &mut Rgba<u8>) {
+        let [r, g, b] = self.0[self.index_of(color)];
+        color.0[..3].copy_from_slice(&[r, g, b]);
+    }
+}
+
+/// Reduces every pixel to the nearest palette color, optionally diffusing the error to its
+/// neighbors so gradients survive as patterns.
+pub fn quantize(img: &mut DynamicImage, palette: &Palette, method: Dither) {
+    let mut rgba = img.to_rgba8();
+    match method {
+        Dither::None => rgba.pixels_mut().for_each(|p| palette.map_color(p)),
+        Dither::FloydSteinberg => dither(&mut rgba, palette),
+    }
+    *img = DynamicImage::ImageRgba8(rgba);
+}
@@ -0,0 +1,261 @@
+use super::errors::ParsePerspectiveError;
+use anyhow::{bail, Result};
+use image::{DynamicImage, GrayImage, Rgba, RgbaImage};
+use std::fmt;
+use std::str::FromStr;
+
+/// Longest side images are scaled down to before `--perspective auto` looks for a document.
+const DETECT_SIDE: u32 = 400;
+/// Least share of the frame a found document must cover; anything smaller is more likely a
+/// stray bright patch than the page.
+const MIN_COVERAGE: f32 = 0.1;
+
+/// A corner of the quadrilateral, in pixels from the top left.
+pub type Point = (f32, f32);
+
+/// What `--perspective` straightens: four given corners, or a document found in each image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Perspective {
+    Corners([Point; 4]),
+    Auto,
+}
+
+impl FromStr for Perspective {
+    type Err = ParsePerspectiveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParsePerspectiveError(s.to_string());
+        if s.trim().eq_ignore_ascii_case("auto") {
+            return Ok(Perspective::Auto);
+        }
+        let points = s
+            .split_whitespace()
+            .map(|point| {
+                let (x, y) = point.split_once(',').ok_or_else(err)?;
+                let (x, y) = (x.parse::<f32>().map_err(|_| err())?, y.parse::<f32>().map_err(|_| err())?);
+                if x.is_finite() && y.is_finite() && x >= 0.0 && y >= 0.0 { Ok((x, y)) } else { Err(err()) }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let corners: [Point; 4] = points.try_into().map_err(|_| err())?;
+        order(corners).map(Perspective::Corners).ok_or_else(err)
+    }
+}
+
+impl fmt::Display for Perspective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Perspective::Corners(corners) => {
+                let points: Vec<String> = corners.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+                write!(f, "{}", points.join(" "))
+            }
+            Perspective::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+/// Warps the quadrilateral `perspective` names onto an upright rectangle as wide as its longer
+/// horizontal edge and as tall as its longer vertical one. Returns the straightened image and
+/// the corners used, top left first and clockwise, or `None` if `auto` found no document.
+pub fn correct(img: &DynamicImage, perspective: Perspective) -> Result<Option<(DynamicImage, [Point; 4])>> {
+    let corners = match perspective {
+        Perspective::Corners(corners) => {
+            let (width, height) = (img.width() as f32, img.height() as f32);
+            if let Some((x, y)) = corners.iter().find(|(x, y)| *x > width || *y > height) {
+                bail!("Perspective corner {},{} lies outside the {}x{} image", x, y, width, height);
+            }
+            corners
+        }
+        Perspective::Auto => match find_document(img) {
+            Some(corners) => corners,
+            None => return Ok(None),
+        },
+    };
+    let [top_left, top_right, bottom_right, bottom_left] = corners;
+    let width = distance(top_left, top_right).max(distance(bottom_left, bottom_right)).round().max(1.0);
+    let height = distance(top_left, bottom_left).max(distance(top_right, bottom_right)).round().max(1.0);
+    let rectangle = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)];
+    let Some(homography) = Homography::mapping(rectangle, corners) else {
+        bail!("Perspective corners {} don't span an area", Perspective::Corners(corners));
+    };
+    Ok(Some((homography.warp(img, width as u32, height as u32), corners)))
+}
+
+/// Puts four corners in order, top left first and clockwise, or `None` if they don't form a
+/// convex quadrilateral.
+fn order(mut corners: [Point; 4]) -> Option<[Point; 4]> {
+    let (cx, cy) = (corners.iter().map(|p| p.0).sum::<f32>() / 4.0, corners.iter().map(|p| p.1).sum::<f32>() / 4.0);
+    // With y pointing down, rising angles go clockwise, starting from the left.
+    corners.sort_by(|a, b| (a.1 - cy).atan2(a.0 - cx).total_cmp(&(b.1 - cy).atan2(b.0 - cx)));
+    let turns: Vec<f32> = (0..4)
+        .map(|i| {
+            let (a, b, c) = (corners[i], corners[(i + 1) % 4], corners[(i + 2) % 4]);
+            (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0)
+        })
+        .collect();
+    turns.iter().all(|&turn| turn > 0.0).then_some(corners)
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+/// Finds the corners of a document: the largest region that stands out from the background
+/// the frame's border shows, such as a page on a desk. Only its extreme points are used, so
+/// text and shadows inside it don't matter, but a page turned by much more than 30 degrees
+/// gets the wrong corners.
+fn find_document(img: &DynamicImage) -> Option<[Point; 4]> {
+    let small = img.thumbnail(DETECT_SIDE, DETECT_SIDE).to_luma8();
+    let (width, height) = small.dimensions();
+    if width < 8 || height < 8 {
+        return None;
+    }
+    let threshold = otsu(&small);
+    let border: Vec<bool> = small
+        .enumerate_pixels()
+        .filter(|(x, y, _)| *x == 0 || *y == 0 || *x == width - 1 || *y == height - 1)
+        .map(|(_, _, p)| p[0] > threshold)
+        .collect();
+    let bright_background = border.iter().filter(|&&bright| bright).count() * 2 > border.len();
+    let foreground: Vec<bool> = small.pixels().map(|p| (p[0] > threshold) != bright_background).collect();
+
+    let region = largest_region(&foreground, width as usize, height as usize)?;
+    let area = (width * height) as f32;
+    if (region.len() as f32) < area * MIN_COVERAGE {
+        return None;
+    }
+    // The outer corner of the pixel furthest in each diagonal direction.
+    let extreme = |key: fn(f32, f32) -> f32| {
+        region.iter().map(|&(x, y)| (x as f32, y as f32)).max_by(|a, b| key(a.0, a.1).total_cmp(&key(b.0, b.1)))
+    };
+    let top_left = extreme(|x, y| -x - y)?;
+    let top_right = extreme(|x, y| x - y)?;
+    let bottom_right = extreme(|x, y| x + y)?;
+    let bottom_left = extreme(|x, y| y - x)?;
+    let corners = [top_left, (top_right.0 + 1.0, top_right.1), (bottom_right.0 + 1.0, bottom_right.1 + 1.0), (bottom_left.0, bottom_left.1 + 1.0)];
+    let (sx, sy) = (img.width() as f32 / width as f32, img.height() as f32 / height as f32);
+    let corners = order(corners.map(|(x, y)| (x * sx, y * sy)))?;
+    let [a, b, c, d] = corners;
+    let quad_area = ((a.0 * b.1 - b.0 * a.1) + (b.0 * c.1 - c.0 * b.1) + (c.0 * d.1 - d.0 * c.1) + (d.0 * a.1 - a.0 * d.1)) / 2.0;
+    (quad_area >= img.width() as f32 * img.height() as f32 * MIN_COVERAGE).then_some(corners)
+}
+
+/// The gray level that best splits the image into a dark and a bright class (Otsu's method).
+fn otsu(img: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for p in img.pixels() {
+        histogram[p[0] as usize] += 1;
+    }
+    let total = img.pixels().len() as f64;
+    let sum: f64 = histogram.iter().enumerate().map(|(level, &n)| level as f64 * n as f64).sum();
+    let (mut dark, mut dark_sum, mut best, mut threshold) = (0.0, 0.0, -1.0, 0);
+    for (level, &n) in histogram.iter().enumerate() {
+        dark += n as f64;
+        dark_sum += level as f64 * n as f64;
+        let bright = total - dark;
+        if dark == 0.0 || bright == 0.0 {
+            continue;
+        }
+        let between = dark * bright * (dark_sum / dark - (sum - dark_sum) / bright).powi(2);
+        if between > best {
+            best = between;
+            threshold = level as u8;
+        }
+    }
+    threshold
+}
+
+/// The pixels of the largest 4-connected region of `mask`.
+fn largest_region(mask: &[bool], width: usize, height: usize) -> Option<Vec<(usize, usize)>> {
+    let mut seen = vec![false; mask.len()];
+    let mut largest: Vec<(usize, usize)> = Vec::new();
+    for start in 0..mask.len() {
+        if !mask[start] || seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        let (mut region, mut stack) = (Vec::new(), vec![start]);
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % width, i / width);
+            region.push((x, y));
+            let neighbors = [
+                (x > 0).then(|| i - 1),
+                (x + 1 < width).then_some(i + 1),
+                (y > 0).then(|| i - width),
+                (y + 1 < height).then_some(i + width),
+            ];
+            for n in neighbors.into_iter().flatten() {
+                if mask[n] && !seen[n] {
+                    seen[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+        if region.len() > largest.len() {
+            largest = region;
+        }
+    }
+    (!largest.is_empty()).then_some(largest)
+}
+
+/// A projective map of the plane, taking `(x, y)` to
+/// `((h0 x + h1 y + h2) / w, (h3 x + h4 y + h5) / w)` with `w = h6 x + h7 y + 1`.
+struct Homography([f64; 8]);
+
+impl Homography {
+    /// The homography taking each of `from` to the matching point of `to`, or `None` if the
+    /// points are degenerate.
+    fn mapping(from: [Point; 4], to: [Point; 4]) -> Option<Homography> {
+        let mut rows = [[0.0f64; 9]; 8];
+        for (i, (&(x, y), &(u, v))) in from.iter().zip(&to).enumerate() {
+            let (x, y, u, v) = (x as f64, y as f64, u as f64, v as f64);
+            rows[2 * i] = [x, y, 1.0, 0.0, 0.0, 0.0, -x * u, -y * u, u];
+            rows[2 * i + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -x * v, -y * v, v];
+        }
+        // Gaussian elimination with partial pivoting.
+        for col in 0..8 {
+            let pivot = (col..8).max_by(|&a, &b| rows[a][col].abs().total_cmp(&rows[b][col].abs()))?;
+            if rows[pivot][col].abs() < 1e-9 {
+                return None;
+            }
+            rows.swap(col, pivot);
+            for row in 0..8 {
+                if row != col {
+                    let factor = rows[row][col] / rows[col][col];
+                    let pivot_row = rows[col];
+                    rows[row].iter_mut().zip(pivot_row).for_each(|(value, p)| *value -= factor * p);
+                }
+            }
+        }
+        Some(Homography(std::array::from_fn(|i| rows[i][8] / rows[i][i])))
+    }
+
+    fn apply(&self, x: f32, y: f32) -> Point {
+        let h = &self.0;
+        let (x, y) = (x as f64, y as f64);
+        let w = h[6] * x + h[7] * y + 1.0;
+        (((h[0] * x + h[1] * y + h[2]) / w) as f32, ((h[3] * x + h[4] * y + h[5]) / w) as f32)
+    }
+
+    /// Renders a `width` x `height` image whose pixels are sampled, bilinearly, from `img`
+    /// where the homography takes their centers.
+    fn warp(&self, img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+        let rgba = img.to_rgba8();
+        let (source_width, source_height) = rgba.dimensions();
+        let (w, h) = (source_width as f32, source_height as f32);
+        let warped = RgbaImage::from_fn(width, height, |x, y| {
+            let (sx, sy) = self.apply(x as f32 + 0.5, y as f32 + 0.5);
+            let (sx, sy) = ((sx - 0.5).clamp(0.0, w - 1.0), (sy - 0.5).clamp(0.0, h - 1.0));
+            let (x0, y0) = (sx.floor() as u32, sy.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(source_width - 1), (y0 + 1).min(source_height - 1));
+            let (fx, fy) = (sx - x0 as f32, sy - y0 as f32);
+            let at = |x: u32, y: u32, c: usize| rgba.get_pixel(x, y)[c] as f32;
+            Rgba([0, 1, 2, 3].map(|c| {
+                let top = at(x0, y0, c) * (1.0 - fx) + at(x1, y0, c) * fx;
+                let bottom = at(x0, y1, c) * (1.0 - fx) + at(x1, y1, c) * fx;
+                (top * (1.0 - fy) + bottom * fy).round() as u8
+            }))
+        });
+        let warped = DynamicImage::ImageRgba8(warped);
+        if img.color().has_alpha() { warped } else { DynamicImage::ImageRgb8(warped.to_rgb8()) }
+    }
+}
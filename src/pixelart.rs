@@ -0,0 +1,52 @@
+use anyhow::{bail, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GenericImageView, RgbaImage};
+
+/// An integer scale for `--pixel-perfect`: every source pixel becomes an `n` x `n` block, or
+/// every `n` x `n` block becomes one pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntegerScale {
+    Up(u32),
+    Down(u32),
+}
+
+/// Picks the largest integer scale that fits the image inside the given targets. Shrinking
+/// uses the smallest factor that fits, and only when it divides both sides; otherwise pixels
+/// would be dropped unevenly, so this fails instead.
+pub fn integer_scale((width, height): (u32, u32), (target_width, target_height): (Option<u32>, Option<u32>)) -> Result<IntegerScale> {
+    let sides = [(width, target_width), (height, target_height)];
+    let sides = sides.iter().filter_map(|&(side, target)| Some((side.max(1), target?.max(1))));
+    let up = sides.clone().map(|(side, target)| target / side).min().unwrap_or(1);
+    if up >= 1 {
+        return Ok(IntegerScale::Up(up));
+    }
+    let down = sides.map(|(side, target)| side.div_ceil(target)).max().unwrap_or(1);
+    if width % down != 0 || height % down != 0 {
+        bail!("--pixel-perfect can't shrink a {}x{} image by a whole factor of {}", width, height, down);
+    }
+    Ok(IntegerScale::Down(down))
+}
+
+/// Scales `img` by an integer factor with nearest-neighbor sampling, then centers it on a
+/// transparent canvas of the target size. A missing target takes the scaled size.
+pub fn pixel_perfect(img: &DynamicImage, targets: (Option<u32>, Option<u32>)) -> Result<(DynamicImage, IntegerScale)> {
+    let (width, height) = img.dimensions();
+    let scale = integer_scale((width, height), targets)?;
+    let (scaled_width, scaled_height) = match scale {
+        IntegerScale::Up(n) => (width * n, height * n),
+        IntegerScale::Down(n) => (width / n, height / n),
+    };
+    let scaled = if (scaled_width, scaled_height) == (width, height) {
+        img.clone()
+    } else {
+        img.resize_exact(scaled_width, scaled_height, FilterType::Nearest)
+    };
+    let (canvas_width, canvas_height) = (targets.0.unwrap_or(scaled_width), targets.1.unwrap_or(scaled_height));
+    if (canvas_width, canvas_height) == (scaled_width, scaled_height) {
+        return Ok((scaled, scale));
+    }
+    let mut canvas = RgbaImage::new(canvas_width, canvas_height);
+    let (x, y) = ((canvas_width - scaled_width) / 2, (canvas_height - scaled_height) / 2);
+    imageops::overlay(&mut canvas, &scaled.to_rgba8(), x as i64, y as i64);
+    Ok((DynamicImage::ImageRgba8(canvas), scale))
+}
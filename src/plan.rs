@@ -0,0 +1,169 @@
+use super::cli::{Cli, OutputFormat, ResizeMode};
+use super::context::RunContext;
+use super::decode::exif_orientation;
+use super::i18n::Msg;
+use super::lens::LensCorrect;
+use super::pages::{is_pdf, is_tiff, page_count};
+use super::perspective::Perspective;
+use super::processor::{output_path_for, planned_outputs};
+use super::rules::Pipeline;
+use super::thumbs::is_raw;
+use anyhow::Result;
+use image::metadata::Orientation;
+use image::ImageFormat;
+use std::path::{Path, PathBuf};
+
+/// Prints what a run would do to each input for `--dry-run`: its outputs and the steps leading
+/// to them. Only headers and EXIF data are read; nothing is decoded or written.
+pub fn print(cli: &Cli, context: &RunContext, inputs: &[PathBuf]) -> Result<()> {
+    let relative = |path: &Path, dir: &Path| path.strip_prefix(dir).unwrap_or(path).display().to_string();
+    let mut planned = 0;
+    for input in inputs {
+        let source = relative(input, &cli.input_dir);
+        let pipeline = Pipeline::for_path(cli, input)?;
+        if pipeline.skip {
+            println!("{}", Msg::DryRunRuleSkip(&source));
+            continue;
+        }
+        let outputs: Vec<String> =
+            planned_outputs(input, cli, context, &pipeline)?.iter().map(|output| relative(output, &cli.output_dir)).collect();
+        println!("{} -> {}", source, outputs.join(", "));
+        for step in steps(cli, context, input, &pipeline) {
+            println!("    {}", step);
+        }
+        planned += 1;
+    }
+    println!("{}", Msg::DryRunPlanned(planned));
+    Ok(())
+}
+
+/// Describes the steps processing `path` takes, in the order they're applied.
+pub fn steps(cli: &Cli, context: &RunContext, path: &Path, pipeline: &Pipeline) -> Vec<String> {
+    if pipeline.copy {
+        return vec!["copy unchanged".to_string()];
+    }
+    let mut steps = Vec::new();
+    let which = match &cli.pages {
+        Some(selection) => format!("pages {}", selection),
+        None => "each page".to_string(),
+    };
+    if let (Some(dpi), true) = (cli.pdf_dpi, is_pdf(path)) {
+        steps.push(format!("render {} of the PDF at {} dpi", which, dpi));
+    }
+    if let (Some(count), true) = (page_count(path), is_tiff(path)) {
+        steps.push(format!("take {} of the {}-page TIFF as its own image", which, count));
+    }
+    if cli.fast_thumbs && is_raw(path) {
+        steps.push("start from the embedded JPEG preview".to_string());
+    }
+    if !cli.no_auto_orient {
+        let orientation = exif_orientation(path);
+        if orientation != Orientation::NoTransforms {
+            steps.push(format!("orient: EXIF orientation {}", orientation.to_exif()));
+        }
+    }
+    if let Some(mode) = cli.lens_correct {
+        let what = match mode {
+            LensCorrect::Auto => "distortion and color fringing",
+            LensCorrect::Distortion => "distortion",
+            LensCorrect::Tca => "color fringing",
+        };
+        steps.push(format!("correct lens {} by the EXIF lens's profile", what));
+    }
+    match cli.perspective {
+        Some(Perspective::Auto) => steps.push("straighten perspective: the document found in the image".to_string()),
+        Some(corners) => steps.push(format!("straighten perspective: corners {}", corners)),
+        None => {}
+    }
+    if cli.trim_transparent {
+        steps.push("trim transparent edges".to_string());
+    }
+    if let Some(min) = cli.min_sharpness {
+        steps.push(format!("hold back if sharpness is below {}", min));
+    }
+
+    let dimensions = image::image_dimensions(path).ok();
+    let (width, height) = dimensions.unwrap_or_default();
+    if let (Some(scale), Some(_)) = (cli.upscale, &cli.upscale_model) {
+        steps.push(format!("upscale: {}x with the AI model", scale.factor));
+    }
+    if let (Some(size), Some(dpi)) = (cli.physical_size, cli.effective_dpi()) {
+        let (w, h) = size.pixels(dpi, width >= height);
+        steps.push(format!("resize: fill {}x{} for print at {} dpi", w, h, dpi));
+    } else if !cli.widths.is_empty() {
+        let widths: Vec<String> = cli.widths.iter().map(u32::to_string).collect();
+        steps.push(format!("resize: widths {}", widths.join(", ")));
+    } else {
+        let target = match pipeline.size_for(width, height) {
+            (Some(w), None) => Some(format!("width {}", w)),
+            (None, Some(h)) => Some(format!("height {}", h)),
+            (Some(w), Some(h)) => Some(format!("{}x{}", w, h)),
+            (None, None) => cli
+                .max_dimension
+                .filter(|&cap| dimensions.is_none_or(|_| width.max(height) > cap))
+                .map(|cap| format!("fit within {}px", cap)),
+        };
+        if let Some(target) = target {
+            let how = if cli.pixel_perfect {
+                " (integer scale)"
+            } else if cli.nine_slice.is_some() {
+                " (nine-slice)"
+            } else if cli.resize_mode == ResizeMode::SeamCarve {
+                " (seam carving)"
+            } else {
+                ""
+            };
+            steps.push(format!("resize: {}{}", target, how));
+        }
+    }
+
+    if !cli.adjustments.is_empty() {
+        steps.push(format!("adjust colors: {} adjustments", cli.adjustments.len()));
+    }
+    if cli.grayscale {
+        steps.push("grayscale".to_string());
+    }
+    if cli.proof_style {
+        steps.push("proof style".to_string());
+    }
+    if let Some(dir) = &cli.overlay_dir {
+        steps.push(format!("overlay: from {}", dir.display()));
+    }
+    if let Some(logo) = &cli.watermark_image {
+        steps.push(format!("watermark: image {} ({})", logo.display(), cli.watermark_position));
+    }
+    for spec in context.watermark_specs(cli) {
+        steps.push(format!("watermark: \"{}\" ({})", spec.text, spec.position));
+    }
+    if let Some(palette) = &cli.palette {
+        steps.push(format!("palette: {} ({})", palette, cli.dither.name()));
+    }
+
+    let source = ImageFormat::from_path(path).ok().map_or("?", |f| f.extensions_str()[0]);
+    let target = match &pipeline.output_format {
+        Some(OutputFormat::Auto) => "jpg or png by content",
+        _ => match output_path_for(path, cli, context, pipeline) {
+            Ok((_, Some(format))) => format.extensions_str()[0],
+            Ok((_, None)) => "raw pixels",
+            Err(_) => source,
+        },
+    };
+    let encode = if source == target { format!("encode: {}", target) } else { format!("convert: {} -> {}", source, target) };
+    steps.push(format!("{}, quality {}", encode, pipeline.quality));
+
+    if cli.keep_metadata {
+        steps.push("copy EXIF, ICC and XMP".to_string());
+    } else if cli.strip_metadata {
+        steps.push("strip metadata".to_string());
+    }
+    if let Some(dpi) = cli.effective_dpi() {
+        steps.push(format!("set print resolution: {} dpi", dpi));
+    }
+    if cli.c2pa_cert.is_some() {
+        steps.push("sign with C2PA".to_string());
+    }
+    if !cli.encrypt.is_empty() {
+        steps.push(format!("encrypt for {} recipients", cli.encrypt.len()));
+    }
+    steps
+}
@@ -0,0 +1,47 @@
+use super::assets::{load_chain, load_fonts_with};
+use super::cli::PreviewWatermarkArgs;
+use super::decode::open_image;
+use super::i18n::Msg;
+use super::processor::{draw_watermark, WatermarkSpec};
+use anyhow::{Context, Result};
+use image::imageops::FilterType;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// Side of one checkerboard square, in pixels.
+const SQUARE: u32 = 16;
+
+/// Renders the watermark onto a checkerboard (or the sample image) and saves the result.
+pub fn run(args: &PreviewWatermarkArgs) -> Result<()> {
+    let fonts = if args.font_chain.is_empty() { load_fonts_with(&args.fonts)? } else { load_chain(&args.font_chain)? };
+    let (width, height) = (args.size.width, args.size.height);
+    let mut img = match &args.sample {
+        Some(path) => open_image(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?
+            .resize_to_fill(width, height, FilterType::Lanczos3),
+        None => checkerboard(width, height),
+    };
+    let spec = WatermarkSpec {
+        text: args.text.clone(),
+        position: args.position,
+        font_size: args.font_size,
+        relative_size: args.relative_size,
+        color: args.color,
+        letter_spacing: args.letter_spacing,
+        line_height: args.line_height,
+        align: args.align,
+        arc: args.arc,
+        angle: args.angle,
+        ..WatermarkSpec::default()
+    };
+    draw_watermark(&mut img, &spec, &fonts);
+    img.save(&args.output).with_context(|| format!("Failed to save {}", args.output.display()))?;
+    println!("{}", Msg::SavedPreview(&args.output));
+    Ok(())
+}
+
+/// A light and dark gray checkerboard, so both light and dark watermarks stay visible.
+pub fn checkerboard(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+        if (x / SQUARE + y / SQUARE).is_multiple_of(2) { Rgba([204, 204, 204, 255]) } else { Rgba([102, 102, 102, 255]) }
+    }))
+}
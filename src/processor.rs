@@ -1,22 +1,429 @@
-use super::cli::{Cli, HexColor, WatermarkPosition};
-use anyhow::{Context, Result};
-use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use super::adjust::apply_adjustments;
+use super::autoformat;
+use super::c2pa::sign_file;
+use super::cli::{
+    Cli, HexColor, IfExists, OutputFormat, Percentage, PngFilter, ResizeMode, SafetyMode, TextAlign, TiffCompression,
+    WatermarkPosition,
+};
+use super::context::RunContext;
+use super::decode::{exif_orientation, open_image, swaps_dimensions};
+use super::density::set_dpi;
+use super::discard::{make_room, remove_file, temp_path};
+use super::encrypt::{encrypt_file, sealed_path, staging_path};
+use super::fastio;
+use super::interlace::write_interlaced_png;
+use super::lens;
+use super::metadata::{copy_icc_profile, copy_metadata, strip_metadata, write_keywords};
+use super::overlay::{blend_onto, composite, find_overlay};
+use super::palette::quantize;
+use super::pages::{self, is_pdf};
+use super::perspective;
+use super::pixelart::{pixel_perfect, IntegerScale};
+use super::random::Rng;
+use super::rawdump::{layout_path, write_raw, RawLayout, RAW_EXTENSION};
+use super::rules::Pipeline;
+use super::safety::{self, SafetyCheck};
+use super::seamcarve::carve;
+use super::sharpness;
+use super::sidecar::{sidecar_path, EncoderSettings, Operation, Sidecar, SidecarBundle, Timings};
+use super::sync::hash_file;
+use super::tagging::auto_tag;
+use super::thumbs::{fast_thumbnail, is_raw};
+use super::trim::content_bounds;
+use super::upscale::upscale;
+use super::zones::{self, Zone};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use image::codecs::png::{CompressionType, PngEncoder};
+use image::codecs::pnm::{PnmEncoder, PnmSubtype, SampleEncoding};
+use image::metadata::Orientation;
 use image::{DynamicImage, GenericImage, GenericImageView, ImageEncoder, Pixel, ImageFormat};
 use rusttype::{point, Font, PositionedGlyph, Scale};
+use tiff::encoder::TiffEncoder;
 use std::fs;
-use std::io::BufWriter;
-use std::path::Path;
+use std::io::{BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-/// The core function for processing a single image.
-pub fn process_image(path: &Path, cli: &Cli, fonts: &[Font<'static>]) -> Result<()> {
-    println!("Processing {}...", path.display());
+/// Margin between watermarks and the image edges, in pixels.
+const WATERMARK_PADDING: u32 = 10;
 
-    let mut img = image::open(path)?;
+/// A single watermark layer. Layers are drawn in order, so later layers sit on top.
+#[derive(Debug, Clone)]
+pub struct WatermarkSpec {
+    pub text: String,
+    pub position: WatermarkPosition,
+    pub font_size: u32,
+    /// When set, the font size is this fraction of the image width instead of `font_size`.
+    pub relative_size: Option<Percentage>,
+    pub color: HexColor,
+    /// Extra space between characters, in pixels at `font_size`.
+    pub letter_spacing: f32,
+    /// Distance between baselines, as a multiple of the font's natural line height.
+    pub line_height: f32,
+    /// How lines of multi-line text line up with each other.
+    pub align: TextAlign,
+    /// Degrees of arc the text is bent along; positive bulges upward, `0` keeps it straight.
+    pub arc: f32,
+    /// Degrees the text is turned counterclockwise, after any arc; `0` keeps it level.
+    pub angle: f32,
+    /// Shift from the anchored position in pixels; the text still stays inside the image.
+    pub offset: (i32, i32),
+    /// Areas of the image the text must not cover. When it would, the nearest other position
+    /// that keeps clear of them is used instead.
+    pub avoid: Vec<Zone>,
+    /// An image such as a logo to stamp instead of the text.
+    pub image: Option<PathBuf>,
+    /// Width of `image` as a fraction of the image's width.
+    pub image_scale: Percentage,
+    /// Repeats the layer across the whole image instead of placing it once; position, offset and
+    /// avoided zones don't apply.
+    pub tile: bool,
+}
+
+impl Default for WatermarkSpec {
+    fn default() -> Self {
+        WatermarkSpec {
+            text: String::new(),
+            position: WatermarkPosition::Se,
+            font_size: 24,
+            relative_size: None,
+            color: HexColor(image::Rgba([255, 255, 255, 128])),
+            letter_spacing: 0.0,
+            line_height: 1.0,
+            align: TextAlign::Left,
+            arc: 0.0,
+            angle: 0.0,
+            offset: (0, 0),
+            avoid: Vec::new(),
+            image: None,
+            image_scale: Percentage(0.2),
+            tile: false,
+        }
+    }
+}
+
+impl WatermarkSpec {
+    /// Returns the font size to use on an image of the given width.
+    pub fn font_size_for(&self, image_width: u32) -> u32 {
+        match self.relative_size {
+            Some(Percentage(fraction)) => ((image_width as f32 * fraction).round() as u32).max(1),
+            None => self.font_size,
+        }
+    }
+
+    /// The keys [`set_style`](Self::set_style) takes, with `-` or `_`.
+    pub const STYLE_KEYS: [&'static str; 10] =
+        ["size", "relative-size", "color", "letter-spacing", "line-height", "align", "arc", "angle", "offset", "scale"];
+
+    /// Sets one styling field by the name of its option without the `watermark-` prefix: `size`
+    /// (the font size), `relative-size`, `color`, `letter-spacing`, `line-height`, `align`,
+    /// `arc`, `angle`, `offset` (`x,y` in pixels) or `scale` (an image layer's width).
+    pub fn set_style(&mut self, key: &str, value: &str) -> Result<()> {
+        let number = || value.trim().parse::<f32>().map_err(|_| anyhow!("'{}' is not a number", value));
+        match key.replace('_', "-").as_str() {
+            "size" => {
+                let size = value.trim().parse().ok().filter(|&size| size > 0);
+                self.font_size = size.ok_or_else(|| anyhow!("'{}' is not a font size", value))?;
+            }
+            "relative-size" => self.relative_size = Some(value.parse()?),
+            "color" => self.color = value.parse()?,
+            "letter-spacing" => self.letter_spacing = number()?,
+            "line-height" => self.line_height = number()?,
+            "align" => {
+                self.align = TextAlign::from_str(value, true).map_err(|_| anyhow!("'{}' is not left, center or right", value))?;
+            }
+            "arc" => self.arc = number()?,
+            "angle" => self.angle = number()?,
+            "offset" => {
+                let (x, y) = value.split_once(',').ok_or_else(|| anyhow!("'{}' is not an offset such as '10,-5'", value))?;
+                let pixels = |n: &str| n.trim().parse::<i32>().map_err(|_| anyhow!("'{}' is not an offset such as '10,-5'", value));
+                self.offset = (pixels(x)?, pixels(y)?);
+            }
+            "scale" => self.image_scale = value.parse()?,
+            _ => bail!("Unknown watermark style '{}'", key),
+        }
+        Ok(())
+    }
+}
+
+/// What processing one image produced.
+#[derive(Debug, Clone, Default)]
+pub struct Processed {
+    /// The files written: one per `--widths` entry, smallest first, or else just one.
+    pub outputs: Vec<PathBuf>,
+    /// Labels from `--auto-tag`, most likely first.
+    pub tags: Vec<String>,
+    /// The `--safety-filter` verdict. A suspect image under `--safety-filter block` has no outputs.
+    pub safety: Option<SafetyCheck>,
+    /// The focus score, with `--min-sharpness`. An image scoring below it has no outputs.
+    pub sharpness: Option<f64>,
+}
+
+impl Processed {
+    /// Folds in what another page of the same source produced: its outputs and labels are
+    /// added, a suspect safety verdict wins, and the lowest focus score is kept.
+    fn merge(&mut self, page: Processed) {
+        self.outputs.extend(page.outputs);
+        for tag in page.tags {
+            if !self.tags.contains(&tag) {
+                self.tags.push(tag);
+            }
+        }
+        if self.safety.is_none_or(|check| !check.suspect) {
+            self.safety = page.safety.or(self.safety);
+        }
+        self.sharpness = match (self.sharpness, page.sharpness) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// The core function for processing a single image. Provenance goes to `bundle` when given,
+/// or next to the output with `--sidecar-json`.
+/// Returns what was written, or `None` if the image was skipped.
+pub fn process_image(
+    path: &Path,
+    cli: &Cli,
+    context: &RunContext,
+    fonts: &[Font<'static>],
+    bundle: Option<&SidecarBundle>,
+) -> Result<Option<Processed>> {
+    let started = Instant::now();
+    let batch = Batch { cli, context, fonts, bundle };
+
+    let mut pipeline = Pipeline::for_path(cli, path)?;
+    if let Some(quality) = context.quality {
+        pipeline.quality = pipeline.quality.min(quality);
+    }
+    let (output_path, image_format) = output_path_for(path, cli, context, &pipeline)?;
+
+    if pipeline.skip {
+        return Ok(None);
+    }
+    if pipeline.copy {
+        let output_path = free_path(output_path, cli);
+        let final_path = write_path(path, &output_path, cli, context)?;
+        if let Some(parent) = final_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        make_room(&final_path, cli.use_trash)?;
+        fs::copy(path, &final_path)
+            .with_context(|| format!("Failed to copy image to {}", final_path.display()))?;
+        if let (Some(cert), Some(key)) = (&cli.c2pa_cert, &cli.c2pa_key) {
+            sign_file(&final_path, path, &[Operation::Copy], cert, key)
+                .with_context(|| format!("Failed to sign {}", final_path.display()))?;
+        }
+        let (output_path, final_path) = bucket(cli, context, output_path, final_path)?;
+        if cli.sidecar_json || bundle.is_some() {
+            let timings = Timings { total: Timings::ms(started.elapsed()), ..Timings::default() };
+            write_sidecar(path, &output_path, vec![Operation::Copy], None, timings, bundle)?;
+        }
+        seal(&final_path, &output_path, cli)?;
+        return Ok(Some(Processed { outputs: vec![output_path], ..Processed::default() }));
+    }
+
+    if pages::is_pdf(path) {
+        let dpi = cli.pdf_dpi.context("PDF inputs need --pdf-dpi")?;
+        let mut processed = Processed::default();
+        let mut page_started = started;
+        pages::rasterize(path, dpi, cli.pages.as_ref(), |page, count, img| {
+            // Single-page documents keep the plain name.
+            let output = if count > 1 { page_variant_path(&output_path, page, cli) } else { output_path.clone() };
+            let source = Source { img, operations: vec![Operation::Rasterize { page, dpi }], decode_time: page_started.elapsed() };
+            processed.merge(develop(path, &batch, &pipeline, source, (output, image_format))?);
+            page_started = Instant::now();
+            Ok(())
+        })?;
+        return Ok(Some(processed));
+    }
+    if pages::is_multipage_tiff(path) {
+        let mut processed = Processed::default();
+        let mut page_started = started;
+        pages::tiff_pages(path, cli.pages.as_ref(), |page, _, mut img, orientation| {
+            let mut operations = vec![Operation::Page { page }];
+            if !cli.no_auto_orient && orientation != Orientation::NoTransforms {
+                img.apply_orientation(orientation);
+                operations.push(Operation::Orient { exif: orientation.to_exif() });
+            }
+            let source = Source { img, operations, decode_time: page_started.elapsed() };
+            let output = page_variant_path(&output_path, page, cli);
+            processed.merge(develop(path, &batch, &pipeline, source, (output, image_format))?);
+            page_started = Instant::now();
+            Ok(())
+        })?;
+        return Ok(Some(processed));
+    }
+
+    let mut operations = Vec::new();
+    let orientation = if cli.no_auto_orient { Orientation::NoTransforms } else { exif_orientation(path) };
+    let preview = if cli.fast_thumbs {
+        // The header gives the orientation without decoding; RAW files get the landscape targets.
+        let (width, height) = image::image_dimensions(path).unwrap_or((0, 0));
+        let (width, height) = if swaps_dimensions(orientation) { (height, width) } else { (width, height) };
+        let (target_width, target_height) = pipeline.size_for(width, height);
+        fast_thumbnail(path, target_width, target_height)?
+    } else {
+        None
+    };
+    let mut img = match preview {
+        Some(preview) => {
+            operations.push(Operation::EmbeddedPreview { width: preview.width(), height: preview.height() });
+            preview
+        }
+        None if cli.fast_io => fastio::open_image(path)?,
+        None => open_image(path)?,
+    };
+    let decode_time = started.elapsed();
+    // Pixels are turned upright before anything else; outputs carry no EXIF, so no viewer
+    // turns them again.
+    if orientation != Orientation::NoTransforms {
+        img.apply_orientation(orientation);
+        operations.push(Operation::Orient { exif: orientation.to_exif() });
+    }
+    let source = Source { img, operations, decode_time };
+    develop(path, &batch, &pipeline, source, (output_path, image_format)).map(Some)
+}
+
+/// What every image of a run is processed with.
+struct Batch<'a> {
+    cli: &'a Cli,
+    context: &'a RunContext,
+    fonts: &'a [Font<'static>],
+    bundle: Option<&'a SidecarBundle>,
+}
+
+/// A decoded image, or one page of a document, with what has been done to it so far.
+struct Source {
+    img: DynamicImage,
+    operations: Vec<Operation>,
+    decode_time: Duration,
+}
+
+/// Takes a decoded source through the rest of the pipeline to its outputs: the corrections,
+/// the sharpness and safety checks, then one render per `--widths` entry, or just one.
+fn develop(
+    path: &Path,
+    batch: &Batch,
+    pipeline: &Pipeline,
+    source: Source,
+    (output_path, image_format): (PathBuf, Option<ImageFormat>),
+) -> Result<Processed> {
+    let cli = batch.cli;
+    let Source { mut img, mut operations, decode_time } = source;
+    if let Some(mode) = cli.lens_correct {
+        let db = lens::load(cli.lens_db.as_deref())?;
+        if let Some((corrected, name)) = lens::correct(&img, path, mode, &db) {
+            img = corrected;
+            operations.push(Operation::LensCorrect { lens: name });
+        }
+    }
+    if let Some(perspective) = cli.perspective {
+        if let Some((straightened, corners)) = perspective::correct(&img, perspective)? {
+            img = straightened;
+            let (width, height) = img.dimensions();
+            operations.push(Operation::Perspective { corners: corners.map(|(x, y)| [x, y]), width, height });
+        }
+    }
+    if cli.trim_transparent {
+        if let Some((x, y, width, height)) = content_bounds(&img) {
+            img = img.crop_imm(x, y, width, height);
+            operations.push(Operation::Trim { x, y, width, height });
+        }
+    }
+
+    let sharpness = cli.min_sharpness.map(|_| sharpness::sharpness(&img));
+    if let (Some(min), Some(score)) = (cli.min_sharpness, sharpness) {
+        if score < min {
+            if let Some(dir) = &cli.quarantine_dir {
+                let quarantined = dir.join(path.strip_prefix(&cli.input_dir)?);
+                if let Some(parent) = quarantined.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::copy(path, &quarantined)
+                    .with_context(|| format!("Failed to quarantine image to {}", quarantined.display()))?;
+            }
+            return Ok(Processed { sharpness, ..Processed::default() });
+        }
+    }
+    let safety = safety::check(&img, cli)?;
+    if cli.safety_filter == Some(SafetyMode::Block) && safety.is_some_and(|check| check.suspect) {
+        return Ok(Processed { safety, ..Processed::default() });
+    }
+    // With `--output-format auto` the source's content picks the format, and so the extension.
+    let (output_path, image_format) = match pipeline.output_format {
+        Some(OutputFormat::Auto) => {
+            let format = autoformat::choose(&img);
+            (with_format(&output_path, format, cli), Some(format))
+        }
+        _ => (output_path, image_format),
+    };
+    // Labels describe the source, so every output of it shares them.
+    let tags = match (&cli.auto_tag, &cli.auto_tag_labels) {
+        (Some(model), Some(labels)) => {
+            auto_tag(&img, model, labels, cli.auto_tag_max as usize, cli.auto_tag_min_score.0)?
+        }
+        _ => Vec::new(),
+    };
+
+    if cli.widths.is_empty() {
+        let work = Work { img, operations, tags: &tags, decode_time };
+        let output = render(path, batch, pipeline, work, (output_path, image_format))?;
+        return Ok(Processed { outputs: vec![output], tags, safety, sharpness });
+    }
+    // Every width starts from the one decoded image. As with `ssg`, widths at or above the
+    // original's collapse into one full-size variant.
+    let mut widths: Vec<u32> = cli.widths.iter().map(|&w| w.min(img.width())).collect();
+    widths.sort_unstable();
+    widths.dedup();
+    let outputs = widths
+        .into_iter()
+        .map(|width| {
+            let pipeline = Pipeline {
+                width: Some(width),
+                height: None,
+                landscape: (None, None),
+                portrait: (None, None),
+                ..pipeline.clone()
+            };
+            let work = Work { img: img.clone(), operations: operations.clone(), tags: &tags, decode_time };
+            let output = (width_variant_path(&output_path, width, cli), image_format);
+            render(path, batch, &pipeline, work, output)
+        })
+        .collect::<Result<_>>()?;
+    Ok(Processed { outputs, tags, safety, sharpness })
+}
+
+/// A decoded source on its way to one output.
+struct Work<'a> {
+    img: DynamicImage,
+    /// What has been done to the image so far.
+    operations: Vec<Operation>,
+    /// Labels from `--auto-tag`, embedded once the output is saved.
+    tags: &'a [String],
+    /// Time taken to decode the source, shared by all of its outputs.
+    decode_time: Duration,
+}
+
+/// Resizes, edits and writes one output of `path`, returning where it ended up.
+fn render(
+    path: &Path,
+    batch: &Batch,
+    pipeline: &Pipeline,
+    work: Work<'_>,
+    (output_path, image_format): (PathBuf, Option<ImageFormat>),
+) -> Result<PathBuf> {
+    let Batch { cli, context, fonts, bundle } = *batch;
+    let begun = Instant::now();
+    let Work { mut img, mut operations, tags, decode_time } = work;
+    let output_path = free_path(output_path, cli);
+    let final_path = write_path(path, &output_path, cli, context)?;
     let (original_width, original_height) = img.dimensions();
 
     // Smart resizing logic.
     let mut needs_resize = false;
-    let (new_width, new_height) = match (cli.width, cli.height) {
+    let (new_width, new_height) = match pipeline.size_for(original_width, original_height) {
         (Some(w), None) => {
             needs_resize = true;
             if original_width > 0 {
@@ -34,43 +441,550 @@ pub fn process_image(path: &Path, cli: &Cli, fonts: &[Font<'static>]) -> Result<
             } else { (original_width, h) }
         },
         (Some(w), Some(h)) => { needs_resize = true; (w, h) },
-        (None, None) => (original_width, original_height),
+        (None, None) => match cli.max_dimension {
+            Some(cap) if original_width.max(original_height) > cap => {
+                needs_resize = true;
+                let ratio = cap as f32 / original_width.max(original_height) as f32;
+                let fit = |side: u32| ((side as f32 * ratio).round() as u32).clamp(1, cap);
+                (fit(original_width), fit(original_height))
+            }
+            _ => (original_width, original_height),
+        },
     };
 
-    if needs_resize && (new_width != original_width || new_height != original_height) {
-        img = img.resize_exact(new_width, new_height, image::imageops::FilterType::Lanczos3);
+    if let (Some(scale), Some(model)) = (cli.upscale, &cli.upscale_model) {
+        let target = match (cli.physical_size, cli.effective_dpi()) {
+            (Some(size), Some(dpi)) => Some(size.pixels(dpi, original_width >= original_height)),
+            _ => needs_resize.then_some((new_width, new_height)),
+        };
+        // Without a target size the model's factor sets the output size.
+        if target.is_none_or(|(w, h)| w > original_width || h > original_height) {
+            img = upscale(&img, scale.factor, model)
+                .with_context(|| format!("Failed to upscale with {}", model.display()))?;
+            operations.push(Operation::Upscale {
+                model: model.clone(),
+                factor: scale.factor,
+                width: img.width(),
+                height: img.height(),
+            });
+        }
+    }
+
+    if let (Some(size), Some(dpi)) = (cli.physical_size, cli.effective_dpi()) {
+        // Prints have exact dimensions, so crop to the paper's aspect ratio rather than stretch.
+        let (w, h) = size.pixels(dpi, original_width >= original_height);
+        img = img.resize_to_fill(w, h, image::imageops::FilterType::Lanczos3);
+        operations.push(Operation::Fill { width: w, height: h, filter: "lanczos3" });
+    } else if cli.pixel_perfect {
+        let targets = pipeline.size_for(original_width, original_height);
+        if targets != (None, None) {
+            let (scaled, scale) = pixel_perfect(&img, targets)?;
+            img = scaled;
+            let factor = match scale {
+                IntegerScale::Up(n) => format!("{}x", n),
+                IntegerScale::Down(n) => format!("1/{}", n),
+            };
+            operations.push(Operation::PixelScale { factor, width: img.width(), height: img.height() });
+        }
+    } else if let Some(slice) = cli.nine_slice {
+        // Only the given sides change; stretching a panel one way must not scale it the other.
+        let (width, height) = pipeline.size_for(original_width, original_height);
+        let (width, height) = (width.unwrap_or(original_width), height.unwrap_or(original_height));
+        if (width, height) != (original_width, original_height) {
+            img = slice.resize(&img, width, height)?;
+            operations.push(Operation::NineSlice { insets: slice.to_string(), width, height });
+        }
+    } else if needs_resize && (new_width, new_height) != img.dimensions() {
+        match cli.resize_mode {
+            ResizeMode::Stretch => {
+                img = img.resize_exact(new_width, new_height, image::imageops::FilterType::Lanczos3);
+                operations.push(Operation::Resize { width: new_width, height: new_height, filter: "lanczos3" });
+            }
+            ResizeMode::SeamCarve => {
+                img = carve(&img, new_width, new_height);
+                operations.push(Operation::SeamCarve { width: new_width, height: new_height });
+            }
+        }
     }
 
-    if let Some(text) = &cli.watermark_text {
-        add_watermark(&mut img, text, fonts, cli.font_size, cli.watermark_position, cli.watermark_color);
+    apply_adjustments(&mut img, &cli.adjustments);
+    operations.extend(cli.adjustments.iter().cloned().map(Operation::Adjust));
+    if cli.grayscale {
+        img = img.grayscale();
+        operations.push(Operation::Grayscale);
+    }
+    if cli.proof_style {
+        let image = path.strip_prefix(&cli.input_dir).unwrap_or(path).to_string_lossy();
+        apply_proof_style(&mut img, fonts, &mut Rng::new(cli.watermark_seed, &format!("proof-border/{}", image)));
+        operations.push(Operation::Proof);
     }
 
-    let relative_path = path.strip_prefix(&cli.input_dir)?;
-    let base_output_path = cli.output_dir.join(relative_path);
+    if let Some(dir) = &cli.overlay_dir {
+        let relative = path.strip_prefix(&cli.input_dir).unwrap_or(path);
+        if let Some(overlay_path) = find_overlay(dir, relative) {
+            let overlay = open_image(&overlay_path)
+                .with_context(|| format!("Failed to open overlay {}", overlay_path.display()))?;
+            let (width, height) = composite(&mut img, &overlay, cli.overlay_position, cli.overlay_scale);
+            operations.push(Operation::Overlay {
+                source: overlay_path,
+                position: cli.overlay_position.to_string(),
+                width,
+                height,
+            });
+        }
+    }
 
-    let (final_path, image_format) = if let Some(format_arg) = &cli.output_format {
-        // Case 1: User specified an output format.
-        let format: ImageFormat = format_arg.clone().into();
-        let path = base_output_path.with_extension(format.extensions_str()[0]);
-        (path, format)
+    let specs = context.watermark_specs(cli);
+    // Zones are given in source pixels and follow the image through resizing.
+    let zones: Vec<Zone> = if specs.is_empty() && cli.watermark_image.is_none() {
+        Vec::new()
     } else {
-        // Case 2: User did not specify a format; infer from the original path.
-        let format = ImageFormat::from_path(&base_output_path)?;
-        (base_output_path, format)
+        let mut zones = cli.watermark_avoid.clone();
+        zones.extend(zones::read_sidecar(path)?);
+        zones.iter().map(|zone| zone.scaled((original_width, original_height), img.dimensions())).collect()
     };
-    save_image_with_format(&img, &final_path, image_format, cli.quality)
-        .with_context(|| format!("Failed to save image to {}", final_path.display()))?;
+    // A logo goes under any text, which stays readable on top of it.
+    if let Some(logo_path) = &cli.watermark_image {
+        let logo = open_image(logo_path)
+            .with_context(|| format!("Failed to open watermark image {}", logo_path.display()))?;
+        let spec = WatermarkSpec { position: cli.watermark_position, avoid: zones.clone(), ..WatermarkSpec::default() };
+        let (position, (width, height)) = add_image_watermark(&mut img, &logo, &spec, cli.watermark_scale);
+        operations.push(Operation::ImageWatermark {
+            source: logo_path.clone(),
+            position: position.to_string(),
+            width,
+            height,
+        });
+    }
+    for (layer, mut spec) in specs.into_iter().enumerate() {
+        if let Some(max) = cli.watermark_random_offset {
+            spec.offset = random_offset(cli, path, layer, max);
+        }
+        spec.avoid.extend_from_slice(&zones);
+        match spec.image.clone() {
+            Some(source) => {
+                let logo = open_image(&source)
+                    .with_context(|| format!("Failed to open watermark image {}", source.display()))?;
+                let (position, (width, height)) = add_image_watermark(&mut img, &logo, &spec, spec.image_scale);
+                let position = if spec.tile { "tile".to_string() } else { position.to_string() };
+                operations.push(Operation::ImageWatermark { source, position, width, height });
+            }
+            None => {
+                spec.position = draw_watermark(&mut img, &spec, fonts);
+                operations.push(Operation::watermark(&spec, spec.font_size_for(img.width())));
+            }
+        }
+    }
+    if let Some(palette) = &cli.palette {
+        quantize(&mut img, palette, cli.dither);
+        operations.push(Operation::Palette { colors: palette.to_string(), dither: cli.dither.name() });
+    }
+    let processed = Instant::now();
 
-    println!("Saved to {}", final_path.display());
-    Ok(())
+    if pipeline.output_format == Some(OutputFormat::Auto) && cli.if_exists == IfExists::Overwrite {
+        // An earlier run may have picked the other format for this source.
+        for format in [ImageFormat::Jpeg, ImageFormat::Png].into_iter().filter(|&f| Some(f) != image_format) {
+            let other = with_format(&output_path, format, cli);
+            let stale: Vec<PathBuf> = match &cli.bucket_by_size {
+                Some(buckets) => buckets.candidates(&cli.output_dir, &other).collect(),
+                None => vec![other],
+            };
+            for file in stale.iter().filter(|f| f.exists() && fs::canonicalize(f).ok() != fs::canonicalize(path).ok()) {
+                remove_file(file, cli.use_trash)?;
+                let sidecar = sidecar_path(file);
+                if sidecar.exists() {
+                    remove_file(&sidecar, cli.use_trash)?;
+                }
+            }
+        }
+    }
+    make_room(&final_path, cli.use_trash)?;
+    let options = EncodeOptions {
+        png_filter: cli.png_filter,
+        png_interlace: cli.png_interlace,
+        tiff_compression: cli.tiff_compression,
+    };
+    let saved = match image_format {
+        Some(format) if cli.fast_io => fastio::save_image(&img, &final_path, format, pipeline.quality, options),
+        Some(format) => save_image_with_format(&img, &final_path, format, pipeline.quality, options),
+        None => write_raw(&img, &final_path),
+    };
+    if saved.is_err() && final_path != output_path {
+        // A partial staged file is useless; whatever it was to replace is still intact.
+        let _ = fs::remove_file(&final_path);
+    }
+    saved.with_context(|| format!("Failed to save image to {}", final_path.display()))?;
+    if cli.keep_metadata && image_format.is_some() {
+        copy_metadata(path, &final_path, !cli.no_auto_orient)
+            .with_context(|| format!("Failed to copy metadata to {}", final_path.display()))?;
+    } else if !cli.strip_metadata && image_format.is_some() {
+        copy_icc_profile(path, &final_path)
+            .with_context(|| format!("Failed to copy the ICC profile to {}", final_path.display()))?;
+    }
+    if let (Some(model), false) = (&cli.auto_tag, tags.is_empty()) {
+        write_keywords(&final_path, tags)
+            .with_context(|| format!("Failed to write keywords to {}", final_path.display()))?;
+        operations.push(Operation::AutoTag { model: model.clone(), tags: tags.to_vec() });
+    }
+    if let Some(dpi) = cli.effective_dpi() {
+        set_dpi(&final_path, dpi).with_context(|| format!("Failed to set DPI of {}", final_path.display()))?;
+        operations.push(Operation::SetDpi { dpi });
+    }
+    if cli.strip_metadata {
+        strip_metadata(&final_path)
+            .with_context(|| format!("Failed to strip metadata from {}", final_path.display()))?;
+    }
+    if let (Some(cert), Some(key)) = (&cli.c2pa_cert, &cli.c2pa_key) {
+        sign_file(&final_path, path, &operations, cert, key)
+            .with_context(|| format!("Failed to sign {}", final_path.display()))?;
+    }
+    let (output_path, final_path) = bucket(cli, context, output_path, final_path)?;
+    if image_format.is_none() {
+        RawLayout::of(img.width(), img.height()).write(&output_path)?;
+    }
+
+    if cli.sidecar_json || bundle.is_some() {
+        let timings = Timings {
+            decode: Timings::ms(decode_time),
+            process: Timings::ms(processed - begun),
+            encode: Timings::ms(processed.elapsed()),
+            total: Timings::ms(decode_time + begun.elapsed()),
+        };
+        let encoder = match image_format {
+            Some(format) => EncoderSettings::new(format, pipeline.quality, img.dimensions()),
+            None => EncoderSettings::raw(img.dimensions()),
+        };
+        write_sidecar(path, &output_path, operations, Some(encoder), timings, bundle)?;
+    }
+    seal(&final_path, &output_path, cli)?;
+    Ok(output_path)
+}
+
+/// Returns where `output` is first written. Encrypted outputs are written to the private
+/// staging directory and outputs that replace their source under a temporary name, then sealed
+/// or renamed into place, so a crash mid-encode never leaves plaintext or a half-written file
+/// in the output directory, nor destroys the original.
+fn write_path(source: &Path, output: &Path, cli: &Cli, context: &RunContext) -> Result<PathBuf> {
+    let replaces_source = fs::canonicalize(output).ok() == fs::canonicalize(source).ok();
+    if cli.encrypt.is_empty() && !replaces_source {
+        Ok(output.to_path_buf())
+    } else {
+        staged_path(output, cli, context)
+    }
+}
+
+/// Gives an `--output-format auto` output the extension of the format chosen for it:
+/// `photo.jpg` becomes `photo.png`, and `photo.jpg.age` becomes `photo.png.age`.
+fn with_format(output: &Path, format: ImageFormat, cli: &Cli) -> PathBuf {
+    let image = if cli.encrypt.is_empty() { output.to_path_buf() } else { output.with_extension("") };
+    let renamed = image.with_extension(format.extensions_str()[0]);
+    if cli.encrypt.is_empty() { renamed } else { sealed_path(&renamed) }
+}
+
+/// Names the `--widths` variant of `output` that is `width` pixels wide: `photo.jpg` becomes
+/// `photo-800w.jpg`, and `photo.jpg.age` becomes `photo-800w.jpg.age`.
+fn width_variant_path(output: &Path, width: u32, cli: &Cli) -> PathBuf {
+    variant_path(output, &format!("-{}w", width), !cli.encrypt.is_empty())
+}
+
+/// Inserts `suffix` at the end of the stem of `output`, before its extension. A `sealed`
+/// output's `.age` is set aside first, so the suffix goes before the image's own extension.
+fn variant_path(output: &Path, suffix: &str, sealed: bool) -> PathBuf {
+    let image = if sealed { output.with_extension("") } else { output.to_path_buf() };
+    let mut name = image.file_stem().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    if let Some(ext) = image.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    let variant = image.with_file_name(name);
+    if sealed { sealed_path(&variant) } else { variant }
+}
+
+/// With `--if-exists rename`, returns `output`, or if it exists, the first numbered name
+/// beside it that doesn't. Bucketed outputs are renamed once their bucket is known.
+fn free_path(output: PathBuf, cli: &Cli) -> PathBuf {
+    if cli.if_exists == IfExists::Rename && cli.bucket_by_size.is_none() { numbered_path(&output, cli) } else { output }
+}
+
+/// Finds a free name for `output`: itself if nothing is there, or else the first of
+/// `photo-1.jpg`, `photo-2.jpg`, ... that is free, and likewise `photo-1.jpg.age`.
+fn numbered_path(output: &Path, cli: &Cli) -> PathBuf {
+    if !output.exists() {
+        return output.to_path_buf();
+    }
+    (1..)
+        .map(|n| variant_path(output, &format!("-{}", n), !cli.encrypt.is_empty()))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| output.to_path_buf())
+}
+
+/// Names the output for page `page` of a multi-page source: `scan.png` becomes `scan_p2.png`,
+/// and `scan.png.age` becomes `scan_p2.png.age`.
+fn page_variant_path(output: &Path, page: u32, cli: &Cli) -> PathBuf {
+    variant_path(output, &format!("_p{}", page), !cli.encrypt.is_empty())
+}
+
+/// With `--bucket-by-size`, moves the written file into the subfolder for its encoded size and
+/// removes what an earlier run left in the other buckets. Returns the new output path and the
+/// new path of the written file, which differ only while the output is staged.
+fn bucket(cli: &Cli, context: &RunContext, output: PathBuf, written: PathBuf) -> Result<(PathBuf, PathBuf)> {
+    let Some(buckets) = &cli.bucket_by_size else {
+        return Ok((output, written));
+    };
+    let placed = buckets.place(&cli.output_dir, &output, fs::metadata(&written)?.len());
+    let placed = if cli.if_exists == IfExists::Rename { numbered_path(&placed, cli) } else { placed };
+    let replaces = cli.if_exists == IfExists::Overwrite;
+    for stale in buckets.candidates(&cli.output_dir, &output).filter(|c| replaces && *c != placed) {
+        for file in [sidecar_path(&stale), layout_path(&stale), stale] {
+            if file.exists() {
+                remove_file(&file, cli.use_trash)?;
+            }
+        }
+    }
+    let placed_written = if written == output { placed.clone() } else { staged_path(&placed, cli, context)? };
+    if let Some(parent) = placed_written.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    make_room(&placed_written, cli.use_trash)?;
+    fs::rename(&written, &placed_written)?;
+    Ok((placed, placed_written))
+}
+
+/// Returns the temporary name `output` is written under before [`seal`] moves it into place.
+fn staged_path(output: &Path, cli: &Cli, context: &RunContext) -> Result<PathBuf> {
+    if cli.encrypt.is_empty() {
+        return Ok(temp_path(output));
+    }
+    let dir = context.staging_dir.as_deref().context("Encrypted outputs need a staging directory")?;
+    Ok(staging_path(dir, output))
+}
+
+/// Moves a staged output into its final place: encrypted with `--encrypt`, which then removes
+/// the plaintext, or else renamed over whatever is there, which replaces an in-place source
+/// atomically. Does nothing for outputs written directly.
+fn seal(staged: &Path, sealed: &Path, cli: &Cli) -> Result<()> {
+    if staged == sealed {
+        return Ok(());
+    }
+    make_room(sealed, cli.use_trash)?;
+    if cli.encrypt.is_empty() {
+        return fs::rename(staged, sealed).with_context(|| format!("Failed to replace {}", sealed.display()));
+    }
+    let result =
+        encrypt_file(staged, sealed, &cli.encrypt).with_context(|| format!("Failed to encrypt {}", sealed.display()));
+    fs::remove_file(staged)?;
+    result
+}
+
+fn write_sidecar(
+    source: &Path,
+    output: &Path,
+    operations: Vec<Operation>,
+    encoder: Option<EncoderSettings>,
+    timings_ms: Timings,
+    bundle: Option<&SidecarBundle>,
+) -> Result<()> {
+    let sidecar = Sidecar {
+        source: source.to_path_buf(),
+        source_blake3: hash_file(source)?,
+        output: output.to_path_buf(),
+        operations,
+        encoder,
+        timings_ms,
+    };
+    match bundle {
+        Some(bundle) => {
+            bundle.add(sidecar);
+            Ok(())
+        }
+        None => sidecar.write(),
+    }
+}
+
+/// Picks a watermark layer's shift of up to `max` pixels each way from `--watermark-seed`. The
+/// shift is shared by every image unless `--watermark-random-per-image` is set, in which case
+/// the source's path relative to the input dir picks it, so it is stable between runs.
+fn random_offset(cli: &Cli, source: &Path, layer: usize, max: u32) -> (i32, i32) {
+    let image = if cli.watermark_random_per_image {
+        source.strip_prefix(&cli.input_dir).unwrap_or(source).to_string_lossy().into_owned()
+    } else {
+        String::new()
+    };
+    let mut rng = Rng::new(cli.watermark_seed, &format!("watermark-offset/{}/{}", layer, image));
+    let max = max as i64;
+    (rng.range(-max, max) as i32, rng.range(-max, max) as i32)
+}
+
+/// Format-specific encoder settings: how PNG outputs are filtered and laid out, and how
+/// TIFF outputs are compressed.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodeOptions {
+    pub png_filter: PngFilter,
+    pub png_interlace: bool,
+    pub tiff_compression: TiffCompression,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions { png_filter: PngFilter::Adaptive, png_interlace: false, tiff_compression: TiffCompression::Lzw }
+    }
+}
+
+/// Computes where the output for an input image is written, and in which format. The format is
+/// `None` for raw pixel dumps, which are written as `.rgba`.
+pub fn output_path_for(
+    path: &Path,
+    cli: &Cli,
+    context: &RunContext,
+    pipeline: &Pipeline,
+) -> Result<(PathBuf, Option<ImageFormat>)> {
+    let relative_path = path.strip_prefix(&cli.input_dir)?;
+    let mut base_output_path = match context.chunk_dirs.get(path) {
+        Some(chunk) => cli.output_dir.join(chunk).join(relative_path),
+        None => cli.output_dir.join(relative_path),
+    };
+    if let Some(stem) = context.sequence_names.get(path) {
+        let renamed = match path.extension() {
+            Some(ext) => format!("{}.{}", stem, ext.to_string_lossy()),
+            None => stem.clone(),
+        };
+        base_output_path.set_file_name(renamed);
+    }
+
+    match &pipeline.output_format {
+        // Case 1: User specified an output format (copies always keep the original).
+        Some(format_arg) if !pipeline.copy => match format_arg.image_format() {
+            Some(format) => Ok((base_output_path.with_extension(format.extensions_str()[0]), Some(format))),
+            None => Ok((base_output_path.with_extension(RAW_EXTENSION), None)),
+        },
+        // RAW files are written as JPEG, or copied unchanged.
+        _ if is_raw(path) => {
+            if context.raw_twins.contains(path) {
+                let stem = base_output_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                base_output_path.set_file_name(format!("{}-raw.{}", stem, path.extension().unwrap_or_default().to_string_lossy()));
+            }
+            let path = if pipeline.copy { base_output_path } else { base_output_path.with_extension("jpg") };
+            Ok((path, Some(ImageFormat::Jpeg)))
+        }
+        // PDF pages are written as PNG, which keeps text sharp.
+        _ if is_pdf(path) => {
+            let path = if pipeline.copy { base_output_path } else { base_output_path.with_extension("png") };
+            Ok((path, Some(ImageFormat::Png)))
+        }
+        // Case 2: User did not specify a format; infer from the original path. Formats that
+        // can only be read, such as DDS, are written as PNG.
+        _ => match ImageFormat::from_path(&base_output_path)? {
+            format if format.writing_enabled() => Ok((base_output_path, Some(format))),
+            _ => Ok((base_output_path.with_extension("png"), Some(ImageFormat::Png))),
+        },
+    }
+    .map(|(path, format)| if cli.encrypt.is_empty() { (path, format) } else { (sealed_path(&path), format) })
+}
+
+/// Lists the outputs processing `path` writes, for `--dry-run`: one per `--widths` entry, with
+/// those at or above the source's width collapsing into one, one per page of a multi-page
+/// source, and under both extensions with
+/// `--output-format auto`, which picks one once the image is decoded. `--bucket-by-size`
+/// buckets are left out, as they depend on the encoded size.
+pub(crate) fn planned_outputs(path: &Path, cli: &Cli, context: &RunContext, pipeline: &Pipeline) -> Result<Vec<PathBuf>> {
+    let (output, _) = output_path_for(path, cli, context, pipeline)?;
+    let outputs = match pipeline.output_format {
+        Some(OutputFormat::Auto) if !pipeline.copy => {
+            [ImageFormat::Jpeg, ImageFormat::Png].into_iter().map(|format| with_format(&output, format, cli)).collect()
+        }
+        _ => vec![output],
+    };
+    let outputs = page_variants(outputs, path, cli, pipeline);
+    if cli.widths.is_empty() || pipeline.copy {
+        return Ok(outputs);
+    }
+    // Pages are only measured once decoded, so each keeps its widths.
+    if is_pdf(path) || pages::is_multipage_tiff(path) {
+        return Ok(outputs.iter().flat_map(|o| cli.widths.iter().map(|&w| width_variant_path(o, w, cli))).collect());
+    }
+    let (source_width, _) = image::image_dimensions(path)?;
+    let mut widths: Vec<u32> = cli.widths.iter().map(|&w| w.min(source_width)).collect();
+    widths.sort_unstable();
+    widths.dedup();
+    Ok(outputs.iter().flat_map(|o| widths.iter().map(|&w| width_variant_path(o, w, cli))).collect())
+}
+
+/// Splits each output of a multi-page source into one per page.
+fn page_variants(outputs: Vec<PathBuf>, path: &Path, cli: &Cli, pipeline: &Pipeline) -> Vec<PathBuf> {
+    match pages::page_count(path) {
+        Some(count) if count > 1 && !pipeline.copy => outputs
+            .iter()
+            .flat_map(|o| {
+                (1..=count).filter(|&page| pages::wanted(cli.pages.as_ref(), page, count)).map(|page| page_variant_path(o, page, cli))
+            })
+            .collect(),
+        _ => outputs,
+    }
+}
+
+/// Lists every path the output for `path` may be written to: under either extension with
+/// `--output-format auto`, for each page of a multi-page source, at each `--widths` entry and
+/// the source's own width, and in any `--bucket-by-size` bucket. Which one a run picks depends
+/// on the content, so runs that look for existing outputs check them all.
+pub fn output_candidates(path: &Path, cli: &Cli, context: &RunContext, pipeline: &Pipeline) -> Result<Vec<PathBuf>> {
+    let (output, _) = output_path_for(path, cli, context, pipeline)?;
+    let outputs = match pipeline.output_format {
+        Some(OutputFormat::Auto) if !pipeline.copy => {
+            [ImageFormat::Jpeg, ImageFormat::Png].into_iter().map(|format| with_format(&output, format, cli)).collect()
+        }
+        _ => vec![output],
+    };
+    let mut outputs = page_variants(outputs, path, cli, pipeline);
+    if !cli.widths.is_empty() && !pipeline.copy {
+        let source_width = image::image_dimensions(path).ok().map(|(width, _)| width);
+        let widths: Vec<u32> = cli.widths.iter().copied().chain(source_width).collect();
+        outputs = outputs.iter().flat_map(|o| widths.iter().map(|&w| width_variant_path(o, w, cli))).collect();
+    }
+    Ok(match &cli.bucket_by_size {
+        Some(buckets) => outputs.iter().flat_map(|o| buckets.candidates(&cli.output_dir, o)).collect(),
+        None => outputs,
+    })
+}
+
+/// Splits watermark text into lines at newlines and at a typed `\n`, as a shell passes it
+/// through. A typed `\\n` stands for a literal backslash followed by `n`.
+fn text_lines(text: &str) -> Vec<String> {
+    let mut lines = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let line = lines.last_mut().expect("there is always a line");
+        match (ch, chars.peek()) {
+            ('\n', _) => lines.push(String::new()),
+            ('\\', Some('n')) => {
+                chars.next();
+                lines.push(String::new());
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                if chars.next_if_eq(&'n').is_some() {
+                    line.push_str("\\n");
+                } else {
+                    line.push_str("\\\\");
+                }
+            }
+            _ => line.push(ch),
+        }
+    }
+    lines
 }
 
 /// Lays out glyphs for the given text, scale, and list of fonts, with fallback support.
+/// Each `\n` starts a new line, `line_height` baselines apart; `letter_spacing` pixels are
+/// added after every glyph.
 /// Returns a vector of positioned glyphs, along with the precise pixel bounding box of the entire text.
 fn layout_text<'a>(
     text: &str,
     scale: Scale,
     fonts: &'a [Font<'static>],
+    letter_spacing: f32,
+    line_height: f32,
+    align: TextAlign,
 ) -> (Vec<PositionedGlyph<'a>>, u32, u32, i32) {
     if fonts.is_empty() {
         return (vec![], 0, 0, 0);
@@ -80,28 +994,52 @@ fn layout_text<'a>(
     let mut glyphs = Vec::new();
     let v_metrics = primary_font.v_metrics(scale);
     let base_ascent = v_metrics.ascent;
-    let mut caret = 0.0;
-    let mut last_glyph_id = None;
+    let line_advance = (v_metrics.ascent - v_metrics.descent + v_metrics.line_gap) * line_height;
 
-    for ch in text.chars() {
-        let (font_used, glyph) = fonts
-            .iter()
-            .find_map(|f| {
-                let g = f.glyph(ch);
-                if g.id() != rusttype::GlyphId(0) { Some((f, g)) } else { None }
-            })
-            .unwrap_or_else(|| (primary_font, primary_font.glyph('\u{FFFD}')));
+    let mut lines = Vec::new();
+    for (line_index, line) in text_lines(text).iter().enumerate() {
+        let baseline = base_ascent + line_index as f32 * line_advance;
+        let mut caret = 0.0;
+        let mut last_glyph_id = None;
+        let first_glyph = glyphs.len();
 
-        let scaled_glyph = glyph.scaled(scale);
-        if let Some(id) = last_glyph_id {
-            caret += font_used.pair_kerning(scale, id, scaled_glyph.id());
-        }
+        for ch in line.trim_end_matches('\r').chars() {
+            let (font_used, glyph) = fonts
+                .iter()
+                .find_map(|f| {
+                    let g = f.glyph(ch);
+                    if g.id() != rusttype::GlyphId(0) { Some((f, g)) } else { None }
+                })
+                .unwrap_or_else(|| (primary_font, primary_font.glyph('\u{FFFD}')));
+
+            let scaled_glyph = glyph.scaled(scale);
+            if let Some(id) = last_glyph_id {
+                caret += font_used.pair_kerning(scale, id, scaled_glyph.id());
+            }
 
-        let positioned_glyph = scaled_glyph.positioned(point(caret, base_ascent));
-        caret += positioned_glyph.unpositioned().h_metrics().advance_width;
-        last_glyph_id = Some(positioned_glyph.id());
+            let positioned_glyph = scaled_glyph.positioned(point(caret, baseline));
+            caret += positioned_glyph.unpositioned().h_metrics().advance_width + letter_spacing;
+            last_glyph_id = Some(positioned_glyph.id());
 
-        glyphs.push(positioned_glyph);
+            glyphs.push(positioned_glyph);
+        }
+        lines.push((first_glyph, (caret - letter_spacing).max(0.0)));
+    }
+
+    // Shorter lines move right by half (centered) or all (right-aligned) of what they lack.
+    let share = match align {
+        TextAlign::Left => 0.0,
+        TextAlign::Center => 0.5,
+        TextAlign::Right => 1.0,
+    };
+    let widest = lines.iter().map(|&(_, width)| width).fold(0.0, f32::max);
+    for (index, &(first, width)) in lines.iter().enumerate() {
+        let end = lines.get(index + 1).map_or(glyphs.len(), |&(next, _)| next);
+        let shift = (widest - width) * share;
+        for g in &mut glyphs[first..end] {
+            let at = g.position();
+            g.set_position(point(at.x + shift, at.y));
+        }
     }
 
     // After all glyphs are laid out, calculate the overall pixel bounding box.
@@ -129,18 +1067,35 @@ pub fn add_watermark(
     position: WatermarkPosition,
     color: HexColor,
 ) {
-    if fonts.is_empty() { return; }
+    let spec = WatermarkSpec { text: text.to_string(), position, font_size, color, ..WatermarkSpec::default() };
+    draw_watermark(img, &spec, fonts);
+}
 
-    let padding = 10u32;
+/// Draws one watermark layer, honoring all of its styling options.
+pub fn draw_watermark(img: &mut DynamicImage, spec: &WatermarkSpec, fonts: &[Font<'static>]) -> WatermarkPosition {
+    if fonts.is_empty() { return spec.position; }
+    if spec.tile {
+        draw_tiled_watermark(img, spec, fonts);
+        return spec.position;
+    }
+    if spec.arc != 0.0 || spec.angle != 0.0 {
+        return draw_shaped_watermark(img, spec, fonts);
+    }
+
+    let text = spec.text.as_str();
+    let padding = WATERMARK_PADDING;
     let (img_width, img_height) = img.dimensions();
-    let watermark_color = color.0;
+    let watermark_color = spec.color.0;
+    let font_size = spec.font_size_for(img_width);
 
     let mut scale = Scale::uniform(font_size as f32);
+    // Spacing is specified at the requested size and shrinks along with the font.
+    let spacing_at = |scale: Scale| spec.letter_spacing * scale.x / font_size as f32;
 
     let max_drawable_width = img_width.saturating_sub(padding * 2);
     let max_drawable_height = img_height.saturating_sub(padding * 2);
 
-    let (_, text_width, text_height, _) = layout_text(text, scale, fonts);
+    let (_, text_width, text_height, _) = layout_text(text, scale, fonts, spacing_at(scale), spec.line_height, spec.align);
 
     if text_width > max_drawable_width || text_height > max_drawable_height {
         let width_ratio = if text_width > 0 { max_drawable_width as f32 / text_width as f32 } else { 1.0 };
@@ -150,23 +1105,10 @@ pub fn add_watermark(
         scale = Scale::uniform(new_font_size.max(1.0));
     }
 
-    let (glyphs, text_width, text_height, x_offset) = layout_text(text, scale, fonts);
+    let (glyphs, text_width, text_height, x_offset) =
+        layout_text(text, scale, fonts, spacing_at(scale), spec.line_height, spec.align);
 
-    let (target_x, target_y) = {
-        let iw = img_width; let ih = img_height;
-        let tw = text_width; let th = text_height;
-        match position {
-            WatermarkPosition::Nw => (padding, padding),
-            WatermarkPosition::North => ((iw.saturating_sub(tw)) / 2, padding),
-            WatermarkPosition::Ne => (iw.saturating_sub(tw).saturating_sub(padding), padding),
-            WatermarkPosition::West => (padding, (ih.saturating_sub(th)) / 2),
-            WatermarkPosition::Center => ((iw.saturating_sub(tw)) / 2, (ih.saturating_sub(th)) / 2),
-            WatermarkPosition::East => (iw.saturating_sub(tw).saturating_sub(padding), (ih.saturating_sub(th)) / 2),
-            WatermarkPosition::Sw => (padding, ih.saturating_sub(th).saturating_sub(padding)),
-            WatermarkPosition::South => ((iw.saturating_sub(tw)) / 2, ih.saturating_sub(th).saturating_sub(padding)),
-            WatermarkPosition::Se => (iw.saturating_sub(tw).saturating_sub(padding), ih.saturating_sub(th).saturating_sub(padding)),
-        }
-    };
+    let (position, (target_x, target_y)) = place(spec, (img_width, img_height), (text_width, text_height), padding);
 
     let final_x_offset = target_x as i32 - x_offset;
     let final_y_offset = target_y as i32;
@@ -175,29 +1117,429 @@ pub fn add_watermark(
         if let Some(bb) = g.pixel_bounding_box() {
             let bb_x = bb.min.x + final_x_offset;
             let bb_y = bb.min.y + final_y_offset;
+            g.draw(|x, y, v| blend_coverage(img, bb_x + x as i32, bb_y + y as i32, watermark_color, v));
+        }
+    }
+    position
+}
+
+/// Stamps `logo` onto the image like a text watermark: at `spec`'s position inside the padding,
+/// moved off its avoided zones, and blended by the logo's own alpha. The logo is scaled to
+/// `scale` of the image's width, keeping its aspect ratio, or less if it wouldn't fit. Returns
+/// the position used and the logo's drawn size.
+pub fn add_image_watermark(
+    img: &mut DynamicImage,
+    logo: &DynamicImage,
+    spec: &WatermarkSpec,
+    scale: Percentage,
+) -> (WatermarkPosition, (u32, u32)) {
+    let (width, height) = img.dimensions();
+    let room = |side: u32| side.saturating_sub(WATERMARK_PADDING * 2).max(1) as f32;
+    let ratio = (width as f32 * scale.0 / logo.width() as f32)
+        .min(room(width) / logo.width() as f32)
+        .min(room(height) / logo.height() as f32);
+    let size = (
+        ((logo.width() as f32 * ratio).round() as u32).max(1),
+        ((logo.height() as f32 * ratio).round() as u32).max(1),
+    );
+    let resized = image::imageops::resize(&logo.to_rgba8(), size.0, size.1, image::imageops::FilterType::Lanczos3);
+    if spec.tile {
+        let mut canvas = img.to_rgba8();
+        for (x, y) in tile_corners((width, height), size) {
+            image::imageops::overlay(&mut canvas, &resized, x, y);
+        }
+        *img = if img.color().has_alpha() {
+            DynamicImage::ImageRgba8(canvas)
+        } else {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8())
+        };
+        return (spec.position, size);
+    }
+    let (position, at) = place(spec, (width, height), size, WATERMARK_PADDING);
+    blend_onto(img, &resized, at);
+    (position, size)
+}
+
+/// Returns the top-left corners of copies of a block repeated across an image: rows of copies
+/// half the block's longer side apart, every other row shifted by half a step. Copies along the
+/// edges are cut off rather than left out.
+fn tile_corners((width, height): (u32, u32), (bw, bh): (u32, u32)) -> Vec<(i64, i64)> {
+    let gap = (bw.max(bh) / 2).max(WATERMARK_PADDING) as i64;
+    let (step_x, step_y) = (bw as i64 + gap, bh as i64 + gap);
+    let mut corners = Vec::new();
+    for (row, y) in (gap / 2..height as i64).step_by(step_y as usize).enumerate() {
+        let first = if row % 2 == 0 { gap / 2 } else { gap / 2 - step_x / 2 };
+        corners.extend((first..width as i64).step_by(step_x as usize).map(|x| (x, y)));
+    }
+    corners
+}
+
+/// Returns the position used for a watermark block and its top-left corner: the anchored
+/// position shifted by the spec's offset, clamped so the block stays inside the image. If the
+/// block would cover one of the spec's zones, the other positions are tried nearest first, and
+/// the one covering the least is used when none keeps clear.
+fn place(spec: &WatermarkSpec, image: (u32, u32), block: (u32, u32), padding: u32) -> (WatermarkPosition, (u32, u32)) {
+    let corner = |position| {
+        let (x, y) = anchor(position, image, block, padding);
+        let shift = |at: u32, by: i32, room: u32| (at as i64 + by as i64).clamp(0, room as i64) as u32;
+        (shift(x, spec.offset.0, image.0.saturating_sub(block.0)), shift(y, spec.offset.1, image.1.saturating_sub(block.1)))
+    };
+    let covered = |at| spec.avoid.iter().map(|zone| zone.overlap(at, block)).sum::<u64>();
+    let preferred = corner(spec.position);
+    if covered(preferred) == 0 {
+        return (spec.position, preferred);
+    }
+
+    let distance = |(x, y): (u32, u32)| (x as i64 - preferred.0 as i64).pow(2) + (y as i64 - preferred.1 as i64).pow(2);
+    let mut candidates: Vec<(WatermarkPosition, (u32, u32))> =
+        WatermarkPosition::ALL.iter().map(|&position| (position, corner(position))).collect();
+    // The sort is stable, so positions equally far away keep their usual order.
+    candidates.sort_by_key(|&(_, at)| distance(at));
+    candidates.into_iter().min_by_key(|&(_, at)| covered(at)).unwrap_or((spec.position, preferred))
+}
+
+/// Returns the top-left corner for a block of the given size at one of the nine positions.
+pub(crate) fn anchor(position: WatermarkPosition, (iw, ih): (u32, u32), (tw, th): (u32, u32), padding: u32) -> (u32, u32) {
+    match position {
+        WatermarkPosition::Nw => (padding, padding),
+        WatermarkPosition::North => ((iw.saturating_sub(tw)) / 2, padding),
+        WatermarkPosition::Ne => (iw.saturating_sub(tw).saturating_sub(padding), padding),
+        WatermarkPosition::West => (padding, (ih.saturating_sub(th)) / 2),
+        WatermarkPosition::Center => ((iw.saturating_sub(tw)) / 2, (ih.saturating_sub(th)) / 2),
+        WatermarkPosition::East => (iw.saturating_sub(tw).saturating_sub(padding), (ih.saturating_sub(th)) / 2),
+        WatermarkPosition::Sw => (padding, ih.saturating_sub(th).saturating_sub(padding)),
+        WatermarkPosition::South => ((iw.saturating_sub(tw)) / 2, ih.saturating_sub(th).saturating_sub(padding)),
+        WatermarkPosition::Se => (iw.saturating_sub(tw).saturating_sub(padding), ih.saturating_sub(th).saturating_sub(padding)),
+    }
+}
+
+/// Blends `color` into the pixel at (px, py), weighted by the antialiasing coverage `v`.
+/// Pixels outside the image are ignored.
+fn blend_coverage(img: &mut DynamicImage, px: i32, py: i32, color: image::Rgba<u8>, v: f32) {
+    let (img_width, img_height) = img.dimensions();
+    if v > 0.0 && px >= 0 && py >= 0 && (px as u32) < img_width && (py as u32) < img_height {
+        let mut weighted_color = color;
+        weighted_color.0[3] = (weighted_color.0[3] as f32 * v.min(1.0)) as u8;
+        let mut background_pixel = img.get_pixel(px as u32, py as u32);
+        background_pixel.blend(&weighted_color);
+        img.put_pixel(px as u32, py as u32, background_pixel);
+    }
+}
+
+/// An antialiased rendering of text, with coverage values in `0.0..=1.0`.
+struct Mask {
+    width: u32,
+    height: u32,
+    data: Vec<f32>,
+}
+
+impl Mask {
+    fn new(width: u32, height: u32) -> Self {
+        Mask { width, height, data: vec![0.0; (width * height) as usize] }
+    }
+
+    fn get(&self, x: i32, y: i32) -> f32 {
+        if x < 0 || y < 0 || x as u32 >= self.width || y as u32 >= self.height {
+            return 0.0;
+        }
+        self.data[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    /// Bilinearly samples the mask at a fractional pixel position.
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+        let top = self.get(x0, y0) * (1.0 - fx) + self.get(x0 + 1, y0) * fx;
+        let bottom = self.get(x0, y0 + 1) * (1.0 - fx) + self.get(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+/// A glyph rasterized upright, with the transform that places it on the arc.
+struct ArcGlyph {
+    mask: Mask,
+    /// Offset of the mask's top-left corner from the glyph's pivot, before rotation.
+    origin: (f32, f32),
+    /// Where the pivot lands, relative to the middle of the arc's first baseline.
+    pivot: (f32, f32),
+    angle: f32,
+}
+
+impl ArcGlyph {
+    /// Maps a point in the glyph's upright frame to arc coordinates.
+    fn forward(&self, x: f32, y: f32) -> (f32, f32) {
+        let (sin, cos) = self.angle.sin_cos();
+        let (lx, ly) = (x + self.origin.0, y + self.origin.1);
+        (self.pivot.0 + lx * cos - ly * sin, self.pivot.1 + lx * sin + ly * cos)
+    }
+
+    /// Maps a point in arc coordinates back into the glyph's mask.
+    fn inverse(&self, x: f32, y: f32) -> (f32, f32) {
+        let (sin, cos) = self.angle.sin_cos();
+        let (dx, dy) = (x - self.pivot.0, y - self.pivot.1);
+        (dx * cos + dy * sin - self.origin.0, -dx * sin + dy * cos - self.origin.1)
+    }
+}
+
+/// Renders text bent along a circular arc spanning `degrees` (clamped to ±360). Each glyph is
+/// rotated to follow the curve; extra lines sit on concentric arcs.
+fn layout_arc(
+    text: &str,
+    scale: Scale,
+    fonts: &[Font<'static>],
+    letter_spacing: f32,
+    line_height: f32,
+    align: TextAlign,
+    degrees: f32,
+) -> Mask {
+    let (glyphs, _, _, _) = layout_text(text, scale, fonts, letter_spacing, line_height, align);
+    let base_ascent = fonts[0].v_metrics(scale).ascent;
+    let advance = |g: &PositionedGlyph| g.unpositioned().h_metrics().advance_width;
+    let total_width = glyphs.iter().map(|g| g.position().x + advance(g)).fold(0.0, f32::max);
+    let sweep = degrees.clamp(-360.0, 360.0).to_radians();
+    if total_width <= 0.0 || sweep == 0.0 {
+        return Mask::new(0, 0);
+    }
+    let radius = total_width / sweep.abs();
+
+    let mut placed = Vec::new();
+    for g in &glyphs {
+        let upright = g.unpositioned().clone().positioned(point(0.0, 0.0));
+        let Some(bb) = upright.pixel_bounding_box() else { continue };
+        let mut mask = Mask::new(bb.width() as u32, bb.height() as u32);
+        upright.draw(|x, y, v| mask.data[(y * mask.width + x) as usize] = v);
+
+        let half_advance = advance(g) / 2.0;
+        let phi = (g.position().x + half_advance - total_width / 2.0) / radius;
+        let drop = radius * (1.0 - phi.cos());
+        // Upward arcs curve down at the ends around a center below the text; downward arcs the reverse.
+        let (pivot, angle) = if sweep > 0.0 {
+            ((radius * phi.sin(), drop), phi)
+        } else {
+            ((radius * phi.sin(), -drop), -phi)
+        };
+        let origin = (bb.min.x as f32 - half_advance, bb.min.y as f32 + g.position().y - base_ascent);
+        placed.push(ArcGlyph { mask, origin, pivot, angle });
+    }
+
+    // Find the bounds of every rotated glyph so the whole arc fits in one mask.
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+    for glyph in &placed {
+        let (w, h) = (glyph.mask.width as f32, glyph.mask.height as f32);
+        for (cx, cy) in [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)] {
+            let (x, y) = glyph.forward(cx, cy);
+            (min_x, min_y, max_x, max_y) = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y));
+        }
+    }
+    if placed.is_empty() {
+        return Mask::new(0, 0);
+    }
+    let (min_x, min_y) = (min_x.floor(), min_y.floor());
+    let mut out = Mask::new((max_x.ceil() - min_x) as u32, (max_y.ceil() - min_y) as u32);
+
+    for glyph in &placed {
+        let (w, h) = (glyph.mask.width as f32, glyph.mask.height as f32);
+        let corners = [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)].map(|(cx, cy)| glyph.forward(cx, cy));
+        let x_range = corners.iter().map(|c| c.0).fold(f32::MAX, f32::min)..corners.iter().map(|c| c.0).fold(f32::MIN, f32::max);
+        let y_range = corners.iter().map(|c| c.1).fold(f32::MAX, f32::min)..corners.iter().map(|c| c.1).fold(f32::MIN, f32::max);
+        for y in (y_range.start - min_y).floor() as u32..((y_range.end - min_y).ceil() as u32).min(out.height) {
+            for x in (x_range.start - min_x).floor() as u32..((x_range.end - min_x).ceil() as u32).min(out.width) {
+                let (mx, my) = glyph.inverse(x as f32 + 0.5 + min_x, y as f32 + 0.5 + min_y);
+                let v = glyph.mask.sample(mx - 0.5, my - 0.5);
+                let cell = &mut out.data[(y * out.width + x) as usize];
+                *cell = cell.max(v);
+            }
+        }
+    }
+    out
+}
+
+/// Renders straight text, every line included, into a mask as wide and tall as the block
+/// [`draw_watermark`] places.
+fn layout_block(
+    text: &str,
+    scale: Scale,
+    fonts: &[Font<'static>],
+    letter_spacing: f32,
+    line_height: f32,
+    align: TextAlign,
+) -> Mask {
+    let (glyphs, width, height, x_offset) = layout_text(text, scale, fonts, letter_spacing, line_height, align);
+    let mut mask = Mask::new(width, height);
+    for g in &glyphs {
+        if let Some(bb) = g.pixel_bounding_box() {
+            g.draw(|x, y, v| {
+                let (mx, my) = (bb.min.x - x_offset + x as i32, bb.min.y + y as i32);
+                if mx >= 0 && my >= 0 && (mx as u32) < width && (my as u32) < height {
+                    let cell = &mut mask.data[(my as u32 * width + mx as u32) as usize];
+                    *cell = cell.max(v);
+                }
+            });
+        }
+    }
+    mask
+}
+
+/// Turns a mask `degrees` counterclockwise about its center, growing it to hold every corner.
+fn rotate_mask(mask: &Mask, degrees: f32) -> Mask {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (w, h) = (mask.width as f32, mask.height as f32);
+    let mut out = Mask::new((w * cos.abs() + h * sin.abs()).ceil() as u32, (w * sin.abs() + h * cos.abs()).ceil() as u32);
+    let (out_cx, out_cy) = (out.width as f32 / 2.0, out.height as f32 / 2.0);
+    for y in 0..out.height {
+        for x in 0..out.width {
+            // Turning back clockwise finds the source point; y grows downward.
+            let (dx, dy) = (x as f32 + 0.5 - out_cx, y as f32 + 0.5 - out_cy);
+            let (sx, sy) = (dx * cos - dy * sin + w / 2.0, dx * sin + dy * cos + h / 2.0);
+            out.data[(y * out.width + x) as usize] = mask.sample(sx - 0.5, sy - 0.5);
+        }
+    }
+    out
+}
+
+/// Renders a layer's text with its arc and angle at `scale`, spacing letters as asked for at
+/// `font_size`.
+fn shaped_mask(spec: &WatermarkSpec, scale: Scale, font_size: u32, fonts: &[Font<'static>]) -> Mask {
+    let spacing = spec.letter_spacing * scale.x / font_size as f32;
+    let mask = if spec.arc != 0.0 {
+        layout_arc(&spec.text, scale, fonts, spacing, spec.line_height, spec.align, spec.arc)
+    } else {
+        layout_block(&spec.text, scale, fonts, spacing, spec.line_height, spec.align)
+    };
+    if spec.angle != 0.0 { rotate_mask(&mask, spec.angle) } else { mask }
+}
+
+/// Draws a layer's text over the whole image at its full size, copies placed by [`tile_corners`].
+fn draw_tiled_watermark(img: &mut DynamicImage, spec: &WatermarkSpec, fonts: &[Font<'static>]) {
+    let font_size = spec.font_size_for(img.width());
+    let mask = shaped_mask(spec, Scale::uniform(font_size as f32), font_size, fonts);
+    if mask.width == 0 || mask.height == 0 {
+        return;
+    }
+    for (left, top) in tile_corners(img.dimensions(), (mask.width, mask.height)) {
+        for y in 0..mask.height {
+            for x in 0..mask.width {
+                let v = mask.data[(y * mask.width + x) as usize];
+                blend_coverage(img, (left + x as i64) as i32, (top + y as i64) as i32, spec.color.0, v);
+            }
+        }
+    }
+}
+
+/// Draws arced or turned text, shrinking it the same way as straight text when it doesn't fit.
+fn draw_shaped_watermark(img: &mut DynamicImage, spec: &WatermarkSpec, fonts: &[Font<'static>]) -> WatermarkPosition {
+    let padding = WATERMARK_PADDING;
+    let (img_width, img_height) = img.dimensions();
+    let font_size = spec.font_size_for(img_width);
+    let max_drawable_width = img_width.saturating_sub(padding * 2);
+    let max_drawable_height = img_height.saturating_sub(padding * 2);
+    let layout = |scale: Scale| shaped_mask(spec, scale, font_size, fonts);
+
+    let mut mask = layout(Scale::uniform(font_size as f32));
+    if mask.width > max_drawable_width || mask.height > max_drawable_height {
+        let width_ratio = if mask.width > 0 { max_drawable_width as f32 / mask.width as f32 } else { 1.0 };
+        let height_ratio = if mask.height > 0 { max_drawable_height as f32 / mask.height as f32 } else { 1.0 };
+        let new_font_size = (font_size as f32 * width_ratio.min(height_ratio)).floor();
+        mask = layout(Scale::uniform(new_font_size.max(1.0)));
+    }
+
+    let (position, (target_x, target_y)) = place(spec, (img_width, img_height), (mask.width, mask.height), padding);
+    for y in 0..mask.height {
+        for x in 0..mask.width {
+            let v = mask.data[(y * mask.width + x) as usize];
+            blend_coverage(img, (target_x + x) as i32, (target_y + y) as i32, spec.color.0, v);
+        }
+    }
+    position
+}
+
+/// Rasterizes a single line of text into a mask trimmed to its ink.
+fn text_mask(text: &str, scale: Scale, fonts: &[Font<'static>]) -> Mask {
+    let (glyphs, width, height, min_x) = layout_text(text, scale, fonts, 0.0, 1.0, TextAlign::Left);
+    let min_y = glyphs.iter().filter_map(|g| g.pixel_bounding_box()).map(|bb| bb.min.y).min().unwrap_or(0);
+    let mut mask = Mask::new(width, height);
+    for g in &glyphs {
+        if let Some(bb) = g.pixel_bounding_box() {
             g.draw(|x, y, v| {
-                if v > 0.0 {
-                    let px = bb_x + x as i32;
-                    let py = bb_y + y as i32;
-                    if px >= 0 && py >= 0 && (px as u32) < img_width && (py as u32) < img_height {
-                        let mut weighted_color = watermark_color;
-                        weighted_color.0[3] = (weighted_color.0[3] as f32 * v) as u8;
-                        let mut background_pixel = img.get_pixel(px as u32, py as u32);
-                        background_pixel.blend(&weighted_color);
-                        img.put_pixel(px as u32, py as u32, background_pixel);
-                    }
+                let (mx, my) = (bb.min.x - min_x + x as i32, bb.min.y - min_y + y as i32);
+                if mx >= 0 && my >= 0 && (mx as u32) < width && (my as u32) < height {
+                    mask.data[(my as u32 * width + mx as u32) as usize] = v;
                 }
             });
         }
     }
+    mask
+}
+
+/// Marks an image as a client proof: colors are mostly drained, a light band with a repeated
+/// "PROOF" runs corner to corner, and a frame of gray tiles drawn from `rng` tells copies of
+/// different images apart at a glance.
+pub fn apply_proof_style(img: &mut DynamicImage, fonts: &[Font<'static>], rng: &mut Rng) {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        let [r, g, b, _] = pixel.0;
+        let luma = 0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32;
+        for channel in &mut pixel.0[..3] {
+            *channel = (luma + (*channel as f32 - luma) * 0.2).round() as u8;
+        }
+    }
+    *img = DynamicImage::ImageRgba8(rgba);
+
+    // The band follows the diagonal from the bottom-left corner to the top-right one.
+    let short_side = width.min(height) as f32;
+    let half_band = (short_side * 0.08).max(4.0);
+    let length = (width as f32).hypot(height as f32);
+    let (ux, uy) = (width as f32 / length, -(height as f32) / length);
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let label = if fonts.is_empty() { Mask::new(0, 0) } else { text_mask("PROOF", Scale::uniform(half_band * 1.3), fonts) };
+    let period = label.width as f32 + half_band * 2.0;
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+            let across = dx * -uy + dy * ux;
+            let coverage = (half_band - across.abs() + 0.5).clamp(0.0, 1.0);
+            if coverage == 0.0 {
+                continue;
+            }
+            blend_coverage(img, x as i32, y as i32, image::Rgba([255, 255, 255, 110]), coverage);
+            if label.width > 0 {
+                let along = (dx * ux + dy * uy + label.width as f32 / 2.0).rem_euclid(period);
+                let ink = label.sample(along - 0.5, across + label.height as f32 / 2.0 - 0.5);
+                blend_coverage(img, x as i32, y as i32, image::Rgba([40, 40, 40, 140]), ink);
+            }
+        }
+    }
+
+    let frame = ((short_side / 60.0).round() as u32).max(2).min(width.min(height) / 2);
+    let tiles_across = width.div_ceil(frame);
+    let tiles_down = height.div_ceil(frame);
+    for ty in 0..tiles_down {
+        for tx in 0..tiles_across {
+            if tx != 0 && ty != 0 && tx != tiles_across - 1 && ty != tiles_down - 1 {
+                continue;
+            }
+            let shade = rng.range(48, 208) as u8;
+            for y in ty * frame..((ty + 1) * frame).min(height) {
+                for x in tx * frame..((tx + 1) * frame).min(width) {
+                    img.put_pixel(x, y, image::Rgba([shade, shade, shade, 255]));
+                }
+            }
+        }
+    }
 }
 
 /// Saves an image using the specified format and quality, encapsulating detailed encoding logic.
-fn save_image_with_format(
+pub(crate) fn save_image_with_format(
     img: &image::DynamicImage,
     path: &Path,
     format: ImageFormat,
     quality: u8,
+    options: EncodeOptions,
 ) -> Result<()> {
     // Ensure the output directory exists.
     if let Some(parent) = path.parent() {
@@ -205,10 +1547,45 @@ fn save_image_with_format(
     }
 
     let mut writer = BufWriter::new(fs::File::create(path)?);
+    encode_image(img, &mut writer, format, quality, options, path)
+}
+
+/// Encodes an image like [`save_image_with_format`], into any seekable writer. `path` is only
+/// used for its extension, which picks the Netpbm flavor.
+pub(crate) fn encode_image<W: Write + Seek>(
+    img: &DynamicImage,
+    mut writer: W,
+    format: ImageFormat,
+    quality: u8,
+    options: EncodeOptions,
+    path: &Path,
+) -> Result<()> {
+    // Neutral images are stored single-channel, which is about a third of the size.
+    let gray;
+    let img = match (format, collapse_gray(img)) {
+        (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Tiff, Some(collapsed)) => {
+            gray = collapsed;
+            &gray
+        }
+        _ => img,
+    };
 
     match format {
         ImageFormat::Jpeg => {
             let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut writer, quality);
+            // JPEG has no alpha channel and the encoder only accepts 8-bit gray or RGB.
+            let converted;
+            let img = match img.color() {
+                image::ColorType::L8 | image::ColorType::Rgb8 => img,
+                image::ColorType::La8 | image::ColorType::L16 | image::ColorType::La16 => {
+                    converted = DynamicImage::ImageLuma8(img.to_luma8());
+                    &converted
+                }
+                _ => {
+                    converted = DynamicImage::ImageRgb8(img.to_rgb8());
+                    &converted
+                }
+            };
             encoder.write_image(img.as_bytes(), img.width(), img.height(), img.color().into())?;
         }
         ImageFormat::Png => {
@@ -217,9 +1594,22 @@ fn save_image_with_format(
                 1..=50 => CompressionType::Fast,
                 _ => CompressionType::Default,
             };
-            let encoder = PngEncoder::new_with_quality(&mut writer, compression, FilterType::Sub);
-            encoder.write_image(img.as_bytes(), img.width(), img.height(), img.color().into())?;
+            if options.png_interlace {
+                write_interlaced_png(&mut writer, img, options.png_filter, compression)?;
+            } else {
+                let encoder = PngEncoder::new_with_quality(&mut writer, compression, options.png_filter.into());
+                encoder.write_image(img.as_bytes(), img.width(), img.height(), img.color().into())?;
+            }
         }
+        ImageFormat::Tiff => write_tiff(&mut writer, img, options.tiff_compression, quality)?,
+        ImageFormat::Pnm => write_pnm(&mut writer, img, path)?,
+        // QOI only stores 8-bit RGB and RGBA.
+        ImageFormat::Qoi if img.color().has_alpha() => DynamicImage::ImageRgba8(img.to_rgba8()).write_to(&mut writer, format)?,
+        ImageFormat::Qoi => DynamicImage::ImageRgb8(img.to_rgb8()).write_to(&mut writer, format)?,
+        // Icons embed PNGs, which ICO readers (including ours) expect to be RGBA.
+        ImageFormat::Ico => DynamicImage::ImageRgba8(img.to_rgba8()).write_to(&mut writer, format)?,
+        // Farbfeld is always 16-bit RGBA.
+        ImageFormat::Farbfeld => DynamicImage::ImageRgba16(img.to_rgba16()).write_to(&mut writer, format)?,
         // A robust fallback for all other formats (e.g., WebP, BMP, GIF).
         _ => {
             img.write_to(&mut writer, format)?;
@@ -227,3 +1617,108 @@ fn save_image_with_format(
     }
     Ok(())
 }
+
+/// Writes a TIFF with the chosen compression. Deflate trades speed for size by `quality`, as
+/// PNG does.
+fn write_tiff<W: Write + Seek>(writer: W, img: &DynamicImage, compression: TiffCompression, quality: u8) -> Result<()> {
+    use tiff::encoder::compression::{Deflate, DeflateLevel, Lzw, Uncompressed};
+
+    let mut encoder = TiffEncoder::new(writer)?;
+    match compression {
+        TiffCompression::None => write_tiff_pixels(&mut encoder, img, Uncompressed),
+        TiffCompression::Lzw => write_tiff_pixels(&mut encoder, img, Lzw),
+        TiffCompression::Deflate => {
+            let level = match quality {
+                100 => DeflateLevel::Best,
+                1..=50 => DeflateLevel::Fast,
+                _ => DeflateLevel::Balanced,
+            };
+            write_tiff_pixels(&mut encoder, img, Deflate::with_level(level))
+        }
+    }
+}
+
+fn write_tiff_pixels<W: Write + Seek, D: tiff::encoder::compression::Compression>(
+    encoder: &mut TiffEncoder<W>,
+    img: &DynamicImage,
+    compression: D,
+) -> Result<()> {
+    use tiff::encoder::colortype::{Gray16, Gray8, RGB16, RGB32Float, RGB8, RGBA16, RGBA32Float, RGBA8};
+
+    let (width, height) = img.dimensions();
+    match img {
+        DynamicImage::ImageLuma8(buf) => encoder.write_image_with_compression::<Gray8, _>(width, height, compression, buf)?,
+        DynamicImage::ImageLuma16(buf) => encoder.write_image_with_compression::<Gray16, _>(width, height, compression, buf)?,
+        DynamicImage::ImageRgb8(buf) => encoder.write_image_with_compression::<RGB8, _>(width, height, compression, buf)?,
+        DynamicImage::ImageRgba8(buf) => encoder.write_image_with_compression::<RGBA8, _>(width, height, compression, buf)?,
+        DynamicImage::ImageRgb16(buf) => encoder.write_image_with_compression::<RGB16, _>(width, height, compression, buf)?,
+        DynamicImage::ImageRgba16(buf) => encoder.write_image_with_compression::<RGBA16, _>(width, height, compression, buf)?,
+        DynamicImage::ImageRgb32F(buf) => {
+            encoder.write_image_with_compression::<RGB32Float, _>(width, height, compression, buf)?
+        }
+        DynamicImage::ImageRgba32F(buf) => {
+            encoder.write_image_with_compression::<RGBA32Float, _>(width, height, compression, buf)?
+        }
+        // Gray with alpha is stored as RGBA, which more readers understand.
+        DynamicImage::ImageLumaA16(_) => {
+            return write_tiff_pixels(encoder, &DynamicImage::ImageRgba16(img.to_rgba16()), compression)
+        }
+        _ => return write_tiff_pixels(encoder, &DynamicImage::ImageRgba8(img.to_rgba8()), compression),
+    }
+    Ok(())
+}
+
+/// Writes a Netpbm image in the flavor its extension names: `.ppm` color, `.pgm` gray, `.pbm`
+/// black and white (thresholded at mid-gray), and `.pam` with whatever channels the image has.
+fn write_pnm(writer: &mut impl std::io::Write, img: &DynamicImage, path: &Path) -> Result<()> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let (subtype, pixels) = match extension.as_str() {
+        "ppm" => (PnmSubtype::Pixmap(SampleEncoding::Binary), DynamicImage::ImageRgb8(img.to_rgb8())),
+        "pgm" => (PnmSubtype::Graymap(SampleEncoding::Binary), DynamicImage::ImageLuma8(img.to_luma8())),
+        // Written by hand: the `image` encoder rejects bitmaps that are entirely black.
+        "pbm" => {
+            let luma = img.to_luma8();
+            write!(writer, "P4\n{} {}\n", luma.width(), luma.height())?;
+            for row in luma.rows() {
+                let pixels: Vec<_> = row.collect();
+                // Eight pixels per byte, most significant bit first; set bits are black.
+                let packed: Vec<u8> = pixels
+                    .chunks(8)
+                    .map(|chunk| chunk.iter().enumerate().fold(0, |byte, (i, p)| byte | (((p.0[0] < 128) as u8) << (7 - i))))
+                    .collect();
+                writer.write_all(&packed)?;
+            }
+            return Ok(());
+        }
+        _ => {
+            let pixels = match (img.color().has_color(), img.color().has_alpha()) {
+                (false, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+                (false, true) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+                (true, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+                (true, true) => DynamicImage::ImageRgba8(img.to_rgba8()),
+            };
+            (PnmSubtype::ArbitraryMap, pixels)
+        }
+    };
+    PnmEncoder::new(writer).with_subtype(subtype).write_image(
+        pixels.as_bytes(),
+        pixels.width(),
+        pixels.height(),
+        pixels.color().into(),
+    )?;
+    Ok(())
+}
+
+/// Returns a single-channel copy of an 8-bit RGB(A) image whose pixels are all neutral gray,
+/// or `None` if the image has color or is already gray.
+fn collapse_gray(img: &DynamicImage) -> Option<DynamicImage> {
+    match img {
+        DynamicImage::ImageRgb8(rgb) if rgb.pixels().all(|p| p[0] == p[1] && p[1] == p[2]) => {
+            Some(DynamicImage::ImageLuma8(img.to_luma8()))
+        }
+        DynamicImage::ImageRgba8(rgba) if rgba.pixels().all(|p| p[0] == p[1] && p[1] == p[2]) => {
+            Some(DynamicImage::ImageLumaA8(img.to_luma_alpha8()))
+        }
+        _ => None,
+    }
+}
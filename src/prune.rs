@@ -0,0 +1,63 @@
+use super::cli::Cli;
+use super::context::RunContext;
+use super::discard::remove_file;
+use super::i18n::Msg;
+use super::is_supported_image;
+use super::processor::output_candidates;
+use super::rules::Pipeline;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Finds image files in the output directory that no input maps to anymore.
+/// Input files themselves are never considered orphans, so in-place runs are safe.
+pub fn find_orphans(cli: &Cli, context: &RunContext, inputs: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut expected: HashSet<PathBuf> = inputs.iter().filter_map(|p| fs::canonicalize(p).ok()).collect();
+    for input in inputs {
+        let pipeline = Pipeline::for_path(cli, input)?;
+        expected.extend(output_candidates(input, cli, context, &pipeline)?.iter().map(|p| canonical_or_self(p)));
+    }
+
+    let mut orphans: Vec<PathBuf> = walkdir::WalkDir::new(&cli.output_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| is_supported_image(e.path()))
+        .map(|e| e.into_path())
+        .filter(|p| !expected.contains(&canonical_or_self(p)))
+        .collect();
+    orphans.sort();
+    Ok(orphans)
+}
+
+/// Deletes orphaned outputs (or only lists them when `dry_run` is set), then removes any
+/// directories left empty. Returns the orphans found.
+pub fn prune(cli: &Cli, context: &RunContext, inputs: &[PathBuf], dry_run: bool) -> Result<Vec<PathBuf>> {
+    let orphans = find_orphans(cli, context, inputs)?;
+    for orphan in &orphans {
+        if dry_run {
+            println!("{}", Msg::WouldRemove(orphan));
+        } else {
+            remove_file(orphan, cli.use_trash)?;
+            println!("{}", Msg::Removed(orphan));
+        }
+    }
+    if !dry_run {
+        remove_empty_dirs(&cli.output_dir);
+    }
+    Ok(orphans)
+}
+
+fn remove_empty_dirs(root: &Path) {
+    // contents_first visits children before their parent, so nested empty dirs collapse fully.
+    for entry in walkdir::WalkDir::new(root).min_depth(1).contents_first(true).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_dir() {
+            // Fails harmlessly on non-empty directories.
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+}
+
+fn canonical_or_self(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
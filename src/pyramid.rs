@@ -0,0 +1,119 @@
+use image::RgbImage;
+
+/// The image and `levels - 1` copies, each blurred and half the size of the one before.
+pub(crate) fn gaussian_pyramid(plane: &Plane, levels: usize) -> Vec<Plane> {
+    let mut pyramid = vec![plane.clone()];
+    while pyramid.len() < levels {
+        let next = pyramid[pyramid.len() - 1].reduce();
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+/// Each level holds the detail lost by the next, smaller one; the last is the smallest image.
+pub(crate) fn laplacian_pyramid(plane: &Plane, levels: usize) -> Vec<Plane> {
+    let gaussian = gaussian_pyramid(plane, levels);
+    let mut pyramid: Vec<Plane> =
+        gaussian.windows(2).map(|pair| pair[0].minus(&pair[1].expand(pair[0].width, pair[0].height))).collect();
+    pyramid.extend(gaussian.last().cloned());
+    pyramid
+}
+
+/// Undoes `laplacian_pyramid`: expands the smallest level and adds back each level's detail.
+pub(crate) fn collapse(mut pyramid: Vec<Plane>) -> Option<Plane> {
+    let mut image = pyramid.pop()?;
+    while let Some(level) = pyramid.pop() {
+        image = level.plus(&image.expand(level.width, level.height));
+    }
+    Some(image)
+}
+
+/// An image of `f32` samples in 0..1 with interleaved channels.
+#[derive(Debug, Clone)]
+pub(crate) struct Plane {
+    pub width: usize,
+    pub height: usize,
+    pub channels: usize,
+    pub data: Vec<f32>,
+}
+
+impl Plane {
+    pub fn from_rgb(rgb: &RgbImage) -> Self {
+        let data = rgb.as_raw().iter().map(|&v| v as f32 / 255.0).collect();
+        Plane { width: rgb.width() as usize, height: rgb.height() as usize, channels: 3, data }
+    }
+
+    pub fn to_rgb(&self) -> RgbImage {
+        let data = self.data.iter().map(|v| (v * 255.0).round().clamp(0.0, 255.0) as u8).collect();
+        RgbImage::from_raw(self.width as u32, self.height as u32, data).unwrap_or_default()
+    }
+
+    /// Smooths with the 5-tap binomial kernel, repeating edge pixels.
+    pub fn blur(&self) -> Plane {
+        const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+        let (w, h, c) = (self.width, self.height, self.channels);
+        let pass = |src: &[f32], horizontal: bool| -> Vec<f32> {
+            let mut out = vec![0.0; src.len()];
+            for y in 0..h {
+                for x in 0..w {
+                    for (k, weight) in KERNEL.iter().enumerate() {
+                        let (sx, sy) = if horizontal {
+                            ((x + k).saturating_sub(2).min(w - 1), y)
+                        } else {
+                            (x, (y + k).saturating_sub(2).min(h - 1))
+                        };
+                        for ch in 0..c {
+                            out[(y * w + x) * c + ch] += weight * src[(sy * w + sx) * c + ch];
+                        }
+                    }
+                }
+            }
+            out
+        };
+        self.with_data(pass(&pass(&self.data, true), false))
+    }
+
+    /// Blurs and halves the size.
+    pub fn reduce(&self) -> Plane {
+        let blurred = self.blur();
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut data = Vec::with_capacity(width * height * self.channels);
+        for y in 0..height {
+            for x in 0..width {
+                data.extend_from_slice(&blurred.data[((2 * y) * self.width + 2 * x) * self.channels..][..self.channels]);
+            }
+        }
+        Plane { width, height, channels: self.channels, data }
+    }
+
+    /// Scales up to `width` by `height`, the size this was reduced from, and smooths.
+    pub fn expand(&self, width: usize, height: usize) -> Plane {
+        let mut data = Vec::with_capacity(width * height * self.channels);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = ((x / 2).min(self.width - 1), (y / 2).min(self.height - 1));
+                data.extend_from_slice(&self.data[(sy * self.width + sx) * self.channels..][..self.channels]);
+            }
+        }
+        Plane { width, height, channels: self.channels, data }.blur()
+    }
+
+    pub fn plus(&self, other: &Plane) -> Plane {
+        self.with_data(self.data.iter().zip(&other.data).map(|(a, b)| a + b).collect())
+    }
+
+    pub fn minus(&self, other: &Plane) -> Plane {
+        self.with_data(self.data.iter().zip(&other.data).map(|(a, b)| a - b).collect())
+    }
+
+    /// Multiplies every channel by the one-channel `weight`.
+    pub fn scaled_by(&self, weight: &Plane) -> Plane {
+        let data = self.data.chunks(self.channels).zip(&weight.data).flat_map(|(p, w)| p.iter().map(move |v| v * w)).collect();
+        self.with_data(data)
+    }
+
+    /// A plane of the same shape holding `data`.
+    pub fn with_data(&self, data: Vec<f32>) -> Plane {
+        Plane { width: self.width, height: self.height, channels: self.channels, data }
+    }
+}
@@ -0,0 +1,39 @@
+/// A small deterministic random number generator (SplitMix64). The same seed and label always
+/// give the same sequence, so randomized styling is reproducible between runs.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    /// Seeds a generator from a number and a label, giving each label its own sequence.
+    pub fn new(seed: u64, label: &str) -> Self {
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&seed.to_le_bytes());
+        hasher.update(label.as_bytes());
+        let hash = hasher.finalize();
+        let mut state = [0u8; 8];
+        state.copy_from_slice(&hash.as_bytes()[..8]);
+        Rng(u64::from_le_bytes(state))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number in `[0, 1)`.
+    pub fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns an integer in `[low, high]`.
+    pub fn range(&mut self, low: i64, high: i64) -> i64 {
+        if high <= low {
+            return low;
+        }
+        let span = (high - low) as u64 + 1;
+        low + (self.next_u64() % span) as i64
+    }
+}
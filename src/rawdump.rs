@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use serde::Serialize;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File extension of `--output-format raw` dumps.
+pub const RAW_EXTENSION: &str = "rgba";
+
+/// Describes the headerless pixels of a raw dump, written next to it as
+/// `<output>.layout.json` so other tools can read them without guessing.
+#[derive(Debug, Serialize)]
+pub struct RawLayout {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: &'static str,
+    pub channels: u32,
+    pub bits_per_channel: u32,
+    /// Bytes from the start of one row to the start of the next; rows are not padded.
+    pub row_stride: u64,
+    pub byte_length: u64,
+    pub color_space: &'static str,
+    /// Color values are not premultiplied by alpha.
+    pub alpha: &'static str,
+    /// The first row is the top of the image.
+    pub origin: &'static str,
+}
+
+impl RawLayout {
+    pub fn of(width: u32, height: u32) -> Self {
+        RawLayout {
+            width,
+            height,
+            pixel_format: "rgba8",
+            channels: 4,
+            bits_per_channel: 8,
+            row_stride: width as u64 * 4,
+            byte_length: width as u64 * height as u64 * 4,
+            color_space: "srgb",
+            alpha: "straight",
+            origin: "top-left",
+        }
+    }
+
+    pub fn write(&self, output: &Path) -> Result<()> {
+        let path = layout_path(output);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write layout {}", path.display()))
+    }
+}
+
+/// Returns where the layout of a raw dump goes, e.g. `frame.rgba.layout.json` for `frame.rgba`.
+pub fn layout_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".layout.json");
+    output.with_file_name(name)
+}
+
+/// Writes the image as bare 8-bit RGBA rows, top to bottom.
+pub fn write_raw(img: &DynamicImage, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, img.to_rgba8().as_raw()).with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,187 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Extracts image references from Markdown (`![alt](src "title")`) and HTML (`<img src="...">`).
+/// References are returned as written, in document order.
+pub fn image_references(text: &str) -> Vec<String> {
+    let mut refs = markdown_images(text);
+    refs.extend(html_images(text));
+    refs
+}
+
+fn markdown_images(text: &str) -> Vec<String> {
+    let mut refs = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("![") {
+        rest = &rest[start + 2..];
+        let Some(close) = rest.find("](") else { break };
+        // Alt text can't span paragraphs; bail out to avoid pairing unrelated brackets.
+        if rest[..close].contains("\n\n") {
+            continue;
+        }
+        rest = &rest[close + 2..];
+        let Some(end) = rest.find(')') else { break };
+        let target = rest[..end].trim();
+        let src = if let Some(stripped) = target.strip_prefix('<') {
+            stripped.split('>').next().unwrap_or_default()
+        } else {
+            // Drop an optional title: ![alt](src "title")
+            target.split_whitespace().next().unwrap_or_default()
+        };
+        if !src.is_empty() {
+            refs.push(src.to_string());
+        }
+        rest = &rest[end + 1..];
+    }
+    refs
+}
+
+fn html_images(text: &str) -> Vec<String> {
+    let lower = text.to_ascii_lowercase();
+    let mut refs = Vec::new();
+    let mut pos = 0;
+    while let Some(start) = lower[pos..].find("<img") {
+        let tag_start = pos + start;
+        let tag_end = lower[tag_start..].find('>').map_or(lower.len(), |e| tag_start + e);
+        let tag = &lower[tag_start..tag_end];
+        if let Some(attr) = find_attr(tag, "src") {
+            let (value_start, value_end) = attr;
+            refs.push(text[tag_start + value_start..tag_start + value_end].to_string());
+        }
+        pos = tag_end;
+    }
+    refs
+}
+
+/// Returns the byte range of an attribute value within a lowercase tag.
+fn find_attr(tag: &str, name: &str) -> Option<(usize, usize)> {
+    let mut search = 0;
+    while let Some(found) = tag[search..].find(name) {
+        let at = search + found;
+        search = at + name.len();
+        // Must be a whole attribute name, e.g. not the tail of `data-src`.
+        let preceded_ok = tag[..at].ends_with(|c: char| c.is_whitespace());
+        let after = tag[search..].trim_start();
+        if !preceded_ok || !after.starts_with('=') {
+            continue;
+        }
+        let value = after[1..].trim_start();
+        let value_start = tag.len() - value.len();
+        return match value.chars().next()? {
+            q @ ('"' | '\'') => {
+                let len = value[1..].find(q)?;
+                Some((value_start + 1, value_start + 1 + len))
+            }
+            _ => {
+                let len = value.find(|c: char| c.is_whitespace() || c == '/').unwrap_or(value.len());
+                Some((value_start, value_start + len))
+            }
+        };
+    }
+    None
+}
+
+/// Returns whether a reference points at a local file rather than a URL.
+pub fn is_local(reference: &str) -> bool {
+    !(reference.contains("://") || reference.starts_with("//") || reference.starts_with("data:"))
+}
+
+/// Strips any query string or fragment from a reference.
+pub fn strip_suffixes(reference: &str) -> &str {
+    reference.split(['?', '#']).next().unwrap_or(reference)
+}
+
+/// Lexically normalizes a path, resolving `.` and `..` without touching the filesystem.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other.as_os_str()),
+        }
+    }
+    out
+}
+
+/// Resolves a local reference found in `page`. Absolute references (`/img/a.png`) resolve
+/// against `site_root`, relative ones against the page's directory.
+pub fn resolve(reference: &str, page: &Path, site_root: &Path) -> Option<PathBuf> {
+    if !is_local(reference) {
+        return None;
+    }
+    let reference = strip_suffixes(reference);
+    let path = match reference.strip_prefix('/') {
+        Some(abs) => site_root.join(abs),
+        None => page.parent()?.join(reference),
+    };
+    Some(normalize(&path))
+}
+
+/// Expands glob patterns (e.g. `content/**/*.md`) into the matching files.
+pub fn expand_globs(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut pages = Vec::new();
+    for pattern in patterns {
+        let matches = glob::glob(pattern).with_context(|| format!("Invalid glob pattern '{}'", pattern))?;
+        pages.extend(matches.filter_map(|m| m.ok()).filter(|p| p.is_file()));
+    }
+    pages.sort();
+    pages.dedup();
+    Ok(pages)
+}
+
+/// Returns the canonical paths of every existing file referenced from the pages.
+pub fn referenced_files(pages: &[PathBuf], site_root: &Path) -> Result<HashSet<PathBuf>> {
+    let mut files = HashSet::new();
+    for page in pages {
+        let text = fs::read_to_string(page).with_context(|| format!("Failed to read {}", page.display()))?;
+        for reference in image_references(&text) {
+            if let Some(path) = resolve(&reference, page, site_root).and_then(|p| fs::canonicalize(p).ok()) {
+                files.insert(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Rewrites references whose target was written with a different extension, e.g.
+/// `photo.png` -> `photo.webp`, keeping the rest of the reference intact. `outputs` maps
+/// canonical source paths to their output paths. Returns the number of pages changed.
+pub fn rewrite_links(pages: &[PathBuf], site_root: &Path, outputs: &HashMap<PathBuf, PathBuf>) -> Result<usize> {
+    let mut changed = 0;
+    for page in pages {
+        let text = fs::read_to_string(page).with_context(|| format!("Failed to read {}", page.display()))?;
+        let mut rewritten = text.clone();
+        for reference in image_references(&text) {
+            let Some(source) = resolve(&reference, page, site_root).and_then(|p| fs::canonicalize(p).ok()) else {
+                continue;
+            };
+            let (Some(output), Some(old_ext)) = (outputs.get(&source), source.extension()) else { continue };
+            let Some(new_ext) = output.extension() else { continue };
+            if old_ext == new_ext {
+                continue;
+            }
+            let path_part = strip_suffixes(&reference);
+            let suffix = &reference[path_part.len()..];
+            let old_ext = old_ext.to_string_lossy();
+            let Some(stem) = path_part.strip_suffix(old_ext.as_ref()) else { continue };
+            let new_reference = format!("{}{}{}", stem, new_ext.to_string_lossy(), suffix);
+            // Only replace where the reference starts a link target or attribute value.
+            for delimiter in ['(', '<', '"', '\'', '='] {
+                rewritten = rewritten.replace(
+                    &format!("{}{}", delimiter, reference),
+                    &format!("{}{}", delimiter, new_reference),
+                );
+            }
+        }
+        if rewritten != text {
+            fs::write(page, rewritten).with_context(|| format!("Failed to rewrite {}", page.display()))?;
+            changed += 1;
+        }
+    }
+    Ok(changed)
+}
@@ -0,0 +1,343 @@
+use super::aspect::Aspect;
+use super::cli::{Cli, SafetyMode};
+use super::errors::ParseGroupKeyError;
+use super::i18n::Msg;
+use super::processor::Processed;
+use super::safety::SafetyCheck;
+use super::sync::{hash_file, relative_key};
+use anyhow::{Context, Result};
+use exif::{In, Reader, Rational, Tag, Value};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Label for images that lack the grouped field.
+const UNKNOWN: &str = "unknown";
+
+/// An EXIF field to break the report summary down by, written as `exif.<Field>`.
+/// `exif.Date` groups by the calendar day of `DateTimeOriginal`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupKey {
+    Exif(Tag),
+    Date,
+}
+
+impl FromStr for GroupKey {
+    type Err = ParseGroupKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseGroupKeyError(s.to_string());
+        let (source, name) = s.split_once('.').ok_or_else(err)?;
+        if !source.eq_ignore_ascii_case("exif") {
+            return Err(err());
+        }
+        Ok(match name.to_lowercase().as_str() {
+            "make" => GroupKey::Exif(Tag::Make),
+            "model" => GroupKey::Exif(Tag::Model),
+            "lensmake" => GroupKey::Exif(Tag::LensMake),
+            "lensmodel" => GroupKey::Exif(Tag::LensModel),
+            "datetimeoriginal" => GroupKey::Exif(Tag::DateTimeOriginal),
+            "date" => GroupKey::Date,
+            _ => return Err(err()),
+        })
+    }
+}
+
+impl fmt::Display for GroupKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GroupKey::Exif(tag) => write!(f, "exif.{}", tag),
+            GroupKey::Date => write!(f, "exif.Date"),
+        }
+    }
+}
+
+impl GroupKey {
+    /// Reads this key's value from the image's EXIF data.
+    fn value(&self, exif: Option<&exif::Exif>) -> Option<String> {
+        let tag = match self {
+            GroupKey::Exif(tag) => *tag,
+            GroupKey::Date => Tag::DateTimeOriginal,
+        };
+        let field = exif?.get_field(tag, In::PRIMARY)?;
+        let text = match &field.value {
+            Value::Ascii(parts) => {
+                parts.iter().map(|p| String::from_utf8_lossy(p).trim().to_string()).collect::<Vec<_>>().join(" ")
+            }
+            value => value.display_as(tag).to_string(),
+        };
+        let text = match self {
+            // EXIF dates look like "2024:05:01 12:30:00".
+            GroupKey::Date => text.split(' ').next()?.replace(':', "-"),
+            GroupKey::Exif(_) => text,
+        };
+        (!text.is_empty()).then_some(text)
+    }
+}
+
+/// What happened to one input image.
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Processed(Processed),
+    /// Left alone because `--baseline` or `--skip-up-to-date` showed the output was already up
+    /// to date.
+    Unchanged(PathBuf),
+    /// Held back by `--safety-filter block`.
+    Blocked(SafetyCheck),
+    /// Held back by `--min-sharpness`, with its focus score.
+    Blurry(f64),
+    /// Left out by `--pick-best-of-burst` in favor of this sharper shot.
+    Discarded(PathBuf),
+    Skipped,
+    Failed(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImageEntry {
+    pub source: PathBuf,
+    /// The source's path relative to the input directory, with `/` separators.
+    pub key: String,
+    /// BLAKE3 hash of the source, for processed and unchanged images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_blake3: Option<String>,
+    pub status: &'static str,
+    /// The output, or with `--widths` the widest one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<PathBuf>,
+    /// Every output written with `--widths`, smallest first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<PathBuf>,
+    /// Labels from `--auto-tag`, most likely first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// The `--safety-filter` verdict, for screened images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub safety: Option<SafetyCheck>,
+    /// The focus score, with `--min-sharpness`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sharpness: Option<f64>,
+    /// The shot of the same burst processed instead, for discarded images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best_of_burst: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Orientation of the source, for processed images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aspect: Option<Aspect>,
+    /// Where the photo was taken, with `--emit-geo`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gps: Option<GpsPosition>,
+}
+
+/// A GPS fix in decimal degrees; south and west are negative.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Meters above sea level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+}
+
+impl GpsPosition {
+    /// Reads the position from EXIF GPS tags, if both coordinates are present.
+    pub fn from_exif(exif: &exif::Exif) -> Option<Self> {
+        let coordinate = |tag, ref_tag, negative: &[u8]| {
+            let Value::Rational(dms) = &exif.get_field(tag, In::PRIMARY)?.value else { return None };
+            let degrees = dms.iter().zip([1.0, 60.0, 3600.0]).map(|(r, div)| r.to_f64() / div).sum::<f64>();
+            let reference = match exif.get_field(ref_tag, In::PRIMARY).map(|f| &f.value) {
+                Some(Value::Ascii(parts)) => parts.first().cloned().unwrap_or_default(),
+                _ => Vec::new(),
+            };
+            degrees.is_finite().then(|| if reference == negative { -degrees } else { degrees })
+        };
+        let latitude = coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, b"S")?;
+        let longitude = coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, b"W")?;
+        let altitude = exif
+            .get_field(Tag::GPSAltitude, In::PRIMARY)
+            .and_then(|f| match &f.value {
+                Value::Rational(v) => v.first().map(Rational::to_f64),
+                _ => None,
+            })
+            .filter(|a| a.is_finite())
+            .map(|a| {
+                // Reference 1 means below sea level.
+                let below = exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY).and_then(|f| f.value.get_uint(0)) == Some(1);
+                if below { -a } else { a }
+            });
+        Some(GpsPosition { latitude, longitude, altitude })
+    }
+}
+
+/// Summary of a batch run, written by `--report`.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    /// Fingerprint of the options that affect outputs, compared by `--baseline`.
+    pub settings: String,
+    pub processed: usize,
+    /// Images `--baseline` found up to date.
+    pub unchanged: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Processed images `--safety-filter flag` marked as suspect.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flagged: Option<usize>,
+    /// Images `--safety-filter block` held back.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked: Option<usize>,
+    /// Images `--min-sharpness` held back as blurry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurry: Option<usize>,
+    /// Burst shots `--pick-best-of-burst` left out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discarded: Option<usize>,
+    /// Processed images with a GPS position, with `--emit-geo`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geotagged: Option<usize>,
+    /// The number of processed images of each orientation.
+    pub aspects: BTreeMap<Aspect, usize>,
+    /// For each `--group-by` key, the number of processed images per value.
+    pub groups: BTreeMap<String, BTreeMap<String, usize>>,
+    pub images: Vec<ImageEntry>,
+}
+
+impl Report {
+    /// Images within `square_tolerance` of 1:1 are classified as square. `settings` is the
+    /// fingerprint of the options as the run was given them.
+    pub fn new(results: &[(PathBuf, Outcome)], cli: &Cli, settings: String) -> Result<Self> {
+        let (group_by, emit_geo, square_tolerance) = (&cli.group_by, cli.emit_geo, cli.square_tolerance());
+        let mut report = Report {
+            settings,
+            processed: 0,
+            unchanged: 0,
+            skipped: 0,
+            failed: 0,
+            flagged: (cli.safety_filter == Some(SafetyMode::Flag)).then_some(0),
+            blocked: (cli.safety_filter == Some(SafetyMode::Block)).then_some(0),
+            blurry: cli.min_sharpness.map(|_| 0),
+            discarded: cli.pick_best_of_burst.map(|_| 0),
+            geotagged: emit_geo.then_some(0),
+            aspects: BTreeMap::new(),
+            groups: group_by.iter().map(|key| (key.to_string(), BTreeMap::new())).collect(),
+            images: Vec::with_capacity(results.len()),
+        };
+
+        for (source, outcome) in results {
+            let mut gps = None;
+            let mut aspect = None;
+            let mut source_blake3 = None;
+            let mut variants = Vec::new();
+            let mut tags = Vec::new();
+            let mut safety = None;
+            let mut best_of_burst = None;
+            let mut sharpness = None;
+            let (status, output, error) = match outcome {
+                Outcome::Processed(Processed { outputs, tags: labels, safety: check, sharpness: score }) => {
+                    report.processed += 1;
+                    aspect = Aspect::of_file(source, square_tolerance);
+                    if let Some(aspect) = aspect {
+                        *report.aspects.entry(aspect).or_default() += 1;
+                    }
+                    if !group_by.is_empty() || emit_geo {
+                        let exif = read_exif(source);
+                        for key in group_by {
+                            let value = key.value(exif.as_ref()).unwrap_or_else(|| UNKNOWN.to_string());
+                            *report.groups.entry(key.to_string()).or_default().entry(value).or_default() += 1;
+                        }
+                        if emit_geo {
+                            gps = exif.as_ref().and_then(GpsPosition::from_exif);
+                            if let (Some(count), Some(_)) = (report.geotagged.as_mut(), gps) {
+                                *count += 1;
+                            }
+                        }
+                    }
+                    source_blake3 = Some(hash_file(source)?);
+                    if !cli.widths.is_empty() {
+                        variants = outputs.clone();
+                    }
+                    tags = labels.clone();
+                    safety = *check;
+                    sharpness = *score;
+                    if let (Some(count), Some(SafetyCheck { suspect: true, .. })) = (report.flagged.as_mut(), check) {
+                        *count += 1;
+                    }
+                    ("processed", outputs.last().cloned(), None)
+                }
+                Outcome::Unchanged(output) => {
+                    report.unchanged += 1;
+                    source_blake3 = Some(hash_file(source)?);
+                    ("unchanged", Some(output.clone()), None)
+                }
+                Outcome::Blocked(check) => {
+                    *report.blocked.get_or_insert(0) += 1;
+                    safety = Some(*check);
+                    ("blocked", None, None)
+                }
+                Outcome::Blurry(score) => {
+                    *report.blurry.get_or_insert(0) += 1;
+                    sharpness = Some(*score);
+                    ("blurry", None, None)
+                }
+                Outcome::Discarded(kept) => {
+                    *report.discarded.get_or_insert(0) += 1;
+                    best_of_burst = Some(kept.clone());
+                    ("discarded", None, None)
+                }
+                Outcome::Skipped => {
+                    report.skipped += 1;
+                    ("skipped", None, None)
+                }
+                Outcome::Failed(e) => {
+                    report.failed += 1;
+                    ("failed", None, Some(e.clone()))
+                }
+            };
+            let key = relative_key(source, &cli.input_dir);
+            report.images.push(ImageEntry {
+                source: source.clone(),
+                key,
+                source_blake3,
+                status,
+                output,
+                variants,
+                tags,
+                safety,
+                sharpness,
+                best_of_burst,
+                error,
+                aspect,
+                gps,
+            });
+        }
+        Ok(report)
+    }
+
+    /// Prints the per-group counts, largest groups first.
+    pub fn print_groups(&self) {
+        for (key, counts) in &self.groups {
+            println!("{}", Msg::ImagesBy(key));
+            let mut counts: Vec<_> = counts.iter().collect();
+            counts.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+            for (value, count) in counts {
+                println!("  {}: {}", value, count);
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write report {}", path.display()))
+    }
+}
+
+pub(crate) fn read_exif(path: &Path) -> Option<exif::Exif> {
+    let file = fs::File::open(path).ok()?;
+    Reader::new().read_from_container(&mut BufReader::new(file)).ok()
+}
@@ -0,0 +1,206 @@
+use super::cli::{Cli, OutputFormat};
+use super::errors::ParseRuleError;
+use clap::ValueEnum;
+use image::ImageFormat;
+use std::path::Path;
+use std::str::FromStr;
+
+/// An image property a rule condition can test.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Width,
+    Height,
+    Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(u32),
+    Format(ImageFormat),
+}
+
+/// A single comparison such as `width > 2000` or `format == png`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub field: Field,
+    pub op: Op,
+    pub value: Value,
+}
+
+impl Condition {
+    /// Evaluates the condition against an image's dimensions and source format.
+    pub fn matches(&self, width: u32, height: u32, format: Option<ImageFormat>) -> bool {
+        match (&self.field, &self.value) {
+            (Field::Width, Value::Number(n)) => compare(self.op, width, *n),
+            (Field::Height, Value::Number(n)) => compare(self.op, height, *n),
+            (Field::Format, Value::Format(f)) => match self.op {
+                Op::Eq => format == Some(*f),
+                Op::Ne => format != Some(*f),
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
+fn compare(op: Op, lhs: u32, rhs: u32) -> bool {
+    match op {
+        Op::Gt => lhs > rhs,
+        Op::Ge => lhs >= rhs,
+        Op::Lt => lhs < rhs,
+        Op::Le => lhs <= rhs,
+        Op::Eq => lhs == rhs,
+        Op::Ne => lhs != rhs,
+    }
+}
+
+/// What happens to an image matched by a rule.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Actions {
+    /// Produce no output for the image.
+    pub skip: bool,
+    /// Copy the source file to the output directory unchanged.
+    pub copy: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub output_format: Option<OutputFormat>,
+    pub quality: Option<u8>,
+}
+
+/// A conditional pipeline override, written as `<field> <op> <value> => <action>[,<action>...]`,
+/// e.g. `width > 2000 => width=1600,quality=80` or `format == png => format=webp`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub when: Condition,
+    pub actions: Actions,
+}
+
+impl FromStr for Rule {
+    type Err = ParseRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseRuleError(s.to_string());
+        let (condition, actions) = s.split_once("=>").ok_or_else(err)?;
+
+        // Longer operators first so `>=` isn't read as `>`.
+        let (field, op, value) = [(">=", Op::Ge), ("<=", Op::Le), ("==", Op::Eq), ("!=", Op::Ne), (">", Op::Gt), ("<", Op::Lt)]
+            .iter()
+            .find_map(|(token, op)| {
+                condition.split_once(token).map(|(f, v)| (f.trim(), *op, v.trim()))
+            })
+            .ok_or_else(err)?;
+
+        let (field, value) = match field.to_lowercase().as_str() {
+            "width" => (Field::Width, Value::Number(value.parse().map_err(|_| err())?)),
+            "height" => (Field::Height, Value::Number(value.parse().map_err(|_| err())?)),
+            "format" => (Field::Format, Value::Format(ImageFormat::from_extension(value).ok_or_else(err)?)),
+            _ => return Err(err()),
+        };
+        if field == Field::Format && !matches!(op, Op::Eq | Op::Ne) {
+            return Err(err());
+        }
+
+        let mut parsed = Actions::default();
+        for action in actions.split(',').map(str::trim) {
+            match action.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                None if action == "skip" => parsed.skip = true,
+                None if action == "copy" => parsed.copy = true,
+                Some(("width", v)) => parsed.width = Some(v.parse().map_err(|_| err())?),
+                Some(("height", v)) => parsed.height = Some(v.parse().map_err(|_| err())?),
+                Some(("format", v)) => parsed.output_format = Some(OutputFormat::from_str(v, true).map_err(|_| err())?),
+                Some(("quality", v)) => {
+                    let q: u8 = v.parse().map_err(|_| err())?;
+                    if !(1..=100).contains(&q) {
+                        return Err(err());
+                    }
+                    parsed.quality = Some(q);
+                }
+                _ => return Err(err()),
+            }
+        }
+
+        Ok(Rule { when: Condition { field, op, value }, actions: parsed })
+    }
+}
+
+/// The settings used to process one image, after applying any matching rule.
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub skip: bool,
+    pub copy: bool,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Resize targets for landscape (and square) images, replacing `width` and `height` when
+    /// either is set.
+    pub landscape: (Option<u32>, Option<u32>),
+    /// Resize targets for portrait images, likewise.
+    pub portrait: (Option<u32>, Option<u32>),
+    pub output_format: Option<OutputFormat>,
+    pub quality: u8,
+}
+
+impl Pipeline {
+    /// Resolves the pipeline for an image file. Only the header is read to evaluate rules,
+    /// so skipped images are never decoded.
+    pub fn for_path(cli: &Cli, path: &Path) -> anyhow::Result<Self> {
+        let source_format = ImageFormat::from_path(path).ok();
+        if cli.rules.is_empty() {
+            return Ok(Self::resolve(cli, 0, 0, source_format));
+        }
+        let (width, height) = image::image_dimensions(path)?;
+        Ok(Self::resolve(cli, width, height, source_format))
+    }
+
+    /// Resolves the pipeline for an image. Rules are checked in order and the first match wins;
+    /// a rule that sets a width or height replaces all of the global resize targets.
+    pub fn resolve(cli: &Cli, width: u32, height: u32, format: Option<ImageFormat>) -> Self {
+        let mut pipeline = Pipeline {
+            skip: false,
+            copy: false,
+            width: cli.width,
+            height: cli.height,
+            landscape: (cli.width_landscape, cli.height_landscape),
+            portrait: (cli.width_portrait, cli.height_portrait),
+            output_format: cli.output_format.clone(),
+            quality: cli.quality,
+        };
+
+        if let Some(rule) = cli.rules.iter().find(|r| r.when.matches(width, height, format)) {
+            let actions = &rule.actions;
+            pipeline.skip = actions.skip;
+            pipeline.copy = actions.copy;
+            if actions.width.is_some() || actions.height.is_some() {
+                pipeline.width = actions.width;
+                pipeline.height = actions.height;
+                pipeline.landscape = (None, None);
+                pipeline.portrait = (None, None);
+            }
+            if let Some(format) = &actions.output_format {
+                pipeline.output_format = Some(format.clone());
+            }
+            if let Some(quality) = actions.quality {
+                pipeline.quality = quality;
+            }
+        }
+        pipeline
+    }
+
+    /// Returns the resize targets for an image of the given size: the ones for its orientation
+    /// if any are set, otherwise `width` and `height`.
+    pub fn size_for(&self, width: u32, height: u32) -> (Option<u32>, Option<u32>) {
+        match if height > width { self.portrait } else { self.landscape } {
+            (None, None) => (self.width, self.height),
+            targets => targets,
+        }
+    }
+}
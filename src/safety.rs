@@ -0,0 +1,35 @@
+use super::classify::{labels, probabilities};
+use super::cli::Cli;
+use anyhow::{bail, Result};
+use image::DynamicImage;
+use serde::Serialize;
+
+/// The `--safety-filter` verdict on one image.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SafetyCheck {
+    /// Combined probability of the `--safety-classes`.
+    pub score: f32,
+    /// Whether the score reached `--safety-threshold`.
+    pub suspect: bool,
+}
+
+/// Screens `img` with the `--safety-model`, or returns `None` without `--safety-filter`.
+pub fn check(img: &DynamicImage, cli: &Cli) -> Result<Option<SafetyCheck>> {
+    let (Some(_), Some(model), Some(labels_path)) = (cli.safety_filter, &cli.safety_model, &cli.safety_labels) else {
+        return Ok(None);
+    };
+    let labels = labels(labels_path)?;
+    let classes: Vec<usize> = labels
+        .iter()
+        .enumerate()
+        .filter(|(_, label)| cli.safety_classes.iter().any(|class| class.eq_ignore_ascii_case(label)))
+        .map(|(index, _)| index)
+        .collect();
+    // A misspelled class would otherwise let every image through.
+    if classes.is_empty() {
+        bail!("None of the safety classes ({}) is listed in {}", cli.safety_classes.join(", "), labels_path.display());
+    }
+    let probabilities = probabilities(img, model)?;
+    let score = classes.iter().filter_map(|&class| probabilities.get(class)).sum::<f32>().min(1.0);
+    Ok(Some(SafetyCheck { score, suspect: score >= cli.safety_threshold.0 }))
+}
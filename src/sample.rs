@@ -0,0 +1,63 @@
+use super::errors::ParseSampleSizeError;
+use super::random::Rng;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How many inputs `--sample` keeps: a fixed number, or a fraction of them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SampleSize {
+    Count(usize),
+    Fraction(f32),
+}
+
+impl FromStr for SampleSize {
+    type Err = ParseSampleSizeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSampleSizeError(s.to_string());
+        match s.trim().strip_suffix('%') {
+            Some(percent) => {
+                let percent: f32 = percent.trim().parse().map_err(|_| err())?;
+                if percent > 0.0 && percent <= 100.0 {
+                    Ok(SampleSize::Fraction(percent / 100.0))
+                } else {
+                    Err(err())
+                }
+            }
+            None => match s.trim().parse::<usize>() {
+                Ok(count) if count > 0 => Ok(SampleSize::Count(count)),
+                _ => Err(err()),
+            },
+        }
+    }
+}
+
+impl SampleSize {
+    /// Returns how many of `total` inputs to keep; a fraction keeps at least one.
+    pub fn of(&self, total: usize) -> usize {
+        match *self {
+            SampleSize::Count(count) => count.min(total),
+            SampleSize::Fraction(fraction) => ((total as f64 * fraction as f64).round() as usize).max(1).min(total),
+        }
+    }
+}
+
+/// Picks a random subset of the inputs. Each input gets a rank drawn from the seed and its path
+/// relative to `input_dir`, and the lowest ranks are kept, so the same seed picks the same files
+/// whatever order they were found in, and adding files to the corpus changes the pick little.
+/// The kept paths are returned in their original order.
+pub fn sample(paths: Vec<PathBuf>, size: SampleSize, seed: u64, input_dir: &Path) -> Vec<PathBuf> {
+    let keep = size.of(paths.len());
+    let mut ranked: Vec<(u64, usize)> = paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let relative = path.strip_prefix(input_dir).unwrap_or(path).to_string_lossy();
+            (Rng::new(seed, &format!("sample/{}", relative)).next_u64(), i)
+        })
+        .collect();
+    ranked.sort_unstable();
+    let kept: HashSet<usize> = ranked.into_iter().take(keep).map(|(_, i)| i).collect();
+    paths.into_iter().enumerate().filter(|(i, _)| kept.contains(i)).map(|(_, path)| path).collect()
+}
@@ -0,0 +1,86 @@
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// Resizes to exactly `width`x`height` without stretching: the image is scaled to cover the
+/// target, then the lowest-energy seams are removed from the side that is too long, so flat
+/// background gives way before detailed subjects. Suits modest aspect-ratio changes; the
+/// further the ratio moves, the more of the background disappears.
+pub fn carve(img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let (w, h) = (img.width() as f64, img.height() as f64);
+    let scale = (width as f64 / w).max(height as f64 / h);
+    let cover = |side: f64, target: u32| ((side * scale).round() as u32).max(target);
+    let (cover_width, cover_height) = (cover(w, width), cover(h, height));
+    let scaled = imageops::resize(&img.to_rgba8(), cover_width, cover_height, FilterType::Lanczos3);
+
+    let carved = if cover_width > width {
+        remove_vertical_seams(scaled, cover_width - width)
+    } else if cover_height > height {
+        // Horizontal seams are vertical seams of the transposed image.
+        let transposed = imageops::rotate90(&scaled);
+        imageops::rotate270(&remove_vertical_seams(transposed, cover_height - height))
+    } else {
+        scaled
+    };
+    if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(carved)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(carved).into_rgb8())
+    }
+}
+
+/// Removes `count` top-to-bottom seams, one at a time, recomputing the energy after each.
+fn remove_vertical_seams(img: RgbaImage, count: u32) -> RgbaImage {
+    let (mut width, height) = (img.width() as usize, img.height() as usize);
+    let mut pixels: Vec<Rgba<u8>> = img.pixels().copied().collect();
+    let mut luma: Vec<f32> = pixels
+        .iter()
+        .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+        .collect();
+    let mut cost = vec![0f32; width * height];
+
+    for _ in 0..count {
+        // Energy is the gradient magnitude; each row's cost adds the cheapest of the three
+        // neighbors above, so the minimum in the last row ends the cheapest connected seam.
+        for y in 0..height {
+            for x in 0..width {
+                let at = |x: usize, y: usize| luma[y * width + x];
+                let dx = at((x + 1).min(width - 1), y) - at(x.saturating_sub(1), y);
+                let dy = at(x, (y + 1).min(height - 1)) - at(x, y.saturating_sub(1));
+                let energy = dx.abs() + dy.abs();
+                cost[y * width + x] = if y == 0 {
+                    energy
+                } else {
+                    let above = &cost[(y - 1) * width..y * width];
+                    let (from, to) = (x.saturating_sub(1), (x + 1).min(width - 1));
+                    energy + above[from..=to].iter().copied().fold(f32::INFINITY, f32::min)
+                };
+            }
+        }
+
+        let mut seam = vec![0usize; height];
+        let last = &cost[(height - 1) * width..];
+        seam[height - 1] = (0..width).min_by(|&a, &b| last[a].total_cmp(&last[b])).unwrap_or(0);
+        for y in (0..height - 1).rev() {
+            let below = seam[y + 1];
+            let row = &cost[y * width..(y + 1) * width];
+            seam[y] = (below.saturating_sub(1)..=(below + 1).min(width - 1))
+                .min_by(|&a, &b| row[a].total_cmp(&row[b]))
+                .unwrap_or(below);
+        }
+
+        // Compact both buffers to the new width, dropping the seam's pixel in each row.
+        let mut write = 0;
+        for (y, &skip) in seam.iter().enumerate() {
+            for x in (0..width).filter(|&x| x != skip) {
+                pixels[write] = pixels[y * width + x];
+                luma[write] = luma[y * width + x];
+                write += 1;
+            }
+        }
+        width -= 1;
+        pixels.truncate(width * height);
+        luma.truncate(width * height);
+    }
+
+    RgbaImage::from_fn(width as u32, height as u32, |x, y| pixels[y as usize * width + x as usize])
+}
@@ -0,0 +1,105 @@
+use super::errors::ParseSequenceTemplateError;
+use super::report::read_exif;
+use exif::{In, Tag, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A `--sequence-rename` file name template such as `IMG_{seq:04}`. `{seq}` is replaced by the
+/// image's position in capture order, zero-padded to the given width; `{stem}` by its
+/// original file name without the extension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceTemplate {
+    prefix: String,
+    width: usize,
+    suffix: String,
+}
+
+impl FromStr for SequenceTemplate {
+    type Err = ParseSequenceTemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseSequenceTemplateError(s.to_string());
+        let start = s.find("{seq").ok_or_else(err)?;
+        let end = start + s[start..].find('}').ok_or_else(err)?;
+        let width = match &s[start + 4..end] {
+            "" => 0,
+            spec => spec.strip_prefix(':').and_then(|w| w.parse().ok()).ok_or_else(err)?,
+        };
+        let (prefix, suffix) = (&s[..start], &s[end + 1..]);
+        if suffix.contains("{seq") || [prefix, suffix].iter().any(|part| part.contains(['/', '\\'])) {
+            return Err(err());
+        }
+        Ok(SequenceTemplate { prefix: prefix.to_string(), width, suffix: suffix.to_string() })
+    }
+}
+
+impl SequenceTemplate {
+    /// Renders the file stem for the image at `seq` (counting from 1).
+    pub fn render(&self, seq: usize, stem: &str) -> String {
+        format!("{}{:0width$}{}", self.prefix, seq, self.suffix, width = self.width).replace("{stem}", stem)
+    }
+}
+
+/// Numbers the images in capture order and returns each one's new file stem.
+/// Images are ordered by EXIF `DateTimeOriginal` (refined by `SubSecTimeOriginal`); images
+/// without a capture time follow the rest. Ties, such as a burst shot within one second,
+/// fall back to the file path, so every image gets its own number and the order is stable
+/// between runs.
+pub fn assign(template: &SequenceTemplate, inputs: &[PathBuf]) -> BTreeMap<PathBuf, String> {
+    capture_order(inputs)
+        .into_iter()
+        .enumerate()
+        .map(|(index, (path, _))| {
+            let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+            (path.clone(), template.render(index + 1, &stem))
+        })
+        .collect()
+}
+
+/// Sorts images by EXIF capture time, as `assign` numbers them, and returns each with its
+/// `DateTimeOriginal` text, if it has one.
+pub(crate) fn capture_order(inputs: &[PathBuf]) -> Vec<(&PathBuf, Option<String>)> {
+    let mut ordered: Vec<(Option<(String, String)>, &PathBuf)> =
+        inputs.iter().map(|path| (capture_time(path), path)).collect();
+    ordered.sort_by(|(a, pa), (b, pb)| match (a, b) {
+        (Some(a), Some(b)) => a.cmp(b).then_with(|| pa.cmp(pb)),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => pa.cmp(pb),
+    });
+    ordered.into_iter().map(|(time, path)| (path, time.map(|(time, _)| time))).collect()
+}
+
+/// Returns the EXIF capture time and sub-second digits of an image, if it has them.
+/// EXIF dates (`2024:05:01 12:30:00`) sort correctly as text.
+fn capture_time(path: &Path) -> Option<(String, String)> {
+    let exif = read_exif(path)?;
+    let ascii = |tag: Tag| match exif.get_field(tag, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Ascii(parts)) => parts.first().map(|p| String::from_utf8_lossy(p).trim().to_string()),
+        _ => None,
+    };
+    let time = ascii(Tag::DateTimeOriginal).filter(|t| !t.is_empty())?;
+    // Sub-second digits are fractions, so "5" must sort after "45": pad them on the right.
+    let subsec = format!("{:0<9}", ascii(Tag::SubSecTimeOriginal).unwrap_or_default());
+    Some((time, subsec))
+}
+
+/// Returns the EXIF capture time of an image in seconds since 1970, read as UTC; only
+/// differences between times matter.
+pub(crate) fn capture_seconds(path: &Path) -> Option<f64> {
+    let (time, subsec) = capture_time(path)?;
+    let mut fields = time.split([':', ' ']).map(|field| field.trim().parse::<i64>().ok());
+    let mut next = || fields.next().flatten();
+    let (year, month, day) = (next()?, next()?, next()?);
+    let (hour, minute, second) = (next()?, next()?, next()?);
+    // Days since 1970-01-01 in the proleptic Gregorian calendar.
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let fraction = format!("0.{}", subsec).parse::<f64>().unwrap_or(0.0);
+    Some((days * 86_400 + hour * 3_600 + minute * 60 + second) as f64 + fraction)
+}
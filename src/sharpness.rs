@@ -0,0 +1,31 @@
+use image::{DynamicImage, GrayImage};
+
+/// Longest side images are scaled down to before their sharpness is measured, so images of
+/// different sizes are scored on the same scale and large files stay quick to score.
+pub(crate) const SHARPNESS_SIDE: u32 = 1024;
+
+/// Scores how in focus an image is, as the variance of the Laplacian of its luma: in-focus
+/// edges give strong, varied responses, whereas blur and camera shake flatten them. Sharp
+/// photos usually score in the hundreds or more, blurry ones in the tens.
+pub fn sharpness(img: &DynamicImage) -> f64 {
+    laplacian_variance(&img.thumbnail(SHARPNESS_SIDE, SHARPNESS_SIDE).to_luma8())
+}
+
+pub(crate) fn laplacian_variance(luma: &GrayImage) -> f64 {
+    let (width, height) = luma.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: u32, y: u32| luma.get_pixel(x, y)[0] as f64;
+    let (mut sum, mut sum_sq, mut count) = (0.0, 0.0, 0.0);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let response = 4.0 * at(x, y) - at(x - 1, y) - at(x + 1, y) - at(x, y - 1) - at(x, y + 1);
+            sum += response;
+            sum_sq += response * response;
+            count += 1.0;
+        }
+    }
+    let mean = sum / count;
+    sum_sq / count - mean * mean
+}
@@ -0,0 +1,216 @@
+use super::adjust::Adjustment;
+use super::processor::WatermarkSpec;
+use anyhow::{Context, Result};
+use image::ImageFormat;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Provenance record written next to an output as `<output>.json`.
+#[derive(Debug, Serialize)]
+pub struct Sidecar {
+    pub source: PathBuf,
+    /// BLAKE3 hash of the source file.
+    pub source_blake3: String,
+    pub output: PathBuf,
+    /// The steps applied to the source, in order.
+    pub operations: Vec<Operation>,
+    /// `None` when the source was copied unchanged.
+    pub encoder: Option<EncoderSettings>,
+    pub timings_ms: Timings,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Copy,
+    /// Rendered from page `page` of a PDF at `dpi`.
+    Rasterize { page: u32, dpi: u32 },
+    /// Decoded page `page` of a multi-page TIFF.
+    Page { page: u32 },
+    /// Started from the file's embedded JPEG preview instead of decoding the full image.
+    EmbeddedPreview { width: u32, height: u32 },
+    /// Turned upright as the EXIF Orientation tag (1-8) asked.
+    Orient { exif: u8 },
+    /// Undid the distortion and color fringing of this lens with its lensfun profile.
+    LensCorrect { lens: String },
+    /// Warped the quadrilateral with these corners, top left first and clockwise, onto an
+    /// upright rectangle of this size.
+    Perspective { corners: [[f32; 2]; 4], width: u32, height: u32 },
+    /// Cropped to the part that isn't fully transparent.
+    Trim { x: u32, y: u32, width: u32, height: u32 },
+    /// Enlarged by a whole factor with a super-resolution model, before any resizing.
+    Upscale { model: PathBuf, factor: u32, width: u32, height: u32 },
+    Resize { width: u32, height: u32, filter: &'static str },
+    /// Scaled to cover this size, then narrowed by removing low-energy seams.
+    SeamCarve { width: u32, height: u32 },
+    /// Resize and center-crop to exactly this size.
+    Fill { width: u32, height: u32, filter: &'static str },
+    /// Resized by stretching only what lies between the `left,top,right,bottom` insets.
+    NineSlice { insets: String, width: u32, height: u32 },
+    /// Scaled by a whole factor such as `3x` or `1/2` with nearest-neighbor sampling, then
+    /// padded to this size.
+    PixelScale { factor: String, width: u32, height: u32 },
+    Adjust(Adjustment),
+    Grayscale,
+    /// Desaturated, banded and framed as a client proof.
+    Proof,
+    /// Composited an image, such as a frame, from `--overlay-dir` on top.
+    Overlay { source: PathBuf, position: String, width: u32, height: u32 },
+    /// Stamped an image such as a logo from `--watermark-image` or a watermark layer; `position`
+    /// is `tile` when it was repeated across the image.
+    ImageWatermark { source: PathBuf, position: String, width: u32, height: u32 },
+    Watermark {
+        text: String,
+        position: String,
+        font_size: u32,
+        color: String,
+        letter_spacing: f32,
+        line_height: f32,
+        align: String,
+        arc: f32,
+        angle: f32,
+        offset: (i32, i32),
+    },
+    /// Reduced to a fixed set of colors.
+    Palette { colors: String, dither: &'static str },
+    SetDpi { dpi: u32 },
+    /// Embedded these classifier labels as XMP keywords.
+    AutoTag { model: PathBuf, tags: Vec<String> },
+}
+
+impl Operation {
+    /// Records a watermark layer with the font size it was drawn at. A tiled layer's position is
+    /// `tile`.
+    pub fn watermark(spec: &WatermarkSpec, font_size: u32) -> Self {
+        Operation::Watermark {
+            text: spec.text.clone(),
+            position: if spec.tile { "tile".to_string() } else { spec.position.to_string() },
+            font_size,
+            color: spec.color.to_string(),
+            letter_spacing: spec.letter_spacing,
+            line_height: spec.line_height,
+            align: spec.align.to_string(),
+            arc: spec.arc,
+            angle: spec.angle,
+            offset: spec.offset,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EncoderSettings {
+    pub format: String,
+    pub quality: u8,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl EncoderSettings {
+    pub fn new(format: ImageFormat, quality: u8, (width, height): (u32, u32)) -> Self {
+        EncoderSettings { format: format.extensions_str()[0].to_string(), quality, width, height }
+    }
+
+    /// Settings for a raw pixel dump, which has no quality to speak of.
+    pub fn raw((width, height): (u32, u32)) -> Self {
+        EncoderSettings { format: "raw".to_string(), quality: 100, width, height }
+    }
+}
+
+/// Wall-clock time spent in each stage, in milliseconds.
+#[derive(Debug, Default, Serialize)]
+pub struct Timings {
+    pub decode: f64,
+    pub process: f64,
+    pub encode: f64,
+    pub total: f64,
+}
+
+impl Timings {
+    pub fn ms(duration: Duration) -> f64 {
+        duration.as_secs_f64() * 1000.0
+    }
+}
+
+/// Returns where the sidecar for an output goes, e.g. `photo.webp.json` for `photo.webp`.
+pub fn sidecar_path(output: &Path) -> PathBuf {
+    let mut name = output.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".json");
+    output.with_file_name(name)
+}
+
+impl Sidecar {
+    pub fn write(&self) -> Result<()> {
+        let path = sidecar_path(&self.output);
+        fs::write(&path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write sidecar {}", path.display()))
+    }
+}
+
+/// The zstd level bundles are written at; the records are small, so the slowest regular level
+/// costs little.
+const ZSTD_LEVEL: i32 = 19;
+
+/// Collects sidecars from a run into one zstd-compressed JSON Lines file, instead of a small
+/// file per output.
+#[derive(Debug, Default)]
+pub struct SidecarBundle {
+    sidecars: Mutex<Vec<Sidecar>>,
+}
+
+impl SidecarBundle {
+    pub fn add(&self, sidecar: Sidecar) {
+        self.sidecars.lock().unwrap_or_else(|e| e.into_inner()).push(sidecar);
+    }
+
+    /// Writes the bundle, one sidecar per line sorted by output. Records from an earlier run
+    /// are kept while their output still exists and was not rewritten by this one, so
+    /// incremental `--sync` runs don't lose them.
+    pub fn write(self, path: &Path) -> Result<()> {
+        let sidecars = self.sidecars.into_inner().unwrap_or_else(|e| e.into_inner());
+        let rewritten: HashSet<&Path> = sidecars.iter().map(|s| s.output.as_path()).collect();
+        let mut records: Vec<(PathBuf, String)> = read_bundle(path)?
+            .into_iter()
+            .filter_map(|record| {
+                let output = PathBuf::from(record.get("output")?.as_str()?);
+                (!rewritten.contains(output.as_path()) && output.exists()).then(|| (output, record.to_string()))
+            })
+            .collect();
+        for sidecar in &sidecars {
+            records.push((sidecar.output.clone(), serde_json::to_string(sidecar)?));
+        }
+        records.sort();
+
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = fs::File::create(path).with_context(|| format!("Failed to write sidecar bundle {}", path.display()))?;
+        let mut zst = zstd::Encoder::new(BufWriter::new(file), ZSTD_LEVEL)?;
+        for (_, line) in records {
+            writeln!(zst, "{}", line)?;
+        }
+        zst.finish()?.flush()?;
+        Ok(())
+    }
+}
+
+/// Reads the records of an existing sidecar bundle; a missing bundle has none.
+pub fn read_bundle(path: &Path) -> Result<Vec<serde_json::Value>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let file = fs::File::open(path)?;
+    let mut records = Vec::new();
+    for line in BufReader::new(zstd::Decoder::new(file)?).lines() {
+        let line = line.with_context(|| format!("Failed to read sidecar bundle {}", path.display()))?;
+        if !line.trim().is_empty() {
+            records.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(records)
+}
@@ -0,0 +1,210 @@
+use super::autoformat;
+use super::cli::{OutputFormat, SsgArgs};
+use super::decode::open_image;
+use super::i18n::Msg;
+use super::processor::{save_image_with_format, EncodeOptions};
+use super::references::{image_references, normalize, resolve};
+use anyhow::{bail, Context, Result};
+use image::{GenericImageView, ImageFormat};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// All generated variants of one source image, keyed in the manifest by the original's site path.
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    pub width: u32,
+    pub height: u32,
+    pub variants: Vec<Variant>,
+    /// Ready-to-use `srcset` attribute values, keyed by format extension.
+    pub srcset: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Variant {
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: String,
+}
+
+/// Processes every image referenced from the content tree and writes the variant manifest.
+pub fn run(args: &SsgArgs) -> Result<()> {
+    let sources = collect_referenced_images(&args.content_dir, &args.static_dir)?;
+    if sources.is_empty() {
+        println!("{}", Msg::NoReferences(&args.content_dir));
+        return Ok(());
+    }
+    println!("{}", Msg::FoundReferenced(sources.len()));
+
+    let url_prefix = match &args.url_prefix {
+        Some(prefix) => prefix.trim_end_matches('/').to_string(),
+        // Outputs under the static dir are served relative to the site root.
+        None => match normalize(&args.output_dir).strip_prefix(normalize(&args.static_dir)) {
+            Ok(rel) => format!("/{}", to_url_path(rel)).trim_end_matches('/').to_string(),
+            Err(_) => bail!("--url-prefix is required when the output directory is outside the static directory"),
+        },
+    };
+
+    let manifest: BTreeMap<String, ManifestEntry> = sources
+        .par_iter()
+        .filter_map(|(site_path, source)| match process_source(source, site_path, &url_prefix, args) {
+            Ok(entry) => Some((site_path.clone(), entry)),
+            Err(e) => {
+                eprintln!("{}", Msg::FailedToProcess { path: source, error: &e.to_string() });
+                None
+            }
+        })
+        .collect();
+
+    write_manifest(&args.manifest, &manifest)?;
+    println!("{}", Msg::WroteManifest { images: manifest.len(), path: &args.manifest });
+    Ok(())
+}
+
+/// Scans Markdown and HTML pages for local image references, mapping each image's site path
+/// (e.g. `/posts/trip/photo.jpg`) to its source file. Absolute references resolve against the
+/// static directory; relative ones against the page's directory inside the content tree.
+pub fn collect_referenced_images(content_dir: &Path, static_dir: &Path) -> Result<BTreeMap<String, PathBuf>> {
+    let mut sources = BTreeMap::new();
+    for entry in walkdir::WalkDir::new(content_dir).into_iter().filter_map(|e| e.ok()) {
+        let page = entry.path();
+        let is_page = page.extension().and_then(|s| s.to_str()).is_some_and(|s| {
+            matches!(s.to_lowercase().as_str(), "md" | "markdown" | "html" | "htm")
+        });
+        if !page.is_file() || !is_page {
+            continue;
+        }
+        let text = fs::read_to_string(page).with_context(|| format!("Failed to read {}", page.display()))?;
+        for reference in image_references(&text) {
+            let Some(source) = resolve(&reference, page, static_dir) else { continue };
+            if !source.is_file() || ImageFormat::from_path(&source).is_err() {
+                continue;
+            }
+            let root = if reference.starts_with('/') { static_dir } else { content_dir };
+            if let Ok(rel) = source.strip_prefix(normalize(root)) {
+                let site_path = format!("/{}", to_url_path(rel));
+                sources.insert(site_path, source);
+            }
+        }
+    }
+    Ok(sources)
+}
+
+fn process_source(source: &Path, site_path: &str, url_prefix: &str, args: &SsgArgs) -> Result<ManifestEntry> {
+    let img = open_image(source)?;
+    let (width, height) = img.dimensions();
+
+    // Never upscale: widths at or above the original collapse into one full-size variant.
+    let mut targets: Vec<u32> = args.widths.iter().map(|&w| w.min(width)).collect();
+    targets.sort_unstable();
+    targets.dedup();
+
+    // `auto` is picked once per source, and a format listed twice is written once.
+    let mut formats: Vec<ImageFormat> = Vec::new();
+    for format_arg in &args.formats {
+        let format = match format_arg {
+            OutputFormat::Auto => autoformat::choose(&img),
+            _ => format_arg.image_format().context("Raw pixel dumps can't be served by a site")?,
+        };
+        if !formats.contains(&format) {
+            formats.push(format);
+        }
+    }
+
+    let rel = Path::new(site_path.trim_start_matches('/'));
+    let stem = rel.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+    let mut variants = Vec::new();
+    for &target_width in &targets {
+        let target_height = ((height as f32 * target_width as f32 / width as f32).round() as u32).max(1);
+        let resized = if target_width == width {
+            img.clone()
+        } else {
+            img.resize_exact(target_width, target_height, image::imageops::FilterType::Lanczos3)
+        };
+        for &format in &formats {
+            let ext = format.extensions_str()[0];
+            let name = format!("{}-{}w.{}", stem, target_width, ext);
+            let variant_rel = rel.with_file_name(&name);
+            let output_path = args.output_dir.join(&variant_rel);
+            save_image_with_format(&resized, &output_path, format, args.quality, EncodeOptions::default())
+                .with_context(|| format!("Failed to save image to {}", output_path.display()))?;
+            variants.push(Variant {
+                url: format!("{}/{}", url_prefix, to_url_path(&variant_rel)),
+                width: target_width,
+                height: resized.height(),
+                format: ext.to_string(),
+            });
+        }
+    }
+    println!("{}", Msg::ProcessedVariants { path: source, variants: variants.len() });
+
+    let mut srcset: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for v in &variants {
+        srcset.entry(v.format.clone()).or_default().push(format!("{} {}w", v.url, v.width));
+    }
+    let srcset = srcset.into_iter().map(|(format, list)| (format, list.join(", "))).collect();
+    let entry = ManifestEntry { width, height, variants, srcset };
+
+    if let Some(dir) = &args.snippets_dir {
+        let path = dir.join(format!("{}.html", rel.display()));
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, picture_html(&entry, &args.sizes))
+            .with_context(|| format!("Failed to write snippet {}", path.display()))?;
+    }
+    Ok(entry)
+}
+
+/// Renders a responsive `<picture>` element for an entry. Formats appear in the order they
+/// were requested: every format but the last becomes a `<source>`, and the last is the
+/// `<img>` fallback. `width`/`height` come from the largest variant to prevent layout shift.
+pub fn picture_html(entry: &ManifestEntry, sizes: &str) -> String {
+    let mut formats: Vec<&str> = Vec::new();
+    for v in &entry.variants {
+        if !formats.contains(&v.format.as_str()) {
+            formats.push(&v.format);
+        }
+    }
+    let Some((fallback, sources)) = formats.split_last() else { return String::new() };
+
+    let mut html = String::from("<picture>\n");
+    for format in sources {
+        let mime = ImageFormat::from_extension(format).map_or("", |f| f.to_mime_type());
+        html.push_str(&format!(
+            "  <source type=\"{}\" srcset=\"{}\" sizes=\"{}\">\n",
+            mime, entry.srcset[*format], sizes
+        ));
+    }
+    let largest = entry.variants.iter().filter(|v| v.format == *fallback).max_by_key(|v| v.width);
+    if let Some(img) = largest {
+        html.push_str(&format!(
+            "  <img src=\"{}\" srcset=\"{}\" sizes=\"{}\" width=\"{}\" height=\"{}\" alt=\"\" loading=\"lazy\" decoding=\"async\">\n",
+            img.url, entry.srcset[*fallback], sizes, img.width, img.height
+        ));
+    }
+    html.push_str("</picture>\n");
+    html
+}
+
+fn write_manifest(path: &Path, manifest: &BTreeMap<String, ManifestEntry>) -> Result<()> {
+    let contents = match path.extension().and_then(|s| s.to_str()) {
+        Some("toml") => toml::to_string_pretty(manifest)?,
+        Some("json") => serde_json::to_string_pretty(manifest)?,
+        _ => bail!("Manifest must have a .json or .toml extension: {}", path.display()),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write manifest {}", path.display()))
+}
+
+fn to_url_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,176 @@
+use super::cli::StitchArgs;
+use super::decode::open_image;
+use super::i18n::Msg;
+use super::is_supported_image;
+use super::processor::{save_image_with_format, EncodeOptions};
+use anyhow::{bail, Context, Result};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, ImageFormat, RgbImage};
+use std::path::PathBuf;
+
+/// Width the coarsest alignment search runs at; finer levels double it up to full size.
+const COARSE_WIDTH: u32 = 128;
+/// Neighbors overlap by at least this share of the left image's width, so a sliver of a few
+/// columns can't match by chance.
+const MIN_OVERLAP: f32 = 0.1;
+/// And by at most this share, or they'd show the same view.
+const MAX_OVERLAP: f32 = 0.9;
+/// How far up or down a neighbor may be shifted, as a share of the height, for a hand-held
+/// sweep that drifts.
+const MAX_DRIFT: f32 = 0.1;
+/// Pixels searched around the doubled estimate at each finer level.
+const REFINE_RADIUS: i32 = 2;
+
+/// Stitches an ordered sequence of overlapping shots into one panorama and saves it.
+pub fn run(args: &StitchArgs) -> Result<()> {
+    let mut paths: Vec<PathBuf> = walkdir::WalkDir::new(&args.input_dir)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| is_supported_image(e.path()))
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    paths.sort();
+    if paths.len() < 2 {
+        bail!("Stitching needs at least two images in {}", args.input_dir.display());
+    }
+    let format = ImageFormat::from_path(&args.output)
+        .with_context(|| format!("Unknown image format for {}", args.output.display()))?;
+
+    let images: Vec<DynamicImage> = paths
+        .iter()
+        .map(|path| open_image(path).with_context(|| format!("Failed to open {}", path.display())))
+        .collect::<Result<_>>()?;
+    let panorama = DynamicImage::ImageRgb8(stitch(&images)?);
+    save_image_with_format(&panorama, &args.output, format, args.quality, EncodeOptions::default())
+        .with_context(|| format!("Failed to save {}", args.output.display()))?;
+    println!(
+        "{}",
+        Msg::Stitched { images: images.len(), path: &args.output, width: panorama.width(), height: panorama.height() }
+    );
+    Ok(())
+}
+
+/// Stitches shots taken left to right, each overlapping the next, into one panorama. Every
+/// shot is scaled to the first one's height, then placed where its overlap with the previous
+/// shot matches best, searched coarse to fine. Only shifts are found, not rotation or
+/// perspective, which suits a steady sweep with a longer lens, or scans of a large page. The
+/// overlaps are cross-faded, and the result is cropped to the rows every shot covers.
+pub fn stitch(images: &[DynamicImage]) -> Result<RgbImage> {
+    let Some(first) = images.first() else { bail!("No images to stitch") };
+    let height = first.height();
+    let shots: Vec<RgbImage> = images
+        .iter()
+        .map(|img| {
+            if img.height() == height {
+                img.to_rgb8()
+            } else {
+                let width = (img.width() as u64 * height as u64 / img.height().max(1) as u64).max(1) as u32;
+                img.resize_exact(width, height, FilterType::Lanczos3).to_rgb8()
+            }
+        })
+        .collect();
+
+    // Where each shot's top-left corner goes, relative to the first.
+    let mut positions = vec![(0i64, 0i64)];
+    for pair in shots.windows(2) {
+        let (dx, dy) = align(&imageops::grayscale(&pair[0]), &imageops::grayscale(&pair[1]))
+            .context("The shots are too small to stitch")?;
+        let (x, y) = positions[positions.len() - 1];
+        positions.push((x + dx as i64, y + dy as i64));
+    }
+    Ok(blend(&shots, &positions))
+}
+
+/// Finds where `right` goes relative to `left` so their overlap matches best. Returns `None`
+/// if they're too small to overlap at all.
+fn align(left: &GrayImage, right: &GrayImage) -> Option<(i32, i32)> {
+    let mut levels = vec![(left.clone(), right.clone())];
+    while levels[levels.len() - 1].0.width() > COARSE_WIDTH {
+        let (a, b) = &levels[levels.len() - 1];
+        let half = |img: &GrayImage| imageops::resize(img, (img.width() / 2).max(1), (img.height() / 2).max(1), FilterType::Triangle);
+        let next = (half(a), half(b));
+        levels.push(next);
+    }
+
+    let (a, b) = levels.pop()?;
+    let (width, height) = (a.width() as f32, a.height() as f32);
+    let drift = (height * MAX_DRIFT).round() as i32;
+    let shifts = (width * (1.0 - MAX_OVERLAP)).floor() as i32..=(width * (1.0 - MIN_OVERLAP)).ceil() as i32;
+    let candidates = shifts.flat_map(|dx| (-drift..=drift).map(move |dy| (dx, dy)));
+    let mut best = best_match(&a, &b, candidates)?;
+    for (a, b) in levels.iter().rev() {
+        let (x, y) = (best.0 * 2, best.1 * 2);
+        let around = (-REFINE_RADIUS..=REFINE_RADIUS)
+            .flat_map(|dx| (-REFINE_RADIUS..=REFINE_RADIUS).map(move |dy| (x + dx, y + dy)));
+        best = best_match(a, b, around)?;
+    }
+    Some(best)
+}
+
+/// Returns the shift whose overlap differs least, ties going to the first.
+fn best_match(a: &GrayImage, b: &GrayImage, shifts: impl Iterator<Item = (i32, i32)>) -> Option<(i32, i32)> {
+    shifts
+        .filter_map(|shift| Some((difference(a, b, shift)?, shift)))
+        .min_by(|x, y| x.0.total_cmp(&y.0))
+        .map(|(_, shift)| shift)
+}
+
+/// Mean absolute difference between `a` and `b` placed at `(dx, dy)` over their overlap,
+/// after taking out each side's mean brightness, so a change in exposure between shots
+/// doesn't hide the match. `None` if they don't overlap.
+fn difference(a: &GrayImage, b: &GrayImage, (dx, dy): (i32, i32)) -> Option<f64> {
+    let x0 = dx.max(0);
+    let x1 = (a.width() as i32).min(dx + b.width() as i32);
+    let y0 = dy.max(0);
+    let y1 = (a.height() as i32).min(dy + b.height() as i32);
+    if x1 - x0 < 2 || y1 - y0 < 2 {
+        return None;
+    }
+    let pairs = || {
+        (y0..y1).flat_map(move |y| {
+            (x0..x1).map(move |x| {
+                (a.get_pixel(x as u32, y as u32)[0] as f64, b.get_pixel((x - dx) as u32, (y - dy) as u32)[0] as f64)
+            })
+        })
+    };
+    let count = ((x1 - x0) * (y1 - y0)) as f64;
+    let (sum_a, sum_b) = pairs().fold((0.0, 0.0), |(sa, sb), (p, q)| (sa + p, sb + q));
+    let offset = (sum_a - sum_b) / count;
+    Some(pairs().map(|(p, q)| (p - q - offset).abs()).sum::<f64>() / count)
+}
+
+/// Paints the shots at their positions, fading each into the next across their overlap, and
+/// crops to the rows all shots cover.
+fn blend(shots: &[RgbImage], positions: &[(i64, i64)]) -> RgbImage {
+    let left = positions.iter().map(|p| p.0).min().unwrap_or(0);
+    let right = shots.iter().zip(positions).map(|(s, p)| p.0 + s.width() as i64).max().unwrap_or(0);
+    let top = positions.iter().map(|p| p.1).max().unwrap_or(0);
+    let bottom = shots.iter().zip(positions).map(|(s, p)| p.1 + s.height() as i64).min().unwrap_or(0);
+    let (width, height) = ((right - left).max(1) as u32, (bottom - top).max(1) as u32);
+
+    let mut sums = vec![[0f32; 4]; width as usize * height as usize];
+    for (i, (shot, &(sx, sy))) in shots.iter().zip(positions).enumerate() {
+        // Fade in over the overlap with the previous shot and out over the next.
+        let fade_in = i.checked_sub(1).map_or(0, |j| positions[j].0 + shots[j].width() as i64 - sx).max(0) as f32;
+        let fade_out = positions.get(i + 1).map_or(0, |next| sx + shot.width() as i64 - next.0).max(0) as f32;
+        for (x, y, pixel) in shot.enumerate_pixels() {
+            let (cx, cy) = (sx + x as i64 - left, sy + y as i64 - top);
+            if cy < 0 || cy >= height as i64 {
+                continue;
+            }
+            let ramp = |distance: f32, span: f32| if span > 0.0 { ((distance + 0.5) / span).min(1.0) } else { 1.0 };
+            let weight = ramp(x as f32, fade_in).min(ramp((shot.width() - 1 - x) as f32, fade_out));
+            let sum = &mut sums[cy as usize * width as usize + cx as usize];
+            for c in 0..3 {
+                sum[c] += pixel[c] as f32 * weight;
+            }
+            sum[3] += weight;
+        }
+    }
+    RgbImage::from_fn(width, height, |x, y| {
+        let sum = sums[y as usize * width as usize + x as usize];
+        let weight = sum[3].max(f32::EPSILON);
+        image::Rgb([0, 1, 2].map(|c| (sum[c] / weight).round().clamp(0.0, 255.0) as u8))
+    })
+}
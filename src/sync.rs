@@ -0,0 +1,187 @@
+use super::cli::{Cli, IfExists, OutputFormat};
+use super::console::ColorChoice;
+use super::context::RunContext;
+use super::discard::remove_file;
+use super::i18n::Msg;
+use super::processor::{output_candidates, output_path_for};
+use super::rawdump::layout_path;
+use super::rules::Pipeline;
+use super::sidecar::sidecar_path;
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the journal file kept in the output directory by `--sync`.
+pub const JOURNAL_FILE: &str = ".imagekit-sync.json";
+
+/// What the previous sync run produced: a fingerprint of the settings used, and for each
+/// source (relative to the input dir) its content hash and output (relative to the output dir).
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Journal {
+    pub settings: String,
+    pub entries: BTreeMap<String, JournalEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub hash: String,
+    pub output: String,
+}
+
+/// The outcome of comparing the input tree against the journal.
+pub struct SyncPlan {
+    /// Inputs that are new or changed and must be processed.
+    pub to_process: Vec<PathBuf>,
+    journal: Journal,
+    hashes: HashMap<PathBuf, String>,
+}
+
+/// Compares inputs with the journal and applies the cheap parts of the sync right away:
+/// outputs of renamed sources are renamed instead of re-encoded, and outputs of deleted
+/// sources are removed. Unchanged sources are left alone. A change in settings invalidates
+/// the whole journal, so everything is re-encoded.
+pub fn plan(cli: &Cli, context: &RunContext, inputs: &[PathBuf]) -> Result<SyncPlan> {
+    let journal_path = cli.output_dir.join(JOURNAL_FILE);
+    let old: Journal = match fs::read_to_string(&journal_path) {
+        Ok(text) => serde_json::from_str(&text).with_context(|| format!("Corrupt sync journal {}", journal_path.display()))?,
+        Err(_) => Journal::default(),
+    };
+    let settings = settings_fingerprint(cli, context);
+    let reuse_outputs = old.settings == settings;
+
+    let hashes: HashMap<PathBuf, String> = inputs
+        .par_iter()
+        .map(|p| Ok((p.clone(), hash_file(p)?)))
+        .collect::<Result<_>>()?;
+
+    let current: HashMap<String, &PathBuf> = inputs.iter().map(|p| (relative_key(p, &cli.input_dir), p)).collect();
+    // Entries whose source is gone are either renames (matched by hash below) or deletions.
+    let mut vanished: HashMap<&str, (&String, &JournalEntry)> = old
+        .entries
+        .iter()
+        .filter(|(key, _)| !current.contains_key(*key))
+        .map(|(key, entry)| (entry.hash.as_str(), (key, entry)))
+        .collect();
+
+    let mut journal = Journal { settings, entries: BTreeMap::new() };
+    let mut to_process = Vec::new();
+    for input in inputs {
+        let key = relative_key(input, &cli.input_dir);
+        let hash = &hashes[input];
+        let pipeline = Pipeline::for_path(cli, input)?;
+        let (expected, _) = output_path_for(input, cli, context, &pipeline)?;
+        // With size buckets or `--output-format auto`, the output may be in several places.
+        let expected_keys: Vec<String> =
+            output_candidates(input, cli, context, &pipeline)?.iter().map(|p| relative_key(p, &cli.output_dir)).collect();
+
+        let unchanged = old.entries.get(&key).filter(|e| {
+            reuse_outputs && &e.hash == hash && expected_keys.contains(&e.output) && cli.output_dir.join(&e.output).exists()
+        });
+        if let Some(entry) = unchanged {
+            journal.entries.insert(key, entry.clone());
+            continue;
+        }
+
+        if let Some((old_key, entry)) = vanished.get(hash.as_str()).copied() {
+            let old_output = cli.output_dir.join(&entry.output);
+            // A renamed output would keep a sidecar naming the old source, so re-encode instead.
+            // So are auto-format outputs, whose extension is only known once decoded.
+            let auto_format = pipeline.output_format == Some(OutputFormat::Auto);
+            if reuse_outputs && !cli.sidecar_json && cli.sidecar_bundle.is_none() && !auto_format && old_output.exists() {
+                // The content is unchanged, so it belongs in the same size bucket as before.
+                let expected = match &cli.bucket_by_size {
+                    Some(buckets) => buckets.place(&cli.output_dir, &expected, fs::metadata(&old_output)?.len()),
+                    None => expected,
+                };
+                let expected_key = relative_key(&expected, &cli.output_dir);
+                if let Some(parent) = expected.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(&old_output, &expected)
+                    .with_context(|| format!("Failed to rename {} to {}", old_output.display(), expected.display()))?;
+                if layout_path(&old_output).exists() {
+                    fs::rename(layout_path(&old_output), layout_path(&expected))?;
+                }
+                println!("{}", Msg::SyncRenamed { from: &entry.output, to: &expected_key, source: old_key });
+                vanished.remove(hash.as_str());
+                journal.entries.insert(key, JournalEntry { hash: hash.clone(), output: expected_key });
+                continue;
+            }
+        }
+        to_process.push(input.clone());
+    }
+
+    for (old_key, entry) in vanished.values() {
+        let output = cli.output_dir.join(&entry.output);
+        if output.exists() {
+            remove_file(&output, cli.use_trash)?;
+            println!("{}", Msg::SyncRemoved { output: &entry.output, source: old_key });
+        }
+        for extra in [sidecar_path(&output), layout_path(&output)] {
+            if extra.exists() {
+                remove_file(&extra, cli.use_trash)?;
+            }
+        }
+    }
+
+    Ok(SyncPlan { to_process, journal, hashes })
+}
+
+impl SyncPlan {
+    /// Records the outputs written this run and saves the journal.
+    pub fn finish(mut self, cli: &Cli, written: &[(PathBuf, PathBuf)]) -> Result<()> {
+        for (source, output) in written {
+            if let Some(hash) = self.hashes.get(source) {
+                self.journal.entries.insert(
+                    relative_key(source, &cli.input_dir),
+                    JournalEntry { hash: hash.clone(), output: relative_key(output, &cli.output_dir) },
+                );
+            }
+        }
+        let path = cli.output_dir.join(JOURNAL_FILE);
+        fs::write(&path, serde_json::to_string_pretty(&self.journal)?)
+            .with_context(|| format!("Failed to write sync journal {}", path.display()))
+    }
+}
+
+/// Hashes a file's contents with BLAKE3, returned as lowercase hex.
+pub fn hash_file(path: &Path) -> Result<String> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(blake3::hash(&data).to_hex().to_string())
+}
+
+/// Fingerprints every option that affects output pixels or naming, and any programmatic
+/// watermark layers. Directories and housekeeping flags are excluded so the same settings match
+/// across machines.
+pub(crate) fn settings_fingerprint(cli: &Cli, context: &RunContext) -> String {
+    let mut settings = cli.clone();
+    settings.input_dir = PathBuf::new();
+    settings.output_dir = PathBuf::new();
+    settings.config = None;
+    settings.prune = false;
+    settings.prune_dry_run = false;
+    settings.sync = false;
+    settings.report = None;
+    settings.baseline = None;
+    settings.skip_up_to_date = false;
+    settings.group_by.clear();
+    settings.emit_geo = false;
+    settings.confirm = false;
+    settings.yes = false;
+    settings.use_trash = false;
+    settings.if_exists = IfExists::Overwrite;
+    settings.lang = None;
+    settings.quiet = false;
+    settings.color = ColorChoice::Auto;
+    settings.order = None;
+    settings.fast_io = false;
+    blake3::hash(format!("{:?} {:?}", settings, context.watermark_layers).as_bytes()).to_hex().to_string()
+}
+
+pub(crate) fn relative_key(path: &Path, root: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
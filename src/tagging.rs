@@ -0,0 +1,19 @@
+use super::classify::{labels, probabilities};
+use anyhow::Result;
+use image::DynamicImage;
+use std::path::Path;
+
+/// Labels `img` with the classifier at `model`, whose class names are in `labels_path`.
+/// Returns up to `max` labels with a probability of at least `min_score`, most likely first.
+/// Classes without a line in the labels file are passed over.
+pub fn auto_tag(img: &DynamicImage, model: &Path, labels_path: &Path, max: usize, min_score: f32) -> Result<Vec<String>> {
+    let labels = labels(labels_path)?;
+    let mut scores: Vec<(usize, f32)> = probabilities(img, model)?.into_iter().enumerate().collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok(scores
+        .into_iter()
+        .take_while(|&(_, score)| score >= min_score)
+        .filter_map(|(class, _)| labels.get(class).filter(|label| !label.is_empty()).cloned())
+        .take(max)
+        .collect())
+}
@@ -0,0 +1,44 @@
+use super::compare::diff;
+use super::decode::open_image;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Set this environment variable to rewrite golden files from the actual images.
+pub const UPDATE_GOLDEN_ENV: &str = "IMAGEKIT_UPDATE_GOLDEN";
+
+/// Asserts that `actual` looks like the `golden` reference image, i.e. that their SSIM is at
+/// least `threshold` (e.g. `0.99`).
+///
+/// A missing golden file, or any golden file when `IMAGEKIT_UPDATE_GOLDEN` is set, is created
+/// from `actual` and the assertion passes. On failure a difference image is written next to
+/// `actual` as `<name>.diff.png` and the panic message points to it.
+#[track_caller]
+pub fn assert_images_similar(actual: impl AsRef<Path>, golden: impl AsRef<Path>, threshold: f64) {
+    let (actual, golden) = (actual.as_ref(), golden.as_ref());
+    if !golden.exists() || env::var_os(UPDATE_GOLDEN_ENV).is_some() {
+        if let Some(parent) = golden.parent() {
+            fs::create_dir_all(parent).expect("failed to create golden file directory");
+        }
+        fs::copy(actual, golden)
+            .unwrap_or_else(|e| panic!("failed to write golden file {}: {}", golden.display(), e));
+        return;
+    }
+
+    let open = |path: &Path| open_image(path).unwrap_or_else(|e| panic!("failed to open {}: {}", path.display(), e));
+    let (visual, stats) = diff(&open(actual), &open(golden), 0)
+        .unwrap_or_else(|e| panic!("cannot compare {} with {}: {}", actual.display(), golden.display(), e));
+    if stats.ssim < threshold {
+        let diff_path = actual.with_extension("diff.png");
+        let saved = visual.save(&diff_path).is_ok();
+        panic!(
+            "{} differs from golden file {}: SSIM {:.6} < {} ({} pixels changed){}",
+            actual.display(),
+            golden.display(),
+            stats.ssim,
+            threshold,
+            stats.differing_pixels,
+            if saved { format!(", see {}", diff_path.display()) } else { String::new() }
+        );
+    }
+}
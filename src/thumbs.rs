@@ -0,0 +1,85 @@
+use super::cli::RawJpegPolicy;
+use super::meta::thumbnail;
+use super::report::read_exif;
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// TIFF-based camera RAW formats, processed through their embedded preview with `--fast-thumbs`.
+const RAW_EXTENSIONS: [&str; 7] = ["dng", "cr2", "nef", "nrw", "arw", "pef", "srw"];
+
+/// Returns whether a path is a file in a camera RAW format `--fast-thumbs` can read.
+pub fn is_raw(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|s| s.to_str())
+            .is_some_and(|s| RAW_EXTENSIONS.contains(&s.to_lowercase().as_str()))
+}
+
+/// Returns the embedded EXIF JPEG preview to use instead of decoding the full image, when it
+/// is at least as large as the requested size and has the image's aspect ratio. RAW files
+/// can only be read through their preview, so for them a missing or too small preview is an
+/// error.
+pub fn fast_thumbnail(path: &Path, width: Option<u32>, height: Option<u32>) -> Result<Option<DynamicImage>> {
+    let raw = is_raw(path);
+    let preview = read_exif(path)
+        .and_then(|exif| thumbnail(&exif).map(<[u8]>::to_vec))
+        .and_then(|data| image::load_from_memory_with_format(&data, ImageFormat::Jpeg).ok());
+    let Some(preview) = preview else {
+        if raw {
+            bail!("{} has no embedded preview", path.display());
+        }
+        return Ok(None);
+    };
+
+    let (pw, ph) = preview.dimensions();
+    let covers = width.is_none_or(|w| pw >= w) && height.is_none_or(|h| ph >= h);
+    if raw {
+        if !covers {
+            bail!("The embedded preview of {} is only {}x{}", path.display(), pw, ph);
+        }
+        return Ok(Some(preview));
+    }
+    if width.is_none() && height.is_none() || !covers {
+        return Ok(None);
+    }
+    // Some cameras letterbox the preview; only its header is read to compare the shapes.
+    let (iw, ih) = image::image_dimensions(path).context("Failed to read image dimensions")?;
+    let aspect = |w: u32, h: u32| w as f64 / h.max(1) as f64;
+    if (aspect(pw, ph) / aspect(iw, ih) - 1.0).abs() > 0.01 {
+        return Ok(None);
+    }
+    Ok(Some(preview))
+}
+
+/// Applies `--raw-jpeg-policy` to the RAW files among `inputs` that have a JPEG of the same
+/// name next to them, as cameras shooting RAW+JPEG write. Returns the inputs to process and,
+/// with `both`, the RAW files whose outputs need a `-raw` suffix to stay apart from their JPEG's.
+pub fn resolve_raw_pairs(policy: RawJpegPolicy, inputs: Vec<PathBuf>) -> (Vec<PathBuf>, BTreeSet<PathBuf>) {
+    let pair_key = |path: &Path| {
+        let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+        Some((path.parent()?.to_path_buf(), stem))
+    };
+    let is_jpeg = |path: &Path| {
+        path.extension().and_then(|s| s.to_str()).is_some_and(|s| matches!(s.to_lowercase().as_str(), "jpg" | "jpeg"))
+    };
+    let jpegs: HashMap<_, &PathBuf> = inputs.iter().filter(|p| is_jpeg(p)).filter_map(|p| Some((pair_key(p)?, p))).collect();
+    let twins: HashMap<&PathBuf, &PathBuf> = inputs
+        .iter()
+        .filter(|p| is_raw(p))
+        .filter_map(|raw| Some((raw, *jpegs.get(&pair_key(raw)?)?)))
+        .collect();
+
+    let skipped: BTreeSet<PathBuf> = match policy {
+        RawJpegPolicy::PreferRaw => twins.values().map(|jpeg| jpeg.to_path_buf()).collect(),
+        RawJpegPolicy::PreferJpeg => twins.keys().map(|raw| raw.to_path_buf()).collect(),
+        RawJpegPolicy::Both => BTreeSet::new(),
+    };
+    let suffixed = match policy {
+        RawJpegPolicy::Both => twins.keys().map(|raw| raw.to_path_buf()).collect(),
+        _ => BTreeSet::new(),
+    };
+    (inputs.into_iter().filter(|p| !skipped.contains(p)).collect(), suffixed)
+}
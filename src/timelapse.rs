@@ -0,0 +1,154 @@
+use super::assets::load_fonts;
+use super::cli::{OutputFormat, TimelapseArgs};
+use super::decode::open_image;
+use super::i18n::Msg;
+use super::is_supported_image;
+use super::processor::{draw_watermark, save_image_with_format, EncodeOptions, WatermarkSpec};
+use super::sequence::capture_order;
+use anyhow::{bail, Context, Result};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Longest side frames are scaled down to before their brightness is measured.
+const MEASURE_SIDE: u32 = 256;
+
+/// Writes the input directory's frames, in capture order, as a numbered sequence, optionally
+/// deflickered, stamped with their capture time and encoded into a video.
+pub fn run(args: &TimelapseArgs) -> Result<()> {
+    if args.video.is_some() && !cfg!(feature = "ffmpeg") {
+        bail!("imagekit was built without the `ffmpeg` feature");
+    }
+    let format = match (&args.frame_format, args.frame_format.image_format()) {
+        (OutputFormat::Auto, _) | (_, None) => bail!("Frames need one image format, not {:?}", args.frame_format),
+        (_, Some(format)) => format,
+    };
+    let paths: Vec<PathBuf> = walkdir::WalkDir::new(&args.input_dir)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| is_supported_image(e.path()))
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    if paths.len() < 2 {
+        bail!("A time-lapse needs at least two frames in {}", args.input_dir.display());
+    }
+    let frames = capture_order(&paths);
+    let untimed = frames.iter().filter(|(_, time)| time.is_none()).count();
+    if untimed > 0 {
+        println!("{}", Msg::UntimedFrames(untimed));
+    }
+
+    let gains = if args.deflicker {
+        let levels: Vec<f64> = frames.par_iter().map(|(path, _)| brightness(path)).collect::<Result<_>>()?;
+        deflicker_gains(&levels, args.deflicker_window as usize)
+    } else {
+        vec![1.0; frames.len()]
+    };
+    let fonts = if args.stamp { load_fonts()? } else { Vec::new() };
+    fs::create_dir_all(&args.output_dir)
+        .with_context(|| format!("Failed to create {}", args.output_dir.display()))?;
+    let extension = format.extensions_str()[0];
+    frames.par_iter().zip(&gains).enumerate().try_for_each(|(index, ((path, time), &gain))| -> Result<()> {
+        let mut frame = open_image(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        if let Some(width) = args.width {
+            let height = (frame.height() as u64 * width as u64 / frame.width().max(1) as u64).max(1) as u32;
+            frame = frame.resize_exact(width, height, FilterType::Lanczos3);
+        }
+        if gain != 1.0 {
+            frame = brighten(&frame, gain);
+        }
+        if let (true, Some(time)) = (args.stamp, time) {
+            let spec = WatermarkSpec {
+                text: stamp_text(time),
+                position: args.stamp_position,
+                relative_size: Some(args.stamp_size),
+                color: args.stamp_color,
+                ..WatermarkSpec::default()
+            };
+            draw_watermark(&mut frame, &spec, &fonts);
+        }
+        let output = args.output_dir.join(format!("frame_{:05}.{}", index + 1, extension));
+        save_image_with_format(&frame, &output, format, args.quality, EncodeOptions::default())
+            .with_context(|| format!("Failed to save {}", output.display()))
+    })?;
+    println!("{}", Msg::WroteFrames { frames: frames.len(), dir: &args.output_dir });
+
+    if let Some(video) = &args.video {
+        encode::video(&args.output_dir, extension, args.fps, video)?;
+        println!("{}", Msg::EncodedVideo { path: video, fps: args.fps });
+    }
+    Ok(())
+}
+
+/// Mean luma of a frame, from a downscaled copy.
+fn brightness(path: &Path) -> Result<f64> {
+    let frame = open_image(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let luma = frame.thumbnail(MEASURE_SIDE, MEASURE_SIDE).to_luma8();
+    Ok(luma.pixels().map(|p| p[0] as f64).sum::<f64>() / (luma.width() * luma.height()).max(1) as f64)
+}
+
+/// The factor each frame's brightness is multiplied by so it matches the average of the
+/// `window` frames around it. Slow changes, such as a sunset, survive; frame-to-frame jumps
+/// from the camera's exposure steps are evened out.
+fn deflicker_gains(levels: &[f64], window: usize) -> Vec<f64> {
+    let half = window / 2;
+    (0..levels.len())
+        .map(|i| {
+            let around = &levels[i.saturating_sub(half)..(i + half + 1).min(levels.len())];
+            let target = around.iter().sum::<f64>() / around.len() as f64;
+            if levels[i] > 0.0 { target / levels[i] } else { 1.0 }
+        })
+        .collect()
+}
+
+/// Multiplies the color channels by `gain`, leaving alpha alone.
+fn brighten(frame: &DynamicImage, gain: f64) -> DynamicImage {
+    let mut rgba = frame.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for channel in &mut pixel.0[..3] {
+            *channel = (*channel as f64 * gain).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    let brightened = DynamicImage::ImageRgba8(rgba);
+    if frame.color().has_alpha() { brightened } else { DynamicImage::ImageRgb8(brightened.to_rgb8()) }
+}
+
+/// Turns an EXIF date, `2024:05:01 12:30:00`, into `2024-05-01 12:30:00`.
+fn stamp_text(time: &str) -> String {
+    time.replacen(':', "-", 2)
+}
+
+#[cfg(feature = "ffmpeg")]
+mod encode {
+    use anyhow::{bail, Context, Result};
+    use std::path::Path;
+    use std::process::Command;
+
+    /// Encodes the numbered frames in `dir` into an H.264 video with the `ffmpeg` on the PATH.
+    /// Odd sizes are rounded down to even, as the YUV 4:2:0 that players expect needs.
+    pub fn video(dir: &Path, extension: &str, fps: u32, output: &Path) -> Result<()> {
+        let status = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-framerate", &fps.to_string(), "-i"])
+            .arg(dir.join(format!("frame_%05d.{}", extension)))
+            .args(["-vf", "scale=trunc(iw/2)*2:trunc(ih/2)*2", "-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .arg(output)
+            .status()
+            .context("Failed to run ffmpeg; is it installed and on the PATH?")?;
+        if !status.success() {
+            bail!("ffmpeg failed with {}", status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "ffmpeg"))]
+mod encode {
+    use std::path::Path;
+
+    pub fn video(_: &Path, _: &str, _: u32, _: &Path) -> anyhow::Result<()> {
+        anyhow::bail!("imagekit was built without the `ffmpeg` feature")
+    }
+}
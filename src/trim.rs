@@ -0,0 +1,22 @@
+use image::{DynamicImage, GenericImageView};
+
+/// Returns the smallest rectangle, as `(x, y, width, height)`, holding every pixel that isn't
+/// fully transparent. `None` when there is nothing to trim: the image has no alpha channel,
+/// no transparent border, or no visible pixels at all.
+pub fn content_bounds(img: &DynamicImage) -> Option<(u32, u32, u32, u32)> {
+    if !img.color().has_alpha() {
+        return None;
+    }
+    let (width, height) = img.dimensions();
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in img.pixels() {
+        if pixel[3] != 0 {
+            (min_x, min_y, max_x, max_y) = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y));
+        }
+    }
+    if min_x == u32::MAX {
+        return None;
+    }
+    let bounds = (min_x, min_y, max_x - min_x + 1, max_y - min_y + 1);
+    (bounds != (0, 0, width, height)).then_some(bounds)
+}
@@ -0,0 +1,125 @@
+use super::errors::ParseUpscaleError;
+use anyhow::Result;
+use image::DynamicImage;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A super-resolution pass for enlarging small images, written `ai:<factor>x`, e.g. `ai:4x`.
+/// The factor must be the one the `--upscale-model` was trained for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Upscale {
+    pub factor: u32,
+}
+
+impl FromStr for Upscale {
+    type Err = ParseUpscaleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseUpscaleError(s.to_string());
+        let (backend, factor) = s.trim().split_once(':').ok_or_else(err)?;
+        if !backend.eq_ignore_ascii_case("ai") {
+            return Err(err());
+        }
+        let factor = factor.strip_suffix(['x', 'X']).unwrap_or(factor);
+        match factor.parse() {
+            Ok(factor @ 2..=8) => Ok(Upscale { factor }),
+            _ => Err(err()),
+        }
+    }
+}
+
+impl fmt::Display for Upscale {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ai:{}x", self.factor)
+    }
+}
+
+/// Enlarges `img` by `factor` with the ONNX model at `model`, which takes an RGB image as a
+/// `1x3xHxW` tensor of values in 0..1 and returns one `factor` times as large. Models only see
+/// color, so any alpha channel is scaled with Lanczos alongside.
+pub fn upscale(img: &DynamicImage, factor: u32, model: &Path) -> Result<DynamicImage> {
+    onnx::upscale(img, factor, model)
+}
+
+#[cfg(feature = "upscale-ai")]
+mod onnx {
+    use anyhow::{bail, Context, Result};
+    use image::imageops::{self, FilterType};
+    use image::{DynamicImage, Rgb, RgbImage};
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex, OnceLock};
+    use tract_onnx::prelude::*;
+
+    /// Parsed models by path, so each file is read once however many images use it.
+    static MODELS: OnceLock<Mutex<HashMap<PathBuf, Arc<InferenceModel>>>> = OnceLock::new();
+
+    fn load(path: &Path) -> Result<Arc<InferenceModel>> {
+        let mut models = MODELS.get_or_init(Default::default).lock().unwrap();
+        if let Some(model) = models.get(path) {
+            return Ok(Arc::clone(model));
+        }
+        let model = tract_onnx::onnx()
+            .model_for_path(path)
+            .with_context(|| format!("Failed to load upscaling model {}", path.display()))?;
+        let model = Arc::new(model);
+        models.insert(path.to_path_buf(), Arc::clone(&model));
+        Ok(model)
+    }
+
+    pub fn upscale(img: &DynamicImage, factor: u32, path: &Path) -> Result<DynamicImage> {
+        let rgb = img.to_rgb8();
+        let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+        // The plan is specialized to each image's size, which lets tract optimize it fully.
+        // Exported models often name their output dimensions, so the output shape is inferred.
+        let plan = (*load(path)?)
+            .clone()
+            .with_input_fact(0, InferenceFact::dt_shape(f32::datum_type(), tvec!(1, 3, height, width)))?
+            .with_output_fact(0, InferenceFact::default())?
+            .into_optimized()?
+            .into_runnable()?;
+        let input: Tensor = tract_ndarray::Array4::from_shape_fn((1, 3, height, width), |(_, c, y, x)| {
+            rgb.get_pixel(x as u32, y as u32)[c] as f32 / 255.0
+        })
+        .into();
+        let outputs = plan.run(tvec!(input.into()))?;
+        let output = outputs[0].to_array_view::<f32>()?;
+
+        let (out_width, out_height) = (rgb.width() * factor, rgb.height() * factor);
+        if output.shape() != [1, 3, out_height as usize, out_width as usize] {
+            bail!(
+                "{} turned a {}x{} image into a {:?} tensor rather than {}x{}; check that --upscale matches the model's factor",
+                path.display(),
+                width,
+                height,
+                output.shape(),
+                out_width,
+                out_height
+            );
+        }
+        let scaled = RgbImage::from_fn(out_width, out_height, |x, y| {
+            let channel = |c| (output[[0, c, y as usize, x as usize]] * 255.0).round().clamp(0.0, 255.0) as u8;
+            Rgb([channel(0), channel(1), channel(2)])
+        });
+        if !img.color().has_alpha() {
+            return Ok(DynamicImage::ImageRgb8(scaled));
+        }
+        let alpha = imageops::resize(&img.to_rgba8(), out_width, out_height, FilterType::Lanczos3);
+        let mut rgba = DynamicImage::ImageRgb8(scaled).into_rgba8();
+        for (pixel, source) in rgba.pixels_mut().zip(alpha.pixels()) {
+            pixel[3] = source[3];
+        }
+        Ok(DynamicImage::ImageRgba8(rgba))
+    }
+}
+
+#[cfg(not(feature = "upscale-ai"))]
+mod onnx {
+    use image::DynamicImage;
+    use std::path::Path;
+
+    pub fn upscale(_: &DynamicImage, _: u32, _: &Path) -> anyhow::Result<DynamicImage> {
+        anyhow::bail!("imagekit was built without the `upscale-ai` feature")
+    }
+}
@@ -0,0 +1,60 @@
+use super::cli::{Cli, OutputFormat};
+use super::context::RunContext;
+use super::processor::{output_candidates, planned_outputs};
+use super::rules::Pipeline;
+use anyhow::Result;
+use rayon::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Inputs split by `--skip-up-to-date`.
+pub struct UpToDateSplit {
+    /// Inputs that are new, or newer than one of their outputs.
+    pub to_process: Vec<PathBuf>,
+    /// Inputs whose outputs are all newer than they are, with one of those outputs.
+    pub up_to_date: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Splits inputs by comparing their modification times with their outputs'. Only times are
+/// compared, so changed settings go unnoticed; `--sync` and `--baseline` catch those.
+pub fn split(cli: &Cli, context: &RunContext, inputs: Vec<PathBuf>) -> Result<UpToDateSplit> {
+    let decisions: Vec<(PathBuf, Option<PathBuf>)> = inputs
+        .into_par_iter()
+        .map(|input| {
+            let output = up_to_date_output(cli, context, &input)?;
+            Ok((input, output))
+        })
+        .collect::<Result<_>>()?;
+
+    let mut split = UpToDateSplit { to_process: Vec::new(), up_to_date: Vec::new() };
+    for (input, output) in decisions {
+        match output {
+            Some(output) => split.up_to_date.push((input, output)),
+            None => split.to_process.push(input),
+        }
+    }
+    Ok(split)
+}
+
+/// Returns an output of `input` if every output it has is at least as new as it is. With
+/// `--widths`, each width must have been written.
+fn up_to_date_output(cli: &Cli, context: &RunContext, input: &Path) -> Result<Option<PathBuf>> {
+    let Some(source) = modified(input) else { return Ok(None) };
+    let pipeline = Pipeline::for_path(cli, input)?;
+    let existing: Vec<PathBuf> = output_candidates(input, cli, context, &pipeline)?.into_iter().filter(|p| p.exists()).collect();
+    if existing.iter().any(|output| modified(output).is_none_or(|time| time < source)) {
+        return Ok(None);
+    }
+    // With `--output-format auto` an output exists under either extension, which the
+    // candidates above already allow for.
+    let auto_format = pipeline.output_format == Some(OutputFormat::Auto);
+    if !cli.widths.is_empty() && !auto_format && !planned_outputs(input, cli, context, &pipeline)?.iter().all(|p| p.exists()) {
+        return Ok(None);
+    }
+    Ok(existing.into_iter().next())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,178 @@
+use super::cli::{Cli, WorkerArgs};
+use super::config::table_args;
+use anyhow::{Context, Result};
+use clap::parser::ValueSource;
+use clap::{CommandFactory, Parser};
+use serde::Deserialize;
+use serde_json::Value;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// One job popped from the queue, as JSON: `{"id": 7, "input": "uploads/a.jpg", "output":
+/// "web/", "options": {"width": 1200, "watermark-text": "© ACME"}}`.
+#[derive(Debug, Deserialize)]
+pub struct Job {
+    /// Echoed in the result so the producer can match it up; any JSON value.
+    #[serde(default)]
+    pub id: Value,
+    /// An image, or a directory processed like a batch run's input directory.
+    pub input: PathBuf,
+    /// The output directory.
+    pub output: PathBuf,
+    /// Batch options keyed by long option name, as in a `--config` file.
+    #[serde(default)]
+    pub options: serde_json::Map<String, Value>,
+}
+
+/// Takes jobs from the Redis list `--jobs-key`, blocking while it is empty, processes each with
+/// the batch pipeline and pushes a JSON result to `--results-key`: the job's `id`, `status`
+/// `ok` with the run's report, or `error` with the message. Runs until `--max-jobs` jobs are done.
+///
+/// A job is moved onto this worker's processing list while it runs and only dropped from it
+/// once its result is pushed, so one a crash interrupts is requeued when the worker restarts.
+pub fn run(args: &WorkerArgs) -> Result<()> {
+    queue::run(args)
+}
+
+/// Builds the batch settings for a job: its own options, then the worker's defaults for the
+/// options it leaves out.
+pub fn job_cli(job: &Job, input_dir: &Path, defaults: &toml::Table) -> Result<Cli> {
+    let options = toml::Table::try_from(&job.options).context("Job options must be strings, numbers, booleans or arrays")?;
+    let mut args: Vec<OsString> = vec!["imagekit".into(), "-i".into(), input_dir.into(), "-o".into(), job.output.clone().into()];
+    args.extend(table_args(&options, |_| false, "the job's options")?);
+    let given = Cli::command().ignore_errors(true).get_matches_from(&args);
+    let from_defaults =
+        table_args(defaults, |id| given.value_source(id) == Some(ValueSource::CommandLine), "the worker's defaults")?;
+    args.extend(from_defaults);
+    Ok(Cli::try_parse_from(args)?)
+}
+
+#[cfg(feature = "worker")]
+mod queue {
+    use super::{job_cli, Job};
+    use crate::cli::WorkerArgs;
+    use crate::context::RunContext;
+    use crate::i18n::Msg;
+    use anyhow::{Context, Result};
+    use redis::{Commands, Connection, Direction};
+    use serde_json::{json, Value};
+    use std::path::{Path, PathBuf};
+    use std::{env, fs};
+
+    pub fn run(args: &WorkerArgs) -> Result<()> {
+        let defaults = match &args.defaults {
+            Some(path) => {
+                let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+                text.parse().with_context(|| format!("Failed to parse {}", path.display()))?
+            }
+            None => toml::Table::new(),
+        };
+        let worker_id = args.worker_id.clone().unwrap_or_else(host_name);
+        let processing = format!("{}:processing:{}", args.jobs_key, worker_id);
+        let mut redis = connect(&args.queue)?;
+        let requeued = requeue(&mut redis, &processing, &args.jobs_key)?;
+        if requeued > 0 {
+            println!("{}", Msg::Requeued { jobs: requeued, list: &processing });
+        }
+        println!("{}", Msg::WaitingForJobs(&args.jobs_key));
+        let mut done = 0;
+        while args.max_jobs.is_none_or(|max| done < max) {
+            let raw = take(&mut redis, &args.jobs_key, &processing)?;
+            let result = match serde_json::from_slice::<Job>(&raw) {
+                Ok(job) => match run_job(&job, &defaults) {
+                    Ok(report) => json!({ "id": job.id, "status": "ok", "report": report }),
+                    Err(e) => {
+                        eprintln!("{}", Msg::JobFailed { id: &job.id.to_string(), error: &format!("{:#}", e) });
+                        json!({ "id": job.id, "status": "error", "error": format!("{:#}", e) })
+                    }
+                },
+                Err(e) => json!({ "id": Value::Null, "status": "error", "error": format!("Invalid job: {}", e) }),
+            };
+            let _: usize = redis.rpush(&args.results_key, result.to_string())?;
+            let _: usize = redis.lrem(&processing, 1, &raw)?;
+            done += 1;
+        }
+        Ok(())
+    }
+
+    /// Connects to `redis://[[user]:password@]host[:port][/db]`.
+    fn connect(url: &str) -> Result<Connection> {
+        let client = redis::Client::open(url).with_context(|| format!("Queue '{}' is not a redis:// URL", url))?;
+        client.get_connection().with_context(|| format!("Failed to connect to Redis at {}", url))
+    }
+
+    /// Moves the first entry of the list `from` onto the end of `to` and returns it, waiting
+    /// for one to arrive.
+    fn take(redis: &mut Connection, from: &str, to: &str) -> Result<Vec<u8>> {
+        loop {
+            if let Some(value) = redis.blmove(from, to, Direction::Left, Direction::Right, 0.0)? {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Moves every entry of the list `from` back onto the front of `to`, keeping their order,
+    /// and returns how many there were.
+    fn requeue(redis: &mut Connection, from: &str, to: &str) -> Result<usize> {
+        let mut count = 0;
+        while redis.lmove::<_, _, Option<Vec<u8>>>(from, to, Direction::Right, Direction::Left)?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// The default worker id: the machine's host name.
+    fn host_name() -> String {
+        env::var("HOSTNAME")
+            .ok()
+            .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "worker".to_string())
+    }
+
+    /// Runs one job and returns its report. A single image is staged on its own first, so the
+    /// run doesn't pick up the files next to it. Fails if any image of the job fails.
+    fn run_job(job: &Job, defaults: &toml::Table) -> Result<Value> {
+        let scratch = tempfile::Builder::new()
+            .prefix("imagekit-worker-")
+            .tempdir()
+            .context("Failed to create a scratch directory")?;
+        let result = stage(job, scratch.path()).and_then(|input_dir| {
+            let mut cli = job_cli(job, &input_dir, defaults)?;
+            let report = cli.report.get_or_insert_with(|| scratch.path().join("report.json")).clone();
+            let outcomes = crate::run_with(cli, RunContext::default())?;
+            crate::ensure_no_failures(&outcomes)?;
+            let text = fs::read_to_string(&report).with_context(|| format!("Failed to read {}", report.display()))?;
+            Ok(serde_json::from_str(&text)?)
+        });
+        scratch.close().context("Failed to remove the scratch directory")?;
+        result
+    }
+
+    /// Returns the input directory for a job, staging a single image under `scratch`.
+    fn stage(job: &Job, scratch: &Path) -> Result<PathBuf> {
+        if job.input.is_dir() {
+            return Ok(job.input.clone());
+        }
+        let name = job.input.file_name().with_context(|| format!("Input {} is not a file", job.input.display()))?;
+        let input_dir = scratch.join("input");
+        fs::create_dir_all(&input_dir)?;
+        let staged = input_dir.join(name);
+        // A hard link is instant; across file systems the image is copied.
+        if fs::hard_link(&job.input, &staged).is_err() {
+            fs::copy(&job.input, &staged).with_context(|| format!("Failed to read {}", job.input.display()))?;
+        }
+        Ok(input_dir)
+    }
+}
+
+#[cfg(not(feature = "worker"))]
+mod queue {
+    use crate::cli::WorkerArgs;
+    use anyhow::{bail, Result};
+
+    pub fn run(_: &WorkerArgs) -> Result<()> {
+        bail!("imagekit was built without the `worker` feature")
+    }
+}
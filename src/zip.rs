@@ -0,0 +1,125 @@
+use ::zip::read::ZipArchive;
+use ::zip::write::SimpleFileOptions;
+use ::zip::{CompressionMethod, DateTime};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{BufWriter, Cursor, Read, Seek, Write};
+use std::path::Path;
+
+/// How an entry's data is stored.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// As is; right for JPEG, PNG and WebP data, which don't compress further.
+    Stored,
+    Deflated,
+}
+
+impl Method {
+    fn compression(self) -> CompressionMethod {
+        match self {
+            Method::Stored => CompressionMethod::Stored,
+            Method::Deflated => CompressionMethod::Deflated,
+        }
+    }
+}
+
+/// Largest entry [`read_entries`] decompresses, well above any page image.
+const MAX_ENTRY_SIZE: u64 = 1 << 30;
+/// Largest total [`read_entries`] decompresses from one archive, which it holds in memory.
+const MAX_TOTAL_SIZE: u64 = 4 << 30;
+
+/// Writes a ZIP archive entry by entry, switching to the ZIP64 extensions for entries and
+/// archives that need them.
+pub struct ZipWriter<W: Write + Seek> {
+    zip: ::zip::ZipWriter<W>,
+}
+
+impl<W: Write + Seek> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        ZipWriter { zip: ::zip::ZipWriter::new(out) }
+    }
+
+    pub fn add(&mut self, name: &str, data: &[u8], method: Method) -> Result<()> {
+        // Entries are dated 1980-01-01, the earliest DOS date, so archives of the same files are
+        // byte-for-byte identical.
+        let options = SimpleFileOptions::default()
+            .compression_method(method.compression())
+            .compression_level((method == Method::Deflated).then_some(9))
+            .last_modified_time(DateTime::default())
+            .large_file(data.len() as u64 >= u32::MAX as u64);
+        self.zip.start_file(name, options).with_context(|| format!("Failed to add {} to the archive", name))?;
+        self.zip.write_all(data)?;
+        Ok(())
+    }
+
+    /// Writes the central directory and returns the underlying writer.
+    pub fn finish(self) -> Result<W> {
+        let mut out = self.zip.finish()?;
+        out.flush()?;
+        Ok(out)
+    }
+}
+
+/// Packs files into a new ZIP archive, stored uncompressed since images don't compress further.
+/// Entries are named by their path under `root` and sorted, so the same files always give the
+/// same archive.
+pub fn pack(path: &Path, root: &Path, files: &[&Path]) -> Result<()> {
+    let mut entries: Vec<(String, &Path)> = files
+        .iter()
+        .map(|file| {
+            let relative = file.strip_prefix(root).unwrap_or(file);
+            let name = relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+            (name, *file)
+        })
+        .collect();
+    entries.sort();
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let file = fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    for (name, file) in entries {
+        zip.add(&name, &fs::read(file)?, Method::Stored)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+
+/// An entry read from a ZIP archive, decompressed.
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String,
+    /// How the entry was stored, so it can be written back the same way.
+    pub method: Method,
+    pub data: Vec<u8>,
+}
+
+/// Reads every entry of a ZIP archive in the order of its central directory, which is the
+/// order they were written in. Encrypted entries and ones compressed other than by deflate are
+/// rejected, as are entries over 1 GiB and archives holding over 4 GiB in all, whatever sizes
+/// their headers claim.
+pub fn read_entries(bytes: &[u8]) -> Result<Vec<ZipEntry>> {
+    let mut archive = ZipArchive::new(Cursor::new(bytes)).context("Not a ZIP archive")?;
+    let mut entries = Vec::with_capacity(archive.len());
+    let mut total = 0;
+    for index in 0..archive.len() {
+        let file = archive.by_index(index).with_context(|| format!("Failed to read entry {} of the archive", index))?;
+        let name = file.name().to_string();
+        let method = match file.compression() {
+            CompressionMethod::Stored => Method::Stored,
+            CompressionMethod::Deflated => Method::Deflated,
+            _ => bail!("{} uses an unsupported compression method", name),
+        };
+        let limit = MAX_ENTRY_SIZE.min(MAX_TOTAL_SIZE - total);
+        // Not preallocated from the header's size, which may lie. Reading to the end checks the
+        // entry's CRC.
+        let mut data = Vec::new();
+        file.take(limit + 1).read_to_end(&mut data).with_context(|| format!("{} is corrupt", name))?;
+        if data.len() as u64 > limit {
+            bail!("{} is too large to read", name);
+        }
+        total += data.len() as u64;
+        entries.push(ZipEntry { name, method, data });
+    }
+    Ok(entries)
+}
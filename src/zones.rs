@@ -0,0 +1,89 @@
+use super::errors::ParseZoneError;
+use anyhow::{Context, Result};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A rectangle watermarks must not cover, such as a product label, written `x,y,wxh`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Zone {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FromStr for Zone {
+    type Err = ParseZoneError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || ParseZoneError(s.to_string());
+        let mut parts = s.split(',').map(str::trim);
+        let (Some(x), Some(y), Some(size), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+            return Err(err());
+        };
+        let (width, height) = size.split_once(['x', 'X']).ok_or_else(err)?;
+        let zone = Zone {
+            x: x.parse().map_err(|_| err())?,
+            y: y.parse().map_err(|_| err())?,
+            width: width.trim().parse().map_err(|_| err())?,
+            height: height.trim().parse().map_err(|_| err())?,
+        };
+        if zone.width == 0 || zone.height == 0 {
+            return Err(err());
+        }
+        Ok(zone)
+    }
+}
+
+impl std::fmt::Display for Zone {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{},{},{}x{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl Zone {
+    /// Maps the zone from an image of size `from` onto the same image resized to `to`,
+    /// growing it to whole pixels so it never shrinks away.
+    pub fn scaled(&self, from: (u32, u32), to: (u32, u32)) -> Zone {
+        if from == to || from.0 == 0 || from.1 == 0 {
+            return *self;
+        }
+        let (sx, sy) = (to.0 as f64 / from.0 as f64, to.1 as f64 / from.1 as f64);
+        let (x, y) = ((self.x as f64 * sx).floor(), (self.y as f64 * sy).floor());
+        let right = ((self.x + self.width) as f64 * sx).ceil();
+        let bottom = ((self.y + self.height) as f64 * sy).ceil();
+        Zone { x: x as u32, y: y as u32, width: (right - x).max(1.0) as u32, height: (bottom - y).max(1.0) as u32 }
+    }
+
+    /// Returns the area, in pixels, that this zone shares with the rectangle at `(x, y)`.
+    pub fn overlap(&self, (x, y): (u32, u32), (width, height): (u32, u32)) -> u64 {
+        let span = |a: u32, a_len: u32, b: u32, b_len: u32| {
+            (a as u64 + a_len as u64).min(b as u64 + b_len as u64).saturating_sub(a.max(b) as u64)
+        };
+        span(self.x, self.width, x, width) * span(self.y, self.height, y, height)
+    }
+}
+
+/// Returns where per-image zones are listed, e.g. `photo.jpg.avoid` for `photo.jpg`.
+pub fn sidecar_path(source: &Path) -> PathBuf {
+    let mut name = source.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".avoid");
+    source.with_file_name(name)
+}
+
+/// Reads the zones listed next to a source image, one `x,y,wxh` per line; blank lines and
+/// lines starting with `#` are ignored. An image without the file has no zones of its own.
+pub fn read_sidecar(source: &Path) -> Result<Vec<Zone>> {
+    let path = sidecar_path(source);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.parse::<Zone>().with_context(|| format!("In {}", path.display())))
+        .collect()
+}
@@ -1,14 +1,17 @@
 use anyhow::{Context, Result};
+use clap::Parser;
 use image::{DynamicImage, GenericImageView, Rgba};
 use tempfile::tempdir;
 use std::fs;
+use std::path::Path;
 
 // Import public items from our library.
 use imagekit::{
     assets::Asset,
     cli::{Cli, HexColor, WatermarkPosition},
-    processor::add_watermark,
-    run,
+    context::RunContext,
+    processor::{add_watermark, WatermarkSpec},
+    run, run_with,
 };
 // Import `Font` to be able to create it in tests.
 use rusttype::Font;
@@ -35,6 +38,17 @@ fn load_test_fonts() -> Result<Vec<Font<'static>>> {
     Ok(vec![primary_font, fallback_font])
 }
 
+/// Helper function to build a `Cli` with default options for the given directories.
+fn test_cli(input_dir: &Path, output_dir: &Path) -> Cli {
+    Cli::parse_from([
+        "imagekit".as_ref(),
+        "-i".as_ref(),
+        input_dir.as_os_str(),
+        "-o".as_ref(),
+        output_dir.as_os_str(),
+    ])
+}
+
 
 /// Tests that watermark position parsing from a string is correct.
 #[test]
@@ -93,16 +107,12 @@ fn test_full_run_with_resize_and_watermark() -> Result<()> {
     let original_bytes = fs::read(&test_image_path)?;
 
     let cli = Cli {
-        input_dir: input_dir.path().to_path_buf(),
-        output_dir: output_dir.path().to_path_buf(),
         width: Some(100),
         height: Some(80),
         watermark_text: Some("Integration Test".to_string()),
         watermark_position: WatermarkPosition::Center,
         font_size: 16,
-        watermark_color: HexColor(Rgba([255, 255, 255, 128])),
-        quality: 85,
-        output_format: None, // FIX: Added missing field.
+        ..test_cli(input_dir.path(), output_dir.path())
     };
 
     run(cli)?;
@@ -129,16 +139,8 @@ fn test_run_proportional_resize_by_width() -> Result<()> {
     image::RgbaImage::new(200, 400).save(&test_image_path)?;
 
     let cli = Cli {
-        input_dir: input_dir.path().to_path_buf(),
-        output_dir: output_dir.path().to_path_buf(),
         width: Some(100),
-        height: None,
-        watermark_text: None,
-        watermark_position: WatermarkPosition::Se,
-        font_size: 24,
-        watermark_color: HexColor(Rgba([255, 255, 255, 128])),
-        quality: 85,
-        output_format: None, // FIX: Added missing field.
+        ..test_cli(input_dir.path(), output_dir.path())
     };
 
     run(cli)?;
@@ -162,16 +164,8 @@ fn test_run_proportional_resize_by_height() -> Result<()> {
     image::RgbaImage::new(400, 200).save(&test_image_path)?;
 
     let cli = Cli {
-        input_dir: input_dir.path().to_path_buf(),
-        output_dir: output_dir.path().to_path_buf(),
-        width: None,
         height: Some(100),
-        watermark_text: None,
-        watermark_position: WatermarkPosition::Se,
-        font_size: 24,
-        watermark_color: HexColor(Rgba([255, 255, 255, 128])),
-        quality: 85,
-        output_format: None, // FIX: Added missing field.
+        ..test_cli(input_dir.path(), output_dir.path())
     };
 
     run(cli)?;
@@ -226,13 +220,8 @@ fn test_quality_options_affect_file_size() -> Result<()> {
     // 1. Save with low quality.
     let low_q_output_dir = tempdir()?;
     let cli_low = Cli {
-        input_dir: input_dir.path().to_path_buf(),
-        output_dir: low_q_output_dir.path().to_path_buf(),
         quality: 10,
-        width: None, height: None, watermark_text: None,
-        watermark_position: WatermarkPosition::Se, font_size: 24,
-        watermark_color: HexColor(Rgba([255,255,255,128])),
-        output_format: None, // FIX: Added missing field.
+        ..test_cli(input_dir.path(), low_q_output_dir.path())
     };
     run(cli_low)?;
     let low_q_size = fs::metadata(low_q_output_dir.path().join("quality_test.jpg"))?.len();
@@ -240,13 +229,8 @@ fn test_quality_options_affect_file_size() -> Result<()> {
     // 2. Save with high quality.
     let high_q_output_dir = tempdir()?;
     let cli_high = Cli {
-        input_dir: input_dir.path().to_path_buf(),
-        output_dir: high_q_output_dir.path().to_path_buf(),
         quality: 100,
-        width: None, height: None, watermark_text: None,
-        watermark_position: WatermarkPosition::Se, font_size: 24,
-        watermark_color: HexColor(Rgba([255,255,255,128])),
-        output_format: None, // FIX: Added missing field.
+        ..test_cli(input_dir.path(), high_q_output_dir.path())
     };
     run(cli_high)?;
     let high_q_size = fs::metadata(high_q_output_dir.path().join("quality_test.jpg"))?.len();
@@ -291,3 +275,3761 @@ fn test_cjk_watermark_support() -> Result<()> {
 
     Ok(())
 }
+
+/// Verifies that every configured watermark layer is drawn, not just the primary one.
+#[test]
+fn test_multiple_watermark_layers() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+
+    let test_image_path = input_dir.path().join("layers.png");
+    image::RgbaImage::from_pixel(300, 200, Rgba([0, 0, 0, 255])).save(&test_image_path)?;
+
+    let white = HexColor(Rgba([255, 255, 255, 255]));
+    let cli = Cli {
+        watermark_text: Some("Top".to_string()),
+        watermark_position: WatermarkPosition::Nw,
+        watermark_color: white,
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    let context = RunContext {
+        watermark_layers: vec![WatermarkSpec {
+            text: "Bottom".to_string(),
+            position: WatermarkPosition::Se,
+            color: white,
+            ..WatermarkSpec::default()
+        }],
+        ..RunContext::default()
+    };
+    assert_eq!(context.watermark_specs(&cli).len(), 2);
+
+    run_with(cli, context)?;
+
+    let output_img = image::open(output_dir.path().join("layers.png"))?.to_rgba8();
+    let has_ink = |x0: u32, y0: u32| {
+        (y0..y0 + 50).any(|y| (x0..x0 + 100).any(|x| output_img.get_pixel(x, y)[0] > 0))
+    };
+    assert!(has_ink(0, 0), "Top-left layer was not drawn");
+    assert!(has_ink(200, 150), "Bottom-right layer was not drawn");
+
+    Ok(())
+}
+
+/// Verifies that repeated `--watermark TEXT@POSITION` options each draw at their own position.
+#[test]
+fn test_repeated_watermark_option() -> Result<()> {
+    use imagekit::cli::WatermarkLayer;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    image::RgbaImage::from_pixel(300, 200, Rgba([0, 0, 0, 255])).save(input_dir.path().join("stamp.png"))?;
+
+    let cli = Cli::parse_from([
+        "imagekit".as_ref(),
+        "-i".as_ref(),
+        input_dir.path().as_os_str(),
+        "-o".as_ref(),
+        output_dir.path().as_os_str(),
+        "--watermark-color".as_ref(),
+        "ffffffff".as_ref(),
+        "--watermark".as_ref(),
+        "©ACME@nw".as_ref(),
+        "--watermark".as_ref(),
+        "DRAFT@se".as_ref(),
+    ]);
+    let positions: Vec<_> = cli.watermark_specs().iter().map(|spec| spec.position).collect();
+    assert_eq!(positions, [WatermarkPosition::Nw, WatermarkPosition::Se]);
+    assert_eq!(
+        "info@acme.com".parse::<WatermarkLayer>()?,
+        WatermarkLayer { text: "info@acme.com".to_string(), position: None, style: Vec::new() },
+        "A suffix that is not a position belongs to the text"
+    );
+
+    run(cli)?;
+
+    let output_img = image::open(output_dir.path().join("stamp.png"))?.to_rgba8();
+    let has_ink = |x0: u32, y0: u32| (y0..y0 + 50).any(|y| (x0..x0 + 100).any(|x| output_img.get_pixel(x, y)[0] > 0));
+    assert!(has_ink(0, 0), "The @nw watermark was not drawn");
+    assert!(has_ink(200, 150), "The @se watermark was not drawn");
+    assert!(!has_ink(200, 0), "Nothing should be drawn in the top-right corner");
+    Ok(())
+}
+
+/// Verifies that `--watermark` styles apply to their own layer only, the rest coming from the
+/// `--watermark-*` options.
+#[test]
+fn test_watermark_layer_styles() -> Result<()> {
+    use imagekit::cli::WatermarkLayer;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    image::RgbaImage::from_pixel(400, 200, Rgba([0, 0, 0, 255])).save(input_dir.path().join("styles.png"))?;
+
+    let cli = Cli::parse_from([
+        "imagekit".as_ref(),
+        "-i".as_ref(),
+        input_dir.path().as_os_str(),
+        "-o".as_ref(),
+        output_dir.path().as_os_str(),
+        "--watermark-color".as_ref(),
+        "ffffffff".as_ref(),
+        "--watermark".as_ref(),
+        "BIG@nw;size=72;color=#ff0000ff".as_ref(),
+        "--watermark".as_ref(),
+        "small@se".as_ref(),
+    ]);
+    let specs = cli.watermark_specs();
+    assert_eq!((specs[0].font_size, specs[0].color.0), (72, Rgba([255, 0, 0, 255])));
+    assert_eq!((specs[1].font_size, specs[1].color.0), (24, Rgba([255, 255, 255, 255])));
+    assert_eq!("a;b=c".parse::<WatermarkLayer>()?.text, "a;b=c", "Only known styles are split off");
+    assert!("DRAFT@nw;size=big".parse::<WatermarkLayer>().is_err());
+
+    run(cli)?;
+
+    let output_img = image::open(output_dir.path().join("styles.png"))?.to_rgba8();
+    let rows = |x0: u32, x1: u32, test: &dyn Fn(&Rgba<u8>) -> bool| {
+        (0..200).filter(|&y| (x0..x1).any(|x| test(output_img.get_pixel(x, y)))).count()
+    };
+    let red = |p: &Rgba<u8>| p[0] > 200 && p[1] < 50;
+    let white = |p: &Rgba<u8>| p[0] > 200 && p[1] > 200;
+    assert!(rows(0, 200, &red) > 40, "The styled layer should be drawn large and red");
+    assert_eq!(rows(200, 400, &red), 0, "The style leaked into the other layer");
+    let small = rows(200, 400, &white);
+    assert!(small > 0 && small < 30, "The other layer should keep the global style");
+    Ok(())
+}
+
+/// Verifies that random watermark shifts are seeded, shared by default and per image on request.
+#[test]
+fn test_watermark_random_offset() -> Result<()> {
+    let input_dir = tempdir()?;
+    for name in ["a.png", "b.png"] {
+        image::RgbaImage::from_pixel(300, 200, Rgba([0, 0, 0, 255])).save(input_dir.path().join(name))?;
+    }
+    // Top-left corner of the drawn text in each output.
+    let ink_origin = |dir: &Path, name: &str| -> Result<(u32, u32)> {
+        let img = image::open(dir.join(name))?.to_rgba8();
+        let inked: Vec<(u32, u32)> =
+            img.enumerate_pixels().filter(|(_, _, p)| p[0] > 0).map(|(x, y, _)| (x, y)).collect();
+        Ok((inked.iter().map(|p| p.0).min().unwrap_or(0), inked.iter().map(|p| p.1).min().unwrap_or(0)))
+    };
+    let run_with = |per_image: bool| -> Result<tempfile::TempDir> {
+        let output_dir = tempdir()?;
+        let cli = Cli {
+            watermark_text: Some("ACME".to_string()),
+            watermark_position: WatermarkPosition::Center,
+            watermark_color: HexColor(Rgba([255, 255, 255, 255])),
+            watermark_random_offset: Some(60),
+            watermark_random_per_image: per_image,
+            watermark_seed: 7,
+            ..test_cli(input_dir.path(), output_dir.path())
+        };
+        run(cli)?;
+        Ok(output_dir)
+    };
+
+    let shared = run_with(false)?;
+    assert_eq!(ink_origin(shared.path(), "a.png")?, ink_origin(shared.path(), "b.png")?);
+    let per_image = run_with(true)?;
+    let (a, b) = (ink_origin(per_image.path(), "a.png")?, ink_origin(per_image.path(), "b.png")?);
+    assert_ne!(a, b, "Each image should get its own shift");
+    assert_eq!(ink_origin(run_with(true)?.path(), "a.png")?, a, "The same seed should give the same shift");
+    Ok(())
+}
+
+/// Verifies rule parsing and that the first matching rule selects the per-image pipeline.
+#[test]
+fn test_rules_select_pipeline_per_image() -> Result<()> {
+    use imagekit::rules::Rule;
+    use std::str::FromStr;
+
+    assert!(Rule::from_str("width > 2000 => width=1600,quality=80").is_ok());
+    assert!(Rule::from_str("format >= png => skip").is_err());
+    assert!(Rule::from_str("width > 2000").is_err());
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    image::RgbaImage::new(400, 200).save(input_dir.path().join("big.png"))?;
+    image::RgbaImage::new(32, 32).save(input_dir.path().join("icon.png"))?;
+    image::RgbImage::new(64, 64).save(input_dir.path().join("photo.jpg"))?;
+
+    let cli = Cli {
+        rules: vec![
+            Rule::from_str("width < 50 => skip")?,
+            Rule::from_str("width > 300 => width=200")?,
+            Rule::from_str("format == jpg => format=png")?,
+        ],
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    assert!(!output_dir.path().join("icon.png").exists(), "Icon should have been skipped");
+    assert_eq!(image::open(output_dir.path().join("big.png"))?.dimensions(), (200, 100));
+    assert!(output_dir.path().join("photo.png").exists(), "JPEG should have been converted to PNG");
+
+    Ok(())
+}
+
+/// Verifies that XMP and IPTC keywords are read and drive the keyword filters.
+#[test]
+fn test_keyword_filters() -> Result<()> {
+    use imagekit::metadata::read_keywords;
+
+    // Inserts a metadata segment right after the JPEG SOI marker.
+    fn with_segment(jpeg: &[u8], marker: u8, payload: &[u8]) -> Vec<u8> {
+        let len = (payload.len() + 2) as u16;
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&[0xFF, marker]);
+        out.extend_from_slice(&len.to_be_bytes());
+        out.extend_from_slice(payload);
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let plain = input_dir.path().join("plain.jpg");
+    image::RgbImage::new(16, 16).save(&plain)?;
+    let jpeg = fs::read(&plain)?;
+
+    let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta><rdf:RDF><rdf:Description>\
+<dc:subject><rdf:Bag><rdf:li>Publish</rdf:li><rdf:li>Sunset &amp; Sea</rdf:li></rdf:Bag></dc:subject>\
+</rdf:Description></rdf:RDF></x:xmpmeta>";
+    let tagged = with_segment(&jpeg, 0xE1, xmp);
+    assert_eq!(read_keywords(&tagged), vec!["Publish", "Sunset & Sea"]);
+    fs::write(input_dir.path().join("tagged.jpg"), &tagged)?;
+
+    let mut iptc = b"Photoshop 3.0\08BIM\x04\x04\0\0".to_vec();
+    let dataset = [&[0x1C, 2, 25, 0, 7][..], b"private"].concat();
+    iptc.extend_from_slice(&(dataset.len() as u32).to_be_bytes());
+    iptc.extend_from_slice(&dataset);
+    let private = with_segment(&tagged, 0xED, &iptc);
+    assert_eq!(read_keywords(&private), vec!["Publish", "Sunset & Sea", "private"]);
+
+    // An odd-sized last block without its pad byte still reads, rather than panicking.
+    let mut unpadded = b"Photoshop 3.0\08BIM\x04\x04\0\0".to_vec();
+    let dataset = [&[0x1C, 2, 25, 0, 2][..], b"ab"].concat();
+    unpadded.extend_from_slice(&(dataset.len() as u32).to_be_bytes());
+    unpadded.extend_from_slice(&dataset);
+    assert_eq!(read_keywords(&with_segment(&jpeg, 0xED, &unpadded)), vec!["ab"]);
+    fs::write(input_dir.path().join("private.jpg"), &private)?;
+
+    let cli = Cli {
+        require_keywords: vec!["publish".to_string()],
+        exclude_keywords: vec!["Private".to_string()],
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    assert!(output_dir.path().join("tagged.jpg").exists());
+    assert!(!output_dir.path().join("plain.jpg").exists());
+    assert!(!output_dir.path().join("private.jpg").exists());
+
+    Ok(())
+}
+
+/// Verifies that output paths map to percent-encoded public URLs for CDN purging.
+#[test]
+fn test_cdn_public_url() {
+    use imagekit::cdn::public_url;
+    let url = public_url("https://cdn.example.com/img/", Path::new("/out"), Path::new("/out/a b/c.jpg"));
+    assert_eq!(url.as_deref(), Some("https://cdn.example.com/img/a%20b/c.jpg"));
+    assert_eq!(public_url("https://cdn.example.com", Path::new("/out"), Path::new("/elsewhere/c.jpg")), None);
+}
+
+/// Verifies that the ssg mode processes only referenced images and writes a variant manifest.
+#[test]
+fn test_ssg_generates_variants_and_manifest() -> Result<()> {
+    use imagekit::cli::SsgArgs;
+    use imagekit::references::image_references;
+
+    let refs = image_references("![a](/img/a.png \"Title\") <IMG alt=x data-src=no.png src='b.jpg'>");
+    assert_eq!(refs, vec!["/img/a.png", "b.jpg"]);
+
+    let site = tempdir()?;
+    let content = site.path().join("content");
+    let static_dir = site.path().join("static");
+    fs::create_dir_all(content.join("posts/trip"))?;
+    fs::create_dir_all(static_dir.join("img"))?;
+    image::RgbaImage::new(1000, 500).save(static_dir.join("img/a.png"))?;
+    image::RgbImage::new(600, 300).save(content.join("posts/trip/b.jpg"))?;
+    image::RgbImage::new(10, 10).save(static_dir.join("img/orphan.png"))?;
+    fs::write(
+        content.join("posts/trip/index.md"),
+        "![hero](/img/a.png)\n\n<img src=\"b.jpg?v=2\">\n\n![remote](https://example.com/x.png)",
+    )?;
+
+    let manifest_path = site.path().join("data/images.json");
+    let args = SsgArgs {
+        content_dir: content,
+        static_dir: static_dir.clone(),
+        output_dir: static_dir.join("processed"),
+        widths: vec![480, 800],
+        formats: vec![imagekit::cli::OutputFormat::Webp, imagekit::cli::OutputFormat::Jpg],
+        quality: 80,
+        manifest: manifest_path.clone(),
+        url_prefix: None,
+        snippets_dir: Some(site.path().join("snippets")),
+        sizes: "100vw".to_string(),
+    };
+    imagekit::ssg::run(&args)?;
+
+    let manifest: serde_json::Value = serde_json::from_str(&fs::read_to_string(&manifest_path)?)?;
+    let entries = manifest.as_object().unwrap();
+    assert_eq!(entries.len(), 2, "Only referenced images belong in the manifest");
+    assert_eq!(manifest["/img/a.png"]["variants"].as_array().unwrap().len(), 4);
+    // The 600px source can't be upscaled to 800px, so it yields 480w and 600w variants.
+    let b_urls: Vec<&str> = manifest["/posts/trip/b.jpg"]["variants"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["url"].as_str().unwrap())
+        .collect();
+    assert!(b_urls.contains(&"/processed/posts/trip/b-600w.webp"));
+    assert!(static_dir.join("processed/img/a-480w.jpg").exists());
+    assert!(!static_dir.join("processed/img/orphan-480w.webp").exists());
+
+    assert_eq!(
+        manifest["/img/a.png"]["srcset"]["webp"],
+        "/processed/img/a-480w.webp 480w, /processed/img/a-800w.webp 800w"
+    );
+    let snippet = fs::read_to_string(site.path().join("snippets/img/a.png.html"))?;
+    assert!(snippet.contains("<source type=\"image/webp\""));
+    assert!(snippet.contains("<img src=\"/processed/img/a-800w.jpg\""));
+    assert!(snippet.contains("width=\"800\" height=\"400\""));
+
+    Ok(())
+}
+
+/// Verifies that --referenced-by limits processing to referenced images and can rewrite links.
+#[test]
+fn test_referenced_by_filters_and_rewrites_links() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let pages_dir = tempdir()?;
+
+    fs::create_dir_all(input_dir.path().join("img"))?;
+    image::RgbaImage::new(20, 20).save(input_dir.path().join("img/used.png"))?;
+    image::RgbaImage::new(20, 20).save(input_dir.path().join("img/orphan.png"))?;
+    let page = pages_dir.path().join("post.md");
+    fs::write(&page, "Intro\n\n![used](/img/used.png?v=1)\n")?;
+
+    let cli = Cli {
+        referenced_by: vec![format!("{}/**/*.md", pages_dir.path().display())],
+        rewrite_links: true,
+        output_format: Some(imagekit::cli::OutputFormat::Webp),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    assert!(output_dir.path().join("img/used.webp").exists());
+    assert!(!output_dir.path().join("img/orphan.webp").exists());
+    assert_eq!(fs::read_to_string(&page)?, "Intro\n\n![used](/img/used.webp?v=1)\n");
+
+    Ok(())
+}
+
+/// Verifies that --prune removes outputs whose input is gone, and the dry run only lists them.
+#[test]
+fn test_prune_removes_orphaned_outputs() -> Result<()> {
+    use imagekit::prune::find_orphans;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    image::RgbaImage::new(10, 10).save(input_dir.path().join("keep.png"))?;
+    fs::create_dir_all(output_dir.path().join("old"))?;
+    image::RgbaImage::new(10, 10).save(output_dir.path().join("old/deleted.png"))?;
+    fs::write(output_dir.path().join("notes.txt"), "not an image")?;
+
+    let dry = Cli { prune_dry_run: true, ..test_cli(input_dir.path(), output_dir.path()) };
+    assert_eq!(find_orphans(&dry, &RunContext::default(), &[input_dir.path().join("keep.png")])?.len(), 1);
+    run(dry)?;
+    assert!(output_dir.path().join("old/deleted.png").exists(), "Dry run must not delete");
+
+    run(Cli { prune: true, ..test_cli(input_dir.path(), output_dir.path()) })?;
+    assert!(!output_dir.path().join("old").exists());
+    assert!(output_dir.path().join("keep.png").exists());
+    assert!(output_dir.path().join("notes.txt").exists(), "Non-image files are left alone");
+
+    Ok(())
+}
+
+/// Verifies that --sync renames outputs of renamed sources instead of re-encoding them,
+/// and removes outputs of deleted sources.
+#[test]
+fn test_sync_tracks_renames_and_deletions() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    image::RgbaImage::from_pixel(10, 10, Rgba([1, 2, 3, 255])).save(input_dir.path().join("a.png"))?;
+    image::RgbaImage::from_pixel(10, 10, Rgba([9, 9, 9, 255])).save(input_dir.path().join("gone.png"))?;
+
+    let sync_cli = || Cli { sync: true, ..test_cli(input_dir.path(), output_dir.path()) };
+    run(sync_cli())?;
+    assert!(output_dir.path().join(imagekit::sync::JOURNAL_FILE).exists());
+
+    // Tag the output so we can tell whether it was moved or regenerated.
+    let marker = b"moved-not-reencoded";
+    let mut tagged = fs::read(output_dir.path().join("a.png"))?;
+    tagged.extend_from_slice(marker);
+    fs::write(output_dir.path().join("a.png"), &tagged)?;
+
+    fs::create_dir_all(input_dir.path().join("sub"))?;
+    fs::rename(input_dir.path().join("a.png"), input_dir.path().join("sub/b.png"))?;
+    fs::remove_file(input_dir.path().join("gone.png"))?;
+    run(sync_cli())?;
+
+    assert!(!output_dir.path().join("a.png").exists());
+    assert!(!output_dir.path().join("gone.png").exists());
+    assert!(fs::read(output_dir.path().join("sub/b.png"))?.ends_with(marker));
+
+    Ok(())
+}
+
+/// Verifies percentage parsing and that relative watermark sizes scale with image width.
+#[test]
+fn test_watermark_relative_size() -> Result<()> {
+    use imagekit::cli::Percentage;
+    use std::str::FromStr;
+
+    assert_eq!(Percentage::from_str("5%")?, Percentage(0.05));
+    assert_eq!(Percentage::from_str("0.25")?, Percentage(0.25));
+    assert!(Percentage::from_str("0%").is_err());
+    assert!(Percentage::from_str("150%").is_err());
+
+    let spec = WatermarkSpec {
+        text: "x".to_string(),
+        font_size: 24,
+        relative_size: Some(Percentage(0.05)),
+        ..WatermarkSpec::default()
+    };
+    assert_eq!(spec.font_size_for(4000), 200);
+    assert_eq!(spec.font_size_for(400), 20);
+    assert_eq!(WatermarkSpec { relative_size: None, ..spec }.font_size_for(4000), 24);
+
+    Ok(())
+}
+
+/// Verifies that letter spacing widens the text and line height separates lines.
+#[test]
+fn test_watermark_letter_spacing_and_line_height() -> Result<()> {
+    use imagekit::processor::draw_watermark;
+
+    let fonts = load_test_fonts()?;
+    // Returns the bounding box (min_x, min_y, max_x, max_y) of drawn pixels.
+    let ink_bounds = |spec: &WatermarkSpec| {
+        let mut img = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(400, 300, Rgba([0, 0, 0, 255])));
+        draw_watermark(&mut img, spec, &fonts);
+        let rgba = img.to_rgba8();
+        let inked: Vec<(u32, u32)> = rgba.enumerate_pixels().filter(|(_, _, p)| p[0] > 0).map(|(x, y, _)| (x, y)).collect();
+        let xs = inked.iter().map(|p| p.0);
+        let ys = inked.iter().map(|p| p.1);
+        (xs.clone().min().unwrap(), ys.clone().min().unwrap(), xs.max().unwrap(), ys.max().unwrap())
+    };
+    let base = WatermarkSpec {
+        text: "ABC".to_string(),
+        position: WatermarkPosition::Nw,
+        color: HexColor(Rgba([255, 255, 255, 255])),
+        ..WatermarkSpec::default()
+    };
+
+    let plain = ink_bounds(&base);
+    let spaced = ink_bounds(&WatermarkSpec { letter_spacing: 20.0, ..base.clone() });
+    assert_eq!(spaced.2 - spaced.0, plain.2 - plain.0 + 40, "Two gaps should each widen by 20px");
+
+    let two_lines = ink_bounds(&WatermarkSpec { text: "ABC\nABC".to_string(), ..base.clone() });
+    let loose = ink_bounds(&WatermarkSpec { text: "ABC\nABC".to_string(), line_height: 2.0, ..base.clone() });
+    assert!(two_lines.3 - two_lines.1 > plain.3 - plain.1, "Second line should add height");
+    assert!(loose.3 - loose.1 > two_lines.3 - two_lines.1, "Larger line height should spread lines further");
+
+    Ok(())
+}
+
+/// Verifies that arced text curves: the middle of an upward arc sits higher than its ends.
+#[test]
+fn test_watermark_arc() -> Result<()> {
+    use imagekit::processor::draw_watermark;
+
+    let fonts = load_test_fonts()?;
+    let mut img = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(600, 400, Rgba([0, 0, 0, 255])));
+    let spec = WatermarkSpec {
+        text: "APPROVED 2024".to_string(),
+        position: WatermarkPosition::Center,
+        font_size: 40,
+        color: HexColor(Rgba([255, 255, 255, 255])),
+        arc: 180.0,
+        ..WatermarkSpec::default()
+    };
+    draw_watermark(&mut img, &spec, &fonts);
+
+    let rgba = img.to_rgba8();
+    let inked: Vec<(u32, u32)> = rgba.enumerate_pixels().filter(|(_, _, p)| p[0] > 0).map(|(x, y, _)| (x, y)).collect();
+    assert!(!inked.is_empty(), "Arced text should be drawn");
+    let min_x = inked.iter().map(|p| p.0).min().unwrap();
+    let max_x = inked.iter().map(|p| p.0).max().unwrap();
+    let mid = (min_x + max_x) / 2;
+    let top_in = |range: std::ops::Range<u32>| inked.iter().filter(|p| range.contains(&p.0)).map(|p| p.1).min().unwrap();
+    let middle_top = top_in(mid - 10..mid + 10);
+    let left_top = top_in(min_x..min_x + 20);
+    assert!(middle_top + 40 < left_top, "Middle ({}) should be well above the ends ({})", middle_top, left_top);
+
+    Ok(())
+}
+
+/// Verifies that selective adjustments only touch the targeted color range.
+#[test]
+fn test_selective_color_adjustments() -> Result<()> {
+    use imagekit::adjust::{apply_adjustments, Adjustment};
+
+    let mut img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(2, 1, |x, _| {
+        if x == 0 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) }
+    }));
+    let adjustments: Vec<Adjustment> = vec!["hue:reds:+120".parse()?, "sat:blues:-100".parse()?];
+    apply_adjustments(&mut img, &adjustments);
+
+    let rgb = img.to_rgb8();
+    assert_eq!(rgb.get_pixel(0, 0).0, [0, 255, 0], "Red should be rotated to green");
+    let [r, g, b] = rgb.get_pixel(1, 0).0;
+    assert!(r == g && g == b, "Blue should be fully desaturated, got {:?}", [r, g, b]);
+
+    assert!("hue:reds:200".parse::<Adjustment>().is_err());
+    assert!("sat:purples:+10".parse::<Adjustment>().is_err());
+
+    Ok(())
+}
+
+/// Verifies that CMYK JPEGs and TIFFs decode to the expected RGB colors.
+#[test]
+fn test_cmyk_input_is_converted_to_rgb() -> Result<()> {
+    use imagekit::decode::open_image;
+
+    let dir = tempdir()?;
+    // Pure cyan ink, 16x16.
+    let cyan: Vec<u8> = [255u8, 0, 0, 0].repeat(16 * 16);
+
+    let jpeg_path = dir.path().join("cyan.jpg");
+    let encoder = jpeg_encoder::Encoder::new_file(&jpeg_path, 100)?;
+    encoder.encode(&cyan, 16, 16, jpeg_encoder::ColorType::Cmyk)?;
+
+    let tiff_path = dir.path().join("cyan.tif");
+    let mut tiff = tiff::encoder::TiffEncoder::new(fs::File::create(&tiff_path)?)?;
+    tiff.write_image::<tiff::encoder::colortype::CMYK8>(16, 16, &cyan)?;
+
+    for path in [jpeg_path, tiff_path] {
+        let img = open_image(&path)?;
+        let [r, g, b] = img.to_rgb8().get_pixel(8, 8).0;
+        assert!(r < 8 && g > 247 && b > 247, "{} should decode as cyan, got {:?}", path.display(), [r, g, b]);
+    }
+
+    Ok(())
+}
+
+/// Verifies that gray results are written as single-channel JPEG/PNG files.
+#[test]
+fn test_grayscale_outputs_are_single_channel() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+
+    // A color photo, and an RGB scan that only contains neutral tones.
+    image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([x as u8 * 8, y as u8 * 8, 90])).save(input_dir.path().join("photo.jpg"))?;
+    image::RgbImage::from_fn(32, 32, |x, _| image::Rgb([x as u8 * 8; 3])).save(input_dir.path().join("scan.png"))?;
+
+    run(test_cli(input_dir.path(), output_dir.path()))?;
+    let scan = image::open(output_dir.path().join("scan.png"))?;
+    assert_eq!(scan.color(), image::ColorType::L8, "Neutral RGB input should be saved as gray");
+    let photo = image::open(output_dir.path().join("photo.jpg"))?;
+    assert_eq!(photo.color(), image::ColorType::Rgb8, "Color input should stay RGB");
+
+    run(Cli { grayscale: true, ..test_cli(input_dir.path(), output_dir.path()) })?;
+    let photo = image::open(output_dir.path().join("photo.jpg"))?;
+    assert_eq!(photo.color(), image::ColorType::L8, "--grayscale should produce a single-channel JPEG");
+
+    Ok(())
+}
+
+/// Verifies that `meta` strips GPS tags and sets text tags without touching pixel data.
+#[test]
+fn test_meta_strips_gps_and_sets_tags() -> Result<()> {
+    use exif::{Field, In, Tag, Value};
+    use img_parts::{jpeg::Jpeg, Bytes, ImageEXIF};
+    use imagekit::cli::MetaArgs;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let source = input_dir.path().join("photo.jpg");
+
+    // A JPEG carrying an artist and a GPS position.
+    let mut encoded = Vec::new();
+    DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([x as u8 * 16, y as u8 * 16, 0])))
+        .write_to(&mut std::io::Cursor::new(&mut encoded), image::ImageFormat::Jpeg)?;
+    let artist = Field { tag: Tag::Artist, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Jane".to_vec()]) };
+    let gps = Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"N".to_vec()]) };
+    let mut writer = exif::experimental::Writer::new();
+    writer.push_field(&artist);
+    writer.push_field(&gps);
+    let mut exif_data = std::io::Cursor::new(Vec::new());
+    writer.write(&mut exif_data, false)?;
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(encoded))?;
+    jpeg.set_exif(Some(Bytes::from(exif_data.into_inner())));
+    jpeg.encoder().write_to(fs::File::create(&source)?)?;
+
+    imagekit::meta::run(&MetaArgs {
+        input_dir: input_dir.path().to_path_buf(),
+        output_dir: output_dir.path().to_path_buf(),
+        strip_gps: true,
+        set: vec!["Copyright=ACME".parse()?],
+    })?;
+
+    let output = output_dir.path().join("photo.jpg");
+    let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(fs::File::open(&output)?))?;
+    assert!(exif.get_field(Tag::GPSLatitudeRef, In::PRIMARY).is_none(), "GPS tags should be removed");
+    let text = |tag| exif.get_field(tag, In::PRIMARY).map(|f| f.display_value().to_string());
+    assert_eq!(text(Tag::Artist).as_deref(), Some("\"Jane\""));
+    assert_eq!(text(Tag::Copyright).as_deref(), Some("\"ACME\""));
+
+    // The pixel data is carried over without re-encoding.
+    assert_eq!(image::open(&source)?.to_rgb8(), image::open(&output)?.to_rgb8());
+
+    Ok(())
+}
+
+/// Verifies print sizing: the image is cropped to the paper's shape and the DPI is recorded.
+#[test]
+fn test_dpi_and_physical_size() -> Result<()> {
+    use imagekit::cli::PhysicalSize;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    image::RgbImage::from_pixel(1200, 600, image::Rgb([10, 120, 200])).save(input_dir.path().join("wide.png"))?;
+    image::RgbImage::from_pixel(300, 400, image::Rgb([10, 120, 200])).save(input_dir.path().join("tall.jpg"))?;
+
+    let size: PhysicalSize = "4x6in".parse()?;
+    assert_eq!("101.6x152.4mm".parse::<PhysicalSize>()?.pixels(100, false), (400, 600));
+    run(Cli { physical_size: Some(size), dpi: Some(100), ..test_cli(input_dir.path(), output_dir.path()) })?;
+
+    // Landscape input gets a landscape 6x4in print.
+    let wide_path = output_dir.path().join("wide.png");
+    assert_eq!(image::image_dimensions(&wide_path)?, (600, 400));
+    let png = img_parts::png::Png::from_bytes(fs::read(&wide_path)?.into())?;
+    let phys = png.chunk_by_type(*b"pHYs").context("PNG should have a pHYs chunk")?;
+    assert_eq!(&phys.contents()[..4], &3937u32.to_be_bytes(), "100 DPI is 3937 pixels per meter");
+
+    let tall_path = output_dir.path().join("tall.jpg");
+    assert_eq!(image::image_dimensions(&tall_path)?, (400, 600));
+    let jpeg = img_parts::jpeg::Jpeg::from_bytes(fs::read(&tall_path)?.into())?;
+    let jfif = jpeg.segment_by_marker(img_parts::jpeg::markers::APP0).context("JPEG should have a JFIF segment")?;
+    assert_eq!(&jfif.contents()[7..12], &[1, 0, 100, 0, 100], "Units should be DPI with 100x100 density");
+
+    Ok(())
+}
+
+/// Verifies that imposition fills pages N-up and writes PNG pages or a multi-page PDF.
+#[test]
+fn test_imposition_pages() -> Result<()> {
+    use imagekit::cli::ImpositionArgs;
+    use imagekit::imposition::{grid, Paper, SheetFormat};
+
+    assert_eq!(grid(4, true), (2, 2));
+    assert_eq!(grid(2, true), (1, 2));
+    assert_eq!(grid(6, false), (3, 2));
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    for i in 0..5 {
+        image::RgbImage::from_pixel(60, 40, image::Rgb([200, 30 * i, 30])).save(input_dir.path().join(format!("{}.png", i)))?;
+    }
+    let args = |format| ImpositionArgs {
+        input_dir: input_dir.path().to_path_buf(),
+        output_dir: output_dir.path().to_path_buf(),
+        paper: Paper::A4,
+        landscape: false,
+        per_page: 4,
+        margin: 10.0,
+        gutter: 8.0,
+        no_crop_marks: false,
+        dpi: 50,
+        format,
+    };
+
+    imagekit::imposition::run(&args(SheetFormat::Png))?;
+    // A4 at 50 DPI.
+    assert_eq!(image::image_dimensions(output_dir.path().join("page-001.png"))?, (413, 585));
+    assert!(output_dir.path().join("page-002.png").exists(), "Five images at four per page need two pages");
+    assert!(!output_dir.path().join("page-003.png").exists());
+
+    imagekit::imposition::run(&args(SheetFormat::Pdf))?;
+    let pdf = fs::read(output_dir.path().join("imposition.pdf"))?;
+    let text = String::from_utf8_lossy(&pdf);
+    assert!(text.starts_with("%PDF-1.4"));
+    assert!(text.contains("/Count 2"), "PDF should have two pages");
+
+    Ok(())
+}
+
+/// Verifies that the diff highlights changed pixels and scores similarity.
+#[test]
+fn test_image_diff() -> Result<()> {
+    use imagekit::compare::diff;
+
+    let base = image::RgbImage::from_fn(64, 64, |x, y| image::Rgb([(x * 4) as u8, (y * 4) as u8, 128]));
+    let mut changed = base.clone();
+    for y in 10..20 {
+        for x in 10..20 {
+            changed.put_pixel(x, y, image::Rgb([255, 255, 255]));
+        }
+    }
+    let (a, b) = (DynamicImage::ImageRgb8(base), DynamicImage::ImageRgb8(changed));
+
+    let (_, same) = diff(&a, &a, 0)?;
+    assert_eq!(same.differing_pixels, 0);
+    assert!((same.ssim - 1.0).abs() < 1e-9, "Identical images should score 1.0");
+
+    let (visual, stats) = diff(&a, &b, 0)?;
+    assert_eq!(stats.differing_pixels, 100);
+    assert!(stats.ssim < 1.0 && stats.ssim > 0.5, "Unexpected SSIM {}", stats.ssim);
+    assert_eq!(visual.get_pixel(15, 15)[1], 0, "Changed pixels should be highlighted red");
+    assert_eq!(visual.get_pixel(40, 40)[0], visual.get_pixel(40, 40)[1], "Unchanged pixels should be gray");
+
+    assert!(diff(&a, &DynamicImage::new_rgb8(10, 10), 0).is_err(), "Size mismatch should fail");
+    Ok(())
+}
+
+/// Verifies the golden-file helper: it records a missing golden file, accepts a close match
+/// and rejects a clearly different image.
+#[test]
+fn test_assert_images_similar() -> Result<()> {
+    use imagekit::testing::assert_images_similar;
+
+    let dir = tempdir()?;
+    let golden = dir.path().join("golden/render.png");
+    let actual = dir.path().join("render.png");
+    let render = image::RgbImage::from_fn(32, 32, |x, y| image::Rgb([(x * 8) as u8, (y * 8) as u8, 64]));
+    render.save(&actual)?;
+
+    assert_images_similar(&actual, &golden, 0.99);
+    assert!(golden.exists(), "A missing golden file should be recorded");
+    assert_images_similar(&actual, &golden, 0.99);
+
+    image::RgbImage::from_pixel(32, 32, image::Rgb([255, 0, 0])).save(&actual)?;
+    let result = std::panic::catch_unwind(|| assert_images_similar(&actual, &golden, 0.99));
+    assert!(result.is_err(), "A different image should fail the assertion");
+    assert!(dir.path().join("render.diff.png").exists(), "A difference image should be written");
+
+    Ok(())
+}
+
+/// Verifies the JSON report and its per-camera breakdown.
+#[test]
+fn test_report_groups_by_camera() -> Result<()> {
+    use imagekit::cli::MetaArgs;
+
+    let plain_dir = tempdir()?;
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let report_path = output_dir.path().join("report.json");
+
+    for name in ["a.jpg", "b.jpg", "c.jpg"] {
+        DynamicImage::ImageRgb8(image::RgbImage::new(20, 20)).save(plain_dir.path().join(name))?;
+    }
+    // Tag two of the images with a camera model; the third has no EXIF data.
+    fs::copy(plain_dir.path().join("c.jpg"), input_dir.path().join("c.jpg"))?;
+    fs::remove_file(plain_dir.path().join("c.jpg"))?;
+    imagekit::meta::run(&MetaArgs {
+        input_dir: plain_dir.path().to_path_buf(),
+        output_dir: input_dir.path().to_path_buf(),
+        strip_gps: false,
+        set: vec!["Model=X100V".parse()?, "DateTimeOriginal=2024:05:01 12:30:00".parse()?],
+    })?;
+
+    let cli = Cli {
+        report: Some(report_path.clone()),
+        group_by: vec!["exif.Model".parse()?, "exif.Date".parse()?],
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!(report["processed"], 3);
+    assert_eq!(report["failed"], 0);
+    assert_eq!(report["groups"]["exif.Model"]["X100V"], 2);
+    assert_eq!(report["groups"]["exif.Model"]["unknown"], 1);
+    assert_eq!(report["groups"]["exif.Date"]["2024-05-01"], 2);
+    assert_eq!(report["images"].as_array().map(Vec::len), Some(3));
+    assert!("lens.Model".parse::<imagekit::report::GroupKey>().is_err());
+
+    Ok(())
+}
+
+/// Verifies that `--sequence-rename` numbers outputs in capture order, breaking ties by name.
+#[test]
+fn test_sequence_rename() -> Result<()> {
+    use imagekit::cli::MetaArgs;
+    use imagekit::sequence::SequenceTemplate;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let shade = |name: &str| image::Rgb([(name.as_bytes()[0] - b'a') * 100 + 20, 0, 0]);
+    // c.jpg was shot first; a.jpg and b.jpg share a timestamp; d.png has no EXIF data.
+    for (names, time) in [(&["c.jpg"][..], "2024:05:01 10:00:00"), (&["a.jpg", "b.jpg"][..], "2024:05:01 12:00:00")] {
+        let plain_dir = tempdir()?;
+        for name in names {
+            DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 20, shade(name))).save(plain_dir.path().join(name))?;
+        }
+        imagekit::meta::run(&MetaArgs {
+            input_dir: plain_dir.path().to_path_buf(),
+            output_dir: input_dir.path().to_path_buf(),
+            strip_gps: false,
+            set: vec![format!("DateTimeOriginal={}", time).parse()?],
+        })?;
+    }
+    DynamicImage::ImageRgb8(image::RgbImage::new(20, 20)).save(input_dir.path().join("d.png"))?;
+
+    let cli = Cli { sequence_rename: Some("IMG_{seq:04}".parse()?), ..test_cli(input_dir.path(), output_dir.path()) };
+    run(cli)?;
+
+    for (output, source) in [("IMG_0001.jpg", "c"), ("IMG_0002.jpg", "a"), ("IMG_0003.jpg", "b")] {
+        let red = image::open(output_dir.path().join(output))?.to_rgb8().get_pixel(10, 10)[0];
+        assert!(red.abs_diff(shade(source)[0]) < 10, "{} should come from {}.jpg", output, source);
+    }
+    assert!(output_dir.path().join("IMG_0004.png").exists(), "Images without a capture time go last");
+    let template: SequenceTemplate = "{stem}-{seq}".parse()?;
+    assert_eq!(template.render(7, "beach"), "beach-7");
+    assert!("IMG_".parse::<SequenceTemplate>().is_err(), "A template without {{seq}} would collide");
+    Ok(())
+}
+
+/// Verifies that `--fast-thumbs` starts from the embedded EXIF preview when it is large
+/// enough, and reads RAW files through it.
+#[test]
+fn test_fast_thumbs_use_embedded_preview() -> Result<()> {
+    use exif::experimental::Writer;
+    use exif::{Field, In, Tag, Value};
+    use img_parts::{jpeg::Jpeg, Bytes, ImageEXIF};
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+
+    // The preview is green and the full image red, so the output shows which one was used.
+    let mut preview = Vec::new();
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(300, 200, image::Rgb([0, 200, 0])))
+        .write_to(&mut std::io::Cursor::new(&mut preview), image::ImageFormat::Jpeg)?;
+    let make = Field { tag: Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"ACME".to_vec()]) };
+    let mut writer = Writer::new();
+    writer.push_field(&make);
+    writer.set_jpeg(&preview, In::THUMBNAIL);
+    let mut tiff = std::io::Cursor::new(Vec::new());
+    writer.write(&mut tiff, false)?;
+    let tiff = tiff.into_inner();
+
+    let mut full = Vec::new();
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1200, 800, image::Rgb([200, 0, 0])))
+        .write_to(&mut std::io::Cursor::new(&mut full), image::ImageFormat::Jpeg)?;
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(full))?;
+    jpeg.set_exif(Some(Bytes::from(tiff.clone())));
+    jpeg.encoder().write_to(fs::File::create(input_dir.path().join("photo.jpg"))?)?;
+    // A TIFF holding only EXIF data stands in for a RAW file.
+    fs::write(input_dir.path().join("raw.dng"), &tiff)?;
+
+    let green_at = |name: &str| -> Result<bool> {
+        let img = image::open(output_dir.path().join(name))?.to_rgb8();
+        let pixel = img.get_pixel(img.width() / 2, img.height() / 2);
+        Ok(pixel[1] > 150 && pixel[0] < 50)
+    };
+
+    let mut cli = test_cli(input_dir.path(), output_dir.path());
+    cli.fast_thumbs = true;
+    cli.width = Some(150);
+    run(cli.clone())?;
+    assert!(green_at("photo.jpg")?, "A small target should come from the preview");
+    assert!(green_at("raw.jpg")?, "RAW files should be read through their preview");
+    assert_eq!(image::image_dimensions(output_dir.path().join("photo.jpg"))?, (150, 100));
+
+    cli.width = Some(600);
+    fs::remove_file(input_dir.path().join("raw.dng"))?;
+    run(cli)?;
+    assert!(!green_at("photo.jpg")?, "A preview smaller than the target must not be upscaled");
+    Ok(())
+}
+
+/// Verifies that interlaced PNGs decode to the same pixels, and that the PNG filter option
+/// reaches the encoder.
+#[test]
+fn test_png_filter_and_interlace() -> Result<()> {
+    use imagekit::cli::PngFilter;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    // Odd dimensions leave some Adam7 passes partly empty.
+    let source = image::RgbImage::from_fn(37, 23, |x, y| {
+        image::Rgb([(x * 7) as u8, (y * 11) as u8, ((x * y) % 256) as u8])
+    });
+    DynamicImage::ImageRgb8(source.clone()).save(input_dir.path().join("gradient.png"))?;
+
+    let mut cli = test_cli(input_dir.path(), output_dir.path());
+    cli.png_interlace = true;
+    run(cli.clone())?;
+    let output = output_dir.path().join("gradient.png");
+    assert_eq!(fs::read(&output)?[28], 1, "The IHDR should declare Adam7 interlacing");
+    assert_eq!(image::open(&output)?.to_rgb8(), source, "Interlacing must be lossless");
+
+    let size_with = |filter: PngFilter, interlace: bool| -> Result<u64> {
+        let mut cli = cli.clone();
+        cli.png_filter = filter;
+        cli.png_interlace = interlace;
+        run(cli)?;
+        Ok(fs::metadata(&output)?.len())
+    };
+    for interlace in [false, true] {
+        assert!(
+            size_with(PngFilter::Adaptive, interlace)? < size_with(PngFilter::None, interlace)?,
+            "Filtering should shrink a gradient (interlace: {})",
+            interlace
+        );
+    }
+    Ok(())
+}
+
+/// Verifies that `--sidecar-json` records provenance next to each output.
+#[test]
+fn test_sidecar_json() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let source = input_dir.path().join("photo.png");
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(40, 20, image::Rgb([200, 40, 40]))).save(&source)?;
+
+    let cli = Cli {
+        width: Some(20),
+        grayscale: true,
+        sidecar_json: true,
+        output_format: Some(imagekit::cli::OutputFormat::Webp),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    let sidecar: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(output_dir.path().join("photo.webp.json"))?)?;
+    assert_eq!(sidecar["source"], source.to_str().unwrap());
+    assert_eq!(sidecar["source_blake3"], blake3::hash(&fs::read(&source)?).to_hex().as_str());
+    assert_eq!(sidecar["operations"][0]["op"], "resize");
+    assert_eq!(sidecar["operations"][0]["height"], 10);
+    assert_eq!(sidecar["operations"][1]["op"], "grayscale");
+    assert_eq!(sidecar["encoder"]["format"], "webp");
+    assert_eq!(sidecar["encoder"]["width"], 20);
+    assert!(sidecar["timings_ms"]["total"].as_f64().unwrap() >= 0.0);
+
+    Ok(())
+}
+
+/// Verifies that `--sidecar-bundle` collects the records into one zstd-compressed file and keeps
+/// records of outputs an incremental run left alone.
+#[test]
+fn test_sidecar_bundle() -> Result<()> {
+    use imagekit::sidecar::read_bundle;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let bundle = output_dir.path().join("outputs.meta.zst");
+    for (name, shade) in [("a.png", 40), ("b.png", 120)] {
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 20, image::Rgb([shade, 0, 0])))
+            .save(input_dir.path().join(name))?;
+    }
+    let cli = Cli {
+        sync: true,
+        sidecar_bundle: Some(bundle.clone()),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli.clone())?;
+
+    let outputs = |records: Vec<serde_json::Value>| -> Vec<String> {
+        records
+            .iter()
+            .filter_map(|r| Path::new(r["output"].as_str()?).file_name().map(|n| n.to_string_lossy().into_owned()))
+            .collect()
+    };
+    assert_eq!(outputs(read_bundle(&bundle)?), ["a.png", "b.png"]);
+    assert_eq!(fs::read(&bundle)?[..4], [0x28, 0xb5, 0x2f, 0xfd], "A zstd frame");
+    assert!(!output_dir.path().join("a.png.json").exists(), "No per-file sidecars with a bundle");
+
+    // Only c.png is processed now; a.png's record must survive and b.png's go with its output.
+    fs::remove_file(input_dir.path().join("b.png"))?;
+    DynamicImage::ImageRgb8(image::RgbImage::new(20, 20)).save(input_dir.path().join("c.png"))?;
+    run(cli)?;
+    let records = read_bundle(&bundle)?;
+    assert_eq!(outputs(records.clone()), ["a.png", "c.png"]);
+    let c_source = fs::read(input_dir.path().join("c.png"))?;
+    assert_eq!(records[1]["source_blake3"], blake3::hash(&c_source).to_hex().as_str());
+    Ok(())
+}
+
+/// Verifies age recipient parsing, and that encrypted outputs are age v1 files with one
+/// stanza per recipient, which each recipient can decrypt, and no plaintext left behind.
+#[test]
+fn test_encrypt_outputs() -> Result<()> {
+    use imagekit::encrypt::Recipient;
+
+    let recipient = "age:age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p";
+    assert!(recipient.parse::<Recipient>().is_ok());
+    let bad_checksum = "age:age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8q";
+    assert!(bad_checksum.parse::<Recipient>().is_err());
+    assert!("gpg:ABCDEF".parse::<Recipient>().is_err());
+
+    #[cfg(feature = "encrypt")]
+    {
+        use std::io::Read;
+
+        let identity = age::x25519::Identity::generate();
+        let own = format!("age:{}", identity.to_public());
+        let input_dir = tempdir()?;
+        let output_dir = tempdir()?;
+        let original = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(40, 40, image::Rgb([10, 120, 200])));
+        original.save(input_dir.path().join("secret.png"))?;
+        let cli = Cli {
+            encrypt: vec![recipient.parse()?, own.parse()?],
+            ..test_cli(input_dir.path(), output_dir.path())
+        };
+        run(cli)?;
+
+        let names: Vec<String> = fs::read_dir(output_dir.path())?
+            .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<_>>()?;
+        assert_eq!(names, ["secret.png.age"], "Only the encrypted output should remain");
+        let sealed = fs::read(output_dir.path().join("secret.png.age"))?;
+        let header_end = sealed.windows(4).position(|w| w == b"\n---").context("Missing header MAC")?;
+        let header = String::from_utf8(sealed[..header_end].to_vec())?;
+        let lines: Vec<&str> = header.lines().collect();
+        assert_eq!(lines[0], "age-encryption.org/v1");
+        // Besides the X25519 stanzas, age may add a random "grease" stanza that readers ignore.
+        let stanzas: Vec<usize> = (1..lines.len()).filter(|&i| lines[i].starts_with("-> X25519 ")).collect();
+        assert_eq!(stanzas.len(), 2);
+        for i in stanzas {
+            assert_eq!((lines[i].len(), lines[i + 1].len()), (53, 43), "Unexpected stanza in {:?}", lines);
+        }
+
+        // Decrypted with the `age` reference implementation, the output is the processed image.
+        let decryptor = age::Decryptor::new(&sealed[..])?;
+        let mut plaintext = Vec::new();
+        decryptor.decrypt(std::iter::once(&identity as &dyn age::Identity))?.read_to_end(&mut plaintext)?;
+        let decrypted = image::load_from_memory(&plaintext)?;
+        assert_eq!(decrypted.to_rgb8(), original.to_rgb8());
+    }
+    Ok(())
+}
+
+/// Verifies that `--encrypt` runs with the same options record the same settings fingerprint,
+/// so a later `--baseline` run recognizes them.
+#[test]
+fn test_encrypt_settings_fingerprint() -> Result<()> {
+    let dir = tempdir()?;
+    let input_dir = dir.path().join("in");
+    fs::create_dir_all(&input_dir)?;
+    image::RgbImage::from_pixel(20, 10, image::Rgb([40, 80, 120])).save(input_dir.join("a.png"))?;
+    let recipient = "age:age1ql3z7hjy54pw3hyww5ayyfg7zqgvc7w3j2elw8zmrj2kg5sfn9aqmcac8p";
+
+    let mut fingerprints = Vec::new();
+    for name in ["first", "second"] {
+        let report = dir.path().join(format!("{}.json", name));
+        let cli = Cli {
+            encrypt: vec![recipient.parse()?],
+            report: Some(report.clone()),
+            ..test_cli(&input_dir, &dir.path().join(name))
+        };
+        run(cli)?;
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report)?)?;
+        fingerprints.push(report["settings"].clone());
+    }
+    assert_eq!(fingerprints[0], fingerprints[1]);
+    Ok(())
+}
+
+/// Verifies that a signed output carries a C2PA manifest whose hash binding and signature check out,
+/// and that `c2patool` accepts it when installed.
+#[cfg(feature = "c2pa")]
+#[test]
+fn test_c2pa_signing() -> Result<()> {
+    use ciborium::Value;
+    use p256::ecdsa::signature::Verifier;
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::DecodePrivateKey;
+    use sha2::{Digest, Sha256};
+
+    // Returns the payload of the content box following the description box with this label.
+    fn content<'a>(store: &'a [u8], label: &str) -> &'a [u8] {
+        let needle = [label.as_bytes(), b"\0"].concat();
+        let at = store.windows(needle.len()).position(|w| w == needle.as_slice()).unwrap() + needle.len();
+        let len = u32::from_be_bytes(store[at..at + 4].try_into().unwrap()) as usize;
+        &store[at + 8..at + len]
+    }
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(64, 32, image::Rgb([10, 120, 200])))
+        .save(input_dir.path().join("photo.png"))?;
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/c2pa");
+
+    let cli = Cli {
+        width: Some(32),
+        output_format: Some(imagekit::cli::OutputFormat::Jpg),
+        c2pa_cert: Some(fixtures.join("cert.pem")),
+        c2pa_key: Some(fixtures.join("key.pem")),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    let output = output_dir.path().join("photo.jpg");
+    let data = fs::read(&output)?;
+    assert_eq!(image::open(&output)?.dimensions(), (32, 16));
+
+    // Collect the APP11 segments, which sit right after JFIF.
+    let start = data.windows(4).position(|w| w[..2] == [0xFF, 0xEB] && w[2..] != [0, 0]).unwrap();
+    let mut end = start;
+    let mut store = Vec::new();
+    while data[end..end + 2] == [0xFF, 0xEB] {
+        let len = u16::from_be_bytes([data[end + 2], data[end + 3]]) as usize;
+        // Skip the JPEG XT header, and the repeated box header after the first segment.
+        let skip = if store.is_empty() { 8 } else { 16 };
+        store.extend_from_slice(&data[end + 4 + skip..end + 2 + len]);
+        end += 2 + len;
+    }
+    assert_eq!(&store[4..8], b"jumb");
+
+    // The hard binding covers everything but the manifest.
+    let asset_hash = Sha256::digest([&data[..start], &data[end..]].concat());
+    let hash_data: Value = ciborium::from_reader(content(&store, "c2pa.hash.data"))?;
+    let field = |value: &Value, key: &str| {
+        value.as_map().unwrap().iter().find(|(k, _)| k.as_text() == Some(key)).map(|(_, v)| v.clone()).unwrap()
+    };
+    assert_eq!(field(&hash_data, "hash").as_bytes().unwrap().as_slice(), asset_hash.as_slice());
+    let exclusion = field(&hash_data, "exclusions").as_array().unwrap()[0].clone();
+    assert_eq!(field(&exclusion, "start"), Value::Integer(start.into()));
+    assert_eq!(field(&exclusion, "length"), Value::Integer((end - start).into()));
+
+    let actions: Value = ciborium::from_reader(content(&store, "c2pa.actions"))?;
+    let names: Vec<String> = field(&actions, "actions")
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|a| field(a, "action").as_text().unwrap().to_string())
+        .collect();
+    assert_eq!(names, ["c2pa.opened", "c2pa.resized", "c2pa.transcoded"]);
+
+    // The COSE signature verifies against the claim with the signer's public key.
+    let claim = content(&store, "c2pa.claim");
+    let cose: Value = ciborium::from_reader(content(&store, "c2pa.signature"))?;
+    let Value::Tag(18, cose) = cose else { panic!("expected COSE_Sign1") };
+    let parts = cose.as_array().unwrap();
+    let protected = parts[0].as_bytes().unwrap().clone();
+    let mut to_be_signed = Vec::new();
+    ciborium::into_writer(
+        &Value::Array(vec![
+            Value::Text("Signature1".into()),
+            Value::Bytes(protected),
+            Value::Bytes(Vec::new()),
+            Value::Bytes(claim.to_vec()),
+        ]),
+        &mut to_be_signed,
+    )?;
+    let key = SigningKey::from_pkcs8_pem(&fs::read_to_string(fixtures.join("key.pem"))?)?;
+    let signature = Signature::from_slice(parts[3].as_bytes().unwrap())?;
+    key.verifying_key().verify(&to_be_signed, &signature)?;
+
+    // When the reference reader is installed, it must find the manifest and report no problem
+    // beyond the test certificate not being on its trust list. Without it this is skipped.
+    if let Ok(checked) = std::process::Command::new("c2patool").arg(&output).output() {
+        assert!(checked.status.success(), "c2patool failed: {}", String::from_utf8_lossy(&checked.stderr));
+        let report: serde_json::Value = serde_json::from_slice(&checked.stdout)?;
+        assert!(report["active_manifest"].is_string(), "No active manifest: {}", report);
+        // Older releases list problems under `validation_status`, newer ones as failures.
+        let problems: Vec<&str> = report["validation_status"]
+            .as_array()
+            .into_iter()
+            .chain(report["validation_results"]["activeManifest"]["failure"].as_array())
+            .flatten()
+            .filter_map(|status| status["code"].as_str())
+            .filter(|&code| code != "signingCredential.untrusted")
+            .collect();
+        assert!(problems.is_empty(), "c2patool reported {:?}", problems);
+    }
+    Ok(())
+}
+
+/// Verifies that `--emit-geo` puts each image's GPS position into the report.
+#[test]
+fn test_report_emits_geo() -> Result<()> {
+    use exif::{Field, In, Rational, Tag, Value};
+    use img_parts::{jpeg::Jpeg, Bytes, ImageEXIF};
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let report_path = output_dir.path().join("report.json");
+
+    // 35°39'36"S 149°0'0"E, 12.5 m above sea level.
+    let rational = |values: &[(u32, u32)]| Value::Rational(values.iter().map(|&(num, denom)| Rational { num, denom }).collect());
+    let fields = [
+        Field { tag: Tag::GPSLatitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"S".to_vec()]) },
+        Field { tag: Tag::GPSLatitude, ifd_num: In::PRIMARY, value: rational(&[(35, 1), (39, 1), (36, 1)]) },
+        Field { tag: Tag::GPSLongitudeRef, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"E".to_vec()]) },
+        Field { tag: Tag::GPSLongitude, ifd_num: In::PRIMARY, value: rational(&[(149, 1), (0, 1), (0, 1)]) },
+        Field { tag: Tag::GPSAltitude, ifd_num: In::PRIMARY, value: rational(&[(25, 2)]) },
+    ];
+    let mut writer = exif::experimental::Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut exif_data = std::io::Cursor::new(Vec::new());
+    writer.write(&mut exif_data, false)?;
+
+    let mut encoded = Vec::new();
+    DynamicImage::ImageRgb8(image::RgbImage::new(16, 16))
+        .write_to(&mut std::io::Cursor::new(&mut encoded), image::ImageFormat::Jpeg)?;
+    let mut jpeg = Jpeg::from_bytes(Bytes::from(encoded.clone()))?;
+    jpeg.set_exif(Some(Bytes::from(exif_data.into_inner())));
+    jpeg.encoder().write_to(fs::File::create(input_dir.path().join("geo.jpg"))?)?;
+    fs::write(input_dir.path().join("plain.jpg"), encoded)?;
+
+    let cli = Cli {
+        report: Some(report_path.clone()),
+        emit_geo: true,
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!(report["geotagged"], 1);
+    let images = report["images"].as_array().unwrap();
+    let geo = images.iter().find(|i| i["source"].as_str().unwrap().ends_with("geo.jpg")).unwrap();
+    assert!((geo["gps"]["latitude"].as_f64().unwrap() + 35.66).abs() < 1e-9);
+    assert_eq!(geo["gps"]["longitude"], 149.0);
+    assert_eq!(geo["gps"]["altitude"], 12.5);
+    let plain = images.iter().find(|i| i["source"].as_str().unwrap().ends_with("plain.jpg")).unwrap();
+    assert!(plain.get("gps").is_none());
+
+    Ok(())
+}
+
+/// Verifies that files about to be overwritten or pruned are detected before the run.
+#[test]
+fn test_confirm_lists_destruction() -> Result<()> {
+    use imagekit::confirm::destruction;
+
+    let dir = tempdir()?;
+    let original = dir.path().join("photo.png");
+    DynamicImage::ImageRgb8(image::RgbImage::new(8, 8)).save(&original)?;
+    fs::write(dir.path().join("stale.jpg"), b"orphan")?;
+    let inputs = vec![original.clone()];
+
+    // An in-place run overwrites the original.
+    let cli = Cli { prune: true, ..test_cli(dir.path(), dir.path()) };
+    let found = destruction(&cli, &RunContext::default(), &inputs, &inputs)?;
+    assert_eq!(found.originals, vec![original.clone()]);
+    assert!(found.overwritten.is_empty());
+    assert_eq!(found.pruned, vec![dir.path().join("stale.jpg")]);
+
+    // With --yes the listed changes go ahead without a prompt.
+    let cli = Cli { confirm: true, yes: true, ..test_cli(dir.path(), dir.path()) };
+    run(cli)?;
+    assert!(original.exists());
+
+    Ok(())
+}
+
+/// Verifies that `--use-trash` moves pruned outputs to the trash instead of deleting them.
+#[test]
+fn test_prune_uses_trash() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::new(8, 8)).save(input_dir.path().join("kept.png"))?;
+    let orphan = output_dir.path().join("imagekit-trash-test-orphan.png");
+    DynamicImage::ImageRgb8(image::RgbImage::new(8, 8)).save(&orphan)?;
+
+    let cli = Cli { prune: true, use_trash: true, ..test_cli(input_dir.path(), output_dir.path()) };
+    run(cli)?;
+    assert!(!orphan.exists());
+    assert!(output_dir.path().join("kept.png").exists());
+
+    #[cfg(target_os = "linux")]
+    {
+        let trashed: Vec<_> = trash::os_limited::list()?
+            .into_iter()
+            .filter(|item| item.original_parent == output_dir.path() && item.name == "imagekit-trash-test-orphan.png")
+            .collect();
+        assert_eq!(trashed.len(), 1, "the orphan should be in the trash");
+        trash::os_limited::purge_all(trashed)?;
+    }
+
+    Ok(())
+}
+
+/// Verifies message translations, of the batch run and of subcommands, human-readable sizes
+/// and column widths.
+#[test]
+fn test_i18n_messages() {
+    use imagekit::i18n::{format_bytes, Lang, Msg};
+
+    assert_eq!(Msg::FoundImages(3).text(Lang::En), "Found 3 images to process.");
+    assert_eq!(Msg::FoundImages(3).text(Lang::Zh), "找到 3 张待处理的图片。");
+    let summary = Msg::SizeSummary { images: 2, input_bytes: 4 * 1024 * 1024, output_bytes: 1024 * 1024 };
+    assert_eq!(summary.text(Lang::En), "2 images: 4.0 MiB -> 1.0 MiB (-75.0%)");
+    assert_eq!(summary.text(Lang::Zh), "2 张图片：4.0 MiB -> 1.0 MiB（-75.0%）");
+    let merged = Msg::Merged { merged: 2, total: 3 };
+    assert_eq!(merged.text(Lang::En), "Merged 2 of 3 brackets.");
+    assert_eq!(merged.text(Lang::Zh), "合并了 3 组包围曝光中的 2 组。");
+
+    assert_eq!(format_bytes(512), "512 B");
+    assert_eq!(format_bytes(1536), "1.5 KiB");
+    assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+
+    // Chinese status labels take two columns per character when aligning the results table.
+    assert_eq!(imagekit::console::display_width(&Msg::StatusDone.text(Lang::Zh)), 4);
+    assert_eq!(imagekit::console::display_width("photos/a.jpg"), 12);
+}
+
+/// Verifies that `doctor` passes for a writable output directory and fails when the output
+/// path is a file.
+#[test]
+fn test_doctor_checks() -> Result<()> {
+    use imagekit::cli::DoctorArgs;
+
+    let dir = tempdir()?;
+    imagekit::doctor::run(&DoctorArgs { output_dir: Some(dir.path().join("out")) })?;
+    assert!(dir.path().join("out").is_dir(), "doctor should create the output directory");
+    assert!(!dir.path().join("out/.imagekit-doctor").exists(), "the probe file should be removed");
+
+    let file = dir.path().join("not-a-dir");
+    fs::write(&file, b"x")?;
+    assert!(imagekit::doctor::run(&DoctorArgs { output_dir: Some(file) }).is_err());
+    Ok(())
+}
+
+/// Verifies that the watermark preview draws on a checkerboard of the requested size.
+#[test]
+fn test_preview_watermark() -> Result<()> {
+    use imagekit::cli::{PixelSize, PreviewWatermarkArgs};
+
+    let dir = tempdir()?;
+    let output = dir.path().join("preview.png");
+    let args = PreviewWatermarkArgs {
+        text: "© ACME".to_string(),
+        size: "400x200".parse()?,
+        sample: None,
+        output: output.clone(),
+        position: WatermarkPosition::Center,
+        font_size: 48,
+        fonts: Vec::new(),
+        font_chain: Vec::new(),
+        color: "ff0000ff".parse()?,
+        relative_size: None,
+        letter_spacing: 0.0,
+        line_height: 1.0,
+        arc: 0.0,
+        align: imagekit::cli::TextAlign::Left,
+        angle: 0.0,
+    };
+    imagekit::preview::run(&args)?;
+
+    let preview = image::open(&output)?;
+    assert_eq!(preview.dimensions(), (400, 200));
+    assert_eq!(preview.get_pixel(0, 0), Rgba([204, 204, 204, 255]), "Corners should show the checkerboard");
+    let red = (150..250)
+        .flat_map(|x| (70..130).map(move |y| (x, y)))
+        .any(|(x, y)| preview.get_pixel(x, y) == Rgba([255, 0, 0, 255]));
+    assert!(red, "The watermark should be drawn in the middle");
+    assert!("1200x".parse::<PixelSize>().is_err());
+    Ok(())
+}
+
+/// Verifies that `--proof-style` drains color, brightens the diagonal band and gives each
+/// image its own stable border pattern.
+#[test]
+fn test_proof_style() -> Result<()> {
+    let input_dir = tempdir()?;
+    for name in ["a.png", "b.png"] {
+        image::RgbaImage::from_pixel(400, 300, Rgba([200, 30, 30, 255])).save(input_dir.path().join(name))?;
+    }
+    let run_proofs = || -> Result<tempfile::TempDir> {
+        let output_dir = tempdir()?;
+        run(Cli { proof_style: true, ..test_cli(input_dir.path(), output_dir.path()) })?;
+        Ok(output_dir)
+    };
+    let border = |img: &image::RgbaImage| -> Vec<u8> { (0..img.width()).map(|x| img.get_pixel(x, 0)[0]).collect() };
+
+    let first = run_proofs()?;
+    let a = image::open(first.path().join("a.png"))?.to_rgba8();
+    let b = image::open(first.path().join("b.png"))?.to_rgba8();
+    // Away from the band and border, the red is mostly gone.
+    let [r, g, _, _] = a.get_pixel(30, 30).0;
+    assert!(r as i32 - (g as i32) < 50, "Expected desaturated color, got {:?}", a.get_pixel(30, 30));
+    // The band runs through the center and is lighter than its surroundings.
+    let luma = |p: &Rgba<u8>| p[0] as u32 + p[1] as u32 + p[2] as u32;
+    assert!(
+        (150..250).any(|x| luma(a.get_pixel(x, 150)) > luma(a.get_pixel(30, 30)) + 60),
+        "Expected a light band across the center"
+    );
+    assert_ne!(border(&a), border(&b), "Each image should get its own border");
+    let again = image::open(run_proofs()?.path().join("a.png"))?.to_rgba8();
+    assert_eq!(border(&again), border(&a), "The border should be stable between runs");
+    Ok(())
+}
+
+/// Verifies `--bucket-by-size` parsing and that outputs land in the subfolder for their
+/// encoded size, where a later `--prune` run leaves them alone.
+#[test]
+fn test_bucket_by_size() -> Result<()> {
+    use imagekit::buckets::SizeBuckets;
+    use imagekit::random::Rng;
+    use std::str::FromStr;
+
+    let buckets = SizeBuckets::from_str("small:<100KB,medium:<0.5MB,large")?;
+    assert_eq!(buckets.bucket_for(99_999), "small");
+    assert_eq!(buckets.bucket_for(100_000), "medium");
+    assert_eq!(buckets.bucket_for(600_000), "large");
+    assert_eq!(SizeBuckets::from_str("a:<1KiB,b")?.bucket_for(1023), "a");
+    for invalid in ["small:<100KB", "small:<500KB,medium:<100KB,large", "small:100KB,large", "a/b:<1KB,c", ""] {
+        assert!(SizeBuckets::from_str(invalid).is_err(), "{} should be rejected", invalid);
+    }
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    image::RgbImage::from_pixel(300, 300, image::Rgb([90, 120, 200])).save(input_dir.path().join("flat.png"))?;
+    let mut rng = Rng::new(1, "noise");
+    image::RgbImage::from_fn(300, 300, |_, _| image::Rgb([rng.range(0, 255) as u8, rng.range(0, 255) as u8, 0]))
+        .save(input_dir.path().join("noise.png"))?;
+
+    let cli = Cli { bucket_by_size: Some("small:<20KB,large".parse()?), ..test_cli(input_dir.path(), output_dir.path()) };
+    run(cli.clone())?;
+    assert!(output_dir.path().join("small/flat.png").exists());
+    assert!(output_dir.path().join("large/noise.png").exists());
+    assert!(!output_dir.path().join("flat.png").exists());
+
+    run(Cli { prune: true, ..cli })?;
+    assert!(output_dir.path().join("small/flat.png").exists(), "Bucketed outputs are not orphans");
+    assert!(output_dir.path().join("large/noise.png").exists());
+    Ok(())
+}
+
+/// Verifies that `--max-dimension` shrinks only images whose longest edge exceeds the cap,
+/// whatever their orientation.
+#[test]
+fn test_max_dimension() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    for (name, width, height) in [("wide.png", 800, 400), ("tall.png", 300, 900), ("small.png", 200, 150)] {
+        image::RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 255])).save(input_dir.path().join(name))?;
+    }
+    run(Cli { max_dimension: Some(600), ..test_cli(input_dir.path(), output_dir.path()) })?;
+    assert_eq!(image::image_dimensions(output_dir.path().join("wide.png"))?, (600, 300));
+    assert_eq!(image::image_dimensions(output_dir.path().join("tall.png"))?, (200, 600));
+    assert_eq!(image::image_dimensions(output_dir.path().join("small.png"))?, (200, 150));
+    Ok(())
+}
+
+/// Verifies `--only-aspect` parsing and filtering, and the aspect classification in the report.
+#[test]
+fn test_only_aspect_and_report() -> Result<()> {
+    use imagekit::aspect::{Aspect, AspectFilter};
+    use std::str::FromStr;
+
+    assert_eq!(AspectFilter::from_str("square,5%")?, AspectFilter { aspect: Aspect::Square, tolerance: 0.05 });
+    assert_eq!(AspectFilter::from_str("Portrait")?.aspect, Aspect::Portrait);
+    assert!(AspectFilter::from_str("panorama").is_err());
+    assert!(AspectFilter::from_str("square,2").is_err());
+    assert!(AspectFilter::from_str("landscape,5%").is_err());
+    assert_eq!(Aspect::classify(200, 203, 0.02), Aspect::Square);
+    assert_eq!(Aspect::classify(200, 203, 0.0), Aspect::Portrait);
+
+    let input_dir = tempdir()?;
+    for (name, width, height) in [("wide.png", 300, 200), ("tall.png", 200, 300), ("square.png", 200, 203)] {
+        image::RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255])).save(input_dir.path().join(name))?;
+    }
+
+    let output_dir = tempdir()?;
+    let report_path = output_dir.path().join("report.json");
+    let only = |filters: &[&str]| filters.iter().map(|f| f.parse()).collect::<Result<Vec<AspectFilter>, _>>();
+    run(Cli {
+        only_aspect: only(&["landscape", "square"])?,
+        report: Some(report_path.clone()),
+        ..test_cli(input_dir.path(), output_dir.path())
+    })?;
+    assert!(output_dir.path().join("wide.png").exists());
+    assert!(output_dir.path().join("square.png").exists());
+    assert!(!output_dir.path().join("tall.png").exists());
+
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!(report["aspects"], serde_json::json!({ "landscape": 1, "square": 1 }));
+    let aspect_of = |name: &str| {
+        let images = report["images"].as_array().unwrap();
+        let entry = images.iter().find(|e| e["source"].as_str().unwrap().ends_with(name)).unwrap();
+        entry["aspect"].clone()
+    };
+    assert_eq!(aspect_of("square.png"), "square");
+
+    // A strict tolerance makes the nearly square image a portrait.
+    let strict = tempdir()?;
+    run(Cli { only_aspect: only(&["portrait", "square,0"])?, ..test_cli(input_dir.path(), strict.path()) })?;
+    assert!(strict.path().join("tall.png").exists());
+    assert!(strict.path().join("square.png").exists());
+    assert!(!strict.path().join("wide.png").exists());
+    Ok(())
+}
+
+/// Verifies that orientation-specific targets pick each image's size, falling back to
+/// `--width` for an orientation without its own.
+#[test]
+fn test_orientation_resize_targets() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    for (name, width, height) in [("wide.png", 800, 400), ("tall.png", 400, 800), ("square.png", 500, 500)] {
+        image::RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 255])).save(input_dir.path().join(name))?;
+    }
+    let cli = Cli { width: Some(100), width_portrait: Some(200), ..test_cli(input_dir.path(), output_dir.path()) };
+    run(cli)?;
+    assert_eq!(image::image_dimensions(output_dir.path().join("wide.png"))?, (100, 50));
+    assert_eq!(image::image_dimensions(output_dir.path().join("tall.png"))?, (200, 400));
+    assert_eq!(image::image_dimensions(output_dir.path().join("square.png"))?, (100, 100));
+
+    let cli = Cli { width_landscape: Some(300), height_portrait: Some(300), ..test_cli(input_dir.path(), output_dir.path()) };
+    run(cli)?;
+    assert_eq!(image::image_dimensions(output_dir.path().join("wide.png"))?, (300, 150));
+    assert_eq!(image::image_dimensions(output_dir.path().join("tall.png"))?, (150, 300));
+    assert_eq!(image::image_dimensions(output_dir.path().join("square.png"))?, (300, 300));
+    Ok(())
+}
+
+/// Verifies that `--trim-transparent` crops to the visible content before resizing, and
+/// leaves images without transparency alone.
+#[test]
+fn test_trim_transparent() -> Result<()> {
+    let input_dir = tempdir()?;
+    let mut sprite = image::RgbaImage::new(100, 80);
+    for y in 10..40 {
+        for x in 20..50 {
+            sprite.put_pixel(x, y, Rgba([200, 40, 40, if x == 20 { 1 } else { 255 }]));
+        }
+    }
+    sprite.save(input_dir.path().join("sprite.png"))?;
+    image::RgbImage::from_pixel(100, 80, image::Rgb([0, 0, 0])).save(input_dir.path().join("opaque.png"))?;
+
+    let output_dir = tempdir()?;
+    run(Cli { trim_transparent: true, ..test_cli(input_dir.path(), output_dir.path()) })?;
+    assert_eq!(image::image_dimensions(output_dir.path().join("sprite.png"))?, (30, 30));
+    assert_eq!(image::image_dimensions(output_dir.path().join("opaque.png"))?, (100, 80));
+
+    let resized = tempdir()?;
+    run(Cli { trim_transparent: true, width: Some(15), ..test_cli(input_dir.path(), resized.path()) })?;
+    assert_eq!(image::image_dimensions(resized.path().join("sprite.png"))?, (15, 15));
+    Ok(())
+}
+
+/// Verifies that `--nine-slice` keeps the corners intact, stretches the edges along their
+/// length and rejects targets smaller than the insets.
+#[test]
+fn test_nine_slice() -> Result<()> {
+    use imagekit::nineslice::NineSlice;
+    use std::str::FromStr;
+
+    assert_eq!(NineSlice::from_str("10, 10,10,10")?, NineSlice { left: 10, top: 10, right: 10, bottom: 10 });
+    assert!(NineSlice::from_str("10,10,10").is_err());
+
+    let (red, green, blue) = (Rgba([255, 0, 0, 255]), Rgba([0, 255, 0, 255]), Rgba([0, 0, 255, 255]));
+    let panel = image::RgbaImage::from_fn(30, 30, |x, y| match (x / 10, y / 10) {
+        (1, 1) => blue,
+        (1, _) | (_, 1) => green,
+        _ => red,
+    });
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    panel.save(input_dir.path().join("panel.png"))?;
+    let slice = NineSlice::from_str("10,10,10,10")?;
+    run(Cli { nine_slice: Some(slice), width: Some(90), ..test_cli(input_dir.path(), output_dir.path()) })?;
+
+    let out = image::open(output_dir.path().join("panel.png"))?.to_rgba8();
+    assert_eq!(out.dimensions(), (90, 30), "The height was not given, so it is kept");
+    for (x, y) in [(0, 0), (9, 9), (80, 0), (89, 29), (0, 29)] {
+        assert_eq!(*out.get_pixel(x, y), red, "Corner pixel ({}, {}) changed", x, y);
+    }
+    assert_eq!(*out.get_pixel(45, 5), green);
+    assert_eq!(*out.get_pixel(45, 15), blue);
+
+    assert!(slice.resize(&DynamicImage::ImageRgba8(panel), 15, 30).is_err());
+    Ok(())
+}
+
+/// Verifies that `--pixel-perfect` scales by whole factors with nearest-neighbor sampling,
+/// pads to the target and refuses uneven shrinking.
+#[test]
+fn test_pixel_perfect() -> Result<()> {
+    use imagekit::pixelart::{integer_scale, IntegerScale};
+
+    assert_eq!(integer_scale((16, 16), (Some(50), None))?, IntegerScale::Up(3));
+    assert_eq!(integer_scale((16, 8), (Some(100), Some(20)))?, IntegerScale::Up(2));
+    assert_eq!(integer_scale((64, 64), (Some(40), None))?, IntegerScale::Down(2));
+    assert!(integer_scale((31, 31), (Some(20), None)).is_err());
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let sprite = image::RgbaImage::from_fn(16, 16, |x, y| {
+        if (x + y) % 2 == 0 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 255]) }
+    });
+    sprite.save(input_dir.path().join("sprite.png"))?;
+    run(Cli { pixel_perfect: true, width: Some(50), ..test_cli(input_dir.path(), output_dir.path()) })?;
+
+    let out = image::open(output_dir.path().join("sprite.png"))?.to_rgba8();
+    assert_eq!(out.dimensions(), (50, 48));
+    assert_eq!(out.get_pixel(0, 0)[3], 0, "The padding is transparent");
+    // The 48x48 sprite starts one pixel in; each source pixel is a sharp 3x3 block.
+    for (x, y) in [(1, 0), (3, 2), (4, 0), (6, 2), (48, 47)] {
+        let source = sprite.get_pixel((x - 1) / 3, y / 3);
+        assert_eq!(out.get_pixel(x, y), source, "Pixel ({}, {})", x, y);
+    }
+    Ok(())
+}
+
+/// Verifies that `--palette` leaves only palette colors, and that Floyd-Steinberg dithering
+/// keeps a gradient's average brightness where nearest-color mapping bands it.
+#[test]
+fn test_palette_and_dither() -> Result<()> {
+    use imagekit::cli::Dither;
+    use imagekit::palette::Palette;
+    use std::str::FromStr;
+
+    assert_eq!(Palette::from_str("000000, #FFFFFF,ff0000")?.0, vec![[0, 0, 0], [255, 255, 255], [255, 0, 0]]);
+    assert!(Palette::from_str("ff000080").is_err());
+    assert!(Palette::from_str("").is_err());
+
+    let input_dir = tempdir()?;
+    image::RgbImage::from_fn(64, 32, |_, _| image::Rgb([100, 100, 100])).save(input_dir.path().join("gray.png"))?;
+    let quantized = |dither: Dither| -> Result<image::RgbImage> {
+        let output_dir = tempdir()?;
+        let cli = Cli { palette: Some("000000,ffffff".parse()?), dither, ..test_cli(input_dir.path(), output_dir.path()) };
+        run(cli)?;
+        Ok(image::open(output_dir.path().join("gray.png"))?.to_rgb8())
+    };
+
+    let nearest = quantized(Dither::None)?;
+    assert!(nearest.pixels().all(|p| p.0 == [0, 0, 0]), "Mid-dark gray maps to black");
+    let dithered = quantized(Dither::FloydSteinberg)?;
+    assert!(dithered.pixels().all(|p| p.0 == [0, 0, 0] || p.0 == [255, 255, 255]));
+    let white = dithered.pixels().filter(|p| p.0[0] == 255).count() as f64 / (64.0 * 32.0);
+    assert!((white - 100.0 / 255.0).abs() < 0.05, "About 39% of pixels should be white, got {:.2}", white);
+    Ok(())
+}
+
+/// Verifies that Netpbm, TGA, ICO and DDS inputs are picked up and written back in their own
+/// format, or as PNG for DDS, which can only be read.
+#[test]
+fn test_embedded_toolchain_inputs() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let color = image::RgbImage::from_fn(8, 4, |x, _| image::Rgb([x as u8 * 30, 100, 200]));
+    let gray = DynamicImage::ImageRgb8(color.clone()).to_luma8();
+    color.save(input_dir.path().join("frame.ppm"))?;
+    color.save(input_dir.path().join("frame.tga"))?;
+    DynamicImage::ImageRgb8(color.clone()).to_rgba8().save(input_dir.path().join("frame.ico"))?;
+    gray.save(input_dir.path().join("gray.pgm"))?;
+    gray.save(input_dir.path().join("mask.pbm"))?;
+    gray.save(input_dir.path().join("any.pam"))?;
+
+    // A 4x4 DXT1 texture: one block, all pixels color0 (pure red in RGB565).
+    let mut dds = b"DDS ".to_vec();
+    let header: [u32; 31] = [
+        124, 0x1 | 0x2 | 0x4 | 0x1000 | 0x80000, 4, 4, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, // size .. reserved1
+        32, 0x4, u32::from_le_bytes(*b"DXT1"), 0, 0, 0, 0, 0, // pixel format
+        0x1000, 0, 0, 0, 0, // caps .. reserved2
+    ];
+    header.iter().for_each(|v| dds.extend_from_slice(&v.to_le_bytes()));
+    dds.extend_from_slice(&[0x00, 0xF8, 0x00, 0x00, 0, 0, 0, 0]);
+    fs::write(input_dir.path().join("texture.dds"), dds)?;
+
+    run(Cli { width: Some(4), ..test_cli(input_dir.path(), output_dir.path()) })?;
+    let magic = |name: &str| -> Result<Vec<u8>> { Ok(fs::read(output_dir.path().join(name))?[..2].to_vec()) };
+    assert_eq!(magic("frame.ppm")?, b"P6");
+    assert_eq!(magic("gray.pgm")?, b"P5");
+    assert_eq!(magic("mask.pbm")?, b"P4");
+    assert_eq!(magic("any.pam")?, b"P7");
+    for name in ["frame.ppm", "frame.tga", "frame.ico", "gray.pgm", "mask.pbm", "any.pam"] {
+        assert_eq!(image::image_dimensions(output_dir.path().join(name))?, (4, 2), "{}", name);
+    }
+    let texture = image::open(output_dir.path().join("texture.png"))?.to_rgb8();
+    assert_eq!(texture.dimensions(), (4, 4));
+    assert!(texture.get_pixel(1, 1)[0] > 240 && texture.get_pixel(1, 1)[2] < 16);
+    Ok(())
+}
+
+/// Verifies QOI as an input format and as an `--output-format`, including gray images,
+/// which QOI can only store as RGB.
+#[test]
+fn test_qoi_format() -> Result<()> {
+    let input_dir = tempdir()?;
+    image::RgbaImage::from_pixel(8, 6, Rgba([10, 200, 30, 128])).save(input_dir.path().join("asset.qoi"))?;
+    image::GrayImage::from_pixel(8, 6, image::Luma([90])).save(input_dir.path().join("gray.png"))?;
+
+    let output_dir = tempdir()?;
+    let cli = Cli { output_format: Some(imagekit::cli::OutputFormat::Qoi), width: Some(4), ..test_cli(input_dir.path(), output_dir.path()) };
+    run(cli)?;
+    let asset = image::open(output_dir.path().join("asset.qoi"))?;
+    assert_eq!(asset.dimensions(), (4, 3));
+    assert_eq!(asset.to_rgba8().get_pixel(1, 1).0, [10, 200, 30, 128]);
+    let gray = image::open(output_dir.path().join("gray.qoi"))?;
+    assert_eq!(gray.to_rgb8().get_pixel(1, 1).0, [90, 90, 90]);
+    Ok(())
+}
+
+#[test]
+fn test_raw_and_farbfeld_formats() -> Result<()> {
+    let input_dir = tempdir()?;
+    image::RgbaImage::from_pixel(6, 4, Rgba([10, 200, 30, 128])).save(input_dir.path().join("sprite.png"))?;
+
+    let raw_dir = tempdir()?;
+    let cli = Cli { output_format: Some(imagekit::cli::OutputFormat::Raw), ..test_cli(input_dir.path(), raw_dir.path()) };
+    run(cli)?;
+    let bytes = fs::read(raw_dir.path().join("sprite.rgba"))?;
+    assert_eq!(bytes.len(), 6 * 4 * 4);
+    assert_eq!(&bytes[..4], &[10, 200, 30, 128]);
+    let layout: serde_json::Value = serde_json::from_str(&fs::read_to_string(raw_dir.path().join("sprite.rgba.layout.json"))?)?;
+    assert_eq!(layout["width"], 6);
+    assert_eq!(layout["height"], 4);
+    assert_eq!(layout["pixel_format"], "rgba8");
+    assert_eq!(layout["row_stride"], 24);
+    assert_eq!(layout["byte_length"], 96);
+
+    let ff_dir = tempdir()?;
+    let cli = Cli { output_format: Some(imagekit::cli::OutputFormat::Farbfeld), ..test_cli(input_dir.path(), ff_dir.path()) };
+    run(cli)?;
+    let ff = image::open(ff_dir.path().join("sprite.ff"))?;
+    assert_eq!(ff.dimensions(), (6, 4));
+    assert_eq!(ff.to_rgba8().get_pixel(0, 0).0, [10, 200, 30, 128]);
+    Ok(())
+}
+
+#[test]
+fn test_sample_inputs() -> Result<()> {
+    let input_dir = tempdir()?;
+    for i in 0..20 {
+        image::RgbImage::from_pixel(4, 4, image::Rgb([i * 10, 0, 0])).save(input_dir.path().join(format!("img{:02}.png", i)))?;
+    }
+    let picked = |args: &[&str]| -> Result<Vec<String>> {
+        let output_dir = tempdir()?;
+        let mut argv = vec!["imagekit", "-i", input_dir.path().to_str().unwrap(), "-o", output_dir.path().to_str().unwrap()];
+        argv.extend_from_slice(args);
+        run(Cli::parse_from(argv))?;
+        let mut names: Vec<String> =
+            fs::read_dir(output_dir.path())?.map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        Ok(names)
+    };
+
+    let first = picked(&["--sample", "5"])?;
+    assert_eq!(first.len(), 5);
+    assert_eq!(picked(&["--sample", "5"])?, first);
+    assert_ne!(picked(&["--sample", "5", "--sample-seed", "7"])?, first);
+    assert_eq!(picked(&["--sample", "25%"])?.len(), 5);
+    assert_eq!(picked(&["--sample", "100"])?.len(), 20);
+    assert!(Cli::try_parse_from(["imagekit", "-i", "in", "-o", "out", "--sample", "0"]).is_err());
+    assert!(Cli::try_parse_from(["imagekit", "-i", "in", "-o", "out", "--sample", "5", "--sync"]).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_processing_order() -> Result<()> {
+    let input_dir = tempdir()?;
+    // Noise makes the encoded size grow with the side, against the name order.
+    for (name, side) in [("a.png", 40), ("b.png", 10), ("c.png", 25)] {
+        let mut rng = imagekit::random::Rng::new(1, name);
+        let img = image::RgbImage::from_fn(side, side, |_, _| image::Rgb([rng.range(0, 255) as u8, 0, 0]));
+        img.save(input_dir.path().join(name))?;
+    }
+    let report_order = |order: &str| -> Result<Vec<String>> {
+        let output_dir = tempdir()?;
+        let report_path = output_dir.path().join("report.json");
+        let cli = Cli {
+            order: Some(order.parse().unwrap()),
+            report: Some(report_path.clone()),
+            ..test_cli(input_dir.path(), output_dir.path())
+        };
+        run(cli)?;
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+        Ok(report["images"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| Path::new(i["source"].as_str().unwrap()).file_name().unwrap().to_string_lossy().into_owned())
+            .collect())
+    };
+
+    assert_eq!(report_order("name")?, ["a.png", "b.png", "c.png"]);
+    assert_eq!(report_order("size")?, ["b.png", "c.png", "a.png"]);
+    let shuffled = report_order("random(42)")?;
+    assert_eq!(report_order("random(42)")?, shuffled);
+    assert!("random(x)".parse::<imagekit::order::Order>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_chunked_output_dirs() -> Result<()> {
+    let input_dir = tempdir()?;
+    fs::create_dir(input_dir.path().join("sub"))?;
+    for name in ["a.png", "b.png", "c.png", "d.png", "sub/e.png"] {
+        image::RgbImage::from_pixel(4, 4, image::Rgb([1, 2, 3])).save(input_dir.path().join(name))?;
+    }
+
+    let output_dir = tempdir()?;
+    let cli = Cli { chunk: Some(2), ..test_cli(input_dir.path(), output_dir.path()) };
+    run(cli)?;
+    for name in ["0001/a.png", "0001/b.png", "0002/c.png", "0002/d.png", "0003/sub/e.png"] {
+        assert!(output_dir.path().join(name).exists(), "missing {}", name);
+    }
+    assert_eq!(fs::read_dir(output_dir.path())?.count(), 3);
+    Ok(())
+}
+
+#[test]
+fn test_appicons() -> Result<()> {
+    use imagekit::appicons::Platform;
+    use imagekit::cli::AppIconsArgs;
+
+    let dir = tempdir()?;
+    let icon_path = dir.path().join("icon.png");
+    // Transparent corners, so flattening and the round mask show.
+    image::RgbaImage::from_fn(256, 256, |x, y| {
+        if x < 25 && y < 25 { Rgba([0, 0, 0, 0]) } else { Rgba([200, 40, 40, 255]) }
+    })
+    .save(&icon_path)?;
+    let out = dir.path().join("icons");
+    let args = AppIconsArgs {
+        input: icon_path.clone(),
+        output_dir: out.clone(),
+        platforms: vec![Platform::Ios, Platform::Android],
+        background: "0000ffff".parse()?,
+    };
+    imagekit::appicons::run(&args)?;
+
+    let contents: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out.join("ios/AppIcon.appiconset/Contents.json"))?)?;
+    let images = contents["images"].as_array().unwrap();
+    assert_eq!(images.len(), 18);
+    for image in images {
+        let file = out.join("ios/AppIcon.appiconset").join(image["filename"].as_str().unwrap());
+        assert!(file.exists(), "missing {}", file.display());
+    }
+    assert_eq!(image::open(out.join("ios/AppIcon.appiconset/Icon-83.5@2x.png"))?.dimensions(), (167, 167));
+    let marketing = image::open(out.join("ios/AppIcon.appiconset/Icon-1024.png"))?;
+    assert_eq!(marketing.dimensions(), (1024, 1024));
+    assert_eq!(marketing.get_pixel(0, 0), Rgba([0, 0, 255, 255]), "iOS icons are flattened onto the background");
+    assert_eq!(image::open(out.join("ios/Splash.imageset/Splash@3x.png"))?.dimensions(), (2730, 2730));
+
+    let res = out.join("android/res");
+    assert_eq!(image::open(res.join("mipmap-xxxhdpi/ic_launcher.png"))?.dimensions(), (192, 192));
+    let round = image::open(res.join("mipmap-xxhdpi/ic_launcher_round.png"))?;
+    assert_eq!(round.get_pixel(140, 5)[3], 0, "round icons are cut to a circle");
+    assert_eq!(image::open(res.join("mipmap-mdpi/ic_launcher_foreground.png"))?.dimensions(), (108, 108));
+    assert_eq!(image::open(res.join("drawable-land-mdpi/splash.png"))?.dimensions(), (480, 320));
+    assert!(fs::read_to_string(res.join("values/ic_launcher_background.xml"))?.contains("#0000FF"));
+    assert!(res.join("mipmap-anydpi-v26/ic_launcher.xml").exists());
+    assert_eq!(image::open(out.join("android/playstore-icon.png"))?.dimensions(), (512, 512));
+
+    image::RgbaImage::new(100, 50).save(&icon_path)?;
+    assert!(imagekit::appicons::run(&args).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_email_preset() -> Result<()> {
+    let input_dir = tempdir()?;
+    for name in ["a.png", "b.png"] {
+        let mut rng = imagekit::random::Rng::new(3, name);
+        image::RgbImage::from_fn(1800, 600, |x, _| image::Rgb([(x / 8) as u8, rng.range(0, 80) as u8, 90]))
+            .save(input_dir.path().join(name))?;
+    }
+    let total = |dir: &Path| -> Result<u64> {
+        Ok(["a.jpg", "b.jpg"].iter().map(|n| fs::metadata(dir.join(n)).map_or(0, |m| m.len())).sum())
+    };
+
+    let output_dir = tempdir()?;
+    let args = |out: &Path, extra: &[&str]| {
+        let mut argv = vec!["imagekit", "-i", input_dir.path().to_str().unwrap(), "-o", out.to_str().unwrap(), "--preset", "email"];
+        argv.extend_from_slice(extra);
+        Cli::parse_from(argv)
+    };
+    run(args(output_dir.path(), &[]))?;
+    assert_eq!(image::open(output_dir.path().join("a.jpg"))?.dimensions(), (1600, 533));
+    let full = total(output_dir.path())?;
+    let zip = fs::read(output_dir.path().join("attachments.zip"))?;
+    assert_eq!(&zip[..4], b"PK\x03\x04");
+    // The end of central directory record counts the entries.
+    assert_eq!(u16::from_le_bytes([zip[zip.len() - 12], zip[zip.len() - 11]]), 2);
+    assert_eq!(zip.len() as u64, full + 2 * (30 + 46 + 2 * 5) + 22, "images are stored uncompressed");
+
+    let budget_dir = tempdir()?;
+    let budget = (full * 3 / 4).to_string();
+    let reports = tempdir()?;
+    let (first, second) = (reports.path().join("first.json"), reports.path().join("second.json"));
+    run(args(budget_dir.path(), &["--total-size", &budget, "--report", first.to_str().unwrap()]))?;
+    assert!(total(budget_dir.path())? <= full * 3 / 4);
+
+    // The report records the options as given, not the lowered quality, so a rerun matches it.
+    let rerun = ["--total-size", &budget, "--baseline", first.to_str().unwrap(), "--report", second.to_str().unwrap()];
+    run(args(budget_dir.path(), &rerun))?;
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&second)?)?;
+    assert_eq!((report["processed"].as_u64(), report["unchanged"].as_u64()), (Some(0), Some(2)));
+    Ok(())
+}
+
+#[test]
+fn test_cbz_rewrite() -> Result<()> {
+    use imagekit::cli::CbzArgs;
+    use imagekit::zip::{read_entries, Method, ZipWriter};
+    use std::io::Cursor;
+
+    let mut rng = imagekit::random::Rng::new(5, "page");
+    let page = DynamicImage::ImageRgb8(image::RgbImage::from_fn(1200, 600, |x, _| {
+        image::Rgb([(x / 5) as u8, rng.range(0, 60) as u8, 120])
+    }));
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 100).encode_image(&page)?;
+    let mut png = Vec::new();
+    image::RgbaImage::from_pixel(10, 10, Rgba([1, 2, 3, 255])).write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)?;
+    let xhtml = b"<html><body><img src=\"images/001.jpg\"/></body></html>".repeat(20);
+
+    let dir = tempdir()?;
+    let book = dir.path().join("book.epub");
+    let mut zip = ZipWriter::new(fs::File::create(&book)?);
+    zip.add("mimetype", b"application/epub+zip", Method::Stored)?;
+    zip.add("OEBPS/page.xhtml", &xhtml, Method::Deflated)?;
+    zip.add("OEBPS/images/001.jpg", &jpeg, Method::Stored)?;
+    zip.add("OEBPS/images/icon.png", &png, Method::Deflated)?;
+    zip.finish()?;
+
+    let output = dir.path().join("book_opt.epub");
+    imagekit::cbz::run(&CbzArgs { input: book.clone(), output: output.clone(), max_dimension: Some(600), quality: 75 })?;
+
+    let entries = read_entries(&fs::read(&output)?)?;
+    let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, ["mimetype", "OEBPS/page.xhtml", "OEBPS/images/001.jpg", "OEBPS/images/icon.png"]);
+    assert_eq!(entries[0].method, Method::Stored, "the EPUB mimetype must stay uncompressed");
+    assert_eq!(entries[1].data, xhtml);
+    assert_eq!(entries[1].method, Method::Deflated);
+    let shrunk = image::load_from_memory(&entries[2].data)?;
+    assert_eq!(shrunk.dimensions(), (600, 300));
+    assert!(entries[2].data.len() < jpeg.len());
+    assert_eq!(image::load_from_memory(&entries[3].data)?.dimensions(), (10, 10));
+    assert!(!dir.path().join("book_opt.epub.partial").exists());
+
+    // A damaged entry fails its CRC check rather than being read as is.
+    let mut damaged = fs::read(&output)?;
+    let at = damaged.windows(20).position(|w| w == b"application/epub+zip").unwrap();
+    damaged[at] = b'A';
+    assert!(read_entries(&damaged).is_err());
+
+    fs::write(&book, b"not a zip")?;
+    assert!(imagekit::cbz::run(&CbzArgs { input: book, output, max_dimension: None, quality: 75 }).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_watermark_avoid_zones() -> Result<()> {
+    let input_dir = tempdir()?;
+    for name in ["flag.png", "file.png"] {
+        image::RgbaImage::from_pixel(400, 300, Rgba([0, 0, 0, 255])).save(input_dir.path().join(name))?;
+    }
+    // A label in the bottom-right corner, where the watermark would go; `file.png` lists it in
+    // its sidecar instead of on the command line.
+    fs::write(input_dir.path().join("file.png.avoid"), "# product label\n250,200,150x100\n")?;
+
+    let inked = |path: &Path| -> Result<Vec<(u32, u32)>> {
+        let img = image::open(path)?.to_rgba8();
+        Ok(img.enumerate_pixels().filter(|(_, _, p)| p[0] > 0).map(|(x, y, _)| (x, y)).collect())
+    };
+    let output_dir = tempdir()?;
+    let mut cli = Cli {
+        watermark_text: Some("AB".to_string()),
+        watermark_color: HexColor(Rgba([255, 255, 255, 255])),
+        width: Some(200),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli.clone())?;
+    let plain = inked(&output_dir.path().join("flag.png"))?;
+    assert!(plain.iter().all(|&(x, y)| x >= 125 && y >= 100), "by default the text sits in the corner");
+    let moved = inked(&output_dir.path().join("file.png"))?;
+    assert!(!moved.is_empty());
+    assert!(moved.iter().all(|&(x, y)| x < 125 || y < 100), "the sidecar zone is kept clear");
+
+    cli.watermark_avoid = vec!["250,200,150x100".parse()?];
+    run(cli)?;
+    let moved = inked(&output_dir.path().join("flag.png"))?;
+    assert!(!moved.is_empty());
+    assert!(moved.iter().all(|&(x, y)| x < 125 || y < 100), "the zone scales with the image");
+    assert!("1,2,0x4".parse::<imagekit::zones::Zone>().is_err());
+    Ok(())
+}
+
+#[test]
+fn test_overlay_dir() -> Result<()> {
+    let input_dir = tempdir()?;
+    fs::create_dir(input_dir.path().join("team"))?;
+    for name in ["team/alice.jpg", "team/bob.jpg"] {
+        image::RgbImage::from_pixel(200, 100, image::Rgb([0, 0, 0])).save(input_dir.path().join(name))?;
+    }
+    // A frame with a clear middle for Alice, and a small opaque badge for everyone else.
+    let frames = tempdir()?;
+    fs::create_dir(frames.path().join("team"))?;
+    image::RgbaImage::from_fn(400, 200, |x, y| {
+        if x < 20 || y < 20 || x >= 380 || y >= 180 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 0, 0]) }
+    })
+    .save(frames.path().join("team/alice.png"))?;
+    image::RgbaImage::from_pixel(10, 10, Rgba([0, 255, 0, 255])).save(frames.path().join("_default.png"))?;
+
+    let output_dir = tempdir()?;
+    let cli = Cli {
+        overlay_dir: Some(frames.path().to_path_buf()),
+        overlay_position: WatermarkPosition::Se,
+        overlay_scale: imagekit::cli::Percentage(0.5),
+        sidecar_json: true,
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    // Alice's frame is 2:1 like the photo, so at 50% it covers the bottom-right quarter.
+    let alice = image::open(output_dir.path().join("team/alice.jpg"))?.to_rgb8();
+    assert!(alice.get_pixel(102, 98)[0] > 200, "frame edge at the bottom right");
+    assert!(alice.get_pixel(150, 75)[0] < 40, "clear middle of the frame");
+    assert!(alice.get_pixel(50, 25)[0] < 40, "outside the overlay");
+    let bob = image::open(output_dir.path().join("team/bob.jpg"))?.to_rgb8();
+    assert!(bob.get_pixel(190, 90)[1] > 200, "the default overlay applies to bob");
+    let sidecar: serde_json::Value = serde_json::from_str(&fs::read_to_string(output_dir.path().join("team/bob.jpg.json"))?)?;
+    let overlay = sidecar["operations"].as_array().unwrap().iter().find(|op| op["op"] == "overlay").unwrap();
+    assert!(overlay["source"].as_str().unwrap().ends_with("_default.png"));
+    assert_eq!((overlay["width"].as_u64(), overlay["height"].as_u64()), (Some(50), Some(50)));
+    Ok(())
+}
+
+/// Verifies that `--upscale` runs the model when enlarging, and leaves images alone that are
+/// already large enough for the target.
+#[cfg(feature = "upscale-ai")]
+#[test]
+fn test_ai_upscale() -> Result<()> {
+    let input_dir = tempdir()?;
+    let source = image::RgbImage::from_fn(4, 3, |x, y| image::Rgb([x as u8 * 60, y as u8 * 100, 200]));
+    DynamicImage::ImageRgb8(source.clone()).save(input_dir.path().join("icon.png"))?;
+    // Stands in for a trained network: a 2x nearest-neighbor Resize, so the result is exact.
+    let model = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/upscale/nearest2x.onnx");
+
+    let output_dir = tempdir()?;
+    let cli = Cli {
+        upscale: Some("ai:2x".parse()?),
+        upscale_model: Some(model.clone()),
+        sidecar_json: true,
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+    let upscaled = image::open(output_dir.path().join("icon.png"))?.to_rgb8();
+    assert_eq!(upscaled.dimensions(), (8, 6));
+    assert_eq!(upscaled.get_pixel(7, 5), source.get_pixel(3, 2));
+    let sidecar: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(output_dir.path().join("icon.png.json"))?)?;
+    assert_eq!(sidecar["operations"][0]["op"], "upscale");
+    assert_eq!(sidecar["operations"][0]["factor"], 2);
+
+    // Shrinking never needs the model.
+    let output_dir = tempdir()?;
+    let cli = Cli {
+        width: Some(2),
+        upscale: Some("ai:2x".parse()?),
+        upscale_model: Some(model),
+        sidecar_json: true,
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+    let sidecar: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(output_dir.path().join("icon.png.json"))?)?;
+    assert_eq!(sidecar["operations"][0]["op"], "resize");
+    Ok(())
+}
+
+/// Verifies that in-place runs replace originals through a temporary file, skip leftovers of
+/// interrupted runs, and never take outputs in a nested output directory for inputs.
+#[test]
+fn test_in_place_processing() -> Result<()> {
+    let dir = tempdir()?;
+    let image = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(40, 20, image::Rgb([200, 30, 30])));
+    image.save(dir.path().join("photo.png"))?;
+    // Left by a run that crashed mid-encode.
+    image.save_with_format(dir.path().join(".banner.partial.png"), image::ImageFormat::Png)?;
+
+    let cli = Cli { width: Some(20), yes: true, ..test_cli(dir.path(), dir.path()) };
+    run(cli)?;
+    assert_eq!(image::open(dir.path().join("photo.png"))?.dimensions(), (20, 10));
+    let mut names: Vec<String> =
+        fs::read_dir(dir.path())?.map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    names.sort();
+    assert_eq!(names, [".banner.partial.png", "photo.png"], "no new temporary files");
+    assert_eq!(image::open(dir.path().join(".banner.partial.png"))?.dimensions(), (40, 20));
+
+    // Runs into a subdirectory leave its contents alone, however often they are repeated.
+    let output = dir.path().join("web");
+    for _ in 0..2 {
+        run(Cli { width: Some(10), ..test_cli(dir.path(), &output) })?;
+    }
+    let outputs: Vec<_> = walkdir::WalkDir::new(&output)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .collect();
+    assert_eq!(outputs.len(), 1, "{:?}", outputs);
+    assert_eq!(image::open(output.join("photo.png"))?.dimensions(), (10, 5));
+    Ok(())
+}
+
+/// Verifies that `--config` fills in options from a TOML file and that the command line wins.
+#[test]
+fn test_config_file() -> Result<()> {
+    let dir = tempdir()?;
+    let config = dir.path().join("imagekit.toml");
+    fs::write(
+        &config,
+        r#"
+input_dir = "content"
+output-dir = "public"
+width = 800
+quality = 70
+watermark_arc = -30
+watermark = ["© ACME@se", "DRAFT@center"]
+grayscale = true
+sidecar_json = false
+"#,
+    )?;
+    let args = ["imagekit", "--config", config.to_str().unwrap(), "-q", "90", "--watermark", "FINAL@nw"];
+    let cli = Cli::parse_from(imagekit::config::expand_args(args.iter().map(Into::into).collect())?.0);
+    assert_eq!(cli.input_dir, Path::new("content"));
+    assert_eq!(cli.output_dir, Path::new("public"));
+    assert_eq!(cli.width, Some(800));
+    assert_eq!(cli.quality, 90);
+    assert_eq!(cli.watermark_arc, -30.0);
+    assert_eq!(cli.watermarks.len(), 1, "the command line replaces the file's list");
+    assert!(cli.grayscale && !cli.sidecar_json);
+
+    fs::write(&config, "widht = 800\n")?;
+    let error = imagekit::config::expand_args(args.iter().map(Into::into).collect()).unwrap_err();
+    assert!(error.to_string().contains("Unknown option 'widht'"), "{}", error);
+    Ok(())
+}
+
+/// Verifies that the `[[watermark_layer]]` tables of a config file are drawn together: a logo in
+/// one corner, text in the other and a tiled stamp across the whole image.
+#[test]
+fn test_config_watermark_layers() -> Result<()> {
+    let dir = tempdir()?;
+    let input_dir = dir.path().join("in");
+    let output_dir = dir.path().join("out");
+    fs::create_dir(&input_dir)?;
+    image::RgbaImage::from_pixel(400, 300, Rgba([0, 0, 0, 255])).save(input_dir.join("layers.png"))?;
+    let logo = dir.path().join("logo.png");
+    image::RgbaImage::from_pixel(40, 40, Rgba([255, 0, 0, 255])).save(&logo)?;
+
+    let config = dir.path().join("imagekit.toml");
+    fs::write(
+        &config,
+        format!(
+            r##"
+input_dir = {:?}
+output_dir = {:?}
+
+[[watermark_layer]]
+kind = "image"
+image = {:?}
+position = "nw"
+scale = "10%"
+
+[[watermark_layer]]
+text = "© ACME"
+position = "se"
+color = "#ffffffff"
+
+[[watermark_layer]]
+kind = "text"
+text = "DRAFT"
+tile = true
+angle = 30
+size = 20
+color = "#0000ffff"
+"##,
+            input_dir, output_dir, logo
+        ),
+    )?;
+    let args = ["imagekit", "--config", config.to_str().unwrap()];
+    let (args, watermark_layers) = imagekit::config::expand_args(args.iter().map(Into::into).collect())?;
+    assert_eq!(watermark_layers.len(), 3);
+    assert!(watermark_layers[2].tile && watermark_layers[2].angle == 30.0);
+    run_with(Cli::parse_from(args), RunContext { watermark_layers, ..RunContext::default() })?;
+
+    let output_img = image::open(output_dir.join("layers.png"))?.to_rgba8();
+    let any_in = |(x0, y0): (u32, u32), (x1, y1): (u32, u32), test: &dyn Fn(&Rgba<u8>) -> bool| {
+        (y0..y1).any(|y| (x0..x1).any(|x| test(output_img.get_pixel(x, y))))
+    };
+    let red = |p: &Rgba<u8>| p[0] > 200 && p[1] < 50 && p[2] < 50;
+    let white = |p: &Rgba<u8>| p[0] > 200 && p[1] > 200 && p[2] > 200;
+    let blue = |p: &Rgba<u8>| p[0] < 50 && p[1] < 50 && p[2] > 200;
+    assert!(any_in((10, 10), (50, 50), &red), "the logo was not stamped top-left");
+    assert!(!any_in((200, 0), (400, 150), &red), "the logo was stamped elsewhere");
+    assert!(any_in((250, 250), (400, 300), &white), "the text was not drawn bottom-right");
+    for quadrant in [(0, 0), (200, 0), (0, 150), (200, 150)] {
+        let end = (quadrant.0 + 200, quadrant.1 + 150);
+        assert!(any_in(quadrant, end, &blue), "the tiled layer misses the quadrant at {:?}", quadrant);
+    }
+
+    fs::write(&config, "[[watermark_layer]]\nkind = \"image\"\ntext = \"DRAFT\"\n")?;
+    let args = ["imagekit", "--config", config.to_str().unwrap()];
+    let error = imagekit::config::expand_args(args.iter().map(Into::into).collect()).unwrap_err();
+    assert!(format!("{:#}", error).contains("watermark layer 1"), "{:#}", error);
+    Ok(())
+}
+
+/// Verifies that `--resize-mode seam-carve` narrows an image by dropping flat background, so
+/// subjects keep their proportions where stretching would squash them.
+#[test]
+fn test_seam_carve_resize() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    // Two 20x20 squares on a plain 160x80 banner.
+    let banner = image::RgbImage::from_fn(160, 80, |x, y| match (x, y) {
+        (10..30, 30..50) => image::Rgb([220, 20, 20]),
+        (130..150, 30..50) => image::Rgb([20, 20, 220]),
+        _ => image::Rgb([240, 240, 240]),
+    });
+    DynamicImage::ImageRgb8(banner).save(input_dir.path().join("banner.png"))?;
+
+    let cli = Cli {
+        width: Some(100),
+        height: Some(100),
+        resize_mode: imagekit::cli::ResizeMode::SeamCarve,
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    let output = image::open(output_dir.path().join("banner.png"))?.to_rgb8();
+    assert_eq!(output.dimensions(), (100, 100));
+    // Scaled by 1.25 to cover the target, each square is 25 pixels each way, and stays so.
+    let count = |row: u32, is: fn(&image::Rgb<u8>) -> bool| (0..100).filter(|&x| is(output.get_pixel(x, row))).count();
+    let red = |p: &image::Rgb<u8>| p[0] > 150 && p[2] < 100;
+    let blue = |p: &image::Rgb<u8>| p[2] > 150 && p[0] < 100;
+    assert!((24..=26).contains(&count(50, red)), "red is {} wide", count(50, red));
+    assert!((24..=26).contains(&count(50, blue)), "blue is {} wide", count(50, blue));
+    Ok(())
+}
+
+/// Verifies that `--watermark-image` scales a logo to a fraction of the image width, places it
+/// like a text watermark and blends it by its alpha.
+#[test]
+fn test_image_watermark() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(200, 100, image::Rgb([0, 0, 0])))
+        .save(input_dir.path().join("photo.png"))?;
+    // A green logo whose left half is transparent.
+    let assets = tempdir()?;
+    let logo_path = assets.path().join("logo.png");
+    image::RgbaImage::from_fn(40, 20, |x, _| if x < 20 { Rgba([0, 0, 0, 0]) } else { Rgba([0, 255, 0, 255]) })
+        .save(&logo_path)?;
+
+    let cli = Cli {
+        watermark_image: Some(logo_path),
+        watermark_scale: imagekit::cli::Percentage(0.25),
+        sidecar_json: true,
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    // 25% of 200 is 50 wide, so the logo covers x 140..190 and y 65..90 with the 10px padding.
+    let output = image::open(output_dir.path().join("photo.png"))?.to_rgb8();
+    assert!(output.get_pixel(180, 80)[1] > 200, "opaque half of the logo");
+    assert_eq!(output.get_pixel(150, 80), &image::Rgb([0, 0, 0]), "transparent half");
+    assert_eq!(output.get_pixel(180, 60), &image::Rgb([0, 0, 0]), "above the logo");
+    let sidecar: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(output_dir.path().join("photo.png.json"))?)?;
+    assert_eq!(sidecar["operations"][0]["op"], "image_watermark");
+    assert_eq!(sidecar["operations"][0]["width"], 50);
+    assert_eq!(sidecar["operations"][0]["position"], "se");
+    Ok(())
+}
+
+/// Verifies that `--fast-io` produces the same files as buffered IO.
+#[test]
+fn test_fast_io_matches_buffered() -> Result<()> {
+    let input_dir = tempdir()?;
+    let photo = image::RgbImage::from_fn(300, 200, |x, y| image::Rgb([x as u8, y as u8, (x ^ y) as u8]));
+    DynamicImage::ImageRgb8(photo.clone()).save(input_dir.path().join("scan.tif"))?;
+    DynamicImage::ImageRgb8(photo).save(input_dir.path().join("photo.png"))?;
+
+    let buffered = tempdir()?;
+    let fast = tempdir()?;
+    run(Cli { width: Some(150), ..test_cli(input_dir.path(), buffered.path()) })?;
+    run(Cli { width: Some(150), fast_io: true, ..test_cli(input_dir.path(), fast.path()) })?;
+    for name in ["scan.tif", "photo.png"] {
+        let expected = fs::read(buffered.path().join(name))?;
+        assert!(!expected.is_empty());
+        assert_eq!(fs::read(fast.path().join(name))?, expected, "{}", name);
+    }
+    Ok(())
+}
+
+/// Verifies that `--baseline` skips images whose content and settings match an earlier report,
+/// even with the directories moved, and carries them into the new report.
+#[test]
+fn test_baseline_report() -> Result<()> {
+    let first = tempdir()?;
+    let (input, output) = (first.path().join("in"), first.path().join("out"));
+    fs::create_dir_all(&input)?;
+    for (name, shade) in [("a.png", 10), ("b.png", 20)] {
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([shade, 0, 0]))).save(input.join(name))?;
+    }
+    let baseline = first.path().join("report.json");
+    run(Cli { width: Some(10), report: Some(baseline.clone()), ..test_cli(&input, &output) })?;
+
+    // Another machine: both directories restored elsewhere, one image edited and one added.
+    let second = tempdir()?;
+    let (input, output) = (second.path().join("src"), second.path().join("dist"));
+    fs::create_dir_all(&input)?;
+    fs::create_dir_all(&output)?;
+    for name in ["a.png", "b.png"] {
+        fs::copy(first.path().join("in").join(name), input.join(name))?;
+        fs::copy(first.path().join("out").join(name), output.join(name))?;
+    }
+    // A marker shows whether a.png's output gets rewritten.
+    fs::write(output.join("a.png"), b"kept")?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([99, 0, 0]))).save(input.join("b.png"))?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([30, 0, 0]))).save(input.join("c.png"))?;
+
+    let report_path = second.path().join("report.json");
+    let cli = Cli {
+        width: Some(10),
+        baseline: Some(baseline.clone()),
+        report: Some(report_path.clone()),
+        ..test_cli(&input, &output)
+    };
+    run(cli)?;
+    assert_eq!(fs::read(output.join("a.png"))?, b"kept");
+    assert_eq!(image::open(output.join("b.png"))?.to_rgb8().get_pixel(0, 0)[0], 99);
+    assert!(output.join("c.png").exists());
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!((report["processed"].as_u64(), report["unchanged"].as_u64()), (Some(2), Some(1)));
+    let a = report["images"].as_array().unwrap().iter().find(|image| image["key"] == "a.png").unwrap();
+    assert_eq!(a["status"], "unchanged");
+
+    // Other settings invalidate the baseline.
+    run(Cli { width: Some(8), baseline: Some(report_path), ..test_cli(&input, &output) })?;
+    assert_eq!(image::open(output.join("a.png"))?.dimensions(), (8, 4));
+    Ok(())
+}
+
+/// Verifies that `--widths` writes one suffixed output per width, capping widths at the source's.
+#[test]
+fn test_responsive_widths() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(1000, 500, image::Rgb([40, 90, 160])))
+        .save(input_dir.path().join("photo.png"))?;
+    let report_path = output_dir.path().join("report.json");
+    let cli = Cli {
+        widths: vec![800, 480, 1600],
+        report: Some(report_path.clone()),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    for (name, size) in [("photo-480w.png", (480, 240)), ("photo-800w.png", (800, 400)), ("photo-1000w.png", (1000, 500))] {
+        assert_eq!(image::open(output_dir.path().join(name))?.dimensions(), size, "{}", name);
+    }
+    assert!(!output_dir.path().join("photo.png").exists());
+    assert!(!output_dir.path().join("photo-1600w.png").exists());
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    let entry = &report["images"][0];
+    assert_eq!(entry["variants"].as_array().map(Vec::len), Some(3));
+    assert!(entry["output"].as_str().unwrap().ends_with("photo-1000w.png"));
+    Ok(())
+}
+
+/// Verifies that `--auto-tag` embeds the classifier's confident labels as XMP keywords and
+/// lists them in the report.
+#[cfg(feature = "ml")]
+#[test]
+fn test_auto_tag() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(32, 24, image::Rgb([230, 20, 10])))
+        .save(input_dir.path().join("poppy.jpg"))?;
+    // Stands in for a trained network: its three classes score the mean of each color channel.
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tagging");
+    let report_path = output_dir.path().join("report.json");
+    let cli = Cli {
+        auto_tag: Some(fixtures.join("channel_means.onnx")),
+        auto_tag_labels: Some(fixtures.join("channel_means.txt")),
+        report: Some(report_path.clone()),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    let output = fs::read(output_dir.path().join("poppy.jpg"))?;
+    assert_eq!(imagekit::metadata::read_keywords(&output), vec!["red".to_string()]);
+    assert!(image::load_from_memory(&output).is_ok());
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!(report["images"][0]["tags"], serde_json::json!(["red"]));
+    Ok(())
+}
+
+/// Verifies that `--safety-filter block` writes nothing for images whose unsafe classes score
+/// above the threshold, and that `flag` processes them but marks them in the report.
+#[cfg(feature = "ml")]
+#[test]
+fn test_safety_filter() -> Result<()> {
+    let input_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(16, 16, image::Rgb([240, 10, 10])))
+        .save(input_dir.path().join("suspect.png"))?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(16, 16, image::Rgb([10, 10, 240])))
+        .save(input_dir.path().join("fine.png"))?;
+    // The fixture classifier scores each color channel; "red" plays the unsafe class.
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tagging");
+    let screened = |mode, output_dir: &Path| Cli {
+        safety_filter: Some(mode),
+        safety_model: Some(fixtures.join("channel_means.onnx")),
+        safety_labels: Some(fixtures.join("channel_means.txt")),
+        safety_classes: vec!["red".to_string()],
+        report: Some(output_dir.join("report.json")),
+        ..test_cli(input_dir.path(), output_dir)
+    };
+    let read_report = |output_dir: &Path| -> Result<serde_json::Value> {
+        Ok(serde_json::from_str(&fs::read_to_string(output_dir.join("report.json"))?)?)
+    };
+
+    let blocked = tempdir()?;
+    run(screened(imagekit::cli::SafetyMode::Block, blocked.path()))?;
+    assert!(!blocked.path().join("suspect.png").exists());
+    assert!(blocked.path().join("fine.png").exists());
+    let report = read_report(blocked.path())?;
+    assert_eq!((report["processed"].as_u64(), report["blocked"].as_u64()), (Some(1), Some(1)));
+    let entry = report["images"].as_array().unwrap().iter().find(|image| image["key"] == "suspect.png").unwrap();
+    assert_eq!(entry["status"], "blocked");
+    assert!(entry["safety"]["score"].as_f64().unwrap() > 0.9);
+
+    let flagged = tempdir()?;
+    run(screened(imagekit::cli::SafetyMode::Flag, flagged.path()))?;
+    assert!(flagged.path().join("suspect.png").exists());
+    let report = read_report(flagged.path())?;
+    assert_eq!((report["processed"].as_u64(), report["flagged"].as_u64()), (Some(2), Some(1)));
+    Ok(())
+}
+
+/// Verifies that `--output-format auto` keeps a screenshot lossless as PNG, turns a photo into
+/// a JPEG, and removes an output an earlier run wrote in the other format.
+#[test]
+fn test_auto_output_format() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    // A window with a gradient title bar, too many colors for a palette, above lines of "text".
+    let screenshot = image::RgbImage::from_fn(400, 200, |x, y| match (x, y) {
+        (_, 0..=29) => image::Rgb([(x * 255 / 400) as u8, 120, 255 - (x * 255 / 400) as u8]),
+        (20..=379, _) if y % 20 < 8 && x % 9 < 6 && y > 40 => image::Rgb([0, 0, 0]),
+        _ => image::Rgb([255, 255, 255]),
+    });
+    DynamicImage::ImageRgb8(screenshot).save(input_dir.path().join("screenshot.png"))?;
+    let mut seed = 7u32;
+    let photo = image::RgbImage::from_fn(400, 200, |x, y| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        let noise = (seed >> 16) % 24;
+        image::Rgb([(x / 2 + noise) as u8, (y + noise) as u8, (150 + noise) as u8])
+    });
+    DynamicImage::ImageRgb8(photo).save(input_dir.path().join("photo.png"))?;
+    fs::write(output_dir.path().join("photo.png"), b"stale")?;
+
+    let cli = Cli {
+        output_format: Some(imagekit::cli::OutputFormat::Auto),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    assert_eq!(image::ImageFormat::from_path(output_dir.path().join("screenshot.png"))?, image::ImageFormat::Png);
+    assert!(image::open(output_dir.path().join("screenshot.png")).is_ok());
+    assert!(image::open(output_dir.path().join("photo.jpg")).is_ok());
+    assert!(!output_dir.path().join("screenshot.jpg").exists());
+    assert!(!output_dir.path().join("photo.png").exists());
+    Ok(())
+}
+
+/// Verifies that --min-rating and --only-flagged keep only images rated or labeled in XMP.
+#[test]
+fn test_rating_filters() -> Result<()> {
+    use imagekit::metadata::{is_flagged, read_rating};
+
+    fn with_xmp(jpeg: &[u8], description: &str) -> Vec<u8> {
+        let payload = format!(
+            "http://ns.adobe.com/xap/1.0/\0<x:xmpmeta><rdf:RDF>{}</rdf:RDF></x:xmpmeta>",
+            description
+        );
+        let mut out = jpeg[..2].to_vec();
+        out.extend_from_slice(&[0xFF, 0xE1]);
+        out.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        out.extend_from_slice(payload.as_bytes());
+        out.extend_from_slice(&jpeg[2..]);
+        out
+    }
+
+    let input_dir = tempdir()?;
+    let plain = input_dir.path().join("plain.jpg");
+    image::RgbImage::new(16, 16).save(&plain)?;
+    let jpeg = fs::read(&plain)?;
+    let pick = with_xmp(&jpeg, r#"<rdf:Description xmp:Rating="4"/>"#);
+    let labeled = with_xmp(&jpeg, "<rdf:Description><xmp:Rating>2</xmp:Rating><xmp:Label>Red</xmp:Label></rdf:Description>");
+    let rejected = with_xmp(&jpeg, r#"<rdf:Description xmp:Rating="-1"/>"#);
+    assert_eq!((read_rating(&pick), read_rating(&labeled), read_rating(&rejected)), (Some(4), Some(2), Some(-1)));
+    assert_eq!(read_rating(&jpeg), None);
+    assert!(is_flagged(&labeled) && !is_flagged(&pick));
+    fs::write(input_dir.path().join("pick.jpg"), &pick)?;
+    fs::write(input_dir.path().join("labeled.jpg"), &labeled)?;
+    fs::write(input_dir.path().join("rejected.jpg"), &rejected)?;
+
+    let rated_dir = tempdir()?;
+    run(Cli { min_rating: Some(3), ..test_cli(input_dir.path(), rated_dir.path()) })?;
+    let flagged_dir = tempdir()?;
+    run(Cli { only_flagged: true, ..test_cli(input_dir.path(), flagged_dir.path()) })?;
+
+    let names = |dir: &Path| -> Result<Vec<String>> {
+        let mut names: Vec<String> =
+            fs::read_dir(dir)?.map(|e| Ok(e?.file_name().to_string_lossy().into_owned())).collect::<Result<_>>()?;
+        names.sort();
+        Ok(names)
+    };
+    assert_eq!(names(rated_dir.path())?, vec!["pick.jpg"]);
+    assert_eq!(names(flagged_dir.path())?, vec!["labeled.jpg"]);
+    Ok(())
+}
+
+/// Verifies that TIFF scans are read and that TIFF outputs are compressed as requested.
+#[test]
+fn test_tiff_input_and_output() -> Result<()> {
+    use imagekit::cli::{OutputFormat, TiffCompression};
+
+    let input_dir = tempdir()?;
+    let scan = image::RgbImage::from_fn(300, 200, |x, y| image::Rgb([(x % 64) as u8 * 4, (y % 32) as u8 * 8, 200]));
+    DynamicImage::ImageRgb8(scan).save(input_dir.path().join("scan.tif"))?;
+
+    let jpeg_dir = tempdir()?;
+    run(Cli { output_format: Some(OutputFormat::Jpg), ..test_cli(input_dir.path(), jpeg_dir.path()) })?;
+    assert_eq!(image::open(jpeg_dir.path().join("scan.jpg"))?.dimensions(), (300, 200));
+
+    let mut sizes = Vec::new();
+    // TIFF compression tags: 1 is none, 5 LZW and 8 deflate.
+    for (compression, tag) in [(TiffCompression::None, 1), (TiffCompression::Lzw, 5), (TiffCompression::Deflate, 8)] {
+        let output_dir = tempdir()?;
+        let cli = Cli {
+            output_format: Some(OutputFormat::Tiff),
+            tiff_compression: compression,
+            ..test_cli(input_dir.path(), output_dir.path())
+        };
+        run(cli)?;
+        let output = output_dir.path().join("scan.tiff");
+        let mut decoder = tiff::decoder::Decoder::new(fs::File::open(&output)?)?;
+        assert_eq!(decoder.get_tag_u32(tiff::tags::Tag::Compression)?, tag);
+        // The LZW decoder trips a debug assertion in unoptimized builds, so only the others
+        // are decoded here.
+        if compression != TiffCompression::Lzw {
+            let decoded = image::open(&output)?;
+            assert_eq!(decoded.to_rgb8().get_pixel(70, 40), &image::Rgb([24, 64, 200]));
+        }
+        sizes.push(fs::metadata(&output)?.len());
+    }
+    assert!(sizes[1] < sizes[0] && sizes[2] < sizes[0], "compressed TIFFs should be smaller: {:?}", sizes);
+    Ok(())
+}
+
+/// Verifies that sideways camera photos are turned upright by their EXIF orientation.
+#[test]
+fn test_auto_orient() -> Result<()> {
+    let input_dir = tempdir()?;
+    let path = input_dir.path().join("portrait.jpg");
+    // Stored sideways: red on the left, which is the top once turned 90 degrees clockwise.
+    let stored = image::RgbImage::from_fn(40, 20, |x, _| if x < 20 { image::Rgb([255, 0, 0]) } else { image::Rgb([0, 0, 255]) });
+    DynamicImage::ImageRgb8(stored).save(&path)?;
+    let jpeg = fs::read(&path)?;
+    // A big-endian TIFF header and one IFD entry: Orientation (0x0112), SHORT, 6.
+    let exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\0\0\0\0";
+    let mut tagged = jpeg[..2].to_vec();
+    tagged.extend_from_slice(&[0xFF, 0xE1]);
+    tagged.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+    tagged.extend_from_slice(exif);
+    tagged.extend_from_slice(&jpeg[2..]);
+    fs::write(&path, tagged)?;
+
+    let upright_dir = tempdir()?;
+    run(test_cli(input_dir.path(), upright_dir.path()))?;
+    let upright = image::open(upright_dir.path().join("portrait.jpg"))?.to_rgb8();
+    assert_eq!(upright.dimensions(), (20, 40));
+    assert!(upright.get_pixel(10, 5)[0] > 200 && upright.get_pixel(10, 35)[2] > 200);
+
+    let stored_dir = tempdir()?;
+    run(Cli { no_auto_orient: true, ..test_cli(input_dir.path(), stored_dir.path()) })?;
+    assert_eq!(image::open(stored_dir.path().join("portrait.jpg"))?.dimensions(), (40, 20));
+    Ok(())
+}
+
+/// Verifies that `--raw-jpeg-policy` processes one file of a RAW+JPEG pair, or both under
+/// separate names.
+#[test]
+fn test_raw_jpeg_policy() -> Result<()> {
+    use exif::experimental::Writer;
+    use exif::{Field, In, Tag, Value};
+    use imagekit::cli::RawJpegPolicy;
+
+    let input_dir = tempdir()?;
+    // The RAW's preview is green and the camera JPEG red, so outputs show their source.
+    let mut preview = Vec::new();
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(300, 200, image::Rgb([0, 200, 0])))
+        .write_to(&mut std::io::Cursor::new(&mut preview), image::ImageFormat::Jpeg)?;
+    let make = Field { tag: Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"ACME".to_vec()]) };
+    let mut writer = Writer::new();
+    writer.push_field(&make);
+    writer.set_jpeg(&preview, In::THUMBNAIL);
+    let mut raw = std::io::Cursor::new(Vec::new());
+    writer.write(&mut raw, false)?;
+    fs::write(input_dir.path().join("IMG_1.DNG"), raw.into_inner())?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(300, 200, image::Rgb([200, 0, 0])))
+        .save(input_dir.path().join("IMG_1.jpg"))?;
+
+    let run_with = |policy: RawJpegPolicy| -> Result<(tempfile::TempDir, Vec<String>)> {
+        let output_dir = tempdir()?;
+        let cli = Cli { fast_thumbs: true, raw_jpeg_policy: policy, ..test_cli(input_dir.path(), output_dir.path()) };
+        run(cli)?;
+        let mut names: Vec<String> = fs::read_dir(output_dir.path())?
+            .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<_>>()?;
+        names.sort();
+        Ok((output_dir, names))
+    };
+    let green = |path: &Path| -> Result<bool> { Ok(image::open(path)?.to_rgb8().get_pixel(150, 100)[1] > 150) };
+
+    let (dir, names) = run_with(RawJpegPolicy::PreferJpeg)?;
+    assert_eq!(names, vec!["IMG_1.jpg"]);
+    assert!(!green(&dir.path().join("IMG_1.jpg"))?);
+    let (dir, names) = run_with(RawJpegPolicy::PreferRaw)?;
+    assert_eq!(names, vec!["IMG_1.jpg"]);
+    assert!(green(&dir.path().join("IMG_1.jpg"))?);
+    let (dir, names) = run_with(RawJpegPolicy::Both)?;
+    assert_eq!(names, vec!["IMG_1-raw.jpg", "IMG_1.jpg"]);
+    assert!(green(&dir.path().join("IMG_1-raw.jpg"))? && !green(&dir.path().join("IMG_1.jpg"))?);
+    Ok(())
+}
+
+/// Verifies that `--pick-best-of-burst` keeps only the sharpest of shots taken moments apart
+/// or looking alike, and reports the others.
+#[test]
+fn test_pick_best_of_burst() -> Result<()> {
+    use imagekit::cli::MetaArgs;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let report_path = output_dir.path().join("report.json");
+    let checker = image::RgbImage::from_fn(120, 80, |x, y| {
+        if (x / 10 + y / 10) % 2 == 0 { image::Rgb([250, 250, 250]) } else { image::Rgb([10, 10, 10]) }
+    });
+    let soft = image::imageops::blur(&checker, 3.0);
+    DynamicImage::ImageRgb8(checker.clone()).save(input_dir.path().join("sharp.png"))?;
+    DynamicImage::ImageRgb8(soft.clone()).save(input_dir.path().join("soft.png"))?;
+    let gradient = image::RgbImage::from_fn(120, 80, |x, _| image::Rgb([(255 - x * 2) as u8, 90, (x * 2) as u8]));
+    DynamicImage::ImageRgb8(gradient).save(input_dir.path().join("other.png"))?;
+
+    // Different subjects shot a second apart: a burst by time alone.
+    let plain_dir = tempdir()?;
+    let rotated = image::imageops::rotate90(&checker);
+    DynamicImage::ImageRgb8(image::imageops::blur(&rotated, 3.0)).save(plain_dir.path().join("burst_1.jpg"))?;
+    let stripes = image::RgbImage::from_fn(80, 120, |_, y| if y % 8 < 4 { image::Rgb([245, 245, 245]) } else { image::Rgb([10, 10, 10]) });
+    DynamicImage::ImageRgb8(stripes).save(plain_dir.path().join("burst_2.jpg"))?;
+    for (name, time) in [("burst_1.jpg", "2024:05:01 10:00:00"), ("burst_2.jpg", "2024:05:01 10:00:01")] {
+        let single = tempdir()?;
+        fs::copy(plain_dir.path().join(name), single.path().join(name))?;
+        imagekit::meta::run(&MetaArgs {
+            input_dir: single.path().to_path_buf(),
+            output_dir: input_dir.path().to_path_buf(),
+            strip_gps: false,
+            set: vec![format!("DateTimeOriginal={}", time).parse()?],
+        })?;
+    }
+
+    let cli = Cli {
+        pick_best_of_burst: Some(2.0),
+        report: Some(report_path.clone()),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    for kept in ["sharp.png", "other.png", "burst_2.jpg"] {
+        assert!(output_dir.path().join(kept).exists(), "{} should be kept", kept);
+    }
+    for discarded in ["soft.png", "burst_1.jpg"] {
+        assert!(!output_dir.path().join(discarded).exists(), "{} should be discarded", discarded);
+    }
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!(report["discarded"], 2);
+    let soft_entry = report["images"].as_array().unwrap().iter().find(|i| i["key"] == "soft.png").unwrap();
+    assert_eq!(soft_entry["status"], "discarded");
+    assert!(soft_entry["best_of_burst"].as_str().unwrap().ends_with("sharp.png"));
+    Ok(())
+}
+
+/// Builds a JPEG carrying EXIF data (a Make, a rotating orientation and a capture date), an
+/// RGB ICC profile and an XMP packet with one keyword. Returns it with the profile.
+fn tagged_jpeg() -> Result<(Vec<u8>, Vec<u8>)> {
+    use exif::experimental::Writer;
+    use exif::{Field, In, Tag, Value};
+    use img_parts::{Bytes, DynImage, ImageEXIF, ImageICC};
+
+    let fields = [
+        Field { tag: Tag::Make, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"ACME".to_vec()]) },
+        Field { tag: Tag::Copyright, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"ACME".to_vec()]) },
+        Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![6]) },
+        Field { tag: Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"2024:05:01 10:00:00".to_vec()]) },
+    ];
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut exif = std::io::Cursor::new(Vec::new());
+    writer.write(&mut exif, false)?;
+    // Only the header matters to imagekit: an RGB profile.
+    let mut icc = vec![0u8; 128];
+    icc[16..20].copy_from_slice(b"RGB ");
+
+    let mut plain = Vec::new();
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(80, 40, image::Rgb([200, 100, 50])))
+        .write_to(&mut std::io::Cursor::new(&mut plain), image::ImageFormat::Jpeg)?;
+    let mut source = DynImage::from_bytes(Bytes::from(plain))?.unwrap();
+    source.set_exif(Some(Bytes::from(exif.into_inner())));
+    source.set_icc_profile(Some(Bytes::from(icc.clone())));
+    let mut tagged = Vec::new();
+    source.encoder().write_to(&mut tagged)?;
+    // An XMP segment right after SOI.
+    let xmp = b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta><rdf:RDF><rdf:Description>\
+<dc:subject><rdf:Bag><rdf:li>holiday</rdf:li></rdf:Bag></dc:subject></rdf:Description></rdf:RDF></x:xmpmeta>";
+    let mut data = tagged[..2].to_vec();
+    data.extend_from_slice(&[0xFF, 0xE1]);
+    data.extend_from_slice(&((xmp.len() + 2) as u16).to_be_bytes());
+    data.extend_from_slice(xmp);
+    data.extend_from_slice(&tagged[2..]);
+    Ok((data, icc))
+}
+
+/// Verifies that `--keep-metadata` carries EXIF, ICC and XMP over, with the orientation reset
+/// and the pixel size updated, and that a WebP's `--dpi` is added to the kept EXIF.
+#[test]
+fn test_keep_metadata() -> Result<()> {
+    use exif::{In, Reader, Tag};
+    use img_parts::{Bytes, DynImage, ImageEXIF, ImageICC};
+    use imagekit::metadata::read_keywords;
+
+    let input_dir = tempdir()?;
+    let (data, icc) = tagged_jpeg()?;
+    fs::write(input_dir.path().join("photo.jpg"), data)?;
+
+    for format in [imagekit::cli::OutputFormat::Jpg, imagekit::cli::OutputFormat::Png] {
+        let output_dir = tempdir()?;
+        let cli = Cli {
+            keep_metadata: true,
+            width: Some(20),
+            output_format: Some(format),
+            ..test_cli(input_dir.path(), output_dir.path())
+        };
+        run(cli)?;
+        let output = fs::read_dir(output_dir.path())?.next().unwrap()?.path();
+        let bytes = fs::read(&output)?;
+        let exif = Reader::new().read_from_container(&mut std::io::Cursor::new(&bytes))?;
+        let value = |tag: Tag| exif.get_field(tag, In::PRIMARY).map(|f| f.display_value().to_string());
+        assert_eq!(value(Tag::Make).as_deref(), Some("\"ACME\""));
+        assert_eq!(value(Tag::DateTimeOriginal).as_deref(), Some("2024-05-01 10:00:00"));
+        // Already turned upright, and resized to 20 pixels wide.
+        assert_eq!(exif.get_field(Tag::Orientation, In::PRIMARY).and_then(|f| f.value.get_uint(0)), Some(1));
+        assert_eq!(exif.get_field(Tag::PixelXDimension, In::PRIMARY).and_then(|f| f.value.get_uint(0)), Some(20));
+        let icc_out = DynImage::from_bytes(Bytes::from(bytes.clone()))?.unwrap().icc_profile();
+        assert_eq!(icc_out.as_deref(), Some(&icc[..]));
+        assert_eq!(read_keywords(&bytes), vec!["holiday"], "{}", output.display());
+    }
+
+    // WebP keeps its DPI in EXIF, which must be merged into the kept block, not replace it.
+    let output_dir = tempdir()?;
+    let cli = Cli {
+        keep_metadata: true,
+        dpi: Some(300),
+        output_format: Some(imagekit::cli::OutputFormat::Webp),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+    let webp = DynImage::from_bytes(Bytes::from(fs::read(output_dir.path().join("photo.webp"))?))?.unwrap();
+    let exif = Reader::new().read_raw(webp.exif().context("WebP should keep its EXIF")?.to_vec())?;
+    let value = |tag: Tag| exif.get_field(tag, In::PRIMARY).map(|f| f.display_value().to_string());
+    assert_eq!(value(Tag::Copyright).as_deref(), Some("\"ACME\""));
+    assert_eq!(value(Tag::XResolution).as_deref(), Some("300"));
+    assert_eq!(exif.get_field(Tag::ResolutionUnit, In::PRIMARY).and_then(|f| f.value.get_uint(0)), Some(2));
+    Ok(())
+}
+
+/// Verifies that `--min-sharpness` holds back a blurred image and copies it to
+/// `--quarantine-dir`, while a sharp one is processed.
+#[test]
+fn test_min_sharpness() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let quarantine_dir = tempdir()?;
+    let report_path = output_dir.path().join("report.json");
+    let checker = image::RgbImage::from_fn(120, 80, |x, y| {
+        if (x / 10 + y / 10) % 2 == 0 { image::Rgb([250, 250, 250]) } else { image::Rgb([10, 10, 10]) }
+    });
+    fs::create_dir(input_dir.path().join("album"))?;
+    DynamicImage::ImageRgb8(image::imageops::blur(&checker, 4.0)).save(input_dir.path().join("album/soft.png"))?;
+    DynamicImage::ImageRgb8(checker).save(input_dir.path().join("sharp.png"))?;
+
+    let cli = Cli {
+        min_sharpness: Some(100.0),
+        quarantine_dir: Some(quarantine_dir.path().to_path_buf()),
+        report: Some(report_path.clone()),
+        ..test_cli(input_dir.path(), output_dir.path())
+    };
+    run(cli)?;
+
+    assert!(output_dir.path().join("sharp.png").exists());
+    assert!(!output_dir.path().join("album/soft.png").exists());
+    assert!(quarantine_dir.path().join("album/soft.png").exists());
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!(report["blurry"], 1);
+    let images = report["images"].as_array().unwrap();
+    let soft = images.iter().find(|i| i["key"] == "album/soft.png").unwrap();
+    assert_eq!(soft["status"], "blurry");
+    assert!(soft["sharpness"].as_f64().unwrap() < 100.0);
+    let sharp = images.iter().find(|i| i["key"] == "sharp.png").unwrap();
+    assert!(sharp["sharpness"].as_f64().unwrap() >= 100.0);
+    Ok(())
+}
+
+/// Verifies that `--strip-metadata` leaves no EXIF, XMP, ICC profile or text in outputs of
+/// any format, including metadata added to them afterwards.
+#[test]
+fn test_strip_metadata() -> Result<()> {
+    use imagekit::cli::OutputFormat;
+    use imagekit::metadata::strip_metadata;
+
+    let traces: [&[u8]; 11] = [
+        b"Exif", b"EXIF", b"eXIf", b"ICC_PROFILE", b"iCCP", b"ICCP", b"http://ns.adobe.com/xap", b"XMP ", b"tEXt", b"iTXt",
+        b"ACME",
+    ];
+    let assert_clean = |path: &Path| -> Result<()> {
+        let bytes = fs::read(path)?;
+        for trace in traces {
+            assert!(!bytes.windows(trace.len()).any(|w| w == trace), "{:?} left in {}", trace, path.display());
+        }
+        image::open(path)?;
+        Ok(())
+    };
+
+    let input_dir = tempdir()?;
+    let (data, _) = tagged_jpeg()?;
+    fs::write(input_dir.path().join("photo.jpg"), &data)?;
+    for format in [OutputFormat::Jpg, OutputFormat::Png, OutputFormat::Webp, OutputFormat::Gif, OutputFormat::Tiff] {
+        let output_dir = tempdir()?;
+        let cli = Cli {
+            strip_metadata: true,
+            // Stored in EXIF for WebP.
+            dpi: Some(300),
+            output_format: Some(format),
+            ..test_cli(input_dir.path(), output_dir.path())
+        };
+        run(cli)?;
+        assert_clean(&fs::read_dir(output_dir.path())?.next().unwrap()?.path())?;
+    }
+
+    // Files that do carry metadata, with a comment segment for the JPEG.
+    let tagged_dir = tempdir()?;
+    let mut commented = data[..2].to_vec();
+    commented.extend_from_slice(&[0xFF, 0xFE, 0, 6]);
+    commented.extend_from_slice(b"ACME");
+    commented.extend_from_slice(&data[2..]);
+    fs::write(tagged_dir.path().join("photo.jpg"), commented)?;
+    let cli = Cli {
+        keep_metadata: true,
+        output_format: Some(OutputFormat::Png),
+        ..test_cli(input_dir.path(), tagged_dir.path())
+    };
+    run(cli)?;
+    for name in ["photo.jpg", "photo.png"] {
+        let path = tagged_dir.path().join(name);
+        assert!(fs::read(&path)?.windows(4).any(|w| w == b"ACME"));
+        strip_metadata(&path)?;
+        assert_clean(&path)?;
+    }
+    Ok(())
+}
+
+/// Verifies that the source's ICC profile is embedded in JPEG, PNG and WebP outputs by
+/// default, without losing a WebP's transparency.
+#[test]
+fn test_icc_profile_preserved() -> Result<()> {
+    use image::GenericImageView;
+    use imagekit::cli::OutputFormat;
+    use img_parts::{Bytes, DynImage, ImageICC};
+
+    let input_dir = tempdir()?;
+    let (data, icc) = tagged_jpeg()?;
+    fs::write(input_dir.path().join("photo.jpg"), &data)?;
+    let mut translucent = Vec::new();
+    DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(40, 40, image::Rgba([200, 100, 50, 128])))
+        .write_to(&mut std::io::Cursor::new(&mut translucent), image::ImageFormat::Png)?;
+    let mut png = DynImage::from_bytes(Bytes::from(translucent))?.unwrap();
+    png.set_icc_profile(Some(Bytes::from(icc.clone())));
+    let mut translucent = Vec::new();
+    png.encoder().write_to(&mut translucent)?;
+    fs::write(input_dir.path().join("overlay.png"), translucent)?;
+
+    for format in [OutputFormat::Jpg, OutputFormat::Png, OutputFormat::Webp] {
+        let output_dir = tempdir()?;
+        let cli = Cli { width: Some(20), output_format: Some(format), ..test_cli(input_dir.path(), output_dir.path()) };
+        run(cli)?;
+        for entry in fs::read_dir(output_dir.path())? {
+            let path = entry?.path();
+            let profile = DynImage::from_bytes(Bytes::from(fs::read(&path)?))?.unwrap().icc_profile();
+            assert_eq!(profile.as_deref(), Some(&icc[..]), "{}", path.display());
+            if path.extension() != Some("jpg".as_ref()) && path.file_stem() == Some("overlay".as_ref()) {
+                assert_eq!(image::open(&path)?.get_pixel(10, 10)[3], 128, "{}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Verifies that `stitch` puts overlapping shots, drifting a little downward, back together
+/// into the scene they were cut from.
+#[test]
+fn test_stitch_panorama() -> Result<()> {
+    use imagekit::cli::StitchArgs;
+
+    let scene = image::RgbImage::from_fn(320, 120, |x, y| {
+        let block = ((x / 8).wrapping_mul(73856093) ^ (y / 8).wrapping_mul(19349663)) % 251;
+        image::Rgb([block as u8, (block * 3 % 256) as u8, ((x + y) % 256) as u8])
+    });
+    let input_dir = tempdir()?;
+    for (name, x, y, width) in [("a.png", 0, 0, 150), ("b.png", 110, 4, 150), ("c.png", 220, 6, 100)] {
+        let shot = image::imageops::crop_imm(&scene, x, y, width, 110).to_image();
+        DynamicImage::ImageRgb8(shot).save(input_dir.path().join(name))?;
+    }
+    let output_dir = tempdir()?;
+    let output = output_dir.path().join("pano.png");
+    imagekit::stitch::run(&StitchArgs { input_dir: input_dir.path().to_path_buf(), output: output.clone(), quality: 90 })?;
+
+    // Cropped to the rows all three shots cover.
+    let panorama = image::open(&output)?.to_rgb8();
+    assert_eq!(panorama.dimensions(), (320, 104));
+    let expected = image::imageops::crop_imm(&scene, 0, 6, 320, 104).to_image();
+    assert!(panorama.pixels().zip(expected.pixels()).all(|(p, q)| p == q));
+    Ok(())
+}
+
+/// Verifies that `hdr-merge` fuses a bracket told apart by exposure bias into one output, in
+/// the shots' format and with detail from both ends, and leaves a lone shot out.
+#[test]
+fn test_hdr_merge() -> Result<()> {
+    use exif::experimental::Writer;
+    use exif::{Field, In, SRational, Tag, Value};
+    use imagekit::cli::HdrMergeArgs;
+    use img_parts::{Bytes, DynImage, ImageEXIF};
+
+    let scene = image::RgbImage::from_fn(64, 48, |x, y| image::Rgb([(x * 4) as u8, (y * 5) as u8, 120]));
+    let input_dir = tempdir()?;
+    let shots = [("a.jpg", 0.35, 0, "10:00:00"), ("b.jpg", 1.0, -2, "10:00:00"), ("c.jpg", 2.5, 2, "10:00:01"), ("lone.jpg", 1.0, 0, "10:05:00")];
+    for (name, gain, bias, time) in shots {
+        let exposed = image::RgbImage::from_fn(64, 48, |x, y| {
+            image::Rgb(scene.get_pixel(x, y).0.map(|c| (c as f32 * gain).min(255.0) as u8))
+        });
+        let mut plain = Vec::new();
+        DynamicImage::ImageRgb8(exposed).write_to(&mut std::io::Cursor::new(&mut plain), image::ImageFormat::Jpeg)?;
+        let fields = [
+            Field { tag: Tag::DateTimeOriginal, ifd_num: In::PRIMARY, value: Value::Ascii(vec![format!("2024:05:01 {}", time).into_bytes()]) },
+            Field { tag: Tag::ExposureBiasValue, ifd_num: In::PRIMARY, value: Value::SRational(vec![SRational { num: bias, denom: 1 }]) },
+        ];
+        let mut writer = Writer::new();
+        for field in &fields {
+            writer.push_field(field);
+        }
+        let mut exif = std::io::Cursor::new(Vec::new());
+        writer.write(&mut exif, false)?;
+        let mut jpeg = DynImage::from_bytes(Bytes::from(plain))?.unwrap();
+        jpeg.set_exif(Some(Bytes::from(exif.into_inner())));
+        let mut data = Vec::new();
+        jpeg.encoder().write_to(&mut data)?;
+        fs::write(input_dir.path().join(name), data)?;
+    }
+
+    let output_dir = tempdir()?;
+    imagekit::hdr::run(&HdrMergeArgs {
+        bracket_window: 2.0,
+        bracket_size: None,
+        batch: test_cli(input_dir.path(), output_dir.path()),
+    })?;
+
+    let outputs: Vec<String> =
+        fs::read_dir(output_dir.path())?.map(|e| Ok(e?.file_name().to_string_lossy().into_owned())).collect::<Result<_>>()?;
+    assert_eq!(outputs, vec!["a-hdr.jpg"]);
+    let fused = image::open(output_dir.path().join("a-hdr.jpg"))?.to_rgb8();
+    assert_eq!(fused.dimensions(), (64, 48));
+    // The dark end is lifted above the darkest shot's, and the bright one kept below the
+    // brightest shot's clipping.
+    let red = |x, y| fused.get_pixel(x, y)[0] as i32;
+    assert!(red(2, 24) > 3, "dark end {}", red(2, 24));
+    assert!(red(60, 24) < 250, "bright end {}", red(60, 24));
+    assert!(red(60, 24) - red(2, 24) > 100, "{} to {}", red(2, 24), red(60, 24));
+    Ok(())
+}
+
+/// Verifies that `--dry-run` plans the resize, watermark and conversion of each image without
+/// writing anything, not even the output directory.
+#[test]
+fn test_dry_run() -> Result<()> {
+    use imagekit::cli::OutputFormat;
+    use imagekit::rules::Pipeline;
+
+    let input_dir = tempdir()?;
+    let output_root = tempdir()?;
+    let output_dir = output_root.path().join("out");
+    let source = input_dir.path().join("photo.png");
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(120, 80, image::Rgb([90, 120, 200]))).save(&source)?;
+
+    let cli = Cli {
+        dry_run: true,
+        width: Some(60),
+        watermark_text: Some("ACME".to_string()),
+        output_format: Some(OutputFormat::Webp),
+        ..test_cli(input_dir.path(), &output_dir)
+    };
+    let steps = imagekit::plan::steps(&cli, &RunContext::default(), &source, &Pipeline::for_path(&cli, &source)?);
+    assert_eq!(steps, vec!["resize: width 60", "watermark: \"ACME\" (se)", "convert: png -> webp, quality 85"]);
+    run(cli)?;
+    assert!(!output_dir.exists());
+    Ok(())
+}
+
+/// Verifies that `focus-stack` aligns a shifted shot and takes each half of the scene from the
+/// shot where it's in focus.
+#[test]
+fn test_focus_stack() -> Result<()> {
+    use imagekit::cli::FocusStackArgs;
+
+    let scene = image::RgbImage::from_fn(130, 96, |x, y| {
+        let block = ((x / 4).wrapping_mul(73856093) ^ (y / 4).wrapping_mul(19349663)) % 251;
+        image::Rgb([block as u8, (block * 3 % 256) as u8, 128])
+    });
+    let blurred = image::imageops::blur(&scene, 2.0);
+    // `a` is in focus on the left, `b` on the right and framed two pixels further left.
+    let a = image::RgbImage::from_fn(128, 96, |x, y| *if x < 64 { &scene } else { &blurred }.get_pixel(x, y));
+    let b = image::RgbImage::from_fn(128, 96, |x, y| *if x < 64 { &blurred } else { &scene }.get_pixel(x + 2, y));
+    let input_dir = tempdir()?;
+    DynamicImage::ImageRgb8(a.clone()).save(input_dir.path().join("a.png"))?;
+    DynamicImage::ImageRgb8(b).save(input_dir.path().join("b.png"))?;
+    let output_dir = tempdir()?;
+    let output = output_dir.path().join("stacked.png");
+    imagekit::focus::run(&FocusStackArgs {
+        input_dir: input_dir.path().to_path_buf(),
+        output: output.clone(),
+        quality: 90,
+        no_align: false,
+    })?;
+
+    // Cropped to the columns both shots cover.
+    let stacked = image::open(&output)?.to_rgb8();
+    assert_eq!(stacked.dimensions(), (126, 96));
+    let error = |img: &image::RgbImage, dx: u32| {
+        let sum: u64 = img.enumerate_pixels().map(|(x, y, p)| p[0].abs_diff(scene.get_pixel(x + dx, y)[0]) as u64).sum();
+        sum as f64 / (img.width() * img.height()) as f64
+    };
+    let (stacked_error, shot_error) = (error(&stacked, 2), error(&a, 0));
+    assert!(stacked_error < shot_error / 3.0, "stacked {} against one shot {}", stacked_error, shot_error);
+    Ok(())
+}
+
+/// Verifies that `--skip-up-to-date` leaves outputs newer than their sources alone and
+/// processes changed and new images, counting the skipped ones as unchanged in the report.
+#[test]
+fn test_skip_up_to_date() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    for (name, shade) in [("a.png", 10), ("b.png", 20)] {
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([shade, 0, 0]))).save(input_dir.path().join(name))?;
+    }
+    run(Cli { width: Some(10), ..test_cli(input_dir.path(), output_dir.path()) })?;
+
+    // A marker shows whether a.png's output gets rewritten; b.png is edited after its output
+    // was written, and c.png is new.
+    fs::write(output_dir.path().join("a.png"), b"kept")?;
+    let edited = input_dir.path().join("b.png");
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([99, 0, 0]))).save(&edited)?;
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+    fs::File::options().write(true).open(&edited)?.set_modified(later)?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([30, 0, 0]))).save(input_dir.path().join("c.png"))?;
+
+    let report_path = output_dir.path().join("report.json");
+    run(Cli {
+        width: Some(10),
+        skip_up_to_date: true,
+        report: Some(report_path.clone()),
+        ..test_cli(input_dir.path(), output_dir.path())
+    })?;
+    assert_eq!(fs::read(output_dir.path().join("a.png"))?, b"kept");
+    assert_eq!(image::open(output_dir.path().join("b.png"))?.to_rgb8().get_pixel(0, 0)[0], 99);
+    assert!(output_dir.path().join("c.png").exists());
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!((report["processed"].as_u64(), report["unchanged"].as_u64()), (Some(2), Some(1)));
+    Ok(())
+}
+
+/// Verifies that `timelapse` numbers frames in capture order rather than by name, stamps them
+/// with their capture time and, with `--deflicker`, evens out their brightness.
+#[test]
+fn test_timelapse() -> Result<()> {
+    use imagekit::cli::{App, Command, MetaArgs};
+
+    // Captured in the reverse of name order, with the exposure flickering between frames;
+    // blue tells the frames apart.
+    let input_dir = tempdir()?;
+    for frame in 0..6u8 {
+        let plain_dir = tempdir()?;
+        let level = if frame % 2 == 0 { 100 } else { 150 };
+        let pixels = image::RgbImage::from_pixel(200, 100, image::Rgb([level, level, 20 + frame * 30]));
+        DynamicImage::ImageRgb8(pixels).save(plain_dir.path().join(format!("f{}.jpg", 5 - frame)))?;
+        imagekit::meta::run(&MetaArgs {
+            input_dir: plain_dir.path().to_path_buf(),
+            output_dir: input_dir.path().to_path_buf(),
+            strip_gps: false,
+            set: vec![format!("DateTimeOriginal=2024:05:01 18:00:0{}", frame).parse()?],
+        })?;
+    }
+    let timelapse = |output_dir: &Path, options: &[&str]| {
+        let dirs = ["-i", input_dir.path().to_str().unwrap(), "-o", output_dir.to_str().unwrap()];
+        let argv = ["imagekit", "timelapse", "--frame-format", "png"].iter().chain(&dirs).chain(options);
+        match App::parse_from(argv).command {
+            Some(Command::Timelapse(args)) => args,
+            other => panic!("parsed as {:?}", other),
+        }
+    };
+
+    let output_dir = tempdir()?;
+    imagekit::timelapse::run(&timelapse(output_dir.path(), &["--stamp", "--stamp-size", "10%"]))?;
+    for frame in 0..6u8 {
+        let img = image::open(output_dir.path().join(format!("frame_{:05}.png", frame + 1)))?.to_rgb8();
+        let blue = img.get_pixel(150, 20)[2];
+        assert!(blue.abs_diff(20 + frame * 30) < 8, "frame {} has blue {}", frame + 1, blue);
+        // The stamp is white, in the bottom-left corner.
+        let stamped = (0..100).flat_map(|x| (50..100).map(move |y| (x, y))).any(|(x, y)| img.get_pixel(x, y).0.iter().all(|&c| c > 240));
+        assert!(stamped, "frame {} has no stamp", frame + 1);
+    }
+
+    let output_dir = tempdir()?;
+    imagekit::timelapse::run(&timelapse(output_dir.path(), &["--deflicker"]))?;
+    let brightness: Vec<u8> = (1..=6)
+        .map(|frame| Ok(image::open(output_dir.path().join(format!("frame_{:05}.png", frame)))?.to_luma8().get_pixel(150, 20)[0]))
+        .collect::<Result<_>>()?;
+    let (darkest, brightest) = (brightness.iter().min().unwrap(), brightness.iter().max().unwrap());
+    assert!(brightest - darkest <= 4, "brightness still flickers: {:?}", brightness);
+    Ok(())
+}
+
+/// Verifies that `--lens-correct` finds the lens named in EXIF in a lensfun database and undoes
+/// its barrel distortion, so points along a radius regain their true spacing.
+#[test]
+fn test_lens_correct() -> Result<()> {
+    use exif::experimental::Writer;
+    use exif::{Field, In, Rational, Tag, Value};
+    use img_parts::{Bytes, DynImage, ImageEXIF};
+
+    let db_dir = tempdir()?;
+    let db = db_dir.path().join("lenses.xml");
+    fs::write(
+        &db,
+        r#"<lensdatabase version="2">
+    <lens>
+        <maker>Test</maker>
+        <model>Test Optics 10mm f/2</model>
+        <mount>Test</mount>
+        <cropfactor>1.5</cropfactor>
+        <calibration>
+            <distortion model="poly3" focal="10" k1="-0.1" />
+        </calibration>
+    </lens>
+</lensdatabase>"#,
+    )?;
+
+    // Dots at 0.3 and 0.9 of the half height from the center, where the lens maps them
+    // (r * (1.1 - 0.1 * r^2)); corrected, the first is a third as far out as the second.
+    let distorted = |r: f32| r * (1.1 - 0.1 * r * r) * 150.0 + 200.0;
+    let mut pixels = image::RgbImage::new(400, 300);
+    for x in [distorted(0.3), distorted(0.9)] {
+        for dx in 0..3 {
+            for dy in 0..3 {
+                pixels.put_pixel(x as u32 - 1 + dx, 149 + dy, image::Rgb([255, 255, 255]));
+            }
+        }
+    }
+    let mut plain = Vec::new();
+    DynamicImage::ImageRgb8(pixels).write_to(&mut std::io::Cursor::new(&mut plain), image::ImageFormat::Jpeg)?;
+    let fields = [
+        Field { tag: Tag::LensModel, ifd_num: In::PRIMARY, value: Value::Ascii(vec![b"Test Optics 10mm f/2".to_vec()]) },
+        Field { tag: Tag::FocalLength, ifd_num: In::PRIMARY, value: Value::Rational(vec![Rational { num: 10, denom: 1 }]) },
+    ];
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut exif = std::io::Cursor::new(Vec::new());
+    writer.write(&mut exif, false)?;
+    let mut jpeg = DynImage::from_bytes(Bytes::from(plain))?.unwrap();
+    jpeg.set_exif(Some(Bytes::from(exif.into_inner())));
+    let input_dir = tempdir()?;
+    let mut data = Vec::new();
+    jpeg.encoder().write_to(&mut data)?;
+    fs::write(input_dir.path().join("wide.jpg"), data)?;
+
+    let output_dir = tempdir()?;
+    run(Cli {
+        lens_correct: Some(imagekit::lens::LensCorrect::Auto),
+        lens_db: Some(db_dir.path().to_path_buf()),
+        output_format: Some(imagekit::cli::OutputFormat::Png),
+        ..test_cli(input_dir.path(), output_dir.path())
+    })?;
+    let corrected = image::open(output_dir.path().join("wide.png"))?.to_luma8();
+    assert_eq!(corrected.dimensions(), (400, 300));
+    // Brightness-weighted distance from the center of the dot in each part of the row.
+    let dot = |from: u32, to: u32| {
+        let (mut sum, mut weight) = (0.0, 0.0);
+        for x in from..to {
+            for y in 140..160 {
+                let v = corrected.get_pixel(x, y)[0] as f32;
+                sum += v * (x as f32 + 0.5 - 200.0);
+                weight += v;
+            }
+        }
+        sum / weight
+    };
+    let ratio = dot(205, 300) / dot(300, 400);
+    assert!((ratio - 1.0 / 3.0).abs() < 0.01, "ratio {}", ratio);
+    Ok(())
+}
+
+/// Verifies the `--if-exists` policies: `skip` leaves existing outputs alone, `rename` writes
+/// beside them under a numbered name, and `error` aborts before anything is written.
+#[test]
+fn test_if_exists() -> Result<()> {
+    use imagekit::cli::IfExists;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    for name in ["a.png", "b.png"] {
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([50, 0, 0]))).save(input_dir.path().join(name))?;
+    }
+    fs::write(output_dir.path().join("a.png"), b"kept")?;
+
+    let report_path = output_dir.path().join("report.json");
+    let if_exists = |policy| Cli { if_exists: policy, ..test_cli(input_dir.path(), output_dir.path()) };
+    run(Cli { report: Some(report_path.clone()), ..if_exists(IfExists::Skip) })?;
+    assert_eq!(fs::read(output_dir.path().join("a.png"))?, b"kept");
+    assert!(output_dir.path().join("b.png").exists());
+    let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_path)?)?;
+    assert_eq!((report["processed"].as_u64(), report["unchanged"].as_u64()), (Some(1), Some(1)));
+
+    run(if_exists(IfExists::Rename))?;
+    assert_eq!(fs::read(output_dir.path().join("a.png"))?, b"kept");
+    assert!(image::open(output_dir.path().join("a-1.png")).is_ok());
+    assert!(image::open(output_dir.path().join("b-1.png")).is_ok());
+
+    fs::remove_file(output_dir.path().join("b.png"))?;
+    fs::remove_file(output_dir.path().join("b-1.png"))?;
+    let error = run(if_exists(IfExists::Error)).unwrap_err();
+    assert!(error.to_string().contains("a.png"), "{}", error);
+    assert!(!output_dir.path().join("b.png").exists());
+
+    // Re-encoding for `--total-size` replaces the run's own output rather than numbering another.
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let mut rng = imagekit::random::Rng::new(7, "budget");
+    image::RgbImage::from_fn(300, 200, |_, _| image::Rgb([rng.range(0, 255) as u8, 80, 160]))
+        .save(input_dir.path().join("a.png"))?;
+    fs::write(output_dir.path().join("a.jpg"), b"kept")?;
+    run(Cli {
+        output_format: Some(imagekit::cli::OutputFormat::Jpg),
+        total_size: Some(imagekit::cli::ByteSize(20_000)),
+        if_exists: IfExists::Rename,
+        ..test_cli(input_dir.path(), output_dir.path())
+    })?;
+    let mut names: Vec<String> = fs::read_dir(output_dir.path())?
+        .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+        .collect::<Result<_>>()?;
+    names.sort();
+    assert_eq!(names, ["a-1.jpg", "a.jpg"]);
+    assert_eq!(fs::read(output_dir.path().join("a.jpg"))?, b"kept");
+    Ok(())
+}
+
+/// Verifies that `--perspective` straightens a page shot at an angle into an upright
+/// rectangle, whether its corners are given, in any order, or found with `auto`.
+#[test]
+fn test_perspective() -> Result<()> {
+    use imagekit::perspective::Perspective;
+
+    // A bright page, skewed, on a dark desk.
+    let corners = [(60.0f32, 40.0f32), (340.0, 70.0), (320.0, 260.0), (80.0, 240.0)];
+    let inside = |x: f32, y: f32| {
+        (0..4).all(|i| {
+            let ((ax, ay), (bx, by)) = (corners[i], corners[(i + 1) % 4]);
+            (bx - ax) * (y - ay) - (by - ay) * (x - ax) >= 0.0
+        })
+    };
+    let input_dir = tempdir()?;
+    let desk = image::RgbImage::from_fn(400, 300, |x, y| {
+        if inside(x as f32 + 0.5, y as f32 + 0.5) { image::Rgb([235, 235, 230]) } else { image::Rgb([40, 35, 30]) }
+    });
+    DynamicImage::ImageRgb8(desk).save(input_dir.path().join("page.png"))?;
+
+    for perspective in ["auto", "80,240 340,70 60,40 320,260"] {
+        let output_dir = tempdir()?;
+        run(Cli { perspective: Some(perspective.parse::<Perspective>()?), ..test_cli(input_dir.path(), output_dir.path()) })?;
+        let page = image::open(output_dir.path().join("page.png"))?.to_luma8();
+        // As wide as the longer of the top and bottom edges, as tall as the longer side.
+        let (width, height) = page.dimensions();
+        assert!(width.abs_diff(282) <= 3 && height.abs_diff(201) <= 3, "{}: {}x{}", perspective, width, height);
+        let dark = page.pixels().filter(|p| p[0] < 128).count();
+        assert!(dark * 100 < page.len(), "{}: {} dark pixels", perspective, dark);
+    }
+    assert!("1,2 3,4 5,6".parse::<Perspective>().is_err());
+    Ok(())
+}
+
+/// Verifies that a `--quiet` run, which draws no progress bar and prints no per-image lines,
+/// still processes every image, in either scheduling mode.
+#[test]
+fn test_quiet_run() -> Result<()> {
+    let input_dir = tempdir()?;
+    for name in ["a.png", "b.png", "c.png"] {
+        DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([80, 0, 0]))).save(input_dir.path().join(name))?;
+    }
+    for order in [None, Some("name")] {
+        let output_dir = tempdir()?;
+        let mut args = vec!["imagekit", "-i", input_dir.path().to_str().unwrap(), "-o", output_dir.path().to_str().unwrap(), "--quiet"];
+        args.extend(order.iter().flat_map(|order| ["--order", *order]));
+        let cli = Cli::parse_from(args);
+        assert!(cli.quiet);
+        run(cli)?;
+        for name in ["a.png", "b.png", "c.png"] {
+            assert!(output_dir.path().join(name).exists());
+        }
+    }
+    Ok(())
+}
+
+/// Verifies that `--font` files are tried ahead of the embedded fonts, and that a file that
+/// isn't a font fails the run.
+#[test]
+fn test_custom_font() -> Result<()> {
+    let input_dir = tempdir()?;
+    let font_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(300, 100, image::Rgb([20, 20, 20]))).save(input_dir.path().join("a.png"))?;
+    // The CJK font has Latin glyphs of its own, drawn differently from Roboto's.
+    let brand = font_dir.path().join("Brand.otf");
+    fs::write(&brand, imagekit::assets::Asset::get("SourceHanSansSC-Regular.otf").unwrap().data)?;
+    let not_a_font = font_dir.path().join("notes.ttf");
+    fs::write(&not_a_font, b"not a font")?;
+
+    let render = |fonts: Vec<std::path::PathBuf>| -> Result<image::RgbImage> {
+        let output_dir = tempdir()?;
+        run(Cli {
+            watermark_text: Some("Brand".to_string()),
+            font_size: 48,
+            fonts,
+            ..test_cli(input_dir.path(), output_dir.path())
+        })?;
+        Ok(image::open(output_dir.path().join("a.png"))?.to_rgb8())
+    };
+    let embedded = render(Vec::new())?;
+    assert_ne!(render(vec![brand])?, embedded);
+    assert!(render(vec![not_a_font]).is_err());
+    Ok(())
+}
+
+/// Verifies that PDFs are only taken as inputs with `--pdf-dpi`, which a build without the
+/// `pdf` feature refuses before processing anything.
+#[cfg(not(feature = "pdf"))]
+#[test]
+fn test_pdf_inputs_need_the_feature() -> Result<()> {
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(20, 10, image::Rgb([80, 0, 0]))).save(input_dir.path().join("a.png"))?;
+    fs::write(input_dir.path().join("scan.pdf"), b"%PDF-1.4\n%%EOF\n")?;
+
+    run(test_cli(input_dir.path(), output_dir.path()))?;
+    assert!(output_dir.path().join("a.png").exists());
+    assert!(!output_dir.path().join("scan.png").exists());
+
+    let error = run(Cli { pdf_dpi: Some(150), ..test_cli(input_dir.path(), output_dir.path()) }).unwrap_err();
+    assert!(error.to_string().contains("`pdf` feature"), "{}", error);
+    Ok(())
+}
+
+/// Verifies that `--fonts` replaces the whole fallback chain, mixing embedded fonts and files,
+/// and that the default chain spelled out renders as the built-in one.
+#[test]
+fn test_font_chain() -> Result<()> {
+    use imagekit::assets::{default_chain, FontSource};
+
+    let input_dir = tempdir()?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(300, 100, image::Rgb([20, 20, 20]))).save(input_dir.path().join("a.png"))?;
+
+    let chain: Vec<FontSource> = "embedded:source-han-sans, Brand.otf".split(',').map(str::parse).collect::<Result<_, _>>()?;
+    assert_eq!(chain, vec![FontSource::Embedded("SourceHanSansSC-Regular.otf"), FontSource::File("Brand.otf".into())]);
+    assert_eq!(chain[0].to_string(), "embedded:source-han-sans");
+    assert!("embedded:comic-sans".parse::<FontSource>().is_err());
+
+    let render = |font_chain: Vec<FontSource>| -> Result<image::RgbImage> {
+        let output_dir = tempdir()?;
+        run(Cli {
+            watermark_text: Some("Brand".to_string()),
+            font_size: 48,
+            font_chain,
+            ..test_cli(input_dir.path(), output_dir.path())
+        })?;
+        Ok(image::open(output_dir.path().join("a.png"))?.to_rgb8())
+    };
+    let embedded = render(Vec::new())?;
+    assert_eq!(render(default_chain())?, embedded);
+    assert_ne!(render(vec![FontSource::Embedded("SourceHanSansSC-Regular.otf")])?, embedded);
+    assert!(render(vec![FontSource::File(input_dir.path().join("missing.ttf"))]).is_err());
+    Ok(())
+}
+
+/// Verifies that each page of a multi-page TIFF is processed as its own image, written as
+/// `<name>_p<page>` and turned upright by its own Orientation tag, and that `--pages` picks
+/// which ones. Single-page TIFFs keep the plain name.
+#[test]
+fn test_multipage_tiff() -> Result<()> {
+    use tiff::encoder::colortype::{Gray8, RGB8};
+
+    let input_dir = tempdir()?;
+    let mut tiff = tiff::encoder::TiffEncoder::new(fs::File::create(input_dir.path().join("scan.tif"))?)?;
+    tiff.write_image::<RGB8>(40, 20, &[200, 0, 0].repeat(40 * 20))?;
+    tiff.write_image::<Gray8>(16, 16, &[128; 16 * 16])?;
+    // Shot sideways: Orientation 6 turns it a quarter clockwise.
+    let mut page = tiff.new_image::<RGB8>(30, 10)?;
+    page.encoder().write_tag(tiff::tags::Tag::Orientation, 6u16)?;
+    page.write_data(&[0, 0, 200].repeat(30 * 10))?;
+    DynamicImage::ImageRgb8(image::RgbImage::from_pixel(12, 12, image::Rgb([0, 200, 0]))).save(input_dir.path().join("single.tif"))?;
+
+    let output_dir = tempdir()?;
+    run(test_cli(input_dir.path(), output_dir.path()))?;
+    assert!(!output_dir.path().join("scan.tif").exists());
+    let first = image::open(output_dir.path().join("scan_p1.tif"))?;
+    assert_eq!((first.width(), first.height()), (40, 20));
+    assert_eq!(first.to_rgb8().get_pixel(5, 5).0, [200, 0, 0]);
+    assert_eq!(image::open(output_dir.path().join("scan_p2.tif"))?.to_luma8().get_pixel(5, 5).0, [128]);
+    let third = image::open(output_dir.path().join("scan_p3.tif"))?;
+    assert_eq!((third.width(), third.height()), (10, 30));
+    assert!(output_dir.path().join("single.tif").exists());
+    assert!(!output_dir.path().join("single_p1.tif").exists());
+
+    let output_dir = tempdir()?;
+    let cli = Cli::parse_from([
+        "imagekit".as_ref(),
+        "-i".as_ref(),
+        input_dir.path().as_os_str(),
+        "-o".as_ref(),
+        output_dir.path().as_os_str(),
+        "--pages".as_ref(),
+        "1,3-".as_ref(),
+    ]);
+    run(cli)?;
+    assert!(output_dir.path().join("scan_p1.tif").exists());
+    assert!(!output_dir.path().join("scan_p2.tif").exists());
+    assert!(output_dir.path().join("scan_p3.tif").exists());
+    assert!(output_dir.path().join("single.tif").exists());
+
+    assert!("2-1".parse::<imagekit::pages::PageSelection>().is_err());
+    assert!("0".parse::<imagekit::pages::PageSelection>().is_err());
+    Ok(())
+}
+
+/// Verifies that turned text runs diagonally: at 45 degrees the ink on the left of a centered
+/// banner sits lower than the ink on its right, and at -45 degrees the reverse.
+#[test]
+fn test_watermark_angle() -> Result<()> {
+    use imagekit::processor::draw_watermark;
+
+    let fonts = load_test_fonts()?;
+    // Returns the average height of the ink in the left and right thirds of the image.
+    let slope = |angle: f32| {
+        let mut img = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(600, 600, Rgba([0, 0, 0, 255])));
+        let spec = WatermarkSpec {
+            text: "DRAFT COPY".to_string(),
+            position: WatermarkPosition::Center,
+            font_size: 60,
+            color: HexColor(Rgba([255, 255, 255, 255])),
+            angle,
+            ..WatermarkSpec::default()
+        };
+        draw_watermark(&mut img, &spec, &fonts);
+        let rgba = img.to_rgba8();
+        let mean_y = |range: std::ops::Range<u32>| {
+            let ys: Vec<u32> = rgba.enumerate_pixels().filter(|(x, _, p)| range.contains(x) && p[0] > 0).map(|(_, y, _)| y).collect();
+            ys.iter().sum::<u32>() as f32 / ys.len().max(1) as f32
+        };
+        (mean_y(0..200), mean_y(400..600))
+    };
+
+    let (left, right) = slope(45.0);
+    assert!(left > right + 100.0, "45 degrees should rise to the right: left {} right {}", left, right);
+    let (left, right) = slope(-45.0);
+    assert!(right > left + 100.0, "-45 degrees should fall to the right: left {} right {}", left, right);
+    let (left, right) = slope(0.0);
+    assert!((left - right).abs() < 10.0, "Level text should stay level: left {} right {}", left, right);
+
+    Ok(())
+}
+
+/// Verifies how the Lambda handler reads S3 event notifications and maps keys to staged files
+/// and output keys, and that a build without the `lambda` feature refuses to serve.
+#[test]
+fn test_lambda_s3_events() -> Result<()> {
+    use imagekit::lambda::{output_key, s3_objects, staged_path, S3Object};
+
+    let event = r#"{"Records":[{"eventName":"ObjectCreated:Put","s3":{"bucket":{"name":"uploads"},"object":{"key":"trips/My+Trip/%C3%A9t%C3%A9+1.jpg","size":1024}}}]}"#;
+    let objects = s3_objects(event)?;
+    assert_eq!(objects, vec![S3Object { bucket: "uploads".to_string(), key: "trips/My Trip/été 1.jpg".to_string() }]);
+    assert!(s3_objects(r#"{"detail":{}}"#).is_err());
+
+    let stage = Path::new("/tmp/stage");
+    assert_eq!(staged_path(stage, &objects[0].key)?, stage.join("trips/My Trip/été 1.jpg"));
+    for key in ["../etc/passwd", "/abs.jpg", "folder/", ""] {
+        assert!(staged_path(stage, key).is_err(), "{:?} should be refused", key);
+    }
+    assert_eq!(output_key("processed/", Path::new("trips/My Trip/été 1.jpg")), "processed/trips/My Trip/été 1.jpg");
+
+    #[cfg(not(feature = "lambda"))]
+    {
+        let input_dir = tempdir()?;
+        let output_dir = tempdir()?;
+        let args = imagekit::cli::LambdaArgs {
+            output_bucket: None,
+            output_prefix: "processed/".to_string(),
+            batch: test_cli(input_dir.path(), output_dir.path()),
+        };
+        let error = imagekit::lambda::run(&args).unwrap_err();
+        assert!(error.to_string().contains("`lambda` feature"), "{}", error);
+    }
+    Ok(())
+}
+
+/// Verifies that `run_with` returns each image's outcome, and that a run in which an image
+/// failed is refused by `ensure_no_failures`, as the Lambda handler and worker rely on.
+#[test]
+fn test_run_outcomes_report_failures() -> Result<()> {
+    use imagekit::ensure_no_failures;
+    use imagekit::report::Outcome;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    DynamicImage::new_rgb8(20, 10).save(input_dir.path().join("good.png"))?;
+    let outcomes = run_with(test_cli(input_dir.path(), output_dir.path()), RunContext::default())?;
+    assert!(matches!(outcomes.as_slice(), [(_, Outcome::Processed(_))]));
+    ensure_no_failures(&outcomes)?;
+
+    fs::write(input_dir.path().join("broken.png"), b"not a png")?;
+    let outcomes = run_with(test_cli(input_dir.path(), output_dir.path()), RunContext::default())?;
+    let error = ensure_no_failures(&outcomes).unwrap_err();
+    assert!(error.to_string().starts_with("1 of 2 images failed"), "{}", error);
+    assert!(error.to_string().contains("broken.png"), "{}", error);
+    Ok(())
+}
+
+/// Verifies that a typed `\n` breaks watermark text into lines while an escaped `\\n` doesn't,
+/// and that the shorter line lines up with the longer one on the left, center or right.
+#[test]
+fn test_watermark_multiline_alignment() -> Result<()> {
+    use imagekit::cli::TextAlign;
+    use imagekit::processor::draw_watermark;
+
+    let fonts = load_test_fonts()?;
+    // Returns the horizontal ink extent of the top and the bottom half of the text block.
+    let line_extents = |align: TextAlign| {
+        let mut img = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(600, 300, Rgba([0, 0, 0, 255])));
+        let spec = WatermarkSpec {
+            text: "WIDE FIRST LINE\\nshort".to_string(),
+            position: WatermarkPosition::Center,
+            font_size: 40,
+            color: HexColor(Rgba([255, 255, 255, 255])),
+            line_height: 1.5,
+            align,
+            ..WatermarkSpec::default()
+        };
+        draw_watermark(&mut img, &spec, &fonts);
+        let rgba = img.to_rgba8();
+        let inked: Vec<(u32, u32)> = rgba.enumerate_pixels().filter(|(_, _, p)| p[0] > 0).map(|(x, y, _)| (x, y)).collect();
+        let (top, bottom) = (inked.iter().map(|p| p.1).min().unwrap(), inked.iter().map(|p| p.1).max().unwrap());
+        let middle = (top + bottom) / 2;
+        let extent = |upper: bool| {
+            let xs: Vec<u32> = inked.iter().filter(|p| (p.1 < middle) == upper).map(|p| p.0).collect();
+            (*xs.iter().min().unwrap() as i32, *xs.iter().max().unwrap() as i32)
+        };
+        (extent(true), extent(false))
+    };
+
+    let (first, second) = line_extents(TextAlign::Left);
+    assert!(second.1 < first.1 - 50, "The second line should be drawn below the first and be shorter");
+    assert!((first.0 - second.0).abs() <= 4, "Left edges should line up: {:?} {:?}", first, second);
+    let (first, second) = line_extents(TextAlign::Right);
+    assert!((first.1 - second.1).abs() <= 4, "Right edges should line up: {:?} {:?}", first, second);
+    let (first, second) = line_extents(TextAlign::Center);
+    assert!(((first.0 + first.1) - (second.0 + second.1)).abs() <= 8, "Centers should line up: {:?} {:?}", first, second);
+
+    let mut img = DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(600, 300, Rgba([0, 0, 0, 255])));
+    let spec = WatermarkSpec {
+        text: "C:\\\\new\\photos".to_string(),
+        position: WatermarkPosition::Center,
+        font_size: 40,
+        color: HexColor(Rgba([255, 255, 255, 255])),
+        ..WatermarkSpec::default()
+    };
+    draw_watermark(&mut img, &spec, &fonts);
+    let rows: Vec<u32> = img.to_rgba8().enumerate_pixels().filter(|(_, _, p)| p[0] > 0).map(|(_, y, _)| y).collect();
+    let ink_height = rows.iter().max().unwrap() - rows.iter().min().unwrap();
+    assert!(ink_height < 50, "An escaped backslash-n should stay on one line, got {} pixels of ink", ink_height);
+    Ok(())
+}
+
+/// Verifies that the worker takes jobs from Redis, processes only the image a job names with its
+/// options over the worker's defaults, and pushes the result, including for a malformed job and
+/// one whose image fails. A job left on its processing list by a crash is requeued first, and
+/// each job leaves that list once its result is pushed.
+#[cfg(feature = "worker")]
+#[test]
+fn test_worker_processes_queued_jobs() -> Result<()> {
+    use std::collections::{HashMap, VecDeque};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    let input_dir = tempdir()?;
+    let output_dir = tempdir()?;
+    let photo = input_dir.path().join("photo.jpg");
+    DynamicImage::new_rgb8(200, 100).save(&photo)?;
+    DynamicImage::new_rgb8(200, 100).save(input_dir.path().join("other.jpg"))?;
+    let broken = input_dir.path().join("broken.png");
+    fs::write(&broken, b"not a png")?;
+    let defaults = input_dir.path().join("defaults.toml");
+    fs::write(&defaults, "width = 32\nquality = 70\n")?;
+
+    let job = serde_json::json!({
+        "id": "upload-7",
+        "input": photo,
+        "output": output_dir.path(),
+        "options": { "width": 64 },
+    });
+    let failing = serde_json::json!({ "id": "upload-8", "input": broken, "output": output_dir.path() });
+    let processing = "imagekit:jobs:processing:w1";
+    let mut lists: HashMap<String, VecDeque<String>> = HashMap::new();
+    lists.insert(processing.to_string(), VecDeque::from([job.to_string()]));
+    lists.insert("imagekit:jobs".to_string(), VecDeque::from(["not json".to_string(), failing.to_string()]));
+
+    // A stand-in Redis keeping lists for LMOVE, BLMOVE, RPUSH and LREM.
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let port = listener.local_addr()?.port();
+    let server = std::thread::spawn(move || -> Result<HashMap<String, VecDeque<String>>> {
+        let (stream, _) = listener.accept()?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            let count: usize = line.trim()[1..].parse()?;
+            let mut command = Vec::new();
+            for _ in 0..count {
+                line.clear();
+                reader.read_line(&mut line)?;
+                let mut data = vec![0; line.trim()[1..].parse::<usize>()? + 2];
+                reader.read_exact(&mut data)?;
+                command.push(String::from_utf8(data[..data.len() - 2].to_vec())?);
+            }
+            line.clear();
+            match command[0].as_str() {
+                "LMOVE" | "BLMOVE" => {
+                    let source = lists.entry(command[1].clone()).or_default();
+                    let value = if command[3] == "LEFT" { source.pop_front() } else { source.pop_back() };
+                    let Some(value) = value else {
+                        assert_eq!(command[0], "LMOVE", "BLMOVE on an empty list would block forever");
+                        write!(writer, "$-1\r\n")?;
+                        continue;
+                    };
+                    let destination = lists.entry(command[2].clone()).or_default();
+                    if command[4] == "LEFT" {
+                        destination.push_front(value.clone());
+                    } else {
+                        destination.push_back(value.clone());
+                    }
+                    write!(writer, "${}\r\n{}\r\n", value.len(), value)?;
+                }
+                "RPUSH" => {
+                    let list = lists.entry(command[1].clone()).or_default();
+                    list.push_back(command[2].clone());
+                    write!(writer, ":{}\r\n", list.len())?;
+                }
+                "LREM" => {
+                    let list = lists.entry(command[1].clone()).or_default();
+                    let removed = list.iter().position(|v| *v == command[3]).map(|i| list.remove(i)).is_some();
+                    write!(writer, ":{}\r\n", removed as u8)?;
+                }
+                // The client names itself on connecting.
+                "CLIENT" => write!(writer, "+OK\r\n")?,
+                other => write!(writer, "-ERR unknown command '{}'\r\n", other)?,
+            }
+        }
+        Ok(lists)
+    });
+
+    let args = imagekit::cli::WorkerArgs {
+        queue: format!("redis://127.0.0.1:{}", port),
+        jobs_key: "imagekit:jobs".to_string(),
+        results_key: "imagekit:results".to_string(),
+        worker_id: Some("w1".to_string()),
+        defaults: Some(defaults),
+        max_jobs: Some(3),
+    };
+    imagekit::worker::run(&args)?;
+    let mut lists = server.join().unwrap()?;
+
+    assert!(lists[processing].is_empty(), "{:?}", lists[processing]);
+    assert!(lists["imagekit:jobs"].is_empty());
+    let pushed = lists.remove("imagekit:results").unwrap_or_default();
+    assert_eq!(pushed.len(), 3);
+    let result: serde_json::Value = serde_json::from_str(&pushed[0])?;
+    assert_eq!(result["id"], "upload-7");
+    assert_eq!(result["status"], "ok", "{}", result);
+    assert_eq!(result["report"]["processed"], 1);
+    let outputs: Vec<_> = fs::read_dir(output_dir.path())?.collect::<Result<_, _>>()?;
+    assert_eq!(outputs.len(), 1);
+    assert_eq!(image::open(outputs[0].path())?.width(), 64);
+
+    let result: serde_json::Value = serde_json::from_str(&pushed[1])?;
+    assert_eq!(result["id"], serde_json::Value::Null);
+    assert_eq!(result["status"], "error");
+
+    let result: serde_json::Value = serde_json::from_str(&pushed[2])?;
+    assert_eq!(result["id"], "upload-8");
+    assert_eq!(result["status"], "error", "{}", result);
+    assert!(result["error"].as_str().unwrap_or_default().contains("broken.png"), "{}", result);
+    Ok(())
+}
+
+/// Verifies that without the `worker` feature the subcommand says what it needs.
+#[cfg(not(feature = "worker"))]
+#[test]
+fn test_worker_needs_feature() {
+    let args = imagekit::cli::WorkerArgs {
+        queue: "redis://127.0.0.1:6379".to_string(),
+        jobs_key: "imagekit:jobs".to_string(),
+        results_key: "imagekit:results".to_string(),
+        worker_id: None,
+        defaults: None,
+        max_jobs: Some(1),
+    };
+    let error = imagekit::worker::run(&args).unwrap_err();
+    assert!(error.to_string().contains("`worker` feature"), "{}", error);
+}